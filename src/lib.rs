@@ -0,0 +1,23 @@
+//! Chunked leeching of large torrents: split a pack into chunks that fit on a small disk,
+//! download each chunk with a torrent client and upload it before the next one.
+//!
+//! The entry points are:
+//! - [`TorrentPack`]: a parsed torrent with the maximum chunk size, its `chunks()` feed
+//!   [`build_queue`] which gives the [`Queue`] of [`Job`]s (one per chunk)
+//! - [`QbitConfig`]: a logged in qBittorrent Web UI session, [`QbitTorrent`] holds the add options
+//! - [`RcloneClient`]: uploads a chunk from the save path to a remote
+//! - [`SeedSettings`]: whether and where the pack is seeded once every chunk is uploaded
+//!
+//! `TorrentPack::leech_torrent` (from [`RplLeech`]) runs the whole loop with these. Every
+//! fallible call returns [`Error`].
+//!
+//! The modules under [`librpl`] are what the `pl` binary is built on, they are public but may
+//! change between releases.
+
+pub mod librpl;
+
+pub use librpl::error::Error;
+pub use librpl::qbittorrent::{QbitConfig, QbitTorrent};
+pub use librpl::rclone::RcloneClient;
+pub use librpl::torrent_parser::TorrentPack;
+pub use librpl::{build_queue, Job, Queue, RplChunk, RplLeech, SeedSettings};
@@ -0,0 +1,659 @@
+use async_trait::async_trait;
+use backoff::future::retry;
+use backoff::ExponentialBackoff;
+use indicatif::ProgressStyle;
+use lava_torrent::torrent::v1::Torrent;
+use log::{debug, error, info, warn};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::cmp::min;
+use std::path::PathBuf;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Duration};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+use crate::librpl::leech_steps;
+use crate::librpl::torrent_parser::TorrentPack;
+use crate::librpl::util;
+use crate::librpl::SeedSettings;
+use crate::librpl::{build_queue, error, Job, RplChunk, RplClient, RplLeech, RplPackConfig};
+use crate::librpl::{Events, LeechFuture, LeechSettings, ProgressEvent, RplUploadClient};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Deserialize)]
+struct RpcResp<T> {
+    result: Option<T>,
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct VersionInfo {
+    version: String,
+}
+
+// aria2 sends every number of tellStatus as a string
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Aria2TorrentInfo {
+    status: String,
+    completed_length: String,
+    total_length: String,
+    #[serde(default)]
+    error_message: String,
+    pub dir: String,
+}
+
+// a download of tellActive or tellWaiting, infoHash is only sent for torrents
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Aria2Download {
+    gid: String,
+    #[serde(default)]
+    info_hash: String,
+}
+
+impl Aria2TorrentInfo {
+    pub fn completed_length(&self) -> i64 {
+        self.completed_length.parse().unwrap_or(0)
+    }
+
+    pub fn total_length(&self) -> i64 {
+        self.total_length.parse().unwrap_or(0)
+    }
+}
+
+pub struct Aria2Config {
+    client: reqwest::Client,
+    // <address>/jsonrpc
+    rpc_url: String,
+    // rpc-secret of aria2, sent as the first param of every call
+    secret: String,
+    pub no_op: bool,
+}
+
+impl RplClient for Aria2Config {}
+impl RplPackConfig for Aria2Torrent {}
+
+impl Aria2Config {
+    pub async fn new(address: &str, secret: &str) -> Result<Self, error::Error> {
+        let config = Self {
+            client: reqwest::Client::new(),
+            rpc_url: format!("{}/jsonrpc", address.trim_end_matches('/')),
+            secret: secret.to_owned(),
+            no_op: false,
+        };
+        let version = config.version().await?;
+        debug!("aria2 version is {}", version);
+        Ok(config)
+    }
+
+    // only let aria2 start downloading each chunk, see QbitConfig::no_op
+    pub fn no_op(mut self, no_op: bool) -> Self {
+        self.no_op = no_op;
+        self
+    }
+
+    async fn rpc<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Vec<serde_json::Value>,
+    ) -> Result<T, error::Error> {
+        let mut all_params = Vec::with_capacity(params.len() + 1);
+        if !self.secret.is_empty() {
+            all_params.push(serde_json::Value::from(format!("token:{}", self.secret)));
+        }
+        all_params.extend(params);
+        let body = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": "rpl",
+            "method": method,
+            "params": all_params,
+        });
+        let res = retry(ExponentialBackoff::default(), || async {
+            Ok(self.client.post(&self.rpc_url).json(&body).send().await?)
+        })
+        .await?;
+
+        // aria2 answers errors with a 400 and the error in the body
+        let resp: RpcResp<T> = res.json().await?;
+        match (resp.result, resp.error) {
+            (_, Some(e)) if e.message == "Unauthorized" => Err(error::Error::Aria2LoginFailed),
+            (_, Some(e)) => Err(error::Error::Aria2RpcError(method.to_owned(), e.message)),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(error::Error::Aria2RpcError(
+                method.to_owned(),
+                String::from("empty response"),
+            )),
+        }
+    }
+
+    // rpc call whose result is not needed
+    async fn call(&self, method: &str, params: Vec<serde_json::Value>) -> Result<(), error::Error> {
+        self.rpc::<serde_json::Value>(method, params).await?;
+        Ok(())
+    }
+
+    pub async fn version(&self) -> Result<String, error::Error> {
+        let info: VersionInfo = self.rpc("aria2.getVersion", Vec::new()).await?;
+        Ok(info.version)
+    }
+
+    // the gid aria2 gave the torrent
+    pub async fn add_new_torrent(&self, data: &Aria2Torrent) -> Result<String, error::Error> {
+        let mut options = serde_json::json!({
+            "pause": data.paused.to_string(),
+            // pieces shared with files of other chunks leave partial files behind otherwise
+            "bt-remove-unselected-file": "true",
+        });
+        if let Some(path) = &data.dir {
+            options["dir"] = path.to_string_lossy().into();
+        }
+        if data.download_limit > 0 {
+            options["max-download-limit"] = data.download_limit.to_string().into();
+        }
+        for (key, value) in &data.seed_options {
+            options[key] = value.to_owned().into();
+        }
+        self.rpc(
+            "aria2.addTorrent",
+            vec![
+                base64::encode(data.metainfo.as_ref().expect("No torrent to add")).into(),
+                serde_json::json!([]),
+                options,
+            ],
+        )
+        .await
+    }
+
+    // the 1-based indices of the files to download, see Job::select_file
+    pub async fn select_files(&self, gid: &str, select_file: &str) -> Result<(), error::Error> {
+        self.call(
+            "aria2.changeOption",
+            vec![
+                gid.into(),
+                serde_json::json!({ "select-file": select_file }),
+            ],
+        )
+        .await
+    }
+
+    pub async fn unpause(&self, gid: &str) -> Result<(), error::Error> {
+        self.call("aria2.unpause", vec![gid.into()]).await
+    }
+
+    // stop the download and forget it, aria2 never deletes the downloaded files itself
+    pub async fn remove_torrent(&self, gid: &str) -> Result<(), error::Error> {
+        let info = self.get_torrent_info(gid).await?;
+        if !matches!(info.status.as_str(), "complete" | "error" | "removed") {
+            self.call("aria2.forceRemove", vec![gid.into()]).await?;
+        }
+        self.call("aria2.removeDownloadResult", vec![gid.into()])
+            .await
+    }
+
+    // the gid of the active or waiting torrent with this info hash, e.g. to clean up after rpl
+    // was interrupted without knowing it
+    pub async fn find_torrent(&self, info_hash: &str) -> Result<Option<String>, error::Error> {
+        let keys = serde_json::json!(["gid", "infoHash"]);
+        let mut downloads: Vec<Aria2Download> =
+            self.rpc("aria2.tellActive", vec![keys.clone()]).await?;
+        downloads.extend(
+            self.rpc::<Vec<Aria2Download>>("aria2.tellWaiting", vec![0.into(), 1000.into(), keys])
+                .await?,
+        );
+        Ok(downloads
+            .into_iter()
+            .find(|download| download.info_hash.eq_ignore_ascii_case(info_hash))
+            .map(|download| download.gid))
+    }
+
+    pub async fn get_torrent_info(&self, gid: &str) -> Result<Aria2TorrentInfo, error::Error> {
+        self.rpc(
+            "aria2.tellStatus",
+            vec![
+                gid.into(),
+                serde_json::json!([
+                    "status",
+                    "completedLength",
+                    "totalLength",
+                    "errorMessage",
+                    "dir",
+                ]),
+            ],
+        )
+        .await
+    }
+
+    // add the torrent back pointing at the upload client's mount, seeding without checking the
+    // data there first
+    pub async fn seed_torrent(
+        &self,
+        config: Aria2Torrent,
+        seed: &SeedSettings,
+    ) -> Result<(), error::Error> {
+        // aria2 reads a seed-ratio of 0.0 as "no ratio limit"
+        let ratio = match *seed.seed_ratio_limit() < 0.0 {
+            true => 0.0,
+            false => *seed.seed_ratio_limit(),
+        };
+        let mut seed_config = config
+            .paused(false)
+            .save_path(PathBuf::from(
+                shellexpand::full(seed.seed_path()).unwrap().into_owned(),
+            ))
+            .seed_option("bt-seed-unverified", "true")
+            .seed_option("seed-ratio", &ratio.to_string());
+        if *seed.seed_time_limit() >= 0 {
+            seed_config = seed_config.seed_option("seed-time", &seed.seed_time_limit().to_string());
+        }
+        self.add_new_torrent(&seed_config).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Aria2Torrent {
+    metainfo: Option<Vec<u8>>,
+    dir: Option<PathBuf>,
+    paused: bool,
+    download_limit: i64,
+    seed_options: Vec<(String, String)>,
+}
+
+impl Aria2Torrent {
+    pub fn torrents(mut self, torrent: Torrent) -> Self {
+        self.metainfo = Some(
+            torrent
+                .encode()
+                .expect("Could not encode Torrent to bencode. Is torrent file corrupted?"),
+        );
+        self
+    }
+
+    pub fn save_path(mut self, path: PathBuf) -> Self {
+        self.dir = Some(path);
+        self
+    }
+
+    pub fn paused(mut self, paused: bool) -> Self {
+        self.paused = paused;
+        self
+    }
+
+    // bytes/s, 0 for unlimited
+    pub fn download_limit(mut self, limit: i64) -> Self {
+        self.download_limit = limit;
+        self
+    }
+
+    // by default aria2 keeps seeding a finished chunk, which would hold up the leech
+    pub fn stop_on_complete(self) -> Self {
+        self.seed_option("seed-time", "0")
+    }
+
+    fn seed_option(mut self, key: &str, value: &str) -> Self {
+        self.seed_options.retain(|(k, _)| k != key);
+        self.seed_options.push((key.to_owned(), value.to_owned()));
+        self
+    }
+}
+
+#[async_trait]
+impl<'a> RplLeech<'a, TorrentPack, Aria2Torrent, Aria2Config> for TorrentPack {
+    async fn leech_torrent(
+        &'a mut self,
+        torrent: Torrent,
+        config: Aria2Torrent,
+        torrent_client: Aria2Config,
+        upload_client: Box<dyn RplUploadClient>,
+        seed: SeedSettings,
+        settings: LeechSettings,
+    ) -> Result<(), error::Error> {
+        leech(
+            self,
+            torrent,
+            config,
+            torrent_client,
+            upload_client,
+            seed,
+            settings,
+            Events::default(),
+        )
+        .await
+    }
+
+    fn leech_torrent_with_events(
+        &'a mut self,
+        torrent: Torrent,
+        config: Aria2Torrent,
+        torrent_client: Aria2Config,
+        upload_client: Box<dyn RplUploadClient>,
+        seed: SeedSettings,
+        settings: LeechSettings,
+    ) -> (UnboundedReceiverStream<ProgressEvent>, LeechFuture<'a>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let events = Events::new(sender);
+        let name = torrent.name.to_owned();
+        let run = Box::pin(async move {
+            let result = leech(
+                self,
+                torrent,
+                config,
+                torrent_client,
+                upload_client,
+                seed,
+                settings,
+                events.clone(),
+            )
+            .await;
+            if let Err(e) = &result {
+                events.send(ProgressEvent::Failed {
+                    name,
+                    error: e.to_string(),
+                });
+            }
+            result
+        });
+        (UnboundedReceiverStream::new(receiver), run)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn leech(
+    pack: &mut TorrentPack,
+    torrent: Torrent,
+    config: Aria2Torrent,
+    torrent_client: Aria2Config,
+    mut upload_client: Box<dyn RplUploadClient>,
+    seed: SeedSettings,
+    mut settings: LeechSettings,
+    events: Events,
+) -> Result<(), error::Error> {
+    info!(
+        "The pack size is {}, maximum size per chunk is {}. Private torrent = {}.",
+        &pack.get_pack_size_human(),
+        &pack.get_max_size_chunk_human(),
+        &pack.is_private()
+    );
+    info!("aria2 Version: {}", torrent_client.version().await?);
+
+    let chunks = pack.chunks()?;
+    let queue = build_queue(chunks, torrent)?;
+    let size = queue.leech_size(settings.skip, settings.only.as_deref());
+    let jobs = queue.job;
+    let no_jobs = jobs.len();
+    events.send(ProgressEvent::Started {
+        name: pack.torrent.name.to_owned(),
+        chunks: no_jobs,
+        size,
+    });
+
+    leech_steps::refuse_existing(pack, &jobs, settings.skip, upload_client.as_ref()).await?;
+
+    let mut skipped = settings.skip;
+    let mut uploaded: u64 = 0;
+    let mut failed = leech_steps::FailedChunks::default();
+    let chunk_config = config.clone().stop_on_complete();
+
+    for job in jobs {
+        leech_steps::check_stop(&settings, &job, no_jobs)?;
+        job.info();
+        if skipped > 0 {
+            info!("Chunk {}/{} has been skipped", job.chunk, no_jobs);
+            events.send(ProgressEvent::ChunkSkipped { chunk: job.chunk });
+            skipped -= 1;
+            continue;
+        }
+        if leech_steps::not_selected(&settings, &job, no_jobs) {
+            events.send(ProgressEvent::ChunkSkipped { chunk: job.chunk });
+            continue;
+        }
+        if leech_steps::over_budget(&settings, &job, uploaded, no_jobs)? {
+            return failed.check();
+        }
+        let mut gid = None;
+        match leech_chunk(
+            pack,
+            &job,
+            no_jobs,
+            &chunk_config,
+            &torrent_client,
+            &mut upload_client,
+            &mut settings,
+            &events,
+            &mut gid,
+        )
+        .await
+        {
+            Ok(()) => uploaded += job.total_size as u64,
+            Err(e) => {
+                failed.record(&mut settings, &job, no_jobs, e, &events)?;
+                // the next chunk starts from a fresh torrent
+                if let Some(gid) = gid {
+                    if let Err(e) = torrent_client.remove_torrent(&gid).await {
+                        debug!("Could not remove the torrent of chunk {}: {}", job.chunk, e);
+                    }
+                }
+                if let Err(e) = job.remove_files(upload_client.get_source()) {
+                    debug!("Could not remove the files of chunk {}: {}", job.chunk, e);
+                }
+            }
+        }
+    }
+    failed.check()?;
+
+    if settings.final_verify_count && !torrent_client.no_op {
+        leech_steps::verify_remote_count(pack, &settings, upload_client.as_ref()).await?;
+    }
+
+    if *seed.seed_enable() && torrent_client.no_op {
+        info!("[no-op] Not adding the torrent back for seeding, nothing has been uploaded");
+    } else if *seed.seed_enable() {
+        info!(
+            "Waiting for {} to refresh mount point...",
+            upload_client.name()
+        );
+        util::wait_with_progress(*seed.seed_wait()).await;
+        info!(
+            "Adding the torrent back to aria2 for seeding through {}'s mount",
+            upload_client.name()
+        );
+        torrent_client.seed_torrent(config, &seed).await?;
+        events.send(ProgressEvent::Seeding {
+            name: pack.torrent.name.to_owned(),
+        });
+    }
+
+    events.send(ProgressEvent::Finished {
+        name: pack.torrent.name.to_owned(),
+    });
+
+    Ok(())
+}
+
+// add the torrent, download and upload one chunk, then remove the torrent and the chunk's files.
+// gid is set once aria2 has the torrent, so a failed chunk can be removed too.
+#[allow(clippy::too_many_arguments)]
+async fn leech_chunk(
+    pack: &mut TorrentPack,
+    job: &Job,
+    no_jobs: usize,
+    config: &Aria2Torrent,
+    torrent_client: &Aria2Config,
+    upload_client: &mut Box<dyn RplUploadClient>,
+    settings: &mut LeechSettings,
+    events: &Events,
+    gid: &mut Option<String>,
+) -> Result<(), error::Error> {
+    leech_steps::check_output_space(settings, job, upload_client.get_source(), no_jobs)?;
+    if let Some(cross_seed) = &settings.cross_seed {
+        cross_seed.link_chunk(&pack.torrent, job, upload_client.get_source())?;
+    }
+    let added = gid.insert(torrent_client.add_new_torrent(config).await?);
+    torrent_client
+        .select_files(added, &job.select_file())
+        .await?;
+    info!("Downloading chunk {}/{}", job.chunk, no_jobs);
+    events.send(ProgressEvent::DownloadStarted { chunk: job.chunk });
+    job.download(torrent_client, added, no_jobs, events).await?;
+    info!("Finished downloading chunk {}/{}", job.chunk, no_jobs);
+    events.send(ProgressEvent::DownloadFinished { chunk: job.chunk });
+    let dir = PathBuf::from(torrent_client.get_torrent_info(added).await?.dir);
+    if dir != upload_client.get_source() {
+        debug!(
+            "Uploading chunk {}/{} from aria2's dir {}",
+            job.chunk,
+            no_jobs,
+            dir.display()
+        );
+        upload_client.set_source(dir);
+    }
+    leech_steps::upload_chunk(
+        pack,
+        job,
+        no_jobs,
+        upload_client.as_ref(),
+        settings,
+        events,
+        torrent_client.no_op,
+    )
+    .await?;
+
+    torrent_client.remove_torrent(added).await?;
+    job.remove_files(upload_client.get_source())
+}
+
+#[async_trait]
+trait RplAria2 {
+    fn select_file(&self) -> String;
+    async fn download(
+        &self,
+        client: &Aria2Config,
+        gid: &str,
+        no_jobs: usize,
+        events: &Events,
+    ) -> Result<(), error::Error>;
+}
+
+#[async_trait]
+impl RplAria2 for Job {
+    // aria2's --select-file counts the files of the torrent from 1
+    fn select_file(&self) -> String {
+        self.indices
+            .iter()
+            .map(|i| (i + 1).to_string())
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+
+    async fn download(
+        &self,
+        client: &Aria2Config,
+        gid: &str,
+        no_jobs: usize,
+        events: &Events,
+    ) -> Result<(), error::Error> {
+        client.unpause(gid).await?;
+        let size = self.total_size;
+
+        let pb = events.progress_bar(size as u64);
+        pb.set_style(ProgressStyle::default_bar()
+            .template("{spinner:.green} {msg} [{elapsed_precise}] [{bar:20.cyan/blue}] {bytes}/{total_bytes} [{binary_bytes_per_sec}] ({eta})")
+            .progress_chars("#>-"));
+        pb.set_message(format!(
+            "Waiting to download chunk {}/{}",
+            self.chunk, no_jobs
+        ));
+
+        loop {
+            let info = client.get_torrent_info(gid).await?;
+            let downloaded = min(info.completed_length(), size);
+            match info.status.as_str() {
+                "complete" => {
+                    pb.finish_and_clear();
+                    return Ok(());
+                }
+                "error" => {
+                    pb.finish_and_clear();
+                    error!(
+                        "aria2 could not download chunk {}/{}: {}",
+                        self.chunk, no_jobs, info.error_message
+                    );
+                    return Err(error::Error::Aria2TorrentErrored(info.error_message));
+                }
+                "removed" => {
+                    pb.finish_and_clear();
+                    error!("The torrent was removed from aria2, maybe it has been removed by the user!");
+                    return Err(error::Error::Aria2TorrentErrored(String::from(
+                        "the torrent was removed",
+                    )));
+                }
+                "paused" => {
+                    warn!("aria2 paused the torrent, starting it again...");
+                    client.unpause(gid).await?;
+                }
+                "waiting" => {
+                    pb.set_message(format!(
+                        "[Queued] Downloading chunk {}/{}",
+                        self.chunk, no_jobs
+                    ));
+                }
+                _ => {
+                    if client.no_op && info.total_length() > 0 {
+                        pb.finish_and_clear();
+                        info!(
+                            "[no-op] Chunk {}/{} started downloading, not waiting for it to finish",
+                            self.chunk, no_jobs
+                        );
+                        return Ok(());
+                    }
+                    pb.set_message(format!("Downloading chunk {}/{}", self.chunk, no_jobs));
+                }
+            }
+            pb.set_position(downloaded as u64);
+            events.send(ProgressEvent::DownloadProgress {
+                chunk: self.chunk,
+                downloaded: downloaded as u64,
+                total: size as u64,
+            });
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tell_status() {
+        let json = r#"{"id":"rpl","jsonrpc":"2.0","result":{"completedLength":"1048576","dir":"/data/rpl","errorMessage":"","status":"active","totalLength":"2097152"}}"#;
+        let resp: RpcResp<Aria2TorrentInfo> = serde_json::from_str(json).unwrap();
+        let info = resp.result.unwrap();
+        assert_eq!(info.status, "active");
+        assert_eq!(info.completed_length(), 1048576);
+        assert_eq!(info.total_length(), 2097152);
+        assert_eq!(info.dir, "/data/rpl");
+
+        let json = r#"{"id":"rpl","jsonrpc":"2.0","error":{"code":1,"message":"Unauthorized"}}"#;
+        let resp: RpcResp<Aria2TorrentInfo> = serde_json::from_str(json).unwrap();
+        assert!(resp.result.is_none());
+        assert_eq!(resp.error.unwrap().message, "Unauthorized");
+
+        let job = Job::new(
+            2,
+            200,
+            2,
+            vec![PathBuf::from("Pack/b"), PathBuf::from("Pack/c")],
+            vec![1, 2],
+            vec![100, 100],
+        );
+        assert_eq!(job.select_file(), "2,3");
+    }
+}
@@ -0,0 +1,563 @@
+use async_trait::async_trait;
+use indicatif::ProgressStyle;
+use log::{debug, info, warn};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::librpl::error;
+use crate::librpl::rclone::OnExistingRemote;
+use crate::librpl::{Events, Job, ProgressEvent, RplUploadClient};
+
+const AUTHORIZE_URL: &str = "https://api.backblazeb2.com/b2api/v2/b2_authorize_account";
+
+// attempts per api call or upload before giving up, with a growing pause in between
+const MAX_ATTEMPTS: u32 = 5;
+
+// files listed per b2_list_file_names call, 1000 is the most a call is billed as one transaction
+const LIST_PAGE_SIZE: u32 = 1000;
+
+// b2_authorize_account
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct B2AuthorizeResp {
+    account_id: String,
+    authorization_token: String,
+    api_url: String,
+    recommended_part_size: u64,
+}
+
+// body of every failed B2 call
+#[derive(Debug, Deserialize)]
+struct B2ErrorResp {
+    status: u16,
+    code: String,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct B2Bucket {
+    bucket_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct B2ListBucketsResp {
+    buckets: Vec<B2Bucket>,
+}
+
+// b2_get_upload_url and b2_get_upload_part_url
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct B2UploadUrl {
+    upload_url: String,
+    authorization_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct B2StartLargeFileResp {
+    file_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct B2FileName {
+    file_name: String,
+    content_length: i64,
+    action: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct B2ListFileNamesResp {
+    files: Vec<B2FileName>,
+    next_file_name: Option<String>,
+}
+
+#[derive(Debug)]
+struct B2Auth {
+    api_url: String,
+    token: String,
+}
+
+// what an upload url is fetched for: a whole file or the parts of a started large file
+enum UploadTarget<'a> {
+    File,
+    Part(&'a str),
+}
+
+// Uploads straight to a Backblaze B2 bucket through B2's native API. Files larger than the
+// account's recommended part size are uploaded as large files, one part at a time.
+#[derive(Debug, Clone)]
+pub struct B2Client {
+    client: reqwest::Client,
+    key_id: String,
+    application_key: String,
+    bucket_id: String,
+    part_size: u64,
+    // shared between clones so a renewed token is used everywhere
+    auth: Arc<Mutex<B2Auth>>,
+    source: PathBuf,
+    // folder inside the bucket
+    destination: String,
+    on_existing: Option<OnExistingRemote>,
+}
+
+impl B2Client {
+    pub async fn new(
+        key_id: &str,
+        application_key: &str,
+        bucket: &str,
+        source: PathBuf,
+        destination: &str,
+    ) -> Result<Self, error::Error> {
+        let client = reqwest::Client::new();
+        let resp = authorize(&client, key_id, application_key).await?;
+        let mut b2 = Self {
+            client,
+            key_id: key_id.to_owned(),
+            application_key: application_key.to_owned(),
+            bucket_id: String::new(),
+            part_size: resp.recommended_part_size,
+            auth: Arc::new(Mutex::new(B2Auth {
+                api_url: resp.api_url,
+                token: resp.authorization_token,
+            })),
+            source,
+            destination: destination.trim_matches('/').to_owned(),
+            on_existing: None,
+        };
+
+        let buckets: B2ListBucketsResp = b2
+            .api(
+                "b2_list_buckets",
+                serde_json::json!({"accountId": resp.account_id, "bucketName": bucket}),
+            )
+            .await?;
+        b2.bucket_id = match buckets.buckets.into_iter().next() {
+            Some(found) => found.bucket_id,
+            None => return Err(error::Error::B2BucketNotFound(bucket.to_owned())),
+        };
+        debug!(
+            "Authorized with B2, bucket `{}` has id {}, part size is {}",
+            bucket, b2.bucket_id, b2.part_size
+        );
+        Ok(b2)
+    }
+
+    pub fn on_existing_remote(mut self, on_existing: Option<OnExistingRemote>) -> Self {
+        self.on_existing = on_existing;
+        self
+    }
+
+    // <destination>/<path> in the bucket
+    fn file_name(&self, path: &Path) -> String {
+        let path = path.to_string_lossy().replace('\\', "/");
+        match self.destination.is_empty() {
+            true => path,
+            false => format!("{}/{}", self.destination, path),
+        }
+    }
+
+    async fn reauthorize(&self) -> Result<(), error::Error> {
+        let resp = authorize(&self.client, &self.key_id, &self.application_key).await?;
+        let mut auth = self.auth.lock().unwrap();
+        auth.api_url = resp.api_url;
+        auth.token = resp.authorization_token;
+        Ok(())
+    }
+
+    // call a B2 api, renewing the token once it expires and retrying what B2 asks to retry
+    async fn api<T: DeserializeOwned>(
+        &self,
+        call: &str,
+        body: serde_json::Value,
+    ) -> Result<T, error::Error> {
+        let mut attempt = 1;
+        loop {
+            let (api_url, token) = {
+                let auth = self.auth.lock().unwrap();
+                (auth.api_url.to_owned(), auth.token.to_owned())
+            };
+            let result = match self
+                .client
+                .post(format!("{}/b2api/v2/{}", api_url, call))
+                .header("Authorization", token)
+                .json(&body)
+                .send()
+                .await
+            {
+                Ok(res) => check(res).await,
+                Err(e) => Err(error::Error::from(e)),
+            };
+            match result {
+                Ok(res) => return Ok(res.json().await?),
+                Err(e) if attempt < MAX_ATTEMPTS && is_retryable(&e) => {
+                    warn!("{} failed ({}), retrying", call, e);
+                    if is_expired_token(&e) {
+                        self.reauthorize().await?;
+                    } else {
+                        tokio::time::sleep(backoff(attempt)).await;
+                    }
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn upload_url(&self, target: &UploadTarget<'_>) -> Result<B2UploadUrl, error::Error> {
+        match target {
+            UploadTarget::File => {
+                self.api(
+                    "b2_get_upload_url",
+                    serde_json::json!({ "bucketId": self.bucket_id }),
+                )
+                .await
+            }
+            UploadTarget::Part(file_id) => {
+                self.api(
+                    "b2_get_upload_part_url",
+                    serde_json::json!({ "fileId": file_id }),
+                )
+                .await
+            }
+        }
+    }
+
+    // Upload data to a fresh upload url and return its sha1. B2 hands out a new url whenever an
+    // upload fails, so one is fetched for every attempt.
+    async fn upload(
+        &self,
+        target: UploadTarget<'_>,
+        headers: &[(&str, String)],
+        data: Vec<u8>,
+    ) -> Result<String, error::Error> {
+        let sha1 = hex(&Sha1::digest(&data));
+        let mut attempt = 1;
+        loop {
+            let url = self.upload_url(&target).await?;
+            let mut request = self
+                .client
+                .post(&url.upload_url)
+                .header("Authorization", &url.authorization_token)
+                .header("Content-Length", data.len())
+                .header("X-Bz-Content-Sha1", &sha1);
+            for (name, value) in headers {
+                request = request.header(*name, value);
+            }
+            let result = match request.body(data.clone()).send().await {
+                Ok(res) => check(res).await,
+                Err(e) => Err(error::Error::from(e)),
+            };
+            match result {
+                Ok(_) => return Ok(sha1),
+                Err(e) if attempt < MAX_ATTEMPTS && is_retryable(&e) => {
+                    warn!("Upload to B2 failed ({}), retrying", e);
+                    tokio::time::sleep(backoff(attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn upload_file(
+        &self,
+        path: &Path,
+        name: &str,
+        length: u64,
+        progress: &(dyn Fn(u64) + Sync),
+    ) -> Result<(), error::Error> {
+        let encoded_name = url::form_urlencoded::byte_serialize(name.as_bytes()).collect();
+        if length <= self.part_size {
+            let data = std::fs::read(path)?;
+            self.upload(
+                UploadTarget::File,
+                &[
+                    ("X-Bz-File-Name", encoded_name),
+                    ("Content-Type", String::from("b2/x-auto")),
+                ],
+                data,
+            )
+            .await?;
+            progress(length);
+            return Ok(());
+        }
+
+        let started: B2StartLargeFileResp = self
+            .api(
+                "b2_start_large_file",
+                serde_json::json!({
+                    "bucketId": self.bucket_id,
+                    "fileName": name,
+                    "contentType": "b2/x-auto",
+                }),
+            )
+            .await?;
+        match self.upload_parts(path, &started.file_id, progress).await {
+            Ok(sha1s) => {
+                self.api::<serde_json::Value>(
+                    "b2_finish_large_file",
+                    serde_json::json!({ "fileId": started.file_id, "partSha1Array": sha1s }),
+                )
+                .await?;
+                Ok(())
+            }
+            Err(e) => {
+                // leave no unfinished large file behind, it is billed as stored data
+                if let Err(cancel) = self
+                    .api::<serde_json::Value>(
+                        "b2_cancel_large_file",
+                        serde_json::json!({ "fileId": started.file_id }),
+                    )
+                    .await
+                {
+                    warn!("Could not cancel the large file `{}`: {}", name, cancel);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn upload_parts(
+        &self,
+        path: &Path,
+        file_id: &str,
+        progress: &(dyn Fn(u64) + Sync),
+    ) -> Result<Vec<String>, error::Error> {
+        let mut file = File::open(path)?;
+        let mut sha1s = Vec::new();
+        let mut uploaded = 0;
+        loop {
+            let mut data = Vec::with_capacity(self.part_size as usize);
+            (&mut file).take(self.part_size).read_to_end(&mut data)?;
+            if data.is_empty() {
+                break;
+            }
+            let length = data.len() as u64;
+            let part_number = sha1s.len() + 1;
+            let sha1 = self
+                .upload(
+                    UploadTarget::Part(file_id),
+                    &[("X-Bz-Part-Number", part_number.to_string())],
+                    data,
+                )
+                .await?;
+            sha1s.push(sha1);
+            uploaded += length;
+            progress(uploaded);
+        }
+        Ok(sha1s)
+    }
+
+    // every file whose name starts with prefix, with its size
+    async fn list(&self, prefix: &str) -> Result<Vec<B2FileName>, error::Error> {
+        let mut files = Vec::new();
+        let mut start: Option<String> = None;
+        loop {
+            let page: B2ListFileNamesResp = self
+                .api(
+                    "b2_list_file_names",
+                    serde_json::json!({
+                        "bucketId": self.bucket_id,
+                        "prefix": prefix,
+                        "startFileName": start,
+                        "maxFileCount": LIST_PAGE_SIZE,
+                    }),
+                )
+                .await?;
+            files.extend(page.files.into_iter().filter(|f| f.action == "upload"));
+            match page.next_file_name {
+                Some(next) => start = Some(next),
+                None => return Ok(files),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl RplUploadClient for B2Client {
+    fn name(&self) -> &str {
+        "b2"
+    }
+
+    fn get_source(&self) -> &Path {
+        &self.source
+    }
+
+    fn set_source(&mut self, source: PathBuf) {
+        self.source = source;
+    }
+
+    fn get_destination(&self) -> &str {
+        &self.destination
+    }
+
+    fn get_on_existing_remote(&self) -> Option<OnExistingRemote> {
+        self.on_existing
+    }
+
+    async fn list_remote(
+        &self,
+        path: &str,
+        is_file: bool,
+    ) -> Result<HashMap<PathBuf, i64>, error::Error> {
+        let mut prefix = self.file_name(Path::new(path));
+        if !is_file {
+            prefix.push('/');
+        }
+        let strip = match self.destination.is_empty() {
+            true => 0,
+            false => self.destination.len() + 1,
+        };
+        Ok(self
+            .list(&prefix)
+            .await?
+            .into_iter()
+            .filter(|f| !is_file || f.file_name == prefix)
+            .map(|f| (PathBuf::from(&f.file_name[strip..]), f.content_length))
+            .collect())
+    }
+
+    async fn upload_chunk(
+        &self,
+        job: &Job,
+        no_jobs: usize,
+        events: &Events,
+    ) -> Result<(), error::Error> {
+        // b2 keeps every upload as a new version, so only skip needs to look at the bucket
+        let existing = match self.on_existing {
+            Some(OnExistingRemote::Skip) => self.list(&self.file_name(Path::new(""))).await?,
+            _ => Vec::new(),
+        };
+
+        let pb = events.progress_bar(job.total_size as u64);
+        pb.set_style(ProgressStyle::default_bar()
+            .template("{spinner:.green} {msg} [{elapsed_precise}] [{bar:20.cyan/blue}] {bytes}/{total_bytes} [{binary_bytes_per_sec}] ({eta})")
+            .progress_chars("#>-"));
+        pb.set_message(format!("Uploading chunk {}/{}", job.chunk, no_jobs));
+
+        let mut done: u64 = 0;
+        for (file, length) in job.files.iter().zip(&job.lengths) {
+            let name = self.file_name(file);
+            if existing.iter().any(|f| f.file_name == name) {
+                info!("`{}` is already in the bucket, it will be skipped", name);
+            } else {
+                let progress = |uploaded: u64| {
+                    pb.set_position(done + uploaded);
+                    events.send(ProgressEvent::UploadProgress {
+                        chunk: job.chunk,
+                        uploaded: done + uploaded,
+                        total: job.total_size as u64,
+                    });
+                };
+                debug!("Uploading `{}` to B2 as `{}`", file.display(), name);
+                self.upload_file(&self.source.join(file), &name, *length as u64, &progress)
+                    .await?;
+            }
+            done += *length as u64;
+            pb.set_position(done);
+        }
+        pb.finish_and_clear();
+        Ok(())
+    }
+
+    // uploads run inside rpl and stop when the leech is dropped
+    fn interrupt(&self) {}
+
+    fn boxed(&self) -> Box<dyn RplUploadClient> {
+        Box::new(self.clone())
+    }
+}
+
+async fn authorize(
+    client: &reqwest::Client,
+    key_id: &str,
+    application_key: &str,
+) -> Result<B2AuthorizeResp, error::Error> {
+    let res = client
+        .get(AUTHORIZE_URL)
+        .basic_auth(key_id, Some(application_key))
+        .send()
+        .await?;
+    Ok(check(res).await?.json().await?)
+}
+
+// turn a failed B2 response into its error, caps get their own variants
+async fn check(res: reqwest::Response) -> Result<reqwest::Response, error::Error> {
+    if res.status().is_success() {
+        return Ok(res);
+    }
+    let status = res.status().as_u16();
+    let body = res.text().await?;
+    let resp: B2ErrorResp = serde_json::from_str(&body).unwrap_or(B2ErrorResp {
+        status,
+        code: String::new(),
+        message: body,
+    });
+    Err(match resp.code.as_str() {
+        "cap_exceeded" => error::Error::B2CapExceeded,
+        "transaction_cap_exceeded" => error::Error::B2TransactionCapExceeded,
+        _ => error::Error::B2ApiError(resp.status, resp.code, resp.message),
+    })
+}
+
+// B2 asks clients to retry timeouts, rate limits and busy servers, network errors are retried too
+fn is_retryable(e: &error::Error) -> bool {
+    match e {
+        error::Error::ReqErr(_) => true,
+        error::Error::B2ApiError(status, _, _) => {
+            matches!(status, 408 | 429 | 500 | 503) || is_expired_token(e)
+        }
+        _ => false,
+    }
+}
+
+fn is_expired_token(e: &error::Error) -> bool {
+    matches!(e, error::Error::B2ApiError(401, code, _) if code == "expired_auth_token")
+}
+
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(1 << attempt)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retryable() {
+        assert!(is_retryable(&error::Error::B2ApiError(
+            503,
+            String::from("service_unavailable"),
+            String::new()
+        )));
+        assert!(is_retryable(&error::Error::B2ApiError(
+            401,
+            String::from("expired_auth_token"),
+            String::new()
+        )));
+        assert!(!is_retryable(&error::Error::B2ApiError(
+            401,
+            String::from("unauthorized"),
+            String::new()
+        )));
+        assert!(!is_retryable(&error::Error::B2CapExceeded));
+        assert_eq!(
+            hex(&Sha1::digest(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+    }
+}
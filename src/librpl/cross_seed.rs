@@ -0,0 +1,202 @@
+use lava_torrent::torrent::v1::Torrent;
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::librpl::error;
+use crate::librpl::pieces;
+use crate::librpl::Job;
+
+// Data already on disk from another torrent. Files are matched by name and size like the upload
+// manifest. The torrent client writes into whatever is linked in when its pieces do not match,
+// so a hardlink is only kept when the pieces of the file are verified, any other match is copied
+// and left to the client's hash check.
+#[derive(Debug, Default)]
+pub struct CrossSeed {
+    // "<size>:<file name>" -> path of the existing file
+    files: HashMap<String, PathBuf>,
+}
+
+fn key(name: &str, length: u64) -> String {
+    format!("{}:{}", length, name)
+}
+
+impl CrossSeed {
+    pub fn load(dir: &Path) -> Result<Self, error::Error> {
+        let mut cross_seed = Self::default();
+        cross_seed.scan(dir)?;
+        debug!(
+            "Found {} files for cross-seeding in {}",
+            cross_seed.files.len(),
+            dir.display()
+        );
+        Ok(cross_seed)
+    }
+
+    fn scan(&mut self, dir: &Path) -> Result<(), error::Error> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                self.scan(&entry.path())?;
+            } else if metadata.is_file() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                self.files.insert(key(&name, metadata.len()), entry.path());
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, path: &Path, length: i64) -> Option<&PathBuf> {
+        let name = path.file_name()?.to_string_lossy();
+        self.files.get(&key(&name, length as u64))
+    }
+
+    // hardlink (or copy, see link_verified) the existing files of a chunk into save_path so the
+    // torrent client only downloads what is missing. Returns the number of files reused.
+    pub fn link_chunk(
+        &self,
+        torrent: &Torrent,
+        job: &Job,
+        save_path: &Path,
+    ) -> Result<usize, error::Error> {
+        let mut linked = 0;
+        for ((path, length), index) in job.files.iter().zip(&job.lengths).zip(&job.indices) {
+            if *length == 0 {
+                continue;
+            }
+            let from = match self.get(path, *length) {
+                Some(from) => from,
+                None => continue,
+            };
+            let to = save_path.join(path);
+            if to.exists() {
+                continue;
+            }
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let file = Job::new(
+                job.chunk,
+                *length,
+                1,
+                vec![path.to_owned()],
+                vec![*index],
+                vec![*length],
+            );
+            if link_verified(torrent, &file, from, save_path)? {
+                linked += 1;
+            }
+        }
+        if linked > 0 {
+            info!(
+                "Chunk {} reuses {} existing files, qBittorrent will check them before downloading the rest",
+                job.chunk, linked
+            );
+        }
+        Ok(linked)
+    }
+}
+
+// Put the existing file from at the place of the job's only file. A hardlink shares its data with
+// the other torrent's file, so it is checked against the pieces first: a false match is dropped,
+// and a file whose pieces are shared with its neighbours (which cannot be checked yet) is copied
+// instead. A copy is all that is possible across filesystems. Returns false for a false match.
+fn link_verified(
+    torrent: &Torrent,
+    file: &Job,
+    from: &Path,
+    save_path: &Path,
+) -> Result<bool, error::Error> {
+    let to = save_path.join(&file.files[0]);
+    if fs::hard_link(from, &to).is_err() {
+        fs::copy(from, &to)?;
+        debug!("Copied {} to {}", from.display(), to.display());
+        return Ok(true);
+    }
+    match pieces::fully_verified(torrent, file, save_path) {
+        Ok(true) => {
+            debug!("Linked {} to {}", from.display(), to.display());
+            Ok(true)
+        }
+        Ok(false) => {
+            fs::remove_file(&to)?;
+            fs::copy(from, &to)?;
+            debug!(
+                "Copied {} to {}, its pieces are shared with other files",
+                from.display(),
+                to.display()
+            );
+            Ok(true)
+        }
+        Err(error::Error::PieceHashMismatch(_, _)) => {
+            fs::remove_file(&to)?;
+            warn!(
+                "{} has the name and size of {} but not its data, it will be downloaded",
+                from.display(),
+                to.display()
+            );
+            Ok(false)
+        }
+        Err(e) => {
+            fs::remove_file(&to)?;
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::librpl::fixtures::pack;
+    use sha1::{Digest, Sha1};
+
+    #[test]
+    fn match_by_name_and_size() {
+        let mut cross_seed = CrossSeed::default();
+        cross_seed.files.insert(
+            key("ep01.mkv", 1000),
+            PathBuf::from("/data/Other Pack/ep01.mkv"),
+        );
+        assert_eq!(
+            cross_seed.get(Path::new("Pack/ep01.mkv"), 1000),
+            Some(&PathBuf::from("/data/Other Pack/ep01.mkv"))
+        );
+        assert_eq!(cross_seed.get(Path::new("Pack/ep01.mkv"), 999), None);
+        assert_eq!(cross_seed.get(Path::new("Pack/ep02.mkv"), 1000), None);
+    }
+
+    #[test]
+    fn link_only_verified_data() {
+        let data: Vec<u8> = (0u8..12).collect();
+        let dir = std::env::temp_dir().join(format!("rpl-cross-seed-{}", std::process::id()));
+        let other = dir.join("Other Pack");
+        fs::create_dir_all(&other).unwrap();
+        fs::write(other.join("a"), &data[0..8]).unwrap();
+        fs::write(other.join("b"), [0u8; 4]).unwrap();
+
+        let mut torrent = pack(&[("a", 8), ("b", 4)]);
+        torrent.piece_length = 4;
+        torrent.pieces = data.chunks(4).map(|p| Sha1::digest(p).to_vec()).collect();
+        let job = Job::new(
+            1,
+            12,
+            2,
+            vec![PathBuf::from("Pack/a"), PathBuf::from("Pack/b")],
+            vec![0, 1],
+            vec![8, 4],
+        );
+        let save_path = dir.join("save");
+        let cross_seed = CrossSeed::load(&other).unwrap();
+        // b has the name and size of the torrent's file but not its data
+        assert_eq!(
+            cross_seed.link_chunk(&torrent, &job, &save_path).unwrap(),
+            1
+        );
+        assert_eq!(fs::read(save_path.join("Pack/a")).unwrap(), &data[0..8]);
+        assert!(!save_path.join("Pack/b").exists());
+        assert_eq!(fs::read(other.join("b")).unwrap(), [0u8; 4]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
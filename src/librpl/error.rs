@@ -1,4 +1,6 @@
 #![allow(clippy::enum_variant_names)]
+use crate::librpl::util;
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Request Error when talking to qBittorrent: {0}")]
@@ -9,46 +11,326 @@ pub enum Error {
     SerdeJson(#[from] serde_json::Error),
     #[error("Header value was malformed: {0}")]
     HeaderError(#[from] reqwest::header::InvalidHeaderValue),
+    #[error("Header name was malformed: {0}")]
+    HeaderNameError(#[from] reqwest::header::InvalidHeaderName),
     #[error("Header value was not correctly set")]
     MissingHeaders,
     #[error("Cookie value was not correctly set")]
     MissingCookie,
-    #[error("Could not slice off SID from returned cookie")]
-    SliceError,
+    #[error("qBittorrent client: Login failed, check username and password")]
+    QbitLoginFailed,
+    #[error("rpl was interrupted")]
+    Interrupted,
     #[error("There is nothing in the pack to leech")]
     NothingToLeech,
     #[error("qBittorrent returned nothing for the hash provided")]
     QbitEmptyTorrentInfo,
     #[error("qBittorrent client: The torrent has encountered an unexpected error")]
     QbitTorrentErrored,
+    #[error("Transmission client: Login failed, check username and password")]
+    TransmissionLoginFailed,
+    #[error("Transmission client: {0} failed: {1}")]
+    TransmissionRpcError(String, String),
+    #[error("Transmission returned nothing for the hash provided")]
+    TransmissionTorrentNotFound,
+    #[error("Transmission client: The torrent has encountered an error: {0}")]
+    TransmissionTorrentErrored(String),
+    #[error("aria2 client: Login failed, check rpc secret")]
+    Aria2LoginFailed,
+    #[error("aria2 client: {0} failed: {1}")]
+    Aria2RpcError(String, String),
+    #[error("aria2 client: The torrent has encountered an error: {0}")]
+    Aria2TorrentErrored(String),
+    #[error("rTorrent client: Login failed, check username and password")]
+    RtorrentLoginFailed,
+    #[error("rTorrent client: {0} failed: {1}")]
+    RtorrentRpcError(String, String),
     #[error("Unexpected rclone stderr capture error encountered")]
     RcloneStderrCaptureError,
+    #[error("rclone failed to upload chunk {0}, rerun rpl to retry it: {1}")]
+    RcloneUploadFailed(i32, String),
+    #[error("rclone hit a fatal error, the local data of the chunk is kept: {0}")]
+    RcloneFatalError(String),
+    #[error("rclone could not list the remote path `{0}`")]
+    RcloneListError(String),
+    #[error("rclone could not delete the remote path `{0}`")]
+    RclonePurgeError(String),
+    #[error("`{0}` is already on the remote, refusing to upload over it (on_existing_remote = \"error\")")]
+    RemoteFileExists(String),
+    #[error("Final verification failed: expected {0} files on the remote but found {1}")]
+    FileCountMismatch(usize, usize),
+    #[error("B2: the storage cap of the account has been reached, raise it in B2's Caps & Alerts")]
+    B2CapExceeded,
+    #[error("B2: the daily transaction cap of the account has been reached, raise it in B2's Caps & Alerts or wait for it to reset")]
+    B2TransactionCapExceeded,
+    #[error("B2 returned {0} {1}: {2}")]
+    B2ApiError(u16, String, String),
+    #[error("B2: bucket `{0}` does not exist or the application key cannot access it")]
+    B2BucketNotFound(String),
+    #[error("sftp: uploading chunk {0} failed: {1}")]
+    SftpUploadFailed(i32, String),
+    #[error("sftp: could not list `{0}` on the remote box, check that ssh can log in without a password")]
+    SftpListError(String),
     #[error("Command io spawning error: {0}")]
     CommandSpawningError(#[from] std::io::Error),
     #[error("File size in pack is larger than maximum allowed size")]
     MaxSizeAllowedTooSmall,
     #[error("Invalid max_size_percentage, allowed value are 0-100")]
     InvalidMaxSizePercentage,
+    #[error(
+        "The pack cannot be split into {0} chunks, a single file is larger than an even share"
+    )]
+    InfeasibleNumChunks(u32),
+    #[error("The pack would be split into {0} chunks, more than max_chunks ({1}), raise max_size")]
+    TooManyChunks(usize, u32),
+    #[error("qBittorrent client: The Web UI did not become ready in time")]
+    QbitClientNotReady,
+    #[error("{0} did not answer in time")]
+    ClientTimeout(&'static str),
+    #[error("qBittorrent client: Could not fetch the metadata of the magnet link in time")]
+    QbitMetadataTimeout,
+    #[error("qBittorrent client: qBittorrent {0} cannot export the metadata of this magnet link (4.5 or later can), pass the torrent file instead")]
+    QbitMagnetUnsupported(String),
+    #[error("qBittorrent client: The torrent was stuck checking resume data")]
+    QbitCheckingResumeDataTimeout,
+    #[error("qBittorrent client: The torrent does not have enough seeders")]
+    NotEnoughSeeders,
+    #[error("qBittorrent client: The torrent is managed by another rpl instance")]
+    QbitTorrentNotOwned,
     #[error("qBittorrent client: The torrent has entered unknown state!")]
     QbitTorrentUnknownState,
     #[error("qBittorrent client: The torrent has entered missing files state!")]
     QbitTorrentMissingFilesState,
+    #[error("Config error: min_seeders_policy must be either \"fail\" or \"skip\"")]
+    InvalidMinSeedersPolicy,
+    #[error("Input error: the torrent is corrupt: {0}")]
+    CorruptTorrent(String),
+    #[error("Input error: the torrent is BitTorrent v2 only, rpl supports v1 and hybrid torrents")]
+    V2OnlyTorrent,
+    #[error("Resume error: chunk {0} does not hold the same files as in the stored plan, rerun with --replan to use the current chunk layout")]
+    PlanMismatch(u32),
+    #[error(
+        "Resume error: there is no interrupted run of `{0}` to resume, see `rpl resume --list`"
+    )]
+    UnknownRun(String),
+    #[error(
+        "Chunk {0}: piece {1} does not match the torrent's hash, the downloaded data is corrupt"
+    )]
+    PieceHashMismatch(i32, usize),
+    #[error("Input error: refusing to change the tracker of a private torrent without -f/--force")]
+    PrivateTorrentAnnounce,
     #[error("Config error: Unsupported torrent client")]
     UnsupportedTorrentClient,
     #[error("Config error: mount path does not exist")]
     MountPathNotExist,
+    #[error("Config error: drive_service_account_file `{0}` does not exist")]
+    ServiceAccountFileNotExist(String),
+    #[error("Config error: `{0}` was not found on PATH")]
+    UploadClientNotFound(String),
     #[error(
         "Config error: Unsupported rclone variant (only rclone/fclone/gclone/xclone is supported)"
     )]
     UnsupportedRcloneVariant,
+    #[error("Config error: key_id, application_key and bucket in [b2] cannot be empty when upload_client is \"b2\"")]
+    B2ConfigMissing,
+    #[error("Input error: {0} is not supported with upload_client = \"b2\"")]
+    B2Unsupported(&'static str),
+    #[error("Config error: host in [sftp] cannot be empty when upload_client is \"sftp\"")]
+    SftpConfigMissing,
+    #[error("Input error: {0} is not supported with upload_client = \"sftp\"")]
+    SftpUnsupported(&'static str),
+    #[error("Input error: {0} is not supported with torrent_client = \"transmission\"")]
+    TransmissionUnsupported(&'static str),
+    #[error("Input error: {0} is not supported when the torrent is read from stdin")]
+    StdinUnsupported(&'static str),
+    #[error("Input error: {0} is not supported with torrent_client = \"aria2\"")]
+    Aria2Unsupported(&'static str),
+    #[error("Input error: {0} is not supported with torrent_client = \"rtorrent\"")]
+    RtorrentUnsupported(&'static str),
+    #[error("Config error: rclone_log_level must be one of DEBUG, INFO, NOTICE or ERROR")]
+    InvalidRcloneLogLevel,
+    #[error("Config error: rclone_retries and rclone_low_level_retries must be positive integers")]
+    InvalidRcloneRetries,
+    #[error("Config error: `{0}` in extra_custom_flags is not supported, use exclude and include in [rclone] instead")]
+    UnsupportedCustomFilter(String),
+    #[error("Config error: rclone_command must be \"copy\" or \"move\"")]
+    InvalidRcloneCommand,
+    #[error("Config error: rclone_command = \"move\" cannot be used with {0}, the local files are gone after the first upload")]
+    RcloneMoveConflict(&'static str),
+    #[error(
+        "Config error: chunk_strategy must be either \"sequential\" or \"firstfit-decreasing\""
+    )]
+    InvalidChunkStrategy,
+    #[error("Config error: upload_mode must be either \"round-robin\" or \"mirror\"")]
+    InvalidUploadMode,
+    #[error("Config error: on_existing_remote must be one of \"skip\", \"overwrite\" or \"error\" (or empty)")]
+    InvalidOnExistingRemote,
+    #[error("Input error: could not parse the file selection `so={0}` of the magnet link (example: \"so=0,2,4-6\")")]
+    InvalidMagnetSelection(String),
+    #[error("Input error: could not parse the chunks `{0}` of --only, expected chunk numbers of the pack (example: \"3,5,7-9\")")]
+    InvalidChunkSelection(String),
+    #[error(
+        "{} chunk(s) failed: {}, rerun rpl with `--only {}` to retry them",
+        .0.len(),
+        util::chunk_list(.0),
+        util::chunk_list(.0)
+    )]
+    ChunksFailed(Vec<i32>),
     #[error("Input error: rpl could not parse the input (only torrent file, url link and magnet link are supported)")]
     RplInvalidInput,
+    #[error("Config error: could not parse size `{0}` (examples: \"5 GiB\", \"500 MB\")")]
+    InvalidSizeFormat(String),
+    #[error("Config error: could not parse the exclude glob `{0}`: {1}")]
+    InvalidGlob(String, glob::PatternError),
+    #[error("Input error: {0} expects a whole number, got `{1}`")]
+    InvalidNumber(String, String),
+    #[error("Input error: could not parse the speed (examples: \"50Mbps\", \"10MiB/s\")")]
+    InvalidSpeed,
     #[error("Config error: save_path cannot be empty")]
     SavePathEmptyError,
     #[error("Config error: save_path and remote_path in config file cannot be empty")]
     SaveRemoteEmptyError,
     #[error("Config error: remote_path cannot be empty")]
     RemotePathEmptyError,
+    #[error("There is not enough free disk space in save_path for the next chunk")]
+    InsufficientDiskSpace,
     #[error("Config error: could not read available disk space from save_path")]
     DiskSpaceReadError,
 }
+
+impl Error {
+    // coarse cause of the error, for wrappers that parse `--json-errors`
+    pub fn category(&self) -> &'static str {
+        match self {
+            Error::ReqErr(_)
+            | Error::ToStringError(_)
+            | Error::HeaderError(_)
+            | Error::HeaderNameError(_)
+            | Error::MissingHeaders
+            | Error::MissingCookie
+            | Error::QbitClientNotReady
+            | Error::ClientTimeout(_) => "connection",
+            Error::QbitLoginFailed
+            | Error::TransmissionLoginFailed
+            | Error::Aria2LoginFailed
+            | Error::RtorrentLoginFailed => "auth",
+            Error::QbitEmptyTorrentInfo
+            | Error::QbitTorrentErrored
+            | Error::QbitCheckingResumeDataTimeout
+            | Error::QbitMetadataTimeout
+            | Error::QbitMagnetUnsupported(_)
+            | Error::NotEnoughSeeders
+            | Error::QbitTorrentNotOwned
+            | Error::QbitTorrentUnknownState
+            | Error::QbitTorrentMissingFilesState
+            | Error::TransmissionRpcError(_, _)
+            | Error::TransmissionTorrentNotFound
+            | Error::TransmissionTorrentErrored(_)
+            | Error::Aria2RpcError(_, _)
+            | Error::Aria2TorrentErrored(_)
+            | Error::RtorrentRpcError(_, _) => "torrent_client",
+            Error::RcloneStderrCaptureError
+            | Error::RcloneUploadFailed(_, _)
+            | Error::RcloneFatalError(_)
+            | Error::RcloneListError(_)
+            | Error::RclonePurgeError(_)
+            | Error::RemoteFileExists(_)
+            | Error::B2ApiError(_, _, _)
+            | Error::B2BucketNotFound(_)
+            | Error::SftpUploadFailed(_, _)
+            | Error::SftpListError(_) => "upload_client",
+            Error::B2CapExceeded | Error::B2TransactionCapExceeded => "quota",
+            Error::InsufficientDiskSpace | Error::DiskSpaceReadError => "disk",
+            Error::PieceHashMismatch(_, _) => "corruption",
+            Error::FileCountMismatch(_, _) => "verification",
+            Error::PlanMismatch(_) | Error::UnknownRun(_) => "resume",
+            Error::Interrupted => "interrupted",
+            Error::ChunksFailed(_) => "partial",
+            Error::NothingToLeech
+            | Error::MaxSizeAllowedTooSmall
+            | Error::InfeasibleNumChunks(_)
+            | Error::TooManyChunks(_, _)
+            | Error::CorruptTorrent(_)
+            | Error::V2OnlyTorrent
+            | Error::PrivateTorrentAnnounce
+            | Error::InvalidMagnetSelection(_)
+            | Error::InvalidChunkSelection(_)
+            | Error::B2Unsupported(_)
+            | Error::SftpUnsupported(_)
+            | Error::TransmissionUnsupported(_)
+            | Error::Aria2Unsupported(_)
+            | Error::RtorrentUnsupported(_)
+            | Error::StdinUnsupported(_)
+            | Error::RplInvalidInput
+            | Error::InvalidNumber(_, _)
+            | Error::InvalidSpeed => "input",
+            Error::InvalidMaxSizePercentage
+            | Error::InvalidMinSeedersPolicy
+            | Error::UnsupportedTorrentClient
+            | Error::MountPathNotExist
+            | Error::ServiceAccountFileNotExist(_)
+            | Error::UnsupportedRcloneVariant
+            | Error::UploadClientNotFound(_)
+            | Error::InvalidRcloneLogLevel
+            | Error::B2ConfigMissing
+            | Error::SftpConfigMissing
+            | Error::InvalidRcloneRetries
+            | Error::InvalidRcloneCommand
+            | Error::UnsupportedCustomFilter(_)
+            | Error::RcloneMoveConflict(_)
+            | Error::InvalidOnExistingRemote
+            | Error::InvalidChunkStrategy
+            | Error::InvalidUploadMode
+            | Error::InvalidSizeFormat(_)
+            | Error::InvalidGlob(_, _)
+            | Error::SavePathEmptyError
+            | Error::SaveRemoteEmptyError
+            | Error::RemotePathEmptyError => "config",
+            Error::SerdeJson(_) | Error::CommandSpawningError(_) => "internal",
+        }
+    }
+
+    // errors that only concern the chunk being leeched, the run goes on with the next chunk.
+    // A fatal rclone error (e.g. a quota that is used up) would fail every upload after it, so
+    // like a used up B2 cap it stops the run.
+    pub fn is_chunk_failure(&self) -> bool {
+        matches!(
+            self.category(),
+            "torrent_client" | "upload_client" | "corruption"
+        ) && !matches!(self, Error::RcloneFatalError(_))
+    }
+
+    // the chunk, piece, file or remote path the error is about, if any
+    pub fn context(&self) -> Vec<(&'static str, serde_json::Value)> {
+        match self {
+            Error::PieceHashMismatch(chunk, piece) => {
+                vec![("chunk", (*chunk).into()), ("piece", (*piece).into())]
+            }
+            Error::FileCountMismatch(expected, actual) => {
+                vec![
+                    ("expected", (*expected).into()),
+                    ("actual", (*actual).into()),
+                ]
+            }
+            Error::PlanMismatch(chunk) => vec![("chunk", (*chunk).into())],
+            Error::RcloneUploadFailed(chunk, _) | Error::SftpUploadFailed(chunk, _) => {
+                vec![("chunk", (*chunk).into())]
+            }
+            Error::InfeasibleNumChunks(chunks) => vec![("chunks", (*chunks).into())],
+            Error::TooManyChunks(chunks, max_chunks) => vec![
+                ("chunks", (*chunks).into()),
+                ("max_chunks", (*max_chunks).into()),
+            ],
+            Error::ChunksFailed(chunks) => vec![("chunks", chunks.clone().into())],
+            Error::ServiceAccountFileNotExist(file) | Error::RemoteFileExists(file) => {
+                vec![("file", file.as_str().into())]
+            }
+            Error::RcloneListError(path)
+            | Error::RclonePurgeError(path)
+            | Error::SftpListError(path) => {
+                vec![("remote_path", path.as_str().into())]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use lava_torrent::torrent::v1::{File, Torrent};
+use std::path::PathBuf;
+
+// a pack named "Pack" holding files with these paths and lengths, without piece hashes
+pub fn pack(files: &[(&str, i64)]) -> Torrent {
+    Torrent {
+        announce: None,
+        announce_list: None,
+        length: files.iter().map(|(_, length)| length).sum(),
+        files: Some(
+            files
+                .iter()
+                .map(|(path, length)| File {
+                    length: *length,
+                    path: PathBuf::from(path),
+                    extra_fields: None,
+                })
+                .collect(),
+        ),
+        name: String::from("Pack"),
+        piece_length: 16384,
+        pieces: Vec::new(),
+        extra_fields: None,
+        extra_info_fields: None,
+    }
+}
@@ -0,0 +1,300 @@
+use humansize::{file_size_opts, FileSize};
+use log::{debug, error, info, warn};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use crate::librpl::error;
+use crate::librpl::pieces;
+use crate::librpl::rclone::OnExistingRemote;
+use crate::librpl::torrent_parser::TorrentPack;
+use crate::librpl::util;
+use crate::librpl::{Events, Job, LeechSettings, ProgressEvent, RplUploadClient};
+
+// Steps of the leech loop that do not depend on the torrent client
+
+// refuse to leech when on_existing_remote = "error" and a file of the chunks after skip is
+// already on the remote
+pub async fn refuse_existing(
+    pack: &TorrentPack,
+    jobs: &[Job],
+    skip: u32,
+    upload_client: &dyn RplUploadClient,
+) -> Result<(), error::Error> {
+    if upload_client.get_on_existing_remote() != Some(OnExistingRemote::Error) {
+        return Ok(());
+    }
+    let remote = upload_client
+        .list_remote(&pack.torrent.name, pack.torrent.files.is_none())
+        .await?;
+    match jobs
+        .iter()
+        .skip(skip as usize)
+        .flat_map(|job| &job.files)
+        .find(|file| remote.contains_key(*file))
+    {
+        Some(file) => {
+            error!("Some files of the pack are already on the remote, clean it up or change on_existing_remote");
+            Err(error::Error::RemoteFileExists(
+                file.to_string_lossy().into_owned(),
+            ))
+        }
+        None => Ok(()),
+    }
+}
+
+// Err(Interrupted) once a stop was requested, checked before every chunk
+pub fn check_stop(settings: &LeechSettings, job: &Job, no_jobs: usize) -> Result<(), error::Error> {
+    if let Some(stop) = &settings.stop {
+        if stop.load(Ordering::SeqCst) {
+            info!(
+                "Stopping before chunk {}/{} as requested",
+                job.chunk, no_jobs
+            );
+            info!(
+                "Rerun rpl with `--skip {}` to continue from chunk {}/{}",
+                job.chunk - 1,
+                job.chunk,
+                no_jobs
+            );
+            return Err(error::Error::Interrupted);
+        }
+    }
+    Ok(())
+}
+
+// true when --only leaves the chunk out of this run
+pub fn not_selected(settings: &LeechSettings, job: &Job, no_jobs: usize) -> bool {
+    match &settings.only {
+        Some(only) if !only.contains(&job.chunk) => {
+            debug!("Chunk {}/{} is not selected by --only", job.chunk, no_jobs);
+            true
+        }
+        _ => false,
+    }
+}
+
+// Chunks that failed for good in this run. Every leech loop records the error of a chunk here
+// and goes on with the next chunk when it only concerns that chunk.
+#[derive(Debug, Default)]
+pub struct FailedChunks(Vec<i32>);
+
+impl FailedChunks {
+    // Ok when the chunk was recorded as failed in the plan, Err(e) for an error that must stop
+    // the run
+    pub fn record(
+        &mut self,
+        settings: &mut LeechSettings,
+        job: &Job,
+        no_jobs: usize,
+        e: error::Error,
+        events: &Events,
+    ) -> Result<(), error::Error> {
+        if !e.is_chunk_failure() {
+            return Err(e);
+        }
+        error!("Chunk {}/{} failed: {}", job.chunk, no_jobs, e);
+        events.send(ProgressEvent::ChunkFailed {
+            chunk: job.chunk,
+            error: e.to_string(),
+        });
+        if let Some((plan, path)) = &mut settings.plan {
+            plan.record_failed(job.chunk);
+            plan.save(path)?;
+        }
+        self.0.push(job.chunk);
+        Ok(())
+    }
+
+    // Err(ChunksFailed) once the loop is over if any chunk failed, the uploaded chunks are in
+    // the plan but the pack is neither verified nor seeded
+    pub fn check(self) -> Result<(), error::Error> {
+        match self.0.is_empty() {
+            true => Ok(()),
+            false => Err(error::Error::ChunksFailed(self.0)),
+        }
+    }
+}
+
+// true when uploading the chunk would take the bytes uploaded by this run over the budget, the
+// plan is saved with the chunks uploaded so far so the next run continues from there
+pub fn over_budget(
+    settings: &LeechSettings,
+    job: &Job,
+    uploaded: u64,
+    no_jobs: usize,
+) -> Result<bool, error::Error> {
+    let budget = match settings.upload_budget {
+        Some(budget) => budget,
+        None => return Ok(false),
+    };
+    if uploaded + job.total_size as u64 <= budget {
+        return Ok(false);
+    }
+    warn!(
+        "Uploading chunk {}/{} would go over the upload budget of {} ({} uploaded so far), stopping",
+        job.chunk,
+        no_jobs,
+        budget.file_size(file_size_opts::BINARY).unwrap(),
+        uploaded.file_size(file_size_opts::BINARY).unwrap(),
+    );
+    info!(
+        "Rerun rpl with `--skip {}` to continue from chunk {}/{}",
+        job.chunk - 1,
+        job.chunk,
+        no_jobs
+    );
+    if let Some((plan, path)) = &settings.plan {
+        plan.save(path)?;
+    }
+    Ok(true)
+}
+
+// archived chunks stay on disk, so the free space of output_dir shrinks with every chunk. On the
+// filesystem of source it is also where the chunk is downloaded, on another one only the archive
+// takes space there.
+pub fn check_output_space(
+    settings: &LeechSettings,
+    job: &Job,
+    source: &Path,
+    no_jobs: usize,
+) -> Result<(), error::Error> {
+    let output_dir = match &settings.output_dir {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    let (path, name) = match util::same_filesystem(source, output_dir) {
+        Ok(false) => (output_dir.as_path(), "output_dir"),
+        _ => (source, "save_path"),
+    };
+    let free_space = match fs2::available_space(path) {
+        Ok(size) => size,
+        Err(_e) => return Err(error::Error::DiskSpaceReadError),
+    };
+    if free_space < job.total_size as u64 {
+        error!(
+            "Chunk {}/{} needs {} but only {} is free in {}",
+            job.chunk,
+            no_jobs,
+            job.total_size
+                .file_size(file_size_opts::BINARY)
+                .expect("File size is a negative number?"),
+            free_space.file_size(file_size_opts::BINARY).unwrap(),
+            name,
+        );
+        return Err(error::Error::InsufficientDiskSpace);
+    }
+    Ok(())
+}
+
+// verify and upload a downloaded chunk, record it in the manifest and plan and move it to
+// output_dir if there is one
+pub async fn upload_chunk(
+    pack: &mut TorrentPack,
+    job: &Job,
+    no_jobs: usize,
+    upload_client: &dyn RplUploadClient,
+    settings: &mut LeechSettings,
+    events: &Events,
+    no_op: bool,
+) -> Result<(), error::Error> {
+    // torrent clients do not check data they were told to skip checking
+    if settings.verify_pieces && !no_op {
+        pieces::verify_chunk(&pack.torrent, job, upload_client.get_source())?;
+    }
+    info!("Uploading chunk {}/{}", job.chunk, no_jobs);
+    events.send(ProgressEvent::UploadStarted { chunk: job.chunk });
+    upload_client.upload_chunk(job, no_jobs, events).await?;
+    info!("Finished uploading chunk {}/{}", job.chunk, no_jobs);
+    if !no_op {
+        pack.record_uploaded(job, upload_client.chunk_destination(job.chunk))?;
+        settings.uploaded_files.extend(job.files.iter().cloned());
+        if let Some((plan, path)) = &mut settings.plan {
+            match settings.only {
+                Some(_) => plan.record_retried(job.chunk),
+                None => plan.record_uploaded(job.chunk),
+            }
+            plan.save(path)?;
+        }
+    }
+    events.send(ProgressEvent::UploadFinished { chunk: job.chunk });
+
+    if let Some(output_dir) = settings.output_dir.as_ref().filter(|_| !no_op) {
+        job.archive(upload_client.get_source(), output_dir)?;
+        info!(
+            "Moved chunk {}/{} to {}",
+            job.chunk,
+            no_jobs,
+            output_dir.display()
+        );
+    }
+    Ok(())
+}
+
+// final_verify_count: every file uploaded by this run must be on the remote. Chunks skipped,
+// left out by --only or failed are not expected, the files of earlier runs do not count.
+pub async fn verify_remote_count(
+    pack: &TorrentPack,
+    settings: &LeechSettings,
+    upload_client: &dyn RplUploadClient,
+) -> Result<(), error::Error> {
+    let expected_files = settings.uploaded_files.len();
+    let remote = upload_client
+        .list_remote(&pack.torrent.name, pack.torrent.files.is_none())
+        .await?;
+    let found = settings
+        .uploaded_files
+        .iter()
+        .filter(|file| remote.contains_key(*file))
+        .count();
+    if found != expected_files {
+        error!(
+            "The remote holds {} of the {} files uploaded by this run, some files or chunks are missing",
+            found, expected_files
+        );
+        return Err(error::Error::FileCountMismatch(expected_files, found));
+    }
+    info!(
+        "Final verification passed: all {} files uploaded by this run are on the remote",
+        expected_files
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn failed_chunks() {
+        let mut settings = LeechSettings::default();
+        let events = Events::default();
+        let job = |chunk| Job::new(chunk, 10, 1, vec![PathBuf::from("a")], vec![0], vec![10]);
+        let mut failed = FailedChunks::default();
+        failed
+            .record(
+                &mut settings,
+                &job(2),
+                4,
+                error::Error::RcloneUploadFailed(2, String::from("exited with code 1")),
+                &events,
+            )
+            .unwrap();
+        // a used up quota would fail every chunk after it
+        assert!(matches!(
+            failed.record(
+                &mut settings,
+                &job(3),
+                4,
+                error::Error::RcloneFatalError(String::from("quota exceeded")),
+                &events,
+            ),
+            Err(error::Error::RcloneFatalError(_))
+        ));
+        assert!(matches!(
+            failed.check(),
+            Err(error::Error::ChunksFailed(chunks)) if chunks == vec![2]
+        ));
+        assert!(FailedChunks::default().check().is_ok());
+    }
+}
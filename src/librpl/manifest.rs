@@ -0,0 +1,89 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::librpl::error;
+
+// Local record of every file rpl has uploaded, so a later pack that shares files with an earlier
+// one does not upload them again. Files are matched by name and size, their content is not hashed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ManifestData {
+    // "<size>:<file name>" -> remote path the file was uploaded to
+    uploaded: HashMap<String, String>,
+}
+
+#[derive(Debug)]
+pub struct Manifest {
+    path: PathBuf,
+    data: ManifestData,
+}
+
+fn key(path: &Path, length: i64) -> String {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    format!("{}:{}", length, name)
+}
+
+impl Manifest {
+    // a missing manifest file is treated as an empty manifest
+    pub fn load(path: PathBuf) -> Result<Self, error::Error> {
+        let data = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            debug!("{} does not exist, starting a new manifest", path.display());
+            ManifestData::default()
+        };
+        Ok(Self { path, data })
+    }
+
+    // remote path of an already uploaded file with the same name and size
+    pub fn get(&self, path: &Path, length: i64) -> Option<&str> {
+        self.data
+            .uploaded
+            .get(&key(path, length))
+            .map(|remote| remote.as_str())
+    }
+
+    pub fn record(&mut self, path: &Path, length: i64, remote: String) {
+        self.data.uploaded.insert(key(path, length), remote);
+    }
+
+    pub fn save(&self) -> Result<(), error::Error> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // write to a temporary file first so an interrupted save does not corrupt the manifest
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&self.data)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_by_name_and_size() {
+        let mut manifest = Manifest {
+            path: PathBuf::from("manifest.json"),
+            data: ManifestData::default(),
+        };
+        manifest.record(
+            Path::new("Pack A/ep01.mkv"),
+            1000,
+            String::from("nugu:/rpl/Pack A/ep01.mkv"),
+        );
+        assert_eq!(
+            manifest.get(Path::new("Pack B/extra/ep01.mkv"), 1000),
+            Some("nugu:/rpl/Pack A/ep01.mkv")
+        );
+        assert_eq!(manifest.get(Path::new("Pack B/ep01.mkv"), 999), None);
+        assert_eq!(manifest.get(Path::new("Pack B/ep02.mkv"), 1000), None);
+    }
+}
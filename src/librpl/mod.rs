@@ -1,20 +1,47 @@
+pub mod aria2;
+pub mod b2;
+pub mod cross_seed;
 pub mod error;
+#[cfg(test)]
+mod fixtures;
+mod leech_steps;
+pub mod manifest;
+pub mod multi_remote;
+pub mod pieces;
+pub mod plan;
 pub mod qbittorrent;
 pub mod rclone;
+pub mod rtorrent;
+pub mod sftp;
+pub mod summary;
 pub mod torrent_parser;
+pub mod transmission;
 pub mod util;
+pub mod webhook;
 
 use async_trait::async_trait;
 use derive_getters::Getters;
 use humansize::{file_size_opts, FileSize};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use lava_torrent::torrent::v1::Torrent;
 use log::info;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cmp::max;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_stream::wrappers::UnboundedReceiverStream;
 
-pub use crate::librpl::error as _;
-pub use crate::librpl::qbittorrent::QbitConfig;
-pub use crate::librpl::rclone::RcloneClient;
+use crate::librpl::cross_seed::CrossSeed;
+use crate::librpl::plan::Plan;
+pub use crate::librpl::rclone::{OnExistingRemote, RcloneClient};
 
 pub trait RplClient {}
 pub trait RplPackConfig {}
@@ -39,26 +66,124 @@ impl<'a> RplFile<'a> {
 }
 
 pub trait RplChunk<'a> {
-    fn chunks(&'a mut self) -> Result<HashMap<&str, RplFile<'a>>, error::Error>;
+    fn chunks(&'a mut self) -> Result<HashMap<&'a str, RplFile<'a>>, error::Error>;
 }
 
+// The files of one chunk, downloaded and uploaded together
+#[derive(Clone, Getters)]
 pub struct Job {
     chunk: i32,
     total_size: i64,
     no_files: i32,
+    // paths of the files in this chunk, relative to the save path
+    files: Vec<PathBuf>,
+    // indices of the files in this chunk in the torrent's file list
+    indices: Vec<i32>,
+    // sizes of the files in this chunk, in the same order as files
+    lengths: Vec<i64>,
+}
+
+// a file of a chunk as it appears in the --list-chunks output
+#[derive(Serialize)]
+struct JobFile<'a> {
+    path: &'a Path,
+    length: i64,
+}
+
+// files and lengths are kept apart for the clients, serialized they are paired up
+impl Serialize for Job {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let files: Vec<JobFile> = self
+            .files
+            .iter()
+            .zip(&self.lengths)
+            .map(|(path, length)| JobFile {
+                path,
+                length: *length,
+            })
+            .collect();
+        let mut job = serializer.serialize_struct("Job", 4)?;
+        job.serialize_field("chunk", &self.chunk)?;
+        job.serialize_field("no_files", &self.no_files)?;
+        job.serialize_field("total_size", &self.total_size)?;
+        job.serialize_field("files", &files)?;
+        job.end()
+    }
 }
 
 impl Job {
-    fn new(chunk: i32, total_size: i64, no_files: i32) -> Self {
+    pub fn new(
+        chunk: i32,
+        total_size: i64,
+        no_files: i32,
+        files: Vec<PathBuf>,
+        indices: Vec<i32>,
+        lengths: Vec<i64>,
+    ) -> Self {
         Self {
             chunk,
             total_size,
             no_files,
+            files,
+            indices,
+            lengths,
+        }
+    }
+
+    // move the downloaded files of this chunk from source into output_dir, keeping the layout
+    fn archive(&self, source: &Path, output_dir: &Path) -> Result<(), error::Error> {
+        for file in &self.files {
+            let from = source.join(file);
+            let to = output_dir.join(file);
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            util::move_file(&from, &to)?;
         }
+        Ok(())
+    }
+
+    // delete the uploaded files of this chunk from source while the torrent keeps running, files
+    // that are already gone (e.g. moved to output_dir) are left alone
+    pub fn remove_files(&self, source: &Path) -> Result<(), error::Error> {
+        for file in &self.files {
+            match fs::remove_file(source.join(file)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    // qBittorrent's file id list of the files in this chunk
+    fn file_ids(&self) -> String {
+        self.indices
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<String>>()
+            .join("|")
+    }
+
+    // average size of the files in this chunk, zero-byte placeholders would drag it down
+    pub fn average_file_size(&self) -> i64 {
+        self.total_size / max(self.no_files as i64 - self.empty_files(), 1)
+    }
+
+    fn empty_files(&self) -> i64 {
+        self.lengths.iter().filter(|length| **length == 0).count() as i64
     }
 
     fn info(&self) {
-        let avg = self.total_size / self.no_files as i64;
+        let empty_files = self.empty_files();
+        let avg = self.average_file_size();
+        if empty_files > 0 {
+            info!(
+                "Chunk {} has {} zero-byte files, they are uploaded but not counted in the average size.",
+                self.chunk, empty_files
+            );
+        }
         info!(
             "Chunk {} has {} files with total size of {}. Average size per file is {}.",
             self.chunk,
@@ -72,6 +197,241 @@ impl Job {
     }
 }
 
+// serialized as e.g. {"event": "download_started", "chunk": 1} for webhooks
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    Started {
+        name: String,
+        chunks: usize,
+        // bytes of the chunks this run leeches, skipped chunks excluded
+        size: u64,
+    },
+    ChunkSkipped {
+        chunk: i32,
+    },
+    DownloadStarted {
+        chunk: i32,
+    },
+    DownloadProgress {
+        chunk: i32,
+        downloaded: u64,
+        total: u64,
+    },
+    DownloadFinished {
+        chunk: i32,
+    },
+    UploadStarted {
+        chunk: i32,
+    },
+    UploadProgress {
+        chunk: i32,
+        uploaded: u64,
+        total: u64,
+    },
+    UploadFinished {
+        chunk: i32,
+    },
+    // the run goes on with the next chunk
+    ChunkFailed {
+        chunk: i32,
+        error: String,
+    },
+    Seeding {
+        name: String,
+    },
+    Finished {
+        name: String,
+    },
+    Failed {
+        name: String,
+        error: String,
+    },
+}
+
+impl ProgressEvent {
+    // progress updates are sent every second, every other event is a state transition
+    pub fn is_progress(&self) -> bool {
+        matches!(
+            self,
+            ProgressEvent::DownloadProgress { .. } | ProgressEvent::UploadProgress { .. }
+        )
+    }
+}
+
+impl fmt::Display for ProgressEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProgressEvent::Started { name, chunks, .. } => {
+                write!(f, "Started leeching `{}` in {} chunks", name, chunks)
+            }
+            ProgressEvent::ChunkSkipped { chunk } => write!(f, "Chunk {} skipped", chunk),
+            ProgressEvent::DownloadStarted { chunk } => {
+                write!(f, "Chunk {} download started", chunk)
+            }
+            ProgressEvent::DownloadProgress {
+                chunk,
+                downloaded,
+                total,
+            } => write!(
+                f,
+                "Chunk {} downloaded {}/{} bytes",
+                chunk, downloaded, total
+            ),
+            ProgressEvent::DownloadFinished { chunk } => {
+                write!(f, "Chunk {} download finished", chunk)
+            }
+            ProgressEvent::UploadStarted { chunk } => write!(f, "Chunk {} upload started", chunk),
+            ProgressEvent::UploadProgress {
+                chunk,
+                uploaded,
+                total,
+            } => write!(f, "Chunk {} uploaded {}/{} bytes", chunk, uploaded, total),
+            ProgressEvent::UploadFinished { chunk } => {
+                write!(f, "Chunk {} upload finished", chunk)
+            }
+            ProgressEvent::ChunkFailed { chunk, error } => {
+                write!(f, "Chunk {} failed: {}", chunk, error)
+            }
+            ProgressEvent::Seeding { name } => write!(f, "Seeding `{}`", name),
+            ProgressEvent::Finished { name } => write!(f, "Finished leeching `{}`", name),
+            ProgressEvent::Failed { name, error } => {
+                write!(f, "Failed leeching `{}`: {}", name, error)
+            }
+        }
+    }
+}
+
+// Bar of the whole pack above the per-chunk bars, counting the bytes downloaded and uploaded
+// of every chunk against twice the pack size
+pub struct PackProgress {
+    multi: Arc<MultiProgress>,
+    overall: ProgressBar,
+    // downloaded, uploaded and total bytes of every chunk seen so far
+    chunks: Mutex<BTreeMap<i32, (u64, u64, u64)>>,
+    no_jobs: AtomicUsize,
+}
+
+impl PackProgress {
+    fn new() -> Self {
+        let multi = Arc::new(MultiProgress::new());
+        let overall = multi.add(ProgressBar::new(0));
+        overall.set_style(
+            ProgressStyle::default_bar()
+                .template(
+                    "{msg} [{elapsed_precise}] [{bar:20.green/blue}] {bytes}/{total_bytes} ({eta})",
+                )
+                .progress_chars("#>-"),
+        );
+        Self {
+            multi,
+            overall,
+            chunks: Mutex::new(BTreeMap::new()),
+            no_jobs: AtomicUsize::new(0),
+        }
+    }
+
+    fn update(&self, event: &ProgressEvent) {
+        let mut chunks = self.chunks.lock().unwrap();
+        match *event {
+            ProgressEvent::Started {
+                chunks: no_jobs,
+                size,
+                ..
+            } => {
+                self.no_jobs.store(no_jobs, Ordering::SeqCst);
+                self.overall.set_length(size * 2);
+                self.overall
+                    .set_message(format!("Pack chunk 0/{}", no_jobs));
+                // the bars of a MultiProgress are only drawn while it is joined
+                let multi = self.multi.clone();
+                std::thread::spawn(move || multi.join_and_clear());
+            }
+            ProgressEvent::DownloadStarted { chunk } | ProgressEvent::UploadStarted { chunk } => {
+                self.overall.set_message(format!(
+                    "Pack chunk {}/{}",
+                    chunk,
+                    self.no_jobs.load(Ordering::SeqCst)
+                ));
+            }
+            ProgressEvent::DownloadProgress {
+                chunk,
+                downloaded,
+                total,
+            } => {
+                let entry = chunks.entry(chunk).or_default();
+                entry.0 = downloaded;
+                entry.2 = total;
+            }
+            ProgressEvent::DownloadFinished { chunk } => {
+                let entry = chunks.entry(chunk).or_default();
+                entry.0 = entry.2;
+            }
+            ProgressEvent::UploadProgress {
+                chunk,
+                uploaded,
+                total,
+            } => {
+                let entry = chunks.entry(chunk).or_default();
+                entry.1 = uploaded;
+                entry.2 = total;
+                entry.0 = total;
+            }
+            ProgressEvent::UploadFinished { chunk } => {
+                let entry = chunks.entry(chunk).or_default();
+                entry.1 = entry.2;
+            }
+            ProgressEvent::Finished { .. } | ProgressEvent::Failed { .. } => {
+                self.overall.finish_and_clear();
+                return;
+            }
+            _ => return,
+        }
+        self.overall.set_position(
+            chunks
+                .values()
+                .map(|(downloaded, uploaded, _)| downloaded + uploaded)
+                .sum(),
+        );
+    }
+}
+
+// Where ProgressEvents go while leeching. The default sink drops every event.
+#[derive(Clone, Default)]
+pub struct Events {
+    sender: Option<UnboundedSender<ProgressEvent>>,
+    progress: Option<Arc<PackProgress>>,
+}
+
+impl Events {
+    pub fn new(sender: UnboundedSender<ProgressEvent>) -> Self {
+        Self {
+            sender: Some(sender),
+            progress: Some(Arc::new(PackProgress::new())),
+        }
+    }
+
+    pub fn send(&self, event: ProgressEvent) {
+        if let Some(progress) = &self.progress {
+            progress.update(&event);
+        }
+        if let Some(sender) = &self.sender {
+            // the receiver may have been dropped, which is fine
+            let _ = sender.send(event);
+        }
+    }
+
+    // a bar for one chunk, drawn under the pack's bar when there is one
+    pub fn progress_bar(&self, len: u64) -> ProgressBar {
+        match &self.progress {
+            Some(progress) => progress.multi.add(ProgressBar::new(len)),
+            None => ProgressBar::new(len),
+        }
+    }
+}
+
+pub type LeechFuture<'a> = Pin<Box<dyn Future<Output = Result<(), error::Error>> + Send + 'a>>;
+
 #[async_trait]
 pub trait RplLeech<'a, T, P, C>
 where
@@ -79,26 +439,183 @@ where
     P: RplPackConfig,
     C: RplClient,
 {
+    #[allow(dead_code)]
     async fn leech_torrent(
         &'a mut self,
         data: Torrent,
         config: P,
         torrent_client: C,
-        upload_client: RcloneClient,
+        upload_client: Box<dyn RplUploadClient>,
         seed: SeedSettings,
-        skip: u32,
+        settings: LeechSettings,
     ) -> Result<(), error::Error>;
+
+    // Same as leech_torrent, but also returns a stream of ProgressEvents that the returned future
+    // drives. The stream ends once the future completes.
+    #[allow(clippy::too_many_arguments)]
+    fn leech_torrent_with_events(
+        &'a mut self,
+        data: Torrent,
+        config: P,
+        torrent_client: C,
+        upload_client: Box<dyn RplUploadClient>,
+        seed: SeedSettings,
+        settings: LeechSettings,
+    ) -> (UnboundedReceiverStream<ProgressEvent>, LeechFuture<'a>);
 }
 
+// Every chunk of a pack in upload order, see build_queue
 pub struct Queue {
     no_all_files: i32,
     job: Vec<Job>,
 }
 
 impl Queue {
-    fn new(no_all_files: i32, job: Vec<Job>) -> Self {
+    pub fn new(no_all_files: i32, job: Vec<Job>) -> Self {
         Self { no_all_files, job }
     }
+
+    pub fn jobs(&self) -> &[Job] {
+        &self.job
+    }
+
+    // number of files in the torrent, chunked or not
+    pub fn no_all_files(&self) -> i32 {
+        self.no_all_files
+    }
+
+    pub fn no_jobs(&self) -> usize {
+        self.job.len()
+    }
+
+    // size of everything that will be downloaded and uploaded, skipped files excluded
+    pub fn total_size(&self) -> i64 {
+        self.job.iter().map(|job| job.total_size).sum()
+    }
+
+    // the chunks after the first skip ones, only those in only when given
+    fn leeched<'a>(&'a self, skip: u32, only: Option<&'a [i32]>) -> impl Iterator<Item = &'a Job> {
+        self.job
+            .iter()
+            .skip(skip as usize)
+            .filter(move |job| only.is_none_or(|only| only.contains(&job.chunk)))
+    }
+
+    pub fn leech_size(&self, skip: u32, only: Option<&[i32]>) -> u64 {
+        self.leeched(skip, only)
+            .map(|job| job.total_size as u64)
+            .sum()
+    }
+
+    // size of the biggest chunk left to leech, chunks 1..=skip and those left out by only
+    // excluded. Whatever else the run does, save_path has to hold this one chunk.
+    pub fn largest_chunk(&self, skip: u32, only: Option<&[i32]>) -> i64 {
+        self.leeched(skip, only)
+            .map(|job| job.total_size)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // The most disk space the run takes at once, chunks 1..=skip and those left out by only
+    // excluded. A chunk is deleted after upload so the largest chunk decides, or the largest two
+    // chunks in a row when the next chunk downloads during the upload, unless every chunk stays
+    // on the same disk.
+    pub fn peak_disk_usage(
+        &self,
+        skip: u32,
+        only: Option<&[i32]>,
+        keep_chunks: bool,
+        pipeline: bool,
+    ) -> i64 {
+        let sizes: Vec<i64> = self.leeched(skip, only).map(|job| job.total_size).collect();
+        match (keep_chunks, pipeline) {
+            (true, _) => sizes.iter().sum(),
+            (false, true) if sizes.len() > 1 => sizes
+                .windows(2)
+                .map(|pair| pair[0] + pair[1])
+                .max()
+                .unwrap(),
+            (false, _) => self.largest_chunk(skip, only),
+        }
+    }
+
+    // every chunk with its files and their sizes, then the number of files no chunk holds
+    pub fn describe(&self) -> String {
+        let human = |size: i64| {
+            size.file_size(file_size_opts::BINARY)
+                .expect("File size is a negative number?")
+        };
+        let mut plan = String::new();
+        for job in &self.job {
+            plan.push_str(&format!(
+                "Chunk {}/{}: {} files, {}\n",
+                job.chunk,
+                self.job.len(),
+                job.no_files,
+                human(job.total_size)
+            ));
+            for (file, length) in job.files.iter().zip(&job.lengths) {
+                plan.push_str(&format!("    {} ({})\n", file.display(), human(*length)));
+            }
+        }
+        let no_queued: usize = self.job.iter().map(|job| job.files.len()).sum();
+        let no_skipped = self.no_all_files as usize - no_queued;
+        if no_skipped > 0 {
+            plan.push_str(&format!(
+                "{} files are skipped (larger than max_size, already in the manifest or not selected)\n",
+                no_skipped
+            ));
+        }
+        plan
+    }
+
+    // compare every chunk against the files found on the remote (path -> size), a file only
+    // counts as present when its size matches
+    pub fn verify(&self, remote: &HashMap<PathBuf, i64>) -> Vec<ChunkReport> {
+        self.job
+            .iter()
+            .map(|job| ChunkReport {
+                chunk: job.chunk,
+                present: job
+                    .files
+                    .iter()
+                    .zip(&job.lengths)
+                    .filter(|(path, length)| remote.get(*path) == Some(length))
+                    .count(),
+                total: job.files.len(),
+            })
+            .collect()
+    }
+}
+
+pub struct ChunkReport {
+    pub chunk: i32,
+    pub present: usize,
+    pub total: usize,
+}
+
+impl ChunkReport {
+    pub fn is_complete(&self) -> bool {
+        self.present == self.total
+    }
+}
+
+// "chunk 2 partial 4/10"
+impl fmt::Display for ChunkReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.is_complete() {
+            "complete"
+        } else if self.present > 0 {
+            "partial"
+        } else {
+            "missing"
+        };
+        write!(
+            f,
+            "chunk {} {} {}/{}",
+            self.chunk, status, self.present, self.total
+        )
+    }
 }
 
 pub fn build_queue(
@@ -109,11 +626,12 @@ pub fn build_queue(
 
     match torrent.files {
         Some(vecs) => {
-            let mut current_chunk = 1;
-            let mut total_size: i64 = 0;
-            let mut files = 0;
+            // chunks are numbered in the order they are leeched, which is not the order of the
+            // files when the largest ones go first. Files keep the torrent's order in a chunk.
+            let mut chunks: BTreeMap<i32, Job> = BTreeMap::new();
             let mut no_all_files: i32 = 0;
             for f in vecs {
+                let index = no_all_files;
                 no_all_files += 1;
 
                 let file = datamap
@@ -123,18 +641,20 @@ pub fn build_queue(
 
                 if file.chunk < 0 {
                     continue;
-                } else if file.chunk != current_chunk {
-                    job.push(Job::new(current_chunk, total_size, files));
-                    files = 0;
-                    total_size = 0;
-                    current_chunk += 1;
                 }
-                files += 1;
-                total_size += file.length;
+                let job = chunks.entry(file.chunk).or_insert_with(|| {
+                    Job::new(file.chunk, 0, 0, Vec::new(), Vec::new(), Vec::new())
+                });
+                job.no_files += 1;
+                job.total_size += file.length;
+                job.files.push(PathBuf::from(&torrent.name).join(&f.path));
+                job.indices.push(index);
+                job.lengths.push(file.length);
             }
-            // finish off last chunk
-            job.push(Job::new(current_chunk, total_size, files));
-            Ok(Queue::new(no_all_files, job))
+            if chunks.is_empty() {
+                return Err(error::Error::NothingToLeech);
+            }
+            Ok(Queue::new(no_all_files, chunks.into_values().collect()))
         }
         None => {
             let file = datamap
@@ -144,14 +664,169 @@ pub fn build_queue(
             if file.chunk < 0 {
                 return Err(error::Error::NothingToLeech);
             }
-            job.push(Job::new(1, torrent.length, 1));
+            job.push(Job::new(
+                1,
+                torrent.length,
+                1,
+                vec![PathBuf::from(&torrent.name)],
+                vec![0],
+                vec![torrent.length],
+            ));
             Ok(Queue::new(1, job))
         }
     }
 }
 
 pub trait RplUpload {
-    fn upload(&self, client: &RcloneClient, no_jobs: usize) -> Result<(), error::Error>;
+    fn upload(
+        &self,
+        client: &RcloneClient,
+        no_jobs: usize,
+        events: &Events,
+    ) -> Result<(), error::Error>;
+}
+
+// The backend a chunk is uploaded with, from the files under source to destination
+#[async_trait]
+pub trait RplUploadClient: Send + Sync {
+    // how the backend is called in logs
+    fn name(&self) -> &str;
+    fn get_source(&self) -> &Path;
+    fn set_source(&mut self, source: PathBuf);
+    fn get_destination(&self) -> &str;
+    // where the chunk is uploaded to, when the backend spreads chunks over several remotes
+    fn chunk_destination(&self, _chunk: i32) -> &str {
+        self.get_destination()
+    }
+    fn get_on_existing_remote(&self) -> Option<OnExistingRemote>;
+    // true when an uploaded chunk is already gone from the source, the torrent client then only
+    // has to forget the torrent
+    fn moves_source(&self) -> bool {
+        false
+    }
+    // sizes of the files under path on the remote, keyed by their path relative to destination
+    async fn list_remote(
+        &self,
+        path: &str,
+        is_file: bool,
+    ) -> Result<HashMap<PathBuf, i64>, error::Error>;
+    async fn upload_chunk(
+        &self,
+        job: &Job,
+        no_jobs: usize,
+        events: &Events,
+    ) -> Result<(), error::Error>;
+    // stop the upload in flight, if the backend runs one outside of rpl
+    fn interrupt(&self);
+    fn boxed(&self) -> Box<dyn RplUploadClient>;
+}
+
+impl Clone for Box<dyn RplUploadClient> {
+    fn clone(&self) -> Self {
+        self.boxed()
+    }
+}
+
+// Options for the leech loop itself, independent of the torrent and upload clients
+#[derive(Default)]
+pub struct LeechSettings {
+    skip: u32,
+    only: Option<Vec<i32>>,
+    cross_seed: Option<CrossSeed>,
+    output_dir: Option<PathBuf>,
+    upload_budget: Option<u64>,
+    verify_pieces: bool,
+    final_verify_count: bool,
+    stop: Option<Arc<AtomicBool>>,
+    plan: Option<(Plan, PathBuf)>,
+    pipeline: bool,
+    keep_files: bool,
+    // files of the chunks uploaded by this run, checked by final_verify_count
+    uploaded_files: Vec<PathBuf>,
+}
+
+impl LeechSettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn skip(mut self, skip: u32) -> Self {
+        self.skip = skip;
+        self
+    }
+
+    // leech these chunks only, every other one is skipped
+    pub fn only(mut self, chunks: Vec<i32>) -> Self {
+        self.only = Some(chunks);
+        self
+    }
+
+    // reuse matching files from another torrent's data instead of downloading them
+    pub fn cross_seed(mut self, cross_seed: CrossSeed) -> Self {
+        self.cross_seed = Some(cross_seed);
+        self
+    }
+
+    // stop before the chunk that would take the uploaded bytes of this run over budget
+    pub fn upload_budget(mut self, budget: u64) -> Self {
+        self.upload_budget = Some(budget);
+        self
+    }
+
+    // check the piece hashes of every downloaded chunk before uploading it
+    pub fn verify_pieces(mut self, verify_pieces: bool) -> Self {
+        self.verify_pieces = verify_pieces;
+        self
+    }
+
+    // count the files on the remote once every chunk is uploaded and fail when some are missing
+    pub fn final_verify_count(mut self, final_verify_count: bool) -> Self {
+        self.final_verify_count = final_verify_count;
+        self
+    }
+
+    // stop cleanly before the next chunk once the flag is set, e.g. on a first Ctrl-C
+    pub fn stop_flag(mut self, stop: Arc<AtomicBool>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    // record the progress of the run in the plan stored at path after every uploaded chunk, for
+    // `rpl resume`
+    pub fn plan(mut self, plan: Plan, path: PathBuf) -> Self {
+        self.plan = Some((plan, path));
+        self
+    }
+
+    // download the next chunk while the previous one is uploaded (qBittorrent only)
+    pub fn pipeline(mut self, pipeline: bool) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    // keep a local copy of every chunk in this directory instead of deleting it after upload
+    pub fn output_dir(mut self, dir: PathBuf) -> Self {
+        self.output_dir = Some(dir);
+        self
+    }
+
+    // leave the files of every chunk in save_path after its upload, only the torrent is removed
+    // (qBittorrent only)
+    pub fn keep_files(mut self, keep_files: bool) -> Self {
+        self.keep_files = keep_files;
+        self
+    }
+}
+
+// -1 lets the torrent seed forever
+pub const UNLIMITED_SHARE_LIMIT: i64 = -1;
+
+fn unlimited_ratio() -> f64 {
+    UNLIMITED_SHARE_LIMIT as f64
+}
+
+fn unlimited_time() -> i64 {
+    UNLIMITED_SHARE_LIMIT
 }
 
 #[derive(Serialize, Deserialize, Getters)]
@@ -159,24 +834,173 @@ pub struct SeedSettings {
     seed_enable: bool,
     seed_path: String,
     seed_wait: u32,
+    #[serde(default = "unlimited_ratio")]
+    seed_ratio_limit: f64,
+    // in minutes
+    #[serde(default = "unlimited_time")]
+    seed_time_limit: i64,
 }
 
 impl Default for SeedSettings {
     fn default() -> Self {
-        Self {
-            seed_enable: false,
-            seed_path: String::from(""),
-            seed_wait: 0,
-        }
+        Self::new(false, String::new(), 0, unlimited_ratio(), unlimited_time())
     }
 }
 
 impl SeedSettings {
-    pub fn new(seed_enable: bool, seed_path: String, seed_wait: u32) -> Self {
+    pub fn new(
+        seed_enable: bool,
+        seed_path: String,
+        seed_wait: u32,
+        seed_ratio_limit: f64,
+        seed_time_limit: i64,
+    ) -> Self {
         Self {
             seed_enable,
             seed_path,
             seed_wait,
+            seed_ratio_limit,
+            seed_time_limit,
         }
     }
+
+    // the pack is in a folder of its own under the mount, see nest_save_path
+    pub fn nest(mut self, dir: &str) -> Self {
+        if !self.seed_path.is_empty() {
+            self.seed_path = format!("{}/{}", self.seed_path.trim_end_matches('/'), dir);
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_payload() {
+        let event = ProgressEvent::UploadFinished { chunk: 2 };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"upload_finished","chunk":2}"#
+        );
+        assert!(!event.is_progress());
+        assert!(ProgressEvent::DownloadProgress {
+            chunk: 1,
+            downloaded: 1,
+            total: 2
+        }
+        .is_progress());
+    }
+
+    #[test]
+    fn pack_progress() {
+        let progress = PackProgress::new();
+        progress
+            .multi
+            .set_draw_target(indicatif::ProgressDrawTarget::hidden());
+        progress.update(&ProgressEvent::Started {
+            name: String::from("Pack"),
+            chunks: 2,
+            size: 100,
+        });
+        progress.update(&ProgressEvent::DownloadProgress {
+            chunk: 1,
+            downloaded: 50,
+            total: 60,
+        });
+        assert_eq!(progress.overall.length(), 200);
+        assert_eq!(progress.overall.position(), 50);
+        progress.update(&ProgressEvent::UploadProgress {
+            chunk: 1,
+            uploaded: 30,
+            total: 60,
+        });
+        assert_eq!(progress.overall.position(), 90);
+        progress.update(&ProgressEvent::UploadFinished { chunk: 1 });
+        progress.update(&ProgressEvent::DownloadFinished { chunk: 2 });
+        assert_eq!(progress.overall.position(), 120);
+    }
+
+    #[test]
+    fn verify_chunks() {
+        let job = |chunk: i32, names: &[&str]| {
+            Job::new(
+                chunk,
+                names.len() as i64 * 100,
+                names.len() as i32,
+                names
+                    .iter()
+                    .map(|name| PathBuf::from("Pack").join(name))
+                    .collect(),
+                (0..names.len() as i32).collect(),
+                vec![100; names.len()],
+            )
+        };
+        let queue = Queue::new(
+            6,
+            vec![
+                job(1, &["a.mkv", "b.mkv"]),
+                job(2, &["c.mkv", "d.mkv"]),
+                job(3, &["e.mkv", "f.mkv"]),
+            ],
+        );
+        let remote: HashMap<PathBuf, i64> = vec![
+            (PathBuf::from("Pack/a.mkv"), 100),
+            (PathBuf::from("Pack/b.mkv"), 100),
+            (PathBuf::from("Pack/c.mkv"), 100),
+            // interrupted upload, wrong size
+            (PathBuf::from("Pack/d.mkv"), 42),
+        ]
+        .into_iter()
+        .collect();
+
+        let report: Vec<String> = queue
+            .verify(&remote)
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        assert_eq!(
+            report,
+            vec![
+                "chunk 1 complete 2/2",
+                "chunk 2 partial 1/2",
+                "chunk 3 missing 0/2"
+            ]
+        );
+
+        assert_eq!(queue.peak_disk_usage(0, None, false, false), 200);
+        assert_eq!(queue.peak_disk_usage(0, None, true, false), 600);
+        assert_eq!(queue.peak_disk_usage(1, None, true, false), 400);
+        assert_eq!(queue.peak_disk_usage(3, None, false, false), 0);
+        assert_eq!(queue.peak_disk_usage(0, None, false, true), 400);
+        assert_eq!(queue.peak_disk_usage(2, None, false, true), 200);
+        assert_eq!(queue.peak_disk_usage(1, None, true, true), 400);
+        assert_eq!(queue.largest_chunk(0, None), 200);
+        assert_eq!(queue.largest_chunk(3, None), 0);
+        assert_eq!(queue.leech_size(1, None), 400);
+        assert_eq!(queue.leech_size(1, Some(&[1, 3])), 200);
+        assert_eq!(queue.largest_chunk(0, Some(&[2])), 200);
+        assert_eq!(queue.peak_disk_usage(0, Some(&[1, 3]), true, false), 400);
+
+        let plan = queue.describe();
+        assert!(plan.starts_with("Chunk 1/3: 2 files, 200 B\n    Pack/a.mkv (100 B)\n"));
+        assert!(!plan.contains("skipped"));
+        let queue = Queue::new(4, vec![job(1, &["a.mkv", "b.mkv"])]);
+        assert!(queue.describe().ends_with("\n2 files are skipped (larger than max_size, already in the manifest or not selected)\n"));
+
+        let listed = serde_json::to_value(queue.jobs()).unwrap();
+        assert_eq!(
+            listed,
+            serde_json::json!([{
+                "chunk": 1,
+                "no_files": 2,
+                "total_size": 200,
+                "files": [
+                    {"path": "Pack/a.mkv", "length": 100},
+                    {"path": "Pack/b.mkv", "length": 100}
+                ]
+            }])
+        );
+    }
 }
@@ -0,0 +1,196 @@
+use async_trait::async_trait;
+use log::info;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::librpl::error;
+use crate::librpl::rclone::OnExistingRemote;
+use crate::librpl::{Events, Job, RplUploadClient};
+
+// How the chunks are spread over the remotes of a MultiRemoteClient
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadMode {
+    // chunk n goes to remote (n - 1) % remotes, so a resumed run picks the same remotes
+    RoundRobin,
+    // every chunk goes to every remote, one after another
+    Mirror,
+}
+
+// Uploads through one client per remote, with the same source for all of them
+pub struct MultiRemoteClient {
+    remotes: Vec<Box<dyn RplUploadClient>>,
+    mode: UploadMode,
+}
+
+impl MultiRemoteClient {
+    pub fn new(remotes: Vec<Box<dyn RplUploadClient>>, mode: UploadMode) -> Self {
+        assert!(!remotes.is_empty(), "MultiRemoteClient needs a remote");
+        Self { remotes, mode }
+    }
+
+    fn remote_of(&self, chunk: i32) -> &dyn RplUploadClient {
+        let index = (chunk - 1).max(0) as usize % self.remotes.len();
+        self.remotes[index].as_ref()
+    }
+}
+
+// round-robin: a file is on the remote if any remote holds it
+// mirror: only the files every remote holds with the same size count
+fn merge_listings(listings: Vec<HashMap<PathBuf, i64>>, mode: UploadMode) -> HashMap<PathBuf, i64> {
+    let mut listings = listings.into_iter();
+    let mut merged = listings.next().unwrap_or_default();
+    for listing in listings {
+        match mode {
+            UploadMode::RoundRobin => merged.extend(listing),
+            UploadMode::Mirror => {
+                merged.retain(|path, size| listing.get(path) == Some(size));
+            }
+        }
+    }
+    merged
+}
+
+#[async_trait]
+impl RplUploadClient for MultiRemoteClient {
+    fn name(&self) -> &str {
+        self.remotes[0].name()
+    }
+
+    fn get_source(&self) -> &Path {
+        self.remotes[0].get_source()
+    }
+
+    fn set_source(&mut self, source: PathBuf) {
+        for remote in &mut self.remotes {
+            remote.set_source(source.clone());
+        }
+    }
+
+    fn get_destination(&self) -> &str {
+        self.remotes[0].get_destination()
+    }
+
+    fn chunk_destination(&self, chunk: i32) -> &str {
+        match self.mode {
+            UploadMode::RoundRobin => self.remote_of(chunk).get_destination(),
+            UploadMode::Mirror => self.get_destination(),
+        }
+    }
+
+    fn get_on_existing_remote(&self) -> Option<OnExistingRemote> {
+        self.remotes[0].get_on_existing_remote()
+    }
+
+    fn moves_source(&self) -> bool {
+        self.remotes[0].moves_source()
+    }
+
+    async fn list_remote(
+        &self,
+        path: &str,
+        is_file: bool,
+    ) -> Result<HashMap<PathBuf, i64>, error::Error> {
+        let mut listings = Vec::with_capacity(self.remotes.len());
+        for remote in &self.remotes {
+            listings.push(remote.list_remote(path, is_file).await?);
+        }
+        Ok(merge_listings(listings, self.mode))
+    }
+
+    async fn upload_chunk(
+        &self,
+        job: &Job,
+        no_jobs: usize,
+        events: &Events,
+    ) -> Result<(), error::Error> {
+        match self.mode {
+            UploadMode::RoundRobin => {
+                let remote = self.remote_of(job.chunk);
+                info!(
+                    "Chunk {}/{} goes to {}",
+                    job.chunk,
+                    no_jobs,
+                    remote.get_destination()
+                );
+                remote.upload_chunk(job, no_jobs, events).await
+            }
+            // the chunk is only done once every remote has it, the first failure keeps its data
+            UploadMode::Mirror => {
+                for remote in &self.remotes {
+                    info!(
+                        "Mirroring chunk {}/{} to {}",
+                        job.chunk,
+                        no_jobs,
+                        remote.get_destination()
+                    );
+                    remote.upload_chunk(job, no_jobs, events).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn interrupt(&self) {
+        for remote in &self.remotes {
+            remote.interrupt();
+        }
+    }
+
+    fn boxed(&self) -> Box<dyn RplUploadClient> {
+        Box::new(Self {
+            remotes: self.remotes.clone(),
+            mode: self.mode,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::librpl::RcloneClient;
+
+    fn remote(destination: &str) -> Box<dyn RplUploadClient> {
+        Box::new(RcloneClient::new(
+            String::from("rclone"),
+            PathBuf::from("/tmp"),
+            String::from(destination),
+            0,
+            0,
+            Vec::new(),
+        ))
+    }
+
+    #[test]
+    fn round_robin_destinations() {
+        let client = MultiRemoteClient::new(
+            vec![remote("gd1:/rpl"), remote("gd2:/rpl")],
+            UploadMode::RoundRobin,
+        );
+        assert_eq!(client.chunk_destination(1), "gd1:/rpl");
+        assert_eq!(client.chunk_destination(2), "gd2:/rpl");
+        assert_eq!(client.chunk_destination(3), "gd1:/rpl");
+
+        let client = MultiRemoteClient::new(
+            vec![remote("gd1:/rpl"), remote("gd2:/rpl")],
+            UploadMode::Mirror,
+        );
+        assert_eq!(client.chunk_destination(2), "gd1:/rpl");
+    }
+
+    #[test]
+    fn merged_listings() {
+        let first: HashMap<PathBuf, i64> = vec![(PathBuf::from("a"), 1), (PathBuf::from("b"), 2)]
+            .into_iter()
+            .collect();
+        let second: HashMap<PathBuf, i64> = vec![(PathBuf::from("b"), 2), (PathBuf::from("c"), 3)]
+            .into_iter()
+            .collect();
+
+        let merged = merge_listings(vec![first.clone(), second.clone()], UploadMode::RoundRobin);
+        assert_eq!(merged.len(), 3);
+
+        let merged = merge_listings(vec![first, second], UploadMode::Mirror);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged.get(Path::new("b")), Some(&2));
+    }
+}
@@ -0,0 +1,154 @@
+use lava_torrent::torrent::v1::Torrent;
+use log::{debug, error, info};
+use sha1::{Digest, Sha1};
+use std::cmp::{max, min};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::librpl::error;
+use crate::librpl::Job;
+
+// a file of the torrent laid out in the piece space, path is only set for files of the chunk
+struct Span<'a> {
+    offset: i64,
+    length: i64,
+    path: Option<&'a PathBuf>,
+}
+
+fn layout<'a>(torrent: &Torrent, job: &'a Job) -> Vec<Span<'a>> {
+    let lengths: Vec<i64> = match &torrent.files {
+        Some(files) => files.iter().map(|f| f.length).collect(),
+        None => vec![torrent.length],
+    };
+    let mut offset = 0;
+    lengths
+        .into_iter()
+        .enumerate()
+        .map(|(index, length)| {
+            let path = job
+                .indices
+                .iter()
+                .position(|i| *i as usize == index)
+                .map(|pos| &job.files[pos]);
+            let span = Span {
+                offset,
+                length,
+                path,
+            };
+            offset += length;
+            span
+        })
+        .collect()
+}
+
+// recompute the piece hashes of a downloaded chunk from the files in source. Pieces that cross
+// into files of other chunks were never fully downloaded and are left out. Returns the number of
+// pieces checked.
+pub fn verify_chunk(torrent: &Torrent, job: &Job, source: &Path) -> Result<usize, error::Error> {
+    let (checked, partial) = check_pieces(torrent, job, source)?;
+    if partial > 0 {
+        debug!(
+            "Chunk {} has {} pieces shared with other chunks, they were not checked",
+            job.chunk, partial
+        );
+    }
+    info!("Verified {} pieces of chunk {}", checked, job.chunk);
+    Ok(checked)
+}
+
+// true when every piece of the job lies within its own files and matches the torrent, a piece
+// shared with another file cannot be checked
+pub fn fully_verified(torrent: &Torrent, job: &Job, source: &Path) -> Result<bool, error::Error> {
+    let (_, partial) = check_pieces(torrent, job, source)?;
+    Ok(partial == 0)
+}
+
+// the number of pieces checked and of pieces left out because they cross into other files
+fn check_pieces(
+    torrent: &Torrent,
+    job: &Job,
+    source: &Path,
+) -> Result<(usize, usize), error::Error> {
+    let spans = layout(torrent, job);
+    let mut checked = 0;
+    let mut partial = 0;
+    let mut first = 0;
+    for (piece, expected) in torrent.pieces.iter().enumerate() {
+        let start = piece as i64 * torrent.piece_length;
+        let end = min(start + torrent.piece_length, torrent.length);
+        while first < spans.len() && spans[first].offset + spans[first].length <= start {
+            first += 1;
+        }
+        let overlapping: Vec<&Span> = spans[first..]
+            .iter()
+            .take_while(|span| span.offset < end)
+            .filter(|span| span.length > 0)
+            .collect();
+        if overlapping.iter().all(|span| span.path.is_none()) {
+            continue;
+        }
+        if overlapping.iter().any(|span| span.path.is_none()) {
+            partial += 1;
+            continue;
+        }
+
+        let mut hasher = Sha1::new();
+        for span in overlapping {
+            let from = max(start, span.offset) - span.offset;
+            let to = min(end, span.offset + span.length) - span.offset;
+            let mut file = File::open(source.join(span.path.unwrap()))?;
+            file.seek(SeekFrom::Start(from as u64))?;
+            let copied = io::copy(&mut file.take((to - from) as u64), &mut hasher)?;
+            if copied != (to - from) as u64 {
+                return Err(error::Error::PieceHashMismatch(job.chunk, piece));
+            }
+        }
+        if hasher.result().as_slice() != expected.as_slice() {
+            error!(
+                "Piece {} of chunk {} does not match the torrent, the local data is corrupt",
+                piece, job.chunk
+            );
+            return Err(error::Error::PieceHashMismatch(job.chunk, piece));
+        }
+        checked += 1;
+    }
+    Ok((checked, partial))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::librpl::fixtures::pack;
+    use std::fs;
+
+    #[test]
+    fn verify() {
+        let data: Vec<u8> = (0u8..16).collect();
+        let dir = std::env::temp_dir().join(format!("rpl-pieces-{}", std::process::id()));
+        fs::create_dir_all(dir.join("Pack")).unwrap();
+        fs::write(dir.join("Pack/a"), &data[0..6]).unwrap();
+        fs::write(dir.join("Pack/b"), &data[6..12]).unwrap();
+
+        let mut torrent = pack(&[("a", 6), ("b", 6), ("c", 4)]);
+        torrent.piece_length = 4;
+        torrent.pieces = data.chunks(4).map(|p| Sha1::digest(p).to_vec()).collect();
+        let job = Job::new(
+            1,
+            12,
+            2,
+            vec![PathBuf::from("Pack/a"), PathBuf::from("Pack/b")],
+            vec![0, 1],
+            vec![6, 6],
+        );
+        // piece 3 only covers c, which belongs to another chunk
+        assert_eq!(verify_chunk(&torrent, &job, &dir).unwrap(), 3);
+
+        fs::write(dir.join("Pack/b"), [0u8; 6]).unwrap();
+        assert!(matches!(
+            verify_chunk(&torrent, &job, &dir),
+            Err(error::Error::PieceHashMismatch(1, 1))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
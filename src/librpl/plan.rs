@@ -0,0 +1,265 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::librpl::error;
+use crate::librpl::Queue;
+
+// Chunk layout and progress of a pack as it was leeched, stored per info hash. `--skip N` only
+// means something if chunks 1..N hold the same files as in the run that uploaded them, which is
+// not the case anymore when max_size, the free space (max_size_percentage) or the manifest changed
+// in between.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Plan {
+    // torrent file indices of every chunk, in chunk order
+    chunks: Vec<Vec<i32>>,
+    #[serde(default)]
+    name: String,
+    // what rpl was started with: a torrent file path, url or magnet link
+    #[serde(default)]
+    input: String,
+    // chunks 1..=uploaded are on the remote, the run continues with `--skip <uploaded>`
+    #[serde(default)]
+    uploaded: u32,
+    // chunks among 1..=uploaded that failed and are not on the remote, retried with `--only`
+    #[serde(default)]
+    failed: Vec<i32>,
+    // the other flags of the command line, replayed by `rpl resume` from the directory it ran in
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    dir: PathBuf,
+}
+
+// flags that pick the chunks to leech, `rpl resume` sets them from the progress instead
+const CHUNK_FLAGS: [&str; 2] = ["--skip", "--only"];
+const CHUNK_SWITCHES: [&str; 2] = ["--no-resume", "--replan"];
+
+impl Plan {
+    pub fn from_queue(queue: &Queue, name: &str, input: &str, skip: u32) -> Self {
+        Self {
+            chunks: queue.job.iter().map(|job| job.indices.clone()).collect(),
+            name: name.to_owned(),
+            input: input.to_owned(),
+            uploaded: skip,
+            failed: Vec::new(),
+            args: Vec::new(),
+            dir: PathBuf::new(),
+        }
+    }
+
+    // keep the command line (program name included) without the input as it was typed and the
+    // chunk selection
+    pub fn replay(mut self, args: &[String], input: &str, dir: &Path) -> Self {
+        let mut input = Some(input);
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            let is_chunk_flag = |flag: &&str| arg.starts_with(&format!("{}=", flag));
+            if CHUNK_FLAGS.contains(&arg.as_str()) {
+                args.next();
+            } else if input == Some(arg.as_str()) {
+                input = None;
+            } else if !CHUNK_SWITCHES.contains(&arg.as_str())
+                && !CHUNK_FLAGS.iter().any(is_chunk_flag)
+            {
+                self.args.push(arg.clone());
+            }
+        }
+        self.dir = dir.to_owned();
+        self
+    }
+
+    pub fn keep_progress(mut self, stored: &Plan) -> Self {
+        self.uploaded = stored.uploaded;
+        self.failed = stored.failed.clone();
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn uploaded(&self) -> u32 {
+        self.uploaded
+    }
+
+    pub fn failed(&self) -> &[i32] {
+        &self.failed
+    }
+
+    // the failed chunks, then every chunk after the last uploaded one
+    pub fn remaining(&self) -> Vec<i32> {
+        let mut chunks = self.failed.clone();
+        chunks.extend(self.uploaded as i32 + 1..=self.no_chunks() as i32);
+        chunks
+    }
+
+    pub fn no_chunks(&self) -> u32 {
+        self.chunks.len() as u32
+    }
+
+    // false for an interrupted run
+    pub fn is_complete(&self) -> bool {
+        self.uploaded >= self.no_chunks() && self.failed.is_empty()
+    }
+
+    pub fn record_uploaded(&mut self, chunk: i32) {
+        self.failed.retain(|failed| *failed != chunk);
+        self.uploaded = chunk as u32;
+    }
+
+    // a run of `--only` some chunks only moves uploaded forward when there is no gap
+    pub fn record_retried(&mut self, chunk: i32) {
+        self.failed.retain(|failed| *failed != chunk);
+        if chunk as u32 == self.uploaded + 1 {
+            self.uploaded = chunk as u32;
+        }
+    }
+
+    // the run goes on with the next chunk, a later `--only` run retries this one
+    pub fn record_failed(&mut self, chunk: i32) {
+        if !self.failed.contains(&chunk) {
+            self.failed.push(chunk);
+            self.failed.sort_unstable();
+        }
+        self.uploaded = chunk as u32;
+    }
+
+    // every stored plan in dir with the info hash it is stored under, sorted by hash
+    pub fn load_all(dir: &Path) -> Result<Vec<(String, Self)>, error::Error> {
+        let mut plans = Vec::new();
+        if !dir.exists() {
+            return Ok(plans);
+        }
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let hash = path.file_stem().unwrap().to_string_lossy().into_owned();
+                if let Some(plan) = Self::load(&path)? {
+                    plans.push((hash, plan));
+                }
+            }
+        }
+        plans.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(plans)
+    }
+
+    // <dir>/<info hash>.json
+    pub fn path(dir: &Path, hash: &str) -> PathBuf {
+        dir.join(hash).with_extension("json")
+    }
+
+    // None when the pack has not been leeched before
+    pub fn load(path: &Path) -> Result<Option<Self>, error::Error> {
+        if !path.exists() {
+            debug!("{} does not exist, there is no stored plan", path.display());
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), error::Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    // the first of the chunks 1..=upto that holds different files in the two plans
+    pub fn diverges(&self, other: &Plan, upto: u32) -> Option<u32> {
+        (0..upto as usize)
+            .find(|i| self.chunks.get(*i) != other.chunks.get(*i))
+            .map(|i| i as u32 + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn divergence() {
+        let stored = Plan {
+            chunks: vec![vec![0, 1], vec![2], vec![3, 4]],
+            ..Plan::default()
+        };
+        let smaller = Plan {
+            chunks: vec![vec![0, 1], vec![2], vec![3], vec![4]],
+            ..Plan::default()
+        };
+        assert_eq!(stored.diverges(&smaller, 2), None);
+        assert_eq!(stored.diverges(&smaller, 3), Some(3));
+        assert_eq!(stored.diverges(&smaller, 5), Some(3));
+        assert_eq!(stored.diverges(&stored, 3), None);
+    }
+
+    #[test]
+    fn failed_chunks() {
+        let mut plan = Plan {
+            chunks: vec![vec![0], vec![1], vec![2], vec![3]],
+            ..Plan::default()
+        };
+        plan.record_uploaded(1);
+        plan.record_failed(2);
+        plan.record_uploaded(3);
+        assert_eq!(plan.uploaded(), 3);
+        assert_eq!(plan.remaining(), vec![2, 4]);
+
+        let mut retry = Plan {
+            chunks: plan.chunks.clone(),
+            ..Plan::default()
+        }
+        .keep_progress(&plan);
+        retry.record_retried(2);
+        assert!(retry.failed().is_empty());
+        assert!(!retry.is_complete());
+        retry.record_retried(4);
+        assert!(retry.is_complete());
+
+        // chunks past a gap do not count as uploaded yet
+        let mut gap = Plan {
+            chunks: plan.chunks.clone(),
+            ..Plan::default()
+        };
+        gap.record_retried(3);
+        assert_eq!(gap.uploaded(), 0);
+    }
+
+    #[test]
+    fn replay_args() {
+        let args: Vec<String> = [
+            "rpl",
+            "--max-size",
+            "50GiB",
+            "pack.torrent",
+            "--skip",
+            "3",
+            "--only=4,5",
+            "--no-resume",
+            "--config",
+            "rpl.toml",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        let plan = Plan::default().replay(&args, "pack.torrent", Path::new("/home/user"));
+        assert_eq!(plan.args(), ["--max-size", "50GiB", "--config", "rpl.toml"]);
+        assert_eq!(plan.dir(), Path::new("/home/user"));
+    }
+}
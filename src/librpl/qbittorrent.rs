@@ -2,22 +2,33 @@ use async_trait::async_trait;
 use backoff::future::retry;
 use backoff::ExponentialBackoff;
 use derive_builder::Builder;
+use humansize::{file_size_opts, FileSize};
 use indicatif::{ProgressBar, ProgressStyle};
-use lava_torrent::torrent::v1::Torrent;
+use lava_torrent::bencode::BencodeElem;
+use lava_torrent::torrent::v1::{File, Torrent};
 use log::{debug, error, info, warn};
 use reqwest::multipart::{Form, Part};
 use serde::{Deserialize, Serialize};
-use std::cmp::min;
-use std::path::PathBuf;
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tokio::sync::{mpsc, Semaphore, SemaphorePermit};
 use tokio::time::{sleep, Duration};
+use tokio_stream::wrappers::UnboundedReceiverStream;
 
-use crate::librpl::rclone::RcloneClient;
-use crate::librpl::torrent_parser::TorrentPack;
+use crate::librpl::leech_steps;
+use crate::librpl::rclone::{self, RcloneClient};
+use crate::librpl::torrent_parser::{self, TorrentPack};
 use crate::librpl::util;
+use crate::librpl::SeedSettings;
+use crate::librpl::UNLIMITED_SHARE_LIMIT;
 use crate::librpl::{build_queue, error, Job, RplChunk, RplClient, RplLeech, RplPackConfig};
-use crate::librpl::{RplUpload, SeedSettings};
+use crate::librpl::{Events, LeechFuture, LeechSettings, ProgressEvent, RplUploadClient};
 
 #[derive(Deserialize, Serialize)]
+#[allow(dead_code)]
 enum TorrentFilter {
     #[serde(rename = "all")]
     All,
@@ -39,7 +50,7 @@ pub enum State {
     MissingFiles,
     #[serde(rename = "uploading")]
     Uploading,
-    #[serde(rename = "pausedUP")]
+    #[serde(rename = "pausedUP", alias = "stoppedUP")]
     PausedUP,
     #[serde(rename = "queuedUP")]
     QueuedUP,
@@ -55,7 +66,7 @@ pub enum State {
     Downloading,
     #[serde(rename = "metaDL")]
     MetaDL,
-    #[serde(rename = "pausedDL")]
+    #[serde(rename = "pausedDL", alias = "stoppedDL")]
     PausedDL,
     #[serde(rename = "queuedDL")]
     QueuedDL,
@@ -105,6 +116,9 @@ pub struct QbitTorrentInfo {
     ratio: f64,
     ratio_limit: f64,
     save_path: String,
+    // only sent by qBittorrent 4.4 and later, empty when not used
+    #[serde(default)]
+    download_path: String,
     seeding_time_limit: i64,
     seen_complete: i64,
     seq_dl: bool,
@@ -121,6 +135,62 @@ pub struct QbitTorrentInfo {
     upspeed: i64,
 }
 
+impl QbitTorrentInfo {
+    // qBittorrent sends tags as a single comma separated string
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.split(',').any(|t| t.trim() == tag)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct QbitLogEntry {
+    id: i64,
+    message: String,
+    // milliseconds since epoch
+    timestamp: i64,
+    #[serde(rename = "type")]
+    kind: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct QbitCategory {
+    name: String,
+    #[serde(rename = "savePath")]
+    save_path: String,
+}
+
+// sync/maindata, only the free space of the server is used
+#[derive(Debug, Deserialize)]
+struct QbitMainData {
+    server_state: QbitServerState,
+}
+
+#[derive(Debug, Deserialize)]
+struct QbitServerState {
+    free_space_on_disk: u64,
+}
+
+// torrents/files, in the order of the files of the torrent
+#[derive(Debug, Deserialize)]
+struct QbitTorrentFile {
+    name: String,
+    size: i64,
+}
+
+// torrents/properties, only the piece size is used
+#[derive(Debug, Deserialize)]
+struct QbitTorrentProperties {
+    piece_size: i64,
+}
+
+// app/buildInfo, only the libtorrent version is used
+#[derive(Debug, Deserialize)]
+struct QbitBuildInfo {
+    libtorrent: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, Builder, Default)]
 #[builder(setter(into, strip_option))]
 pub struct QbitTorrent {
@@ -131,8 +201,18 @@ pub struct QbitTorrent {
     #[builder(default)]
     savepath: Option<String>,
     #[builder(default)]
+    #[serde(rename = "downloadPath")]
+    download_path: Option<String>,
+    #[builder(default)]
+    #[serde(rename = "useDownloadPath")]
+    use_download_path: Option<String>,
+    #[builder(default)]
     cookie: Option<String>,
     #[builder(default)]
+    category: Option<String>,
+    #[builder(default)]
+    tags: Option<String>,
+    #[builder(default)]
     skip_checking: Option<String>,
     #[builder(default)]
     paused: Option<String>,
@@ -146,69 +226,262 @@ pub struct QbitTorrent {
     #[builder(default)]
     #[serde(rename = "dlLimit")]
     download_limit: Option<i64>,
+    #[builder(default)]
+    #[serde(rename = "stopCondition")]
+    stop_condition: Option<String>,
 }
 
 pub struct QbitConfig {
     cookie: String,
     address: String,
     client: reqwest::Client,
+    // qBittorrent 5.x renamed pause/resume to stop/start
+    api_v5: bool,
+    // the version qBittorrent reports, e.g. "v4.3.5"
+    version: String,
+    // built with libtorrent 2.x, which addresses hybrid torrents by their v2 info hash
+    libtorrent_v2: bool,
+    min_seeders: u32,
+    min_seeders_timeout: u32,
+    skip_unseeded: bool,
+    checking_resume_data_timeout: u32,
+    // log the download state every heartbeat_secs seconds (0 to disable)
+    heartbeat_secs: u32,
+    // poll a stalled or queued chunk less and less often, up to every stalled_poll_max_secs
+    // seconds (0 to always poll every second)
+    stalled_poll_max_secs: u32,
+    // wait this long for free space when the disk fills mid-download (0 to fail right away)
+    disk_full_wait_secs: u32,
+    // limits the number of API requests in flight at once
+    requests: Semaphore,
+    // run the whole flow without actually downloading or uploading anything
+    no_op: bool,
 }
 
+// Reusing idle connections saves a TCP (and TLS) handshake per request, but some setups hit
+// https://github.com/hyperium/hyper/issues/2136 (see https://github.com/wyyerd/stripe-rs/issues/173)
+// where a reused connection has already been closed by the server. Connection reuse is therefore
+// disabled unless the user opts in.
+pub const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 0;
+
+// how often a downloading chunk is polled while it makes progress
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// default cap on API requests in flight at once, generous enough to never slow rpl down
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 16;
+
 impl RplClient for QbitConfig {}
 impl RplPackConfig for QbitTorrent {}
 
 impl QbitConfig {
-    pub async fn new(username: &str, password: &str, address: &str) -> Result<Self, error::Error> {
-        let mut headers = reqwest::header::HeaderMap::new();
+    // extra_headers are sent with every request, login included, e.g. the token of an auth proxy
+    // in front of the Web UI. pool_max_idle_per_host is the number of idle connections kept for
+    // reuse, see DEFAULT_POOL_MAX_IDLE_PER_HOST. accept_invalid_certs turns off TLS certificate
+    // verification, for an https Web UI with a self-signed certificate.
+    pub async fn new(
+        username: &str,
+        password: &str,
+        address: &str,
+        extra_headers: &HashMap<String, String>,
+        pool_max_idle_per_host: usize,
+        accept_invalid_certs: bool,
+    ) -> Result<Self, error::Error> {
+        let mut headers = parse_headers(extra_headers)?;
         headers.insert("Referer", address.parse()?);
+        // qBittorrent 5.x also checks Origin when CSRF protection is enabled
+        headers.insert("Origin", address.trim_end_matches('/').parse()?);
 
         let client = reqwest::Client::builder()
-            // Related to issue: https://github.com/hyperium/hyper/issues/2136
-            // https://github.com/wyyerd/stripe-rs/issues/173
-            .pool_max_idle_per_host(0)
+            .pool_max_idle_per_host(pool_max_idle_per_host)
             .default_headers(headers)
+            .danger_accept_invalid_certs(accept_invalid_certs)
             .build()?;
 
         let response = retry(ExponentialBackoff::default(), || async {
+            // credentials go in the body, a query string ends up in access logs
             let res = client
-                .get(&format!(
-                    "{}/api/v2/auth/login?username={}&password={}",
-                    address, username, password
-                ))
+                .post(format!("{}/api/v2/auth/login", address))
+                .form(&[("username", username), ("password", password)])
                 .send()
                 .await?;
             Ok(res)
         })
         .await?;
 
-        let headers = match response.headers().get("set-cookie") {
-            Some(header) => header,
-            None => return Err(error::Error::MissingHeaders),
-        };
+        if response.headers().get("set-cookie").is_none() {
+            // qBittorrent answers 200 "Fails." on wrong credentials
+            return match response.error_for_status()?.text().await?.trim() {
+                "Fails." => Err(error::Error::QbitLoginFailed),
+                _ => Err(error::Error::MissingHeaders),
+            };
+        }
 
-        let cookie_str = headers.to_str()?;
-        let cookie_header = match cookie_str.find(';') {
-            Some(index) => index,
-            None => return Err(error::Error::MissingCookie),
-        };
+        // 4.x names the cookie SID, 5.x may use QBT_SID_<port> and send other cookies alongside
+        let mut cookie_str = None;
+        for header in response.headers().get_all("set-cookie") {
+            let header = header.to_str()?;
+            if cookie_str.is_none() || is_session_cookie(header) {
+                cookie_str = Some(header);
+            }
+        }
 
-        let cookie = match cookie_str.get(0..cookie_header) {
+        let cookie = match cookie_str.and_then(extract_cookie) {
             Some(cookie) => cookie,
-            None => return Err(error::Error::SliceError),
+            None => return Err(error::Error::MissingCookie),
         };
 
-        Ok(Self {
+        let mut config = Self {
             cookie: cookie.to_string(),
             address: address.to_string(),
             client,
-        })
+            api_v5: false,
+            version: String::new(),
+            libtorrent_v2: false,
+            min_seeders: 0,
+            min_seeders_timeout: 0,
+            skip_unseeded: false,
+            checking_resume_data_timeout: 0,
+            heartbeat_secs: 0,
+            disk_full_wait_secs: 0,
+            stalled_poll_max_secs: 0,
+            requests: Semaphore::new(DEFAULT_MAX_CONCURRENT_REQUESTS),
+            no_op: false,
+        };
+
+        let version = config.application_version().await?;
+        config.api_v5 = major_version(&version).is_some_and(|major| major >= 5);
+        config.version = version.trim().to_owned();
+        debug!(
+            "qBittorrent version is {}, using {} API",
+            version,
+            match config.api_v5 {
+                true => "5.x",
+                false => "4.x",
+            }
+        );
+        // app/buildInfo needs qBittorrent 4.2, older ones only ship libtorrent 1.x anyway
+        match config.libtorrent_version().await {
+            Ok(libtorrent) => {
+                config.libtorrent_v2 = major_version(&libtorrent).is_some_and(|major| major >= 2);
+                debug!("qBittorrent is built with libtorrent {}", libtorrent);
+            }
+            Err(e) => debug!("Could not read qBittorrent's build info: {}", e),
+        }
+
+        Ok(config)
+    }
+
+    // fail a chunk that stays in CheckingResumeData for longer than timeout seconds (0 to wait
+    // forever)
+    pub fn checking_resume_data_timeout(mut self, timeout: u32) -> Self {
+        self.checking_resume_data_timeout = timeout;
+        self
+    }
+
+    // allow at most max API requests to be in flight at once, to protect small or remote
+    // qBittorrent instances from request storms
+    pub fn max_concurrent_requests(mut self, max: usize) -> Self {
+        self.requests = Semaphore::new(std::cmp::max(max, 1));
+        self
+    }
+
+    // wait until another API request may be sent, the request must hold the permit until it is
+    // answered
+    async fn permit(&self) -> SemaphorePermit<'_> {
+        self.requests
+            .acquire()
+            .await
+            .expect("The request semaphore is never closed")
+    }
+
+    // log the state, progress and speed of the chunk being downloaded every secs seconds, so a
+    // captured log shows rpl is still alive (0 to disable)
+    pub fn heartbeat(mut self, secs: u32) -> Self {
+        self.heartbeat_secs = secs;
+        self
+    }
+
+    // double the polling interval of a chunk that stays stalled or queued without downloading
+    // anything, up to max_secs seconds. It drops back to a second as soon as bytes come in.
+    pub fn stalled_poll_backoff(mut self, max_secs: u32) -> Self {
+        self.stalled_poll_max_secs = max_secs;
+        self
+    }
+
+    // when qBittorrent runs out of disk space mid-download, wait up to secs seconds for enough
+    // space to be freed instead of failing the chunk with InsufficientDiskSpace (0 to fail fast)
+    pub fn disk_full_wait(mut self, secs: u32) -> Self {
+        self.disk_full_wait_secs = secs;
+        self
+    }
+
+    // treat a chunk as downloaded as soon as qBittorrent starts downloading it
+    pub fn no_op(mut self, no_op: bool) -> Self {
+        self.no_op = no_op;
+        self
+    }
+
+    // require at least min_seeders seeders before downloading a chunk, waiting up to timeout
+    // seconds for them to show up. If they never do, the chunk is skipped when skip is true,
+    // otherwise rpl fails
+    pub fn min_seeders(mut self, min_seeders: u32, timeout: u32, skip: bool) -> Self {
+        self.min_seeders = min_seeders;
+        self.min_seeders_timeout = timeout;
+        self.skip_unseeded = skip;
+        self
+    }
+
+    // poll the Web UI until it answers or wait_secs elapses, used before login when rpl and
+    // qBittorrent are started together
+    pub async fn wait_for_client(
+        address: &str,
+        wait_secs: u32,
+        accept_invalid_certs: bool,
+    ) -> Result<(), error::Error> {
+        let client = reqwest::Client::builder()
+            .pool_max_idle_per_host(0)
+            .timeout(Duration::from_millis(1000))
+            .danger_accept_invalid_certs(accept_invalid_certs)
+            .build()?;
+
+        let pb = ProgressBar::new(wait_secs as u64);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} {msg} [{bar:20.cyan/blue}] ({eta_precise})")
+                .progress_chars("#>-"),
+        );
+        pb.set_message(format!("Waiting for qBittorrent at {}", address));
+
+        for _i in 0..wait_secs {
+            // qBittorrent answers 403 before login, any response means the Web UI is up
+            if client
+                .get(format!("{}/api/v2/app/version", address))
+                .send()
+                .await
+                .is_ok()
+            {
+                pb.finish_and_clear();
+                debug!("qBittorrent Web UI at {} is ready", address);
+                return Ok(());
+            }
+            sleep(Duration::from_millis(1000)).await;
+            pb.inc(1);
+        }
+
+        pb.finish_and_clear();
+        error!(
+            "qBittorrent Web UI at {} did not respond after {} seconds",
+            address, wait_secs
+        );
+        Err(error::Error::QbitClientNotReady)
     }
 
     pub async fn application_version(&self) -> Result<String, error::Error> {
         let res = retry(ExponentialBackoff::default(), || async {
+            let _permit = self.permit().await;
             let res = self
                 .client
-                .get(&format!("{}/api/v2/app/version", self.address))
+                .get(format!("{}/api/v2/app/version", self.address))
                 .headers(self.make_headers().expect("Could not construct headers"))
                 .send()
                 .await?;
@@ -225,6 +498,34 @@ impl QbitConfig {
         }
     }
 
+    pub async fn libtorrent_version(&self) -> Result<String, error::Error> {
+        let res = retry(ExponentialBackoff::default(), || async {
+            let _permit = self.permit().await;
+            let res = self
+                .client
+                .get(format!("{}/api/v2/app/buildInfo", self.address))
+                .headers(self.make_headers().expect("Could not construct headers"))
+                .send()
+                .await?;
+            Ok(res)
+        })
+        .await?;
+
+        match res.error_for_status_ref() {
+            Ok(_) => Ok(res.json::<QbitBuildInfo>().await?.libtorrent),
+            Err(e) => Err(error::Error::from(e)),
+        }
+    }
+
+    // The hash qBittorrent knows the torrent by: the v1 info hash, except for a hybrid torrent with
+    // libtorrent 2.x, which uses its v2 info hash truncated to 40 characters
+    pub fn torrent_id(&self, torrent: &Torrent) -> String {
+        match torrent_parser::info_hash_v2(torrent).filter(|_| self.libtorrent_v2) {
+            Some(hash) => hash[..40].to_owned(),
+            None => torrent.info_hash(),
+        }
+    }
+
     fn make_headers(&self) -> Result<reqwest::header::HeaderMap, error::Error> {
         let mut headers = reqwest::header::HeaderMap::new();
         headers.insert("cookie", self.cookie.parse()?);
@@ -234,10 +535,17 @@ impl QbitConfig {
     pub async fn add_new_torrent(&self, data: &QbitTorrent) -> Result<(), error::Error> {
         // cannot do async move |data| here because https://github.com/rust-lang/rust/issues/62290
         let res = retry(ExponentialBackoff::default(), || async {
+            let _permit = self.permit().await;
+            let mut form = data.clone().build_form(); // TODO: find a way to not clone
+            if self.api_v5 {
+                if let Some(paused) = &data.paused {
+                    form = form.text("stopped", paused.to_owned());
+                }
+            }
             let res = self
                 .client
-                .post(&format!("{}/api/v2/torrents/add", self.address))
-                .multipart(data.clone().build_form()) // TODO: find a way to not clone
+                .post(format!("{}/api/v2/torrents/add", self.address))
+                .multipart(form)
                 .headers(self.make_headers().expect("Could not construct headers"))
                 .send()
                 .await?;
@@ -262,13 +570,14 @@ impl QbitConfig {
         priority: u8,
     ) -> Result<(), error::Error> {
         let res = retry(ExponentialBackoff::default(), || async {
+            let _permit = self.permit().await;
             let form = Form::new()
                 .text("hash", hash.to_string())
                 .text("id", files.to_string())
                 .text("priority", priority.to_string());
             let res = self
                 .client
-                .post(&format!("{}/api/v2/torrents/filePrio", self.address))
+                .post(format!("{}/api/v2/torrents/filePrio", self.address))
                 .multipart(form)
                 .headers(self.make_headers().expect("Could not construct headers"))
                 .send()
@@ -285,11 +594,19 @@ impl QbitConfig {
 
     pub async fn resume_torrent(&self, hash: &str) -> Result<(), error::Error> {
         let res = retry(ExponentialBackoff::default(), || async {
+            let _permit = self.permit().await;
             let form = Form::new().text("hashes", hash.to_string());
 
             let res = self
                 .client
-                .post(&format!("{}/api/v2/torrents/resume", self.address))
+                .post(format!(
+                    "{}/api/v2/torrents/{}",
+                    self.address,
+                    match self.api_v5 {
+                        true => "start",
+                        false => "resume",
+                    }
+                ))
                 .multipart(form)
                 .headers(self.make_headers().expect("Could not construct headers"))
                 .send()
@@ -308,15 +625,21 @@ impl QbitConfig {
         }
     }
 
-    pub async fn delete_torrent(&self, hash: &str, delete_files: bool) -> Result<(), error::Error> {
+    pub async fn pause_torrent(&self, hashes: &str) -> Result<(), error::Error> {
         let res = retry(ExponentialBackoff::default(), || async {
-            let form = Form::new()
-                .text("hashes", hash.to_string())
-                .text("deleteFiles", delete_files.to_string());
+            let _permit = self.permit().await;
+            let form = Form::new().text("hashes", hashes.to_string());
 
             let res = self
                 .client
-                .post(&format!("{}/api/v2/torrents/delete", self.address))
+                .post(format!(
+                    "{}/api/v2/torrents/{}",
+                    self.address,
+                    match self.api_v5 {
+                        true => "stop",
+                        false => "pause",
+                    }
+                ))
                 .multipart(form)
                 .headers(self.make_headers().expect("Could not construct headers"))
                 .send()
@@ -326,23 +649,17 @@ impl QbitConfig {
         .await?;
 
         match res.error_for_status() {
-            Ok(_) => {
-                debug!("Sleeping 3s for qbittorrent to delete the torrent...");
-                sleep(Duration::from_millis(3000)).await;
-                Ok(())
-            }
+            Ok(_) => Ok(()),
             Err(e) => Err(error::Error::from(e)),
         }
     }
 
-    pub async fn get_torrent_info(&self, hash: &str) -> Result<QbitTorrentInfo, error::Error> {
+    pub async fn get_all_torrents_info(&self) -> Result<Vec<QbitTorrentInfo>, error::Error> {
         let res = retry(ExponentialBackoff::default(), || async {
+            let _permit = self.permit().await;
             let res = self
                 .client
-                .get(&format!(
-                    "{}/api/v2/torrents/info?hashes={}&limit=1",
-                    self.address, hash
-                ))
+                .get(format!("{}/api/v2/torrents/info", self.address))
                 .headers(self.make_headers().expect("Could not construct headers"))
                 .send()
                 .await?
@@ -353,23 +670,108 @@ impl QbitConfig {
         .await?;
 
         let all_torrents: Vec<QbitTorrentInfo> = serde_json::from_slice(&res)?;
-        let ret_torrent = all_torrents.into_iter().next();
-        match ret_torrent {
-            Some(torrent_info) => Ok(torrent_info),
-            None => Err(error::Error::QbitEmptyTorrentInfo),
+        Ok(all_torrents)
+    }
+
+    // pause every torrent and return the hashes of those that were not paused already, so they
+    // can be resumed with resume_torrents once rpl is done
+    pub async fn pause_active_torrents(&self) -> Result<Vec<String>, error::Error> {
+        let active: Vec<String> = self
+            .get_all_torrents_info()
+            .await?
+            .into_iter()
+            .filter(|info| !matches!(info.state, State::PausedDL | State::PausedUP))
+            .map(|info| info.hash)
+            .collect();
+        self.pause_torrent("all").await?;
+        info!("Paused {} active torrents in qBittorrent", active.len());
+        Ok(active)
+    }
+
+    pub async fn resume_torrents(&self, hashes: &[String]) -> Result<(), error::Error> {
+        if hashes.is_empty() {
+            return Ok(());
+        }
+        self.resume_torrent(&hashes.join("|")).await?;
+        info!("Resumed {} torrents in qBittorrent", hashes.len());
+        Ok(())
+    }
+
+    // whether qBittorrent's alternative speed limits are enabled
+    pub async fn alt_speed_limits(&self) -> Result<bool, error::Error> {
+        let res = retry(ExponentialBackoff::default(), || async {
+            let _permit = self.permit().await;
+            let res = self
+                .client
+                .get(format!("{}/api/v2/transfer/speedLimitsMode", self.address))
+                .headers(self.make_headers().expect("Could not construct headers"))
+                .send()
+                .await?;
+            Ok(res)
+        })
+        .await?;
+
+        match res.error_for_status_ref() {
+            Ok(_) => Ok(res.text().await?.trim() == "1"),
+            Err(e) => Err(error::Error::from(e)),
+        }
+    }
+
+    // 4.x can only toggle the mode, so it is only called when the mode has to change
+    pub async fn set_alt_speed_limits(&self, enabled: bool) -> Result<(), error::Error> {
+        if !self.api_v5 && self.alt_speed_limits().await? == enabled {
+            return Ok(());
+        }
+        let res = retry(ExponentialBackoff::default(), || async {
+            let _permit = self.permit().await;
+            let request = match self.api_v5 {
+                true => self
+                    .client
+                    .post(format!(
+                        "{}/api/v2/transfer/setSpeedLimitsMode",
+                        self.address
+                    ))
+                    .multipart(Form::new().text("mode", (enabled as u8).to_string())),
+                false => self.client.post(format!(
+                    "{}/api/v2/transfer/toggleSpeedLimitsMode",
+                    self.address
+                )),
+            };
+            let res = request
+                .headers(self.make_headers().expect("Could not construct headers"))
+                .send()
+                .await?;
+            Ok(res)
+        })
+        .await?;
+
+        match res.error_for_status() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(error::Error::from(e)),
+        }
+    }
+
+    // turn the alternative speed limits off for the run and return whether they were on, so they
+    // can be turned back on with set_alt_speed_limits once rpl is done
+    pub async fn disable_alt_speed_limits(&self) -> Result<bool, error::Error> {
+        let enabled = self.alt_speed_limits().await?;
+        if enabled {
+            self.set_alt_speed_limits(false).await?;
+            info!("Turned off qBittorrent's alternative speed limits for this run");
         }
+        Ok(enabled)
     }
 
-    pub async fn set_share_limit(&self, hash: &str) -> Result<(), error::Error> {
+    pub async fn delete_torrent(&self, hash: &str, delete_files: bool) -> Result<(), error::Error> {
         let res = retry(ExponentialBackoff::default(), || async {
+            let _permit = self.permit().await;
             let form = Form::new()
                 .text("hashes", hash.to_string())
-                .text("ratioLimit", "-1")
-                .text("seedingTimeLimit", "-1");
+                .text("deleteFiles", delete_files.to_string());
 
             let res = self
                 .client
-                .post(&format!("{}/api/v2/torrents/setShareLimits", self.address))
+                .post(format!("{}/api/v2/torrents/delete", self.address))
                 .multipart(form)
                 .headers(self.make_headers().expect("Could not construct headers"))
                 .send()
@@ -380,92 +782,627 @@ impl QbitConfig {
 
         match res.error_for_status() {
             Ok(_) => {
-                debug!("Sleeping 500ms for qbittorrent to set the torrent limit to unlimited...");
-                sleep(Duration::from_millis(500)).await;
+                debug!("Sleeping 3s for qbittorrent to delete the torrent...");
+                sleep(Duration::from_millis(3000)).await;
                 Ok(())
             }
             Err(e) => Err(error::Error::from(e)),
         }
     }
-}
 
-impl QbitTorrent {
-    // consume QbitTorrent, return a Form
-    fn build_form(self) -> Form {
-        let mut form = Form::new();
-        form = match self.urls {
-            Some(urls) => form.text("urls", urls),
-            None => form,
-        };
-        form = match self.torrents {
-            Some(torrents) => form.part("torrents", Part::bytes(torrents).file_name("")),
-            None => form,
-        };
-        form = match self.savepath {
-            Some(savepath) => form.text("savepath", savepath),
-            None => form,
-        };
-        form = match self.cookie {
-            Some(cookie) => form.text("cookie", cookie),
-            None => form,
-        };
-        form = match self.skip_checking {
-            Some(skip_checking) => form.text("skip_checking", skip_checking),
-            None => form,
-        };
-        form = match self.paused {
-            Some(paused) => form.text("paused", paused),
-            None => form,
-        };
-        form = match self.root_folder {
-            Some(root_folder) => form.text("root_folder", root_folder),
-            None => form,
-        };
-        form = match self.rename {
-            Some(rename) => form.text("rename", rename),
-            None => form,
-        };
-        form = match self.upload_limit {
-            Some(upload_limit) => form.text("upLimit", upload_limit.to_string()),
-            None => form,
-        };
-        form = match self.download_limit {
-            Some(download_limit) => form.text("dlLimit", download_limit.to_string()),
-            None => form,
-        };
-        form
-    }
+    // the torrent file of a torrent whose metadata qBittorrent has (qBittorrent 4.5 and later)
+    pub async fn export_torrent(&self, hash: &str) -> Result<Vec<u8>, error::Error> {
+        let res = retry(ExponentialBackoff::default(), || async {
+            let _permit = self.permit().await;
+            let res = self
+                .client
+                .get(format!(
+                    "{}/api/v2/torrents/export?hash={}",
+                    self.address, hash
+                ))
+                .headers(self.make_headers().expect("Could not construct headers"))
+                .send()
+                .await?;
+            Ok(res)
+        })
+        .await?;
 
-    pub fn torrents(mut self, torrent: Torrent) -> Self {
-        self.torrents = Some(
-            torrent
-                .encode()
-                .expect("Could not encode Torrent to bencode. Is torrent file corrupted?"),
-        );
-        self
+        Ok(res.error_for_status()?.bytes().await?.to_vec())
     }
 
-    pub fn save_path(mut self, path: PathBuf) -> Self {
-        self.savepath = Some(String::from(
-            path.to_str().expect("Could not convert save path PathBuf"),
-        ));
-        self
-    }
+    // Add the magnet link, let qBittorrent fetch the metadata from peers and export it as a
+    // torrent file. The magnet is removed again unless it was already in qBittorrent.
+    pub async fn fetch_magnet_metadata(
+        &self,
+        magnet: &str,
+        hash: &str,
+        timeout: u32,
+    ) -> Result<Vec<u8>, error::Error> {
+        let added = match self.get_torrent_info(hash).await {
+            Ok(_) => false,
+            Err(error::Error::QbitEmptyTorrentInfo) => {
+                let config = QbitTorrent::default()
+                    .urls(magnet)
+                    .stop_condition("MetadataReceived");
+                self.add_new_torrent(&config).await?;
+                true
+            }
+            Err(e) => return Err(e),
+        };
 
-    pub fn skip_hash_checking(mut self, skip: bool) -> Self {
-        self.skip_checking = match skip {
-            true => Some(String::from("true")),
-            false => Some(String::from("false")),
+        let start = Instant::now();
+        let result = loop {
+            let info = self.get_torrent_info(hash).await?;
+            if !matches!(info.state, State::MetaDL) && info.total_size > 0 {
+                if version_at_least(&self.version, 4, 5) {
+                    break self.export_torrent(hash).await;
+                }
+                // older ones start downloading the pack as soon as they have the metadata
+                if added {
+                    self.pause_torrent(hash).await?;
+                }
+                break self.rebuild_torrent(hash, &info.name).await;
+            }
+            if timeout > 0 && start.elapsed() > Duration::from_secs(timeout as u64) {
+                error!(
+                    "qBittorrent could not fetch the metadata of the magnet link in {}s",
+                    timeout
+                );
+                break Err(error::Error::QbitMetadataTimeout);
+            }
+            sleep(POLL_INTERVAL).await;
         };
-        self
+        if added {
+            self.delete_torrent(hash, true).await?;
+        }
+        result
     }
 
-    pub fn paused(mut self, paused: bool) -> Self {
-        self.paused = match paused {
-            true => Some(String::from("true")),
-            false => Some(String::from("false")),
-        };
-        self
+    // torrents/export came with qBittorrent 4.5, older ones get the torrent file put back together
+    // from the files and piece hashes they report
+    async fn rebuild_torrent(&self, hash: &str, name: &str) -> Result<Vec<u8>, error::Error> {
+        let files: Vec<QbitTorrentFile> = self.get_torrent_json("files", hash).await?;
+        let properties: QbitTorrentProperties = self.get_torrent_json("properties", hash).await?;
+        let piece_hashes: Vec<String> = self.get_torrent_json("pieceHashes", hash).await?;
+        match torrent_from_parts(hash, name, properties.piece_size, &files, &piece_hashes) {
+            Some(torrent) => Ok(torrent),
+            None => {
+                error!(
+                    "qBittorrent {} cannot export the metadata of the magnet link and it could not be rebuilt",
+                    self.version
+                );
+                Err(error::Error::QbitMagnetUnsupported(self.version.to_owned()))
+            }
+        }
+    }
+
+    // a GET of torrents/<endpoint> for a single torrent
+    async fn get_torrent_json<T: serde::de::DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        hash: &str,
+    ) -> Result<T, error::Error> {
+        let res = retry(ExponentialBackoff::default(), || async {
+            let _permit = self.permit().await;
+            let res = self
+                .client
+                .get(format!(
+                    "{}/api/v2/torrents/{}?hash={}",
+                    self.address, endpoint, hash
+                ))
+                .headers(self.make_headers().expect("Could not construct headers"))
+                .send()
+                .await?
+                .bytes()
+                .await?;
+            Ok(res)
+        })
+        .await?;
+
+        Ok(serde_json::from_slice(&res)?)
+    }
+
+    pub async fn get_torrent_info(&self, hash: &str) -> Result<QbitTorrentInfo, error::Error> {
+        let res = retry(ExponentialBackoff::default(), || async {
+            let _permit = self.permit().await;
+            let res = self
+                .client
+                .get(format!(
+                    "{}/api/v2/torrents/info?hashes={}&limit=1",
+                    self.address, hash
+                ))
+                .headers(self.make_headers().expect("Could not construct headers"))
+                .send()
+                .await?
+                .bytes()
+                .await?;
+            Ok(res)
+        })
+        .await?;
+
+        let all_torrents: Vec<QbitTorrentInfo> = serde_json::from_slice(&res)?;
+        let ret_torrent = all_torrents.into_iter().next();
+        match ret_torrent {
+            Some(torrent_info) => Ok(torrent_info),
+            None => Err(error::Error::QbitEmptyTorrentInfo),
+        }
+    }
+
+    // warning and critical entries of qBittorrent's log newer than the entry last_known_id (-1
+    // for the whole log)
+    pub async fn get_log_warnings(
+        &self,
+        last_known_id: i64,
+    ) -> Result<Vec<QbitLogEntry>, error::Error> {
+        let res = retry(ExponentialBackoff::default(), || async {
+            let _permit = self.permit().await;
+            let res = self
+                .client
+                .get(format!(
+                    "{}/api/v2/log/main?normal=false&info=false&warning=true&critical=true&last_known_id={}",
+                    self.address, last_known_id
+                ))
+                .headers(self.make_headers().expect("Could not construct headers"))
+                .send()
+                .await?
+                .bytes()
+                .await?;
+            Ok(res)
+        })
+        .await?;
+
+        Ok(serde_json::from_slice(&res)?)
+    }
+
+    // free space on the disk of qBittorrent's default save path, as the machine qBittorrent runs
+    // on sees it
+    pub async fn free_space(&self) -> Result<u64, error::Error> {
+        let res = retry(ExponentialBackoff::default(), || async {
+            let _permit = self.permit().await;
+            let res = self
+                .client
+                .get(format!("{}/api/v2/sync/maindata?rid=0", self.address))
+                .headers(self.make_headers().expect("Could not construct headers"))
+                .send()
+                .await?
+                .bytes()
+                .await?;
+            Ok(res)
+        })
+        .await?;
+
+        let maindata: QbitMainData = serde_json::from_slice(&res)?;
+        Ok(maindata.server_state.free_space_on_disk)
+    }
+
+    // id of the newest warning in qBittorrent's log, later entries are logged with a higher id
+    pub async fn last_log_id(&self) -> Result<i64, error::Error> {
+        Ok(self
+            .get_log_warnings(-1)
+            .await?
+            .last()
+            .map_or(-1, |entry| entry.id))
+    }
+
+    // the Error state does not say why, so look for a disk full message about the torrent that
+    // was logged after the entry last_known_id
+    pub async fn is_disk_full(&self, name: &str, last_known_id: i64) -> Result<bool, error::Error> {
+        Ok(self
+            .get_log_warnings(last_known_id)
+            .await?
+            .iter()
+            .any(|entry| entry.message.contains(name) && is_disk_full_message(&entry.message)))
+    }
+
+    // poll the free space qBittorrent reports until needed bytes are free or disk_full_wait_secs
+    // elapses. qBittorrent may run on another machine, so the disk on this one says nothing.
+    async fn wait_for_disk_space(&self, needed: u64) -> Result<(), error::Error> {
+        let mut waited = 0;
+        loop {
+            let free_space = self.free_space().await?;
+            if free_space >= needed {
+                info!(
+                    "{} is free for qBittorrent, resuming the download",
+                    free_space.file_size(file_size_opts::BINARY).unwrap(),
+                );
+                return Ok(());
+            }
+            if waited >= self.disk_full_wait_secs {
+                error!(
+                    "Still only {} free for qBittorrent after waiting {} seconds, {} is needed",
+                    free_space.file_size(file_size_opts::BINARY).unwrap(),
+                    waited,
+                    needed.file_size(file_size_opts::BINARY).unwrap(),
+                );
+                return Err(error::Error::InsufficientDiskSpace);
+            }
+            sleep(Duration::from_secs(10)).await;
+            waited += 10;
+        }
+    }
+
+    // add the torrent back using the data already under seed_path and start seeding it
+    pub async fn seed_torrent(
+        &self,
+        config: QbitTorrent,
+        hash: &str,
+        seed: &SeedSettings,
+    ) -> Result<(), error::Error> {
+        let seed_config = config.skip_hash_checking(true).save_path(PathBuf::from(
+            shellexpand::full(seed.seed_path()).unwrap().into_owned(),
+        ));
+        self.add_new_torrent(&seed_config).await?;
+        self.set_share_limit(hash, *seed.seed_ratio_limit(), *seed.seed_time_limit())
+            .await?;
+        self.resume_torrent(hash).await
+    }
+
+    pub async fn get_categories(&self) -> Result<HashMap<String, QbitCategory>, error::Error> {
+        let res = retry(ExponentialBackoff::default(), || async {
+            let _permit = self.permit().await;
+            let res = self
+                .client
+                .get(format!("{}/api/v2/torrents/categories", self.address))
+                .headers(self.make_headers().expect("Could not construct headers"))
+                .send()
+                .await?
+                .bytes()
+                .await?;
+            Ok(res)
+        })
+        .await?;
+
+        let categories: HashMap<String, QbitCategory> = serde_json::from_slice(&res)?;
+        Ok(categories)
+    }
+
+    // the save path qBittorrent will use for torrents in this category, if it defines one
+    pub async fn get_category_save_path(
+        &self,
+        category: &str,
+    ) -> Result<Option<String>, error::Error> {
+        let categories = self.get_categories().await?;
+        match categories.get(category) {
+            Some(c) if !c.save_path.is_empty() => Ok(Some(c.save_path.to_owned())),
+            Some(_) => {
+                warn!(
+                    "Category `{}` does not define a save path, rpl will use its own save_path",
+                    category
+                );
+                Ok(None)
+            }
+            None => {
+                warn!(
+                    "Category `{}` does not exist in qBittorrent, rpl will use its own save_path",
+                    category
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    // seeding stops at ratio_limit or after time_limit minutes, UNLIMITED_SHARE_LIMIT for neither
+    pub async fn set_share_limit(
+        &self,
+        hash: &str,
+        ratio_limit: f64,
+        time_limit: i64,
+    ) -> Result<(), error::Error> {
+        let res = retry(ExponentialBackoff::default(), || async {
+            let _permit = self.permit().await;
+            let form = Form::new()
+                .text("hashes", hash.to_string())
+                .text("ratioLimit", ratio_limit.to_string())
+                .text("seedingTimeLimit", time_limit.to_string());
+
+            let res = self
+                .client
+                .post(format!("{}/api/v2/torrents/setShareLimits", self.address))
+                .multipart(form)
+                .headers(self.make_headers().expect("Could not construct headers"))
+                .send()
+                .await?;
+            Ok(res)
+        })
+        .await?;
+
+        match res.error_for_status() {
+            Ok(_) => {
+                debug!("Sleeping 500ms for qbittorrent to set the share limit of the torrent...");
+                sleep(Duration::from_millis(500)).await;
+                Ok(())
+            }
+            Err(e) => Err(error::Error::from(e)),
+        }
+    }
+}
+
+// turn the extra_headers of the config into headers, failing on invalid names or values
+pub fn parse_headers(
+    headers: &HashMap<String, String>,
+) -> Result<reqwest::header::HeaderMap, error::Error> {
+    let mut map = reqwest::header::HeaderMap::new();
+    for (name, value) in headers {
+        let name: reqwest::header::HeaderName = name.parse()?;
+        map.insert(name, value.parse()?);
+    }
+    Ok(map)
+}
+
+fn is_session_cookie(set_cookie: &str) -> bool {
+    match set_cookie.split('=').next() {
+        Some(name) => name.trim() == "SID" || name.trim().starts_with("QBT_SID"),
+        None => false,
+    }
+}
+
+// the `name=value` part of a set-cookie header, with or without attributes
+fn extract_cookie(set_cookie: &str) -> Option<&str> {
+    let cookie = match set_cookie.find(';') {
+        Some(index) => set_cookie.get(0..index)?,
+        None => set_cookie,
+    }
+    .trim();
+    match cookie.contains('=') {
+        true => Some(cookie),
+        false => None,
+    }
+}
+
+// libtorrent file errors carry the OS message, e.g. "... error: No space left on device"
+fn is_disk_full_message(message: &str) -> bool {
+    let message = message.to_lowercase();
+    [
+        "no space left on device",
+        "not enough space on the disk",
+        "disk full",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+// interval until the next poll of a downloading chunk
+fn next_poll_interval(
+    current: Duration,
+    stalled: bool,
+    progressed: bool,
+    max_secs: u32,
+) -> Duration {
+    if !stalled || progressed {
+        return POLL_INTERVAL;
+    }
+    min(
+        current * 2,
+        max(Duration::from_secs(max_secs as u64), POLL_INTERVAL),
+    )
+}
+
+// every file of the job is in source with its full size
+fn on_disk(job: &Job, source: &Path) -> bool {
+    job.files.iter().zip(&job.lengths).all(|(file, length)| {
+        fs::metadata(source.join(file)).is_ok_and(|meta| meta.len() == *length as u64)
+    })
+}
+
+// "v4.3.5" -> 4
+fn major_version(version: &str) -> Option<u32> {
+    version
+        .trim()
+        .trim_start_matches('v')
+        .split('.')
+        .next()?
+        .parse()
+        .ok()
+}
+
+// The torrent file of a torrent from what qBittorrent reports about it. Keys of the info dictionary
+// qBittorrent does not report other than `private` are lost, so None unless the info hash matches.
+fn torrent_from_parts(
+    hash: &str,
+    name: &str,
+    piece_size: i64,
+    files: &[QbitTorrentFile],
+    piece_hashes: &[String],
+) -> Option<Vec<u8>> {
+    let mut pieces = Vec::with_capacity(piece_hashes.len());
+    for piece_hash in piece_hashes {
+        let bytes = (0..piece_hash.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(piece_hash.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        pieces.push(bytes);
+    }
+    let prefix = format!("{}/", name);
+    let single_file = files.len() == 1 && !files[0].name.contains('/');
+    let mut torrent = Torrent {
+        announce: None,
+        announce_list: None,
+        length: files.iter().map(|file| file.size).sum(),
+        files: match single_file {
+            true => None,
+            false => Some(
+                files
+                    .iter()
+                    .map(|file| File {
+                        length: file.size,
+                        path: PathBuf::from(file.name.strip_prefix(&prefix).unwrap_or(&file.name)),
+                        extra_fields: None,
+                    })
+                    .collect(),
+            ),
+        },
+        name: match single_file {
+            true => files[0].name.to_owned(),
+            false => name.to_owned(),
+        },
+        piece_length: piece_size,
+        pieces,
+        extra_fields: None,
+        extra_info_fields: None,
+    };
+    if torrent.info_hash() != hash {
+        let private = vec![(String::from("private"), BencodeElem::Integer(1))];
+        torrent.extra_info_fields = Some(private.into_iter().collect());
+    }
+    match torrent.info_hash() == hash {
+        true => torrent.encode().ok(),
+        false => None,
+    }
+}
+
+// "v4.5.2" is at least 4.5, a version that cannot be parsed is not
+fn version_at_least(version: &str, major: u32, minor: u32) -> bool {
+    let mut parts = version
+        .trim()
+        .trim_start_matches('v')
+        .split('.')
+        .map(|part| part.parse::<u32>().ok());
+    match (parts.next().flatten(), parts.next().flatten()) {
+        (Some(found_major), Some(found_minor)) => (found_major, found_minor) >= (major, minor),
+        (Some(found_major), None) => found_major > major,
+        _ => false,
+    }
+}
+
+impl QbitTorrent {
+    // every form field except the torrent file itself, in the order they are sent
+    fn text_fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = Vec::new();
+        if let Some(urls) = &self.urls {
+            fields.push(("urls", urls.to_owned()));
+        }
+        if let Some(savepath) = &self.savepath {
+            fields.push(("savepath", savepath.to_owned()));
+        }
+        if let Some(download_path) = &self.download_path {
+            fields.push(("downloadPath", download_path.to_owned()));
+        }
+        if let Some(use_download_path) = &self.use_download_path {
+            fields.push(("useDownloadPath", use_download_path.to_owned()));
+        }
+        if let Some(cookie) = &self.cookie {
+            fields.push(("cookie", cookie.to_owned()));
+        }
+        if let Some(category) = &self.category {
+            fields.push(("category", category.to_owned()));
+        }
+        if let Some(tags) = &self.tags {
+            fields.push(("tags", tags.to_owned()));
+        }
+        if let Some(skip_checking) = &self.skip_checking {
+            fields.push(("skip_checking", skip_checking.to_owned()));
+        }
+        if let Some(paused) = &self.paused {
+            fields.push(("paused", paused.to_owned()));
+        }
+        if let Some(root_folder) = &self.root_folder {
+            fields.push(("root_folder", root_folder.to_owned()));
+        }
+        if let Some(rename) = &self.rename {
+            fields.push(("rename", rename.to_owned()));
+        }
+        if let Some(upload_limit) = self.upload_limit {
+            fields.push(("upLimit", upload_limit.to_string()));
+        }
+        if let Some(download_limit) = self.download_limit {
+            fields.push(("dlLimit", download_limit.to_string()));
+        }
+        if let Some(stop_condition) = &self.stop_condition {
+            fields.push(("stopCondition", stop_condition.to_owned()));
+        }
+        fields
+    }
+
+    // consume QbitTorrent, return a Form
+    fn build_form(self) -> Form {
+        let mut form = Form::new();
+        for (key, value) in self.text_fields() {
+            form = form.text(key, value);
+        }
+        form = match self.torrents {
+            Some(torrents) => form.part("torrents", Part::bytes(torrents).file_name("")),
+            None => form,
+        };
+        form
+    }
+
+    pub fn torrents(mut self, torrent: Torrent) -> Self {
+        self.torrents = Some(
+            torrent
+                .encode()
+                .expect("Could not encode Torrent to bencode. Is torrent file corrupted?"),
+        );
+        self
+    }
+
+    pub fn save_path(mut self, path: PathBuf) -> Self {
+        self.savepath = Some(String::from(
+            path.to_str().expect("Could not convert save path PathBuf"),
+        ));
+        self
+    }
+
+    // keep incomplete downloads in path, qBittorrent moves them to the save path once complete
+    pub fn download_path(mut self, path: PathBuf) -> Self {
+        self.download_path = Some(String::from(
+            path.to_str()
+                .expect("Could not convert download path PathBuf"),
+        ));
+        self.use_download_path = Some(String::from("true"));
+        self
+    }
+
+    pub fn category(mut self, category: &str) -> Self {
+        self.category = Some(String::from(category));
+        self
+    }
+
+    pub fn tags(mut self, tags: &str) -> Self {
+        self.tags = Some(String::from(tags));
+        self
+    }
+
+    pub fn skip_hash_checking(mut self, skip: bool) -> Self {
+        self.skip_checking = match skip {
+            true => Some(String::from("true")),
+            false => Some(String::from("false")),
+        };
+        self
+    }
+
+    // The add for leeching jobs out of source. qBittorrent's skip_checking is libtorrent's seed
+    // mode, which takes every piece as downloaded, so it is only kept when every file of the jobs
+    // is already in source at full size (e.g. hardlinked from cross_seed_dir). Otherwise the
+    // chunk would look complete before anything was downloaded.
+    fn leech_add<'j>(&self, jobs: impl IntoIterator<Item = &'j Job>, source: &Path) -> Self {
+        let mut config = self.clone();
+        if config.skip_checking.as_deref() == Some("true")
+            && !jobs.into_iter().all(|job| on_disk(job, source))
+        {
+            debug!("Not every file of the chunk is on disk yet, qBittorrent will hash check it");
+            config.skip_checking = None;
+        }
+        config
+    }
+
+    pub fn urls(mut self, urls: &str) -> Self {
+        self.urls = Some(String::from(urls));
+        self
+    }
+
+    // stop the torrent once it reaches condition, e.g. "MetadataReceived" (qBittorrent 4.5 and
+    // later, ignored before)
+    pub fn stop_condition(mut self, condition: &str) -> Self {
+        self.stop_condition = Some(String::from(condition));
+        self
+    }
+
+    pub fn paused(mut self, paused: bool) -> Self {
+        self.paused = match paused {
+            true => Some(String::from("true")),
+            false => Some(String::from("false")),
+        };
+        self
     }
 
     pub fn upload_limit(mut self, limit: i64) -> Self {
@@ -479,6 +1416,163 @@ impl QbitTorrent {
     }
 }
 
+// the --files-from of rclone in the emitted script, a shell variable
+const SCRIPT_FILES_FROM: &str = "$FILES_FROM";
+
+// Build a shell script that performs the same qBittorrent API calls and rclone commands that
+// leech_torrent would, without running any of them. `torrent_source` is the multipart field used
+// to add the torrent, e.g. `torrents=@/path/to/file.torrent` or `urls=<link>`.
+#[allow(clippy::too_many_arguments)]
+pub fn emit_script(
+    pack: &mut TorrentPack,
+    torrent: Torrent,
+    config: &QbitTorrent,
+    torrent_source: &str,
+    address: &str,
+    username: &str,
+    upload_client: &RcloneClient,
+    seed: &SeedSettings,
+    settings: &LeechSettings,
+) -> Result<String, error::Error> {
+    let hash = pack.info_hash();
+    let chunks = pack.chunks()?;
+    let queue = build_queue(chunks, torrent)?;
+    let no_all_files = queue.no_all_files;
+    let jobs = queue.job;
+    let no_jobs = jobs.len();
+
+    // the paused field is called stopped from qBittorrent 5 on, see add_new_torrent
+    let add_args = |config: &QbitTorrent| -> String {
+        let mut args = format!("-F {}", util::shell_quote(torrent_source));
+        for (key, value) in config.text_fields() {
+            match key {
+                "paused" => args.push_str(&format!(" -F \"$STOPPED={}\"", value)),
+                _ => args.push_str(&format!(
+                    " -F {}",
+                    util::shell_quote(&format!("{}={}", key, value))
+                )),
+            }
+        }
+        args
+    };
+    // the upload of a chunk as the live run does it, rclone move only gets the chunk's files
+    let rclone_cmd = |job: &Job| -> String {
+        let client = upload_client.for_chunk(job, no_jobs);
+        let files_from = match upload_client.moves_source() {
+            true => Some(Path::new(SCRIPT_FILES_FROM)),
+            false => None,
+        };
+        let args = client
+            .build_args(&client.chunk_args(files_from))
+            .iter()
+            .map(|arg| match arg.as_str() {
+                SCRIPT_FILES_FROM => format!("\"{}\"", SCRIPT_FILES_FROM),
+                _ => util::shell_quote(arg),
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+        format!("{} {}", util::shell_quote(&upload_client.variant), args)
+    };
+    // the files are gone after rclone move, and kept with keep_after_upload
+    let delete_files = !upload_client.moves_source() && !settings.keep_files;
+
+    let mut script = String::new();
+    script.push_str("#!/bin/sh\n");
+    script.push_str("# Generated by rpl --emit-script. Set QB_PASSWORD before running.\n");
+    script.push_str("set -eu\n\n");
+    script.push_str(&format!("QB_ADDRESS={}\n", util::shell_quote(address)));
+    script.push_str(&format!("QB_USERNAME={}\n", util::shell_quote(username)));
+    script.push_str(&format!("HASH={}\n", util::shell_quote(&hash)));
+    script.push_str("COOKIE_JAR=\"$(mktemp)\"\n");
+    script.push_str("FILES_FROM=\"$(mktemp)\"\n");
+    script.push_str("trap 'rm -f \"$COOKIE_JAR\" \"$FILES_FROM\"' EXIT\n\n");
+    script.push_str("qb() {\n");
+    script.push_str("    endpoint=\"$1\"\n    shift\n");
+    script.push_str(
+        "    curl -sf -b \"$COOKIE_JAR\" -H \"Referer: $QB_ADDRESS\" -H \"Origin: ${QB_ADDRESS%/}\" \"$@\" \"$QB_ADDRESS/api/v2/$endpoint\"\n",
+    );
+    script.push_str("}\n\n");
+    script.push_str("wait_for_download() {\n");
+    script.push_str(
+        "    until qb \"torrents/info?hashes=$HASH&limit=1\" | grep -qE '\"state\":\"(uploading|pausedUP|queuedUP|stalledUP|checkingUP|forcedUP)\"'; do\n",
+    );
+    script.push_str("        sleep 1\n    done\n}\n\n");
+    script.push_str(
+        "curl -sf -c \"$COOKIE_JAR\" -H \"Referer: $QB_ADDRESS\" -H \"Origin: ${QB_ADDRESS%/}\" --data-urlencode \"username=$QB_USERNAME\" --data-urlencode \"password=$QB_PASSWORD\" \"$QB_ADDRESS/api/v2/auth/login\"\n",
+    );
+    script.push_str("# qBittorrent 5 renamed resume to start and the paused field to stopped\n");
+    script.push_str("case \"$(qb app/version)\" in\n");
+    script.push_str("    v[0-4].*) START=resume; STOPPED=paused ;;\n");
+    script.push_str("    *) START=start; STOPPED=stopped ;;\n");
+    script.push_str("esac\n");
+    if let Some(hash_v2) = torrent_parser::info_hash_v2(&pack.torrent) {
+        script.push_str(
+            "# a hybrid torrent goes by its truncated v2 info hash with libtorrent 2.x
+",
+        );
+        script.push_str(&format!(
+            "if qb app/buildInfo | grep -q '\"libtorrent\":\"2\\.'; then HASH={}; fi\n",
+            util::shell_quote(&hash_v2[..40])
+        ));
+    }
+
+    let mut skipped = settings.skip;
+    for job in jobs {
+        script.push_str(&format!("\n# chunk {}/{}\n", job.chunk, no_jobs));
+        if skipped > 0 {
+            script.push_str("# skipped\n");
+            skipped -= 1;
+            continue;
+        }
+        if leech_steps::not_selected(settings, &job, no_jobs) {
+            script.push_str("# not selected by --only\n");
+            continue;
+        }
+        script.push_str(&format!("qb torrents/add {}\n", add_args(config)));
+        script.push_str(
+            "qb torrents/setShareLimits -F \"hashes=$HASH\" -F ratioLimit=-1 -F seedingTimeLimit=-1\n",
+        );
+        if let Some(disable_string) = &job.disable_others(no_all_files) {
+            script.push_str(&format!(
+                "qb torrents/filePrio -F \"hash=$HASH\" -F {} -F priority=0\n",
+                util::shell_quote(&format!("id={}", disable_string))
+            ));
+        }
+        script.push_str("qb \"torrents/$START\" -F \"hashes=$HASH\"\n");
+        script.push_str("wait_for_download\n");
+        if upload_client.moves_source() {
+            script.push_str("cat > \"$FILES_FROM\" <<'RPL_FILES'\n");
+            script.push_str(&rclone::files_from(&job));
+            script.push_str("RPL_FILES\n");
+        }
+        script.push_str(&format!("{}\n", rclone_cmd(&job)));
+        script.push_str(&format!(
+            "qb torrents/delete -F \"hashes=$HASH\" -F deleteFiles={}\n",
+            delete_files
+        ));
+    }
+
+    if *seed.seed_enable() {
+        let seed_config = config
+            .clone()
+            .skip_hash_checking(true)
+            .save_path(PathBuf::from(
+                shellexpand::full(seed.seed_path()).unwrap().into_owned(),
+            ));
+        script.push_str("\n# seed through the mount\n");
+        script.push_str(&format!("sleep {}\n", seed.seed_wait()));
+        script.push_str(&format!("qb torrents/add {}\n", add_args(&seed_config)));
+        script.push_str(&format!(
+            "qb torrents/setShareLimits -F \"hashes=$HASH\" -F ratioLimit={} -F seedingTimeLimit={}\n",
+            seed.seed_ratio_limit(),
+            seed.seed_time_limit()
+        ));
+        script.push_str("qb \"torrents/$START\" -F \"hashes=$HASH\"\n");
+    }
+
+    Ok(script)
+}
+
 #[async_trait]
 impl<'a> RplLeech<'a, TorrentPack, QbitTorrent, QbitConfig> for TorrentPack {
     async fn leech_torrent(
@@ -486,123 +1580,657 @@ impl<'a> RplLeech<'a, TorrentPack, QbitTorrent, QbitConfig> for TorrentPack {
         torrent: Torrent,
         config: QbitTorrent,
         torrent_client: QbitConfig,
-        upload_client: RcloneClient,
+        upload_client: Box<dyn RplUploadClient>,
         seed: SeedSettings,
-        skip: u32,
+        settings: LeechSettings,
     ) -> Result<(), error::Error> {
-        let hash = self.info_hash();
+        leech(
+            self,
+            torrent,
+            config,
+            torrent_client,
+            upload_client,
+            seed,
+            settings,
+            Events::default(),
+        )
+        .await
+    }
 
-        info!(
-            "The pack size is {}, maximum size per chunk is {}. Private torrent = {}.",
-            &self.get_pack_size_human(),
-            &self.get_max_size_chunk_human(),
-            &self.is_private()
-        );
-        info!(
-            "qBittorrent App Version: {}",
-            torrent_client.application_version().await?
-        );
+    fn leech_torrent_with_events(
+        &'a mut self,
+        torrent: Torrent,
+        config: QbitTorrent,
+        torrent_client: QbitConfig,
+        upload_client: Box<dyn RplUploadClient>,
+        seed: SeedSettings,
+        settings: LeechSettings,
+    ) -> (UnboundedReceiverStream<ProgressEvent>, LeechFuture<'a>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let events = Events::new(sender);
+        let name = torrent.name.to_owned();
+        let run = Box::pin(async move {
+            let result = leech(
+                self,
+                torrent,
+                config,
+                torrent_client,
+                upload_client,
+                seed,
+                settings,
+                events.clone(),
+            )
+            .await;
+            if let Err(e) = &result {
+                events.send(ProgressEvent::Failed {
+                    name,
+                    error: e.to_string(),
+                });
+            }
+            result
+        });
+        (UnboundedReceiverStream::new(receiver), run)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn leech(
+    pack: &mut TorrentPack,
+    torrent: Torrent,
+    config: QbitTorrent,
+    torrent_client: QbitConfig,
+    mut upload_client: Box<dyn RplUploadClient>,
+    seed: SeedSettings,
+    mut settings: LeechSettings,
+    events: Events,
+) -> Result<(), error::Error> {
+    let hash = torrent_client.torrent_id(&pack.torrent);
+
+    info!(
+        "The pack size is {}, maximum size per chunk is {}. Private torrent = {}.",
+        &pack.get_pack_size_human(),
+        &pack.get_max_size_chunk_human(),
+        &pack.is_private()
+    );
+    info!(
+        "qBittorrent App Version: {}",
+        torrent_client.application_version().await?
+    );
+
+    // another rpl instance may be leeching the same pack, leave its torrent alone
+    // the first tag is rpl's own, the others are only labels
+    if let Some(tag) = config
+        .tags
+        .as_deref()
+        .and_then(|tags| tags.split(',').next())
+    {
+        match torrent_client.get_torrent_info(&hash).await {
+            Ok(info) if !info.has_tag(tag) => {
+                error!(
+                    "The torrent is already in qBittorrent without the `{}` tag, it is managed by someone else",
+                    tag
+                );
+                return Err(error::Error::QbitTorrentNotOwned);
+            }
+            Ok(_) | Err(error::Error::QbitEmptyTorrentInfo) => (),
+            Err(e) => return Err(e),
+        }
+    }
 
-        let chunks = self.chunks()?;
-        let queue = build_queue(chunks, torrent)?;
-        let no_all_files = queue.no_all_files;
-        let jobs = queue.job;
-        let no_jobs = jobs.len();
+    let chunks = pack.chunks()?;
+    let queue = build_queue(chunks, torrent)?;
+    let no_all_files = queue.no_all_files;
+    let size = queue.leech_size(settings.skip, settings.only.as_deref());
+    let jobs = queue.job;
+    let no_jobs = jobs.len();
+    events.send(ProgressEvent::Started {
+        name: pack.torrent.name.to_owned(),
+        chunks: no_jobs,
+        size,
+    });
 
-        let mut offset = 0;
-        let mut skipped = skip;
+    leech_steps::refuse_existing(pack, &jobs, settings.skip, upload_client.as_ref()).await?;
 
+    if settings.pipeline {
+        let finished = leech_pipelined(
+            pack,
+            jobs,
+            no_all_files,
+            &config,
+            &torrent_client,
+            &mut upload_client,
+            &mut settings,
+            &events,
+        )
+        .await?;
+        if !finished {
+            return Ok(());
+        }
+    } else {
+        let mut skipped = settings.skip;
+        let mut uploaded: u64 = 0;
+        let mut failed = leech_steps::FailedChunks::default();
         for job in jobs {
+            leech_steps::check_stop(&settings, &job, no_jobs)?;
             job.info();
             if skipped > 0 {
                 info!("Chunk {}/{} has been skipped", job.chunk, no_jobs);
+                events.send(ProgressEvent::ChunkSkipped { chunk: job.chunk });
                 skipped -= 1;
-                offset += job.no_files;
                 continue;
             }
-            torrent_client.add_new_torrent(&config).await?;
-            torrent_client.set_share_limit(&hash).await?;
-            let disable_others = &job.disable_others(offset, no_all_files);
-            match disable_others {
-                Some(disable_string) => {
-                    torrent_client
-                        .set_priority(&hash, disable_string, 0)
-                        .await?;
+            if leech_steps::not_selected(&settings, &job, no_jobs) {
+                events.send(ProgressEvent::ChunkSkipped { chunk: job.chunk });
+                continue;
+            }
+            if leech_steps::over_budget(&settings, &job, uploaded, no_jobs)? {
+                return failed.check();
+            }
+            match leech_chunk(
+                pack,
+                &job,
+                no_jobs,
+                no_all_files,
+                &config,
+                &torrent_client,
+                &mut upload_client,
+                &mut settings,
+                &events,
+            )
+            .await
+            {
+                Ok(true) => uploaded += job.total_size as u64,
+                Ok(false) => (),
+                Err(e) => {
+                    failed.record(&mut settings, &job, no_jobs, e, &events)?;
+                    // the next chunk starts from a fresh torrent
+                    if let Err(e) = torrent_client
+                        .delete_torrent(&hash, !settings.keep_files)
+                        .await
+                    {
+                        debug!("Could not remove the torrent of chunk {}: {}", job.chunk, e);
+                    }
                 }
-                None => (),
             }
-            info!("Downloading chunk {}/{}", job.chunk, no_jobs);
-            job.download(&torrent_client, &hash, no_jobs).await?;
-            info!("Finished downloading chunk {}/{}", job.chunk, no_jobs);
-            info!("Uploading chunk {}/{}", job.chunk, no_jobs);
-            job.upload(&upload_client, no_jobs)?;
-            info!("Finished uploading chunk {}/{}", job.chunk, no_jobs);
+        }
+        failed.check()?;
+    }
+
+    if settings.final_verify_count && !torrent_client.no_op {
+        leech_steps::verify_remote_count(pack, &settings, upload_client.as_ref()).await?;
+    }
+
+    if *seed.seed_enable() && torrent_client.no_op {
+        info!("[no-op] Not adding the torrent back for seeding, nothing has been uploaded");
+    } else if *seed.seed_enable() {
+        info!(
+            "Waiting for {} to refresh mount point...",
+            upload_client.name()
+        );
+        util::wait_with_progress(*seed.seed_wait()).await;
+        info!(
+            "Adding the torrent back to qBittorrent for seeding through {}'s mount",
+            upload_client.name()
+        );
+        torrent_client.seed_torrent(config, &hash, &seed).await?;
+        events.send(ProgressEvent::Seeding {
+            name: pack.torrent.name.to_owned(),
+        });
+    }
 
-            torrent_client.delete_torrent(&hash, true).await?;
+    events.send(ProgressEvent::Finished {
+        name: pack.torrent.name.to_owned(),
+    });
 
-            offset += job.no_files;
+    Ok(())
+}
+
+// add the torrent, download and upload one chunk, then remove the torrent. Ok(false) when the
+// chunk was skipped for lack of seeders
+#[allow(clippy::too_many_arguments)]
+async fn leech_chunk(
+    pack: &mut TorrentPack,
+    job: &Job,
+    no_jobs: usize,
+    no_all_files: i32,
+    config: &QbitTorrent,
+    torrent_client: &QbitConfig,
+    upload_client: &mut Box<dyn RplUploadClient>,
+    settings: &mut LeechSettings,
+    events: &Events,
+) -> Result<bool, error::Error> {
+    let hash = torrent_client.torrent_id(&pack.torrent);
+    leech_steps::check_output_space(settings, job, upload_client.get_source(), no_jobs)?;
+    if let Some(cross_seed) = &settings.cross_seed {
+        cross_seed.link_chunk(&pack.torrent, job, upload_client.get_source())?;
+    }
+    torrent_client
+        .add_new_torrent(&config.leech_add([job], upload_client.get_source()))
+        .await?;
+    torrent_client
+        .set_share_limit(&hash, UNLIMITED_SHARE_LIMIT as f64, UNLIMITED_SHARE_LIMIT)
+        .await?;
+    if let Some(disable_string) = &job.disable_others(no_all_files) {
+        torrent_client
+            .set_priority(&hash, disable_string, 0)
+            .await?;
+    }
+    if !job.wait_for_seeders(torrent_client, &hash, no_jobs).await? {
+        warn!(
+            "Chunk {}/{} has been skipped because it does not have enough seeders",
+            job.chunk, no_jobs
+        );
+        events.send(ProgressEvent::ChunkSkipped { chunk: job.chunk });
+        torrent_client
+            .delete_torrent(&hash, !settings.keep_files)
+            .await?;
+        return Ok(false);
+    }
+    info!("Downloading chunk {}/{}", job.chunk, no_jobs);
+    events.send(ProgressEvent::DownloadStarted { chunk: job.chunk });
+    job.download(torrent_client, &hash, no_jobs, events).await?;
+    info!("Finished downloading chunk {}/{}", job.chunk, no_jobs);
+    events.send(ProgressEvent::DownloadFinished { chunk: job.chunk });
+    follow_save_path(config, torrent_client, &hash, job, no_jobs, upload_client).await?;
+    leech_steps::upload_chunk(
+        pack,
+        job,
+        no_jobs,
+        upload_client.as_ref(),
+        settings,
+        events,
+        torrent_client.no_op,
+    )
+    .await?;
+
+    torrent_client
+        .delete_torrent(&hash, !upload_client.moves_source() && !settings.keep_files)
+        .await?;
+    Ok(true)
+}
+
+// Download chunk N+1 while chunk N is uploaded. Every chunk goes through the same torrent, whose
+// file priorities follow the chunk being downloaded, and the files of a chunk are deleted once it
+// is uploaded, so at most two chunks are on disk at once. libtorrent treats the pieces of deleted
+// files as missing when a peer asks for them. A chunk that fails is recorded and the run goes on
+// with the next one. Returns false when the run stopped early because of the upload budget.
+#[allow(clippy::too_many_arguments)]
+async fn leech_pipelined(
+    pack: &mut TorrentPack,
+    jobs: Vec<Job>,
+    no_all_files: i32,
+    config: &QbitTorrent,
+    torrent_client: &QbitConfig,
+    upload_client: &mut Box<dyn RplUploadClient>,
+    settings: &mut LeechSettings,
+    events: &Events,
+) -> Result<bool, error::Error> {
+    let hash = torrent_client.torrent_id(&pack.torrent);
+    let no_jobs = jobs.len();
+    let no_op = torrent_client.no_op;
+    let mut skipped = settings.skip;
+    let mut uploaded: u64 = 0;
+    let mut added = false;
+    let mut failed = leech_steps::FailedChunks::default();
+    // downloaded and waiting for its upload
+    let mut pending: Option<Job> = None;
+    // every chunk goes through the torrent added for the first one
+    let config = &config.leech_add(&jobs, upload_client.get_source());
+
+    for job in jobs {
+        if let Err(e) = leech_steps::check_stop(settings, &job, no_jobs) {
+            if let Some(previous) = pending.take() {
+                upload_and_remove(
+                    pack,
+                    &previous,
+                    no_jobs,
+                    upload_client.as_ref(),
+                    settings,
+                    &mut failed,
+                    events,
+                    no_op,
+                )
+                .await?;
+            }
+            return Err(e);
+        }
+        job.info();
+        if skipped > 0 {
+            info!("Chunk {}/{} has been skipped", job.chunk, no_jobs);
+            events.send(ProgressEvent::ChunkSkipped { chunk: job.chunk });
+            skipped -= 1;
+            continue;
+        }
+        if leech_steps::not_selected(settings, &job, no_jobs) {
+            events.send(ProgressEvent::ChunkSkipped { chunk: job.chunk });
+            continue;
+        }
+        let pending_size = pending
+            .as_ref()
+            .map_or(0, |previous| previous.total_size as u64);
+        if leech_steps::over_budget(settings, &job, uploaded + pending_size, no_jobs)? {
+            if let Some(previous) = pending.take() {
+                upload_and_remove(
+                    pack,
+                    &previous,
+                    no_jobs,
+                    upload_client.as_ref(),
+                    settings,
+                    &mut failed,
+                    events,
+                    no_op,
+                )
+                .await?;
+            }
+            if added {
+                torrent_client
+                    .delete_torrent(&hash, !settings.keep_files)
+                    .await?;
+            }
+            failed.check()?;
+            return Ok(false);
+        }
+        leech_steps::check_output_space(settings, &job, upload_client.get_source(), no_jobs)?;
+        if let Some(cross_seed) = &settings.cross_seed {
+            cross_seed.link_chunk(&pack.torrent, &job, upload_client.get_source())?;
+        }
+        let prepared = async {
+            if !added {
+                torrent_client.add_new_torrent(config).await?;
+                added = true;
+                torrent_client
+                    .set_share_limit(&hash, UNLIMITED_SHARE_LIMIT as f64, UNLIMITED_SHARE_LIMIT)
+                    .await?;
+            }
+            if let Some(disable_string) = &job.disable_others(no_all_files) {
+                torrent_client
+                    .set_priority(&hash, disable_string, 0)
+                    .await?;
+            }
+            torrent_client
+                .set_priority(&hash, &job.file_ids(), 1)
+                .await?;
+            job.wait_for_seeders(torrent_client, &hash, no_jobs).await
+        }
+        .await;
+        match prepared {
+            Ok(true) => (),
+            Ok(false) => {
+                warn!(
+                    "Chunk {}/{} has been skipped because it does not have enough seeders",
+                    job.chunk, no_jobs
+                );
+                events.send(ProgressEvent::ChunkSkipped { chunk: job.chunk });
+                torrent_client
+                    .set_priority(&hash, &job.file_ids(), 0)
+                    .await?;
+                continue;
+            }
+            Err(e) => {
+                failed.record(settings, &job, no_jobs, e, events)?;
+                remove_failed(
+                    torrent_client,
+                    &hash,
+                    &job,
+                    upload_client.get_source(),
+                    settings,
+                    pending.is_some(),
+                )
+                .await;
+                added = false;
+                continue;
+            }
         }
 
-        if *seed.seed_enable() {
-            info!(
-                "Waiting for {} to refresh mount point...",
-                upload_client.variant
-            );
-            util::wait_with_progress(*seed.seed_wait()).await;
-            info!(
-                "Adding the torrent back to qBittorrent for seeding through {}'s mount",
-                upload_client.variant
-            );
-            let seed_config = config.skip_hash_checking(true).save_path(PathBuf::from(
-                shellexpand::full(seed.seed_path()).unwrap().into_owned(),
-            ));
-            torrent_client.add_new_torrent(&seed_config).await?;
-            torrent_client.set_share_limit(&hash).await?;
-            torrent_client.resume_torrent(&hash).await?;
+        info!("Downloading chunk {}/{}", job.chunk, no_jobs);
+        events.send(ProgressEvent::DownloadStarted { chunk: job.chunk });
+        let download = job.download(torrent_client, &hash, no_jobs, events);
+        let downloaded = match pending.take() {
+            Some(previous) => {
+                let upload = upload_and_remove(
+                    pack,
+                    &previous,
+                    no_jobs,
+                    upload_client.as_ref(),
+                    settings,
+                    &mut failed,
+                    events,
+                    no_op,
+                );
+                let (downloaded, upload_result) = tokio::join!(download, upload);
+                if upload_result? {
+                    uploaded += previous.total_size as u64;
+                }
+                downloaded
+            }
+            None => download.await,
+        };
+        if let Err(e) = downloaded {
+            failed.record(settings, &job, no_jobs, e, events)?;
+            remove_failed(
+                torrent_client,
+                &hash,
+                &job,
+                upload_client.get_source(),
+                settings,
+                false,
+            )
+            .await;
+            added = false;
+            continue;
         }
+        info!("Finished downloading chunk {}/{}", job.chunk, no_jobs);
+        events.send(ProgressEvent::DownloadFinished { chunk: job.chunk });
+        follow_save_path(config, torrent_client, &hash, &job, no_jobs, upload_client).await?;
+        pending = Some(job);
+    }
 
-        Ok(())
+    if let Some(previous) = pending.take() {
+        upload_and_remove(
+            pack,
+            &previous,
+            no_jobs,
+            upload_client.as_ref(),
+            settings,
+            &mut failed,
+            events,
+            no_op,
+        )
+        .await?;
+    }
+    if added {
+        torrent_client
+            .delete_torrent(&hash, !settings.keep_files)
+            .await?;
+    }
+    failed.check()?;
+    Ok(true)
+}
+
+// With a separate download path the files of a chunk only end up in the save path once complete,
+// so the upload client reads them from the save path qBittorrent reports. Without one the files
+// are where rpl told qBittorrent to put them, a save path qBittorrent reports differently (e.g.
+// from another machine or container) is not a path rpl can read.
+async fn follow_save_path(
+    config: &QbitTorrent,
+    torrent_client: &QbitConfig,
+    hash: &str,
+    job: &Job,
+    no_jobs: usize,
+    upload_client: &mut Box<dyn RplUploadClient>,
+) -> Result<(), error::Error> {
+    if config.download_path.is_none() {
+        return Ok(());
+    }
+    let save_path = PathBuf::from(torrent_client.get_torrent_info(hash).await?.save_path);
+    if save_path != upload_client.get_source() {
+        debug!(
+            "Uploading chunk {}/{} from qBittorrent's save path {}",
+            job.chunk,
+            no_jobs,
+            save_path.display()
+        );
+        upload_client.set_source(save_path);
+    }
+    Ok(())
+}
+
+// upload a chunk of the pipeline and free its space unless keep_files is set, the torrent keeps
+// running. A chunk that fails to upload is recorded in failed and its space freed all the same,
+// Ok(false) then.
+#[allow(clippy::too_many_arguments)]
+async fn upload_and_remove(
+    pack: &mut TorrentPack,
+    job: &Job,
+    no_jobs: usize,
+    upload_client: &dyn RplUploadClient,
+    settings: &mut LeechSettings,
+    failed: &mut leech_steps::FailedChunks,
+    events: &Events,
+    no_op: bool,
+) -> Result<bool, error::Error> {
+    let uploaded =
+        match leech_steps::upload_chunk(pack, job, no_jobs, upload_client, settings, events, no_op)
+            .await
+        {
+            Ok(()) => true,
+            Err(e) => {
+                failed.record(settings, job, no_jobs, e, events)?;
+                false
+            }
+        };
+    if !settings.keep_files {
+        job.remove_files(upload_client.get_source())?;
+    }
+    Ok(uploaded)
+}
+
+// Remove the torrent after a chunk of the pipeline failed before its upload, it may be errored so
+// the next chunk is added again. The files of a chunk still waiting for its upload are kept, only
+// the failed chunk's own files are removed then.
+async fn remove_failed(
+    torrent_client: &QbitConfig,
+    hash: &str,
+    job: &Job,
+    source: &Path,
+    settings: &LeechSettings,
+    pending: bool,
+) {
+    if let Err(e) = torrent_client
+        .delete_torrent(hash, !settings.keep_files && !pending)
+        .await
+    {
+        debug!("Could not remove the torrent of chunk {}: {}", job.chunk, e);
+    }
+    if pending && !settings.keep_files {
+        if let Err(e) = job.remove_files(source) {
+            debug!("Could not remove the files of chunk {}: {}", job.chunk, e);
+        }
     }
 }
 
 #[async_trait]
 trait RplQbit {
-    fn disable_others(&self, offset: i32, no_all_files: i32) -> Option<String>;
+    fn disable_others(&self, no_all_files: i32) -> Option<String>;
+    async fn wait_for_seeders(
+        &self,
+        client: &QbitConfig,
+        hash: &str,
+        no_jobs: usize,
+    ) -> Result<bool, error::Error>;
     async fn download(
         &self,
         client: &QbitConfig,
         hash: &str,
         no_jobs: usize,
+        events: &Events,
     ) -> Result<(), error::Error>;
 }
 
 #[async_trait]
 impl RplQbit for Job {
-    fn disable_others(&self, offset: i32, no_all_files: i32) -> Option<String> {
-        let mut disable_others = String::new();
-        for i in 0..no_all_files {
-            if i < offset || i >= offset + self.no_files {
-                disable_others.push_str(&format!("{} | ", i));
-            }
-        }
-        disable_others.truncate(disable_others.len() - 3);
+    fn disable_others(&self, no_all_files: i32) -> Option<String> {
+        let disable_others: Vec<String> = (0..no_all_files)
+            .filter(|i| !self.indices.contains(i))
+            .map(|i| i.to_string())
+            .collect();
         match disable_others.is_empty() {
-            false => Some(disable_others),
+            false => Some(disable_others.join(" | ")),
             true => None,
         }
     }
 
+    // returns false if the chunk should be skipped
+    async fn wait_for_seeders(
+        &self,
+        client: &QbitConfig,
+        hash: &str,
+        no_jobs: usize,
+    ) -> Result<bool, error::Error> {
+        if client.min_seeders == 0 {
+            return Ok(true);
+        }
+
+        // seeders are only known once the torrent is running and has announced
+        client.resume_torrent(hash).await?;
+        let pb = ProgressBar::new(client.min_seeders_timeout as u64);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} {msg} [{bar:20.cyan/blue}] ({eta_precise})")
+                .progress_chars("#>-"),
+        );
+
+        let mut waited = 0;
+        loop {
+            let info = client.get_torrent_info(hash).await?;
+            let seeders = std::cmp::max(info.num_seeds, info.num_complete);
+            if seeders >= client.min_seeders as i64 {
+                pb.finish_and_clear();
+                debug!(
+                    "Chunk {}/{} has {} seeders, starting download",
+                    self.chunk, no_jobs, seeders
+                );
+                return Ok(true);
+            }
+            if waited >= client.min_seeders_timeout {
+                pb.finish_and_clear();
+                warn!(
+                    "Chunk {}/{} only has {} seeders after {} seconds, {} required",
+                    self.chunk, no_jobs, seeders, waited, client.min_seeders
+                );
+                return match client.skip_unseeded {
+                    true => Ok(false),
+                    false => Err(error::Error::NotEnoughSeeders),
+                };
+            }
+            pb.set_message(format!(
+                "Waiting for seeders of chunk {}/{} ({}/{})",
+                self.chunk, no_jobs, seeders, client.min_seeders
+            ));
+            sleep(Duration::from_millis(1000)).await;
+            pb.inc(1);
+            waited += 1;
+        }
+    }
+
     async fn download(
         &self,
         client: &QbitConfig,
         hash: &str,
         no_jobs: usize,
+        events: &Events,
     ) -> Result<(), error::Error> {
         let mut retry = 1;
+        let mut checking_resume_data_since: Option<Instant> = None;
+        let mut last_heartbeat = Instant::now();
+        let mut poll_interval = POLL_INTERVAL;
+        let mut last_downloaded = 0;
+        // only log entries from this chunk's download count towards a disk full error
+        let mut last_log_id = client.last_log_id().await?;
         client.resume_torrent(hash).await?;
         let size = self.total_size;
 
-        let pb = ProgressBar::new(size as u64);
+        let pb = events.progress_bar(size as u64);
         pb.set_style(ProgressStyle::default_bar()
             .template("{spinner:.green} {msg} [{elapsed_precise}] [{bar:20.cyan/blue}] {bytes}/{total_bytes} [{binary_bytes_per_sec}] ({eta})")
             .progress_chars("#>-"));
@@ -614,7 +2242,41 @@ impl RplQbit for Job {
 
         loop {
             let current_info = client.get_torrent_info(hash).await?;
-            let state = current_info.state;
+            let state = current_info.state.clone();
+            let stalled = matches!(state, State::StalledDL | State::QueuedDL);
+            if !matches!(state, State::CheckingResumeData) {
+                checking_resume_data_since = None;
+            }
+            if client.heartbeat_secs > 0
+                && last_heartbeat.elapsed().as_secs() >= client.heartbeat_secs as u64
+            {
+                last_heartbeat = Instant::now();
+                let downloaded = min(size - current_info.amount_left, size);
+                info!(
+                    "Chunk {}/{} is {:?}: {}",
+                    self.chunk,
+                    no_jobs,
+                    state,
+                    util::progress_summary(
+                        max(downloaded, 0) as u64,
+                        size as u64,
+                        max(current_info.dlspeed, 0) as u64
+                    ),
+                );
+            }
+            if client.no_op
+                && matches!(
+                    state,
+                    State::Downloading | State::StalledDL | State::QueuedDL | State::ForceDL
+                )
+            {
+                pb.finish_and_clear();
+                info!(
+                    "[no-op] Chunk {}/{} reached {:?} state, not waiting for it to finish",
+                    self.chunk, no_jobs, state
+                );
+                return Ok(());
+            }
             match state {
                 State::Moving => {
                     pb.set_message(format!("Moving files of chunk {}/{}", self.chunk, no_jobs));
@@ -669,6 +2331,30 @@ impl RplQbit for Job {
                     }
                 }
                 State::Error => {
+                    // retrying does not free any space
+                    if client.is_disk_full(&current_info.name, last_log_id).await? {
+                        if client.disk_full_wait_secs == 0 {
+                            error!(
+                                "qBittorrent ran out of disk space while downloading chunk {}/{}",
+                                self.chunk, no_jobs
+                            );
+                            return Err(error::Error::InsufficientDiskSpace);
+                        }
+                        warn!(
+                            "qBittorrent ran out of disk space while downloading chunk {}/{}. Waiting up to {} seconds for space to be freed...",
+                            self.chunk, no_jobs, client.disk_full_wait_secs
+                        );
+                        pb.set_message(format!(
+                            "[Disk full] Downloading chunk {}/{}",
+                            self.chunk, no_jobs
+                        ));
+                        client
+                            .wait_for_disk_space(max(current_info.amount_left, 0) as u64)
+                            .await?;
+                        last_log_id = client.last_log_id().await?;
+                        client.resume_torrent(hash).await?;
+                        continue;
+                    }
                     if retry <= 3 {
                         warn!("qBittorrent entered Error state! Waiting 5s before retrying...");
                         sleep(Duration::from_millis(5000)).await;
@@ -721,13 +2407,30 @@ impl RplQbit for Job {
                     ));
                     pb.set_position(min(size - current_info.amount_left, size) as u64);
                 }
-                State::CheckingDL | State::CheckingResumeData => {
+                State::CheckingDL => {
                     pb.set_message(format!(
                         "[Checking] Downloading chunk {}/{}",
                         self.chunk, no_jobs
                     ));
                     pb.set_position(min(size - current_info.amount_left, size) as u64);
                 }
+                State::CheckingResumeData => {
+                    // can happen mid-run when the torrent is re-added, not only at startup
+                    let since = *checking_resume_data_since.get_or_insert_with(Instant::now);
+                    let timeout = client.checking_resume_data_timeout;
+                    if timeout > 0 && since.elapsed().as_secs() > timeout as u64 {
+                        error!(
+                            "The torrent did not leave CheckingResumeData state after {} seconds",
+                            timeout
+                        );
+                        return Err(error::Error::QbitCheckingResumeDataTimeout);
+                    }
+                    pb.set_message(format!(
+                        "[Checking resume data] Downloading chunk {}/{}",
+                        self.chunk, no_jobs
+                    ));
+                    pb.set_position(min(size - current_info.amount_left, size) as u64);
+                }
                 State::PausedUP
                 | State::StalledUP
                 | State::Uploading
@@ -736,7 +2439,220 @@ impl RplQbit for Job {
                 | State::CheckingUP => return Ok(()),
             }
 
-            sleep(Duration::from_millis(1000)).await;
+            events.send(ProgressEvent::DownloadProgress {
+                chunk: self.chunk,
+                downloaded: min(size - current_info.amount_left, size) as u64,
+                total: size as u64,
+            });
+
+            let downloaded = min(size - current_info.amount_left, size);
+            poll_interval = next_poll_interval(
+                poll_interval,
+                stalled,
+                downloaded > last_downloaded,
+                client.stalled_poll_max_secs,
+            );
+            last_downloaded = downloaded;
+            sleep(poll_interval).await;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stalled_poll() {
+        let mut interval = POLL_INTERVAL;
+        for expected in [2, 4, 8, 8] {
+            interval = next_poll_interval(interval, true, false, 8);
+            assert_eq!(interval, Duration::from_secs(expected));
+        }
+        assert_eq!(next_poll_interval(interval, true, true, 8), POLL_INTERVAL);
+        assert_eq!(next_poll_interval(interval, false, false, 8), POLL_INTERVAL);
+        assert_eq!(
+            next_poll_interval(POLL_INTERVAL, true, false, 0),
+            POLL_INTERVAL
+        );
+    }
+
+    #[test]
+    fn disk_full() {
+        assert!(is_disk_full_message(
+            "File error alert. Torrent: \"Pack\". File: \"/data/Pack/ep01.mkv\". Reason: \"Pack file_write (/data/Pack/ep01.mkv) error: No space left on device\""
+        ));
+        assert!(is_disk_full_message(
+            "file_write error: There is not enough space on the disk."
+        ));
+        assert!(!is_disk_full_message(
+            "File error alert. Reason: \"file_open error: Permission denied\""
+        ));
+    }
+
+    #[test]
+    fn login_cookie() {
+        let v4 = "SID=hBc7TxF76ERhvIw0jQQ4LZ7Z1jQUV0tQ; HttpOnly; path=/; SameSite=Strict";
+        assert!(is_session_cookie(v4));
+        assert_eq!(
+            extract_cookie(v4),
+            Some("SID=hBc7TxF76ERhvIw0jQQ4LZ7Z1jQUV0tQ")
+        );
+
+        let v5 = "QBT_SID_8080=hBc7TxF76ERhvIw0jQQ4LZ7Z1jQUV0tQ";
+        assert!(is_session_cookie(v5));
+        assert_eq!(extract_cookie(v5), Some(v5));
+
+        assert!(!is_session_cookie("lang=en; path=/"));
+        assert_eq!(extract_cookie("; path=/"), None);
+    }
+
+    #[test]
+    fn download_path_fields() {
+        let fields = QbitTorrent::default()
+            .save_path(PathBuf::from("/data/rpl"))
+            .download_path(PathBuf::from("/ssd/incomplete"))
+            .text_fields();
+        assert!(fields.contains(&("savepath", String::from("/data/rpl"))));
+        assert!(fields.contains(&("downloadPath", String::from("/ssd/incomplete"))));
+        assert!(fields.contains(&("useDownloadPath", String::from("true"))));
+    }
+
+    #[test]
+    fn skip_checking_needs_data() {
+        let dir = std::env::temp_dir().join(format!("rpl-skip-checking-{}", std::process::id()));
+        fs::create_dir_all(dir.join("Pack")).unwrap();
+        let job = Job::new(
+            1,
+            4,
+            1,
+            vec![PathBuf::from("Pack/e01.mkv")],
+            vec![0],
+            vec![4],
+        );
+        let config = QbitTorrent::default().skip_hash_checking(true);
+        let skip = ("skip_checking", String::from("true"));
+        assert!(!config.leech_add([&job], &dir).text_fields().contains(&skip));
+
+        fs::write(dir.join("Pack/e01.mkv"), b"abc").unwrap();
+        assert!(!config.leech_add([&job], &dir).text_fields().contains(&skip));
+        fs::write(dir.join("Pack/e01.mkv"), b"abcd").unwrap();
+        assert!(config.leech_add([&job], &dir).text_fields().contains(&skip));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn extra_headers() {
+        let mut headers = HashMap::new();
+        headers.insert(String::from("Authorization"), String::from("Bearer abc"));
+        let map = parse_headers(&headers).unwrap();
+        assert_eq!(map.get("authorization").unwrap(), "Bearer abc");
+
+        headers.insert(String::from("Bad Name"), String::from("x"));
+        assert!(parse_headers(&headers).is_err());
+        headers.remove("Bad Name");
+        headers.insert(String::from("X-Token"), String::from("line\nbreak"));
+        assert!(parse_headers(&headers).is_err());
+    }
+
+    #[test]
+    fn version() {
+        assert_eq!(major_version("v4.3.5"), Some(4));
+        assert_eq!(major_version("v5.0.1\n"), Some(5));
+        assert_eq!(major_version("garbage"), None);
+        assert_eq!(major_version("2.0.9.0"), Some(2));
+        assert!(version_at_least("v4.5.0", 4, 5));
+        assert!(version_at_least("v5.0.1", 4, 5));
+        assert!(!version_at_least("v4.4.5", 4, 5));
+        assert!(!version_at_least("garbage", 4, 5));
+    }
+
+    #[test]
+    fn script() {
+        let torrent = crate::librpl::fixtures::pack(&[("a.mkv", 30), ("b.mkv", 30)]);
+        let mut pack = TorrentPack::new(torrent.clone(), false).max_size(40);
+        let upload_client = RcloneClient::new(
+            String::from("rclone"),
+            PathBuf::from("/data/rpl"),
+            String::from("nugu:/rpl"),
+            8,
+            64,
+            Vec::new(),
+        );
+        let emit = |pack: &mut TorrentPack, upload_client: &RcloneClient| {
+            emit_script(
+                pack,
+                torrent.clone(),
+                &QbitTorrent::default().paused(true),
+                "torrents=@pack.torrent",
+                "http://localhost:8080",
+                "admin",
+                upload_client,
+                &SeedSettings::default(),
+                &LeechSettings::new(),
+            )
+            .unwrap()
+        };
+
+        let script = emit(&mut pack, &upload_client);
+        assert!(script.contains("-H \"Origin: ${QB_ADDRESS%/}\""));
+        assert!(script.contains("-F \"$STOPPED=true\""));
+        assert!(script.contains("qb \"torrents/$START\""));
+        assert!(!script.contains("torrents/resume"));
+        assert!(script.contains("deleteFiles=true"));
+        assert!(!script.contains("--files-from"));
+
+        let upload_client = upload_client.move_files(true).adaptive_transfers(true);
+        let script = emit(&mut pack, &upload_client);
+        assert!(script.contains("<<'RPL_FILES'\nPack/a.mkv\nRPL_FILES\n"));
+        assert!(script.contains("'--files-from' \"$FILES_FROM\""));
+        assert!(script.contains("'--transfers' '1'"));
+        assert!(script.contains("deleteFiles=false"));
+    }
+
+    #[test]
+    fn rebuild() {
+        let mut torrent = crate::librpl::fixtures::pack(&[("a.mkv", 20000), ("sub/b.mkv", 12768)]);
+        torrent.pieces = vec![vec![0xab; 20], vec![0x01; 20]];
+        let piece_hashes = vec!["ab".repeat(20), "01".repeat(20)];
+        let files = vec![
+            QbitTorrentFile {
+                name: String::from("Pack/a.mkv"),
+                size: 20000,
+            },
+            QbitTorrentFile {
+                name: String::from("Pack/sub/b.mkv"),
+                size: 12768,
+            },
+        ];
+        let rebuilt = |hash: &str| torrent_from_parts(hash, "Pack", 16384, &files, &piece_hashes);
+        assert_eq!(
+            rebuilt(&torrent.info_hash()),
+            Some(torrent.clone().encode().unwrap())
+        );
+
+        let private = vec![(String::from("private"), BencodeElem::Integer(1))];
+        torrent.extra_info_fields = Some(private.into_iter().collect());
+        let rebuilt_private = rebuilt(&torrent.info_hash()).unwrap();
+        assert_eq!(
+            Torrent::read_from_bytes(rebuilt_private)
+                .unwrap()
+                .info_hash(),
+            torrent.info_hash()
+        );
+
+        let source = vec![(
+            String::from("source"),
+            BencodeElem::String(String::from("x")),
+        )];
+        torrent.extra_info_fields = Some(source.into_iter().collect());
+        assert_eq!(rebuilt(&torrent.info_hash()), None);
+    }
+
+    #[test]
+    fn maindata() {
+        let json = r#"{"rid":1,"full_update":true,"torrents":{},"server_state":{"connection_status":"connected","dl_info_speed":0,"free_space_on_disk":53687091200,"up_info_speed":0}}"#;
+        let maindata: QbitMainData = serde_json::from_str(json).unwrap();
+        assert_eq!(maindata.server_state.free_space_on_disk, 53687091200);
+    }
+}
@@ -1,14 +1,24 @@
-use indicatif::{ProgressBar, ProgressStyle};
+use async_trait::async_trait;
+use indicatif::ProgressStyle;
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::TryInto;
+use std::fs;
 use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
-use std::process::{ChildStderr, Command, Stdio};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStderr, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crate::librpl::error;
-use crate::librpl::{Job, RplUpload};
+use crate::librpl::util;
+use crate::librpl::{Events, Job, ProgressEvent, RplUpload, RplUploadClient};
 
-// rclone copy --stats 1s --use-json-log --verbose <src> <dst> 3>&1 2>&3- | tee -a log
+// rclone copy --stats 1s --use-json-log --log-level INFO <src> <dst> 3>&1 2>&3- | tee -a log
 #[derive(Debug, Serialize, Deserialize)]
 struct RcloneCopyResp {
     level: Option<String>,
@@ -61,49 +71,408 @@ struct RcloneTransferring {
     speed_avg: Option<f32>,
 }
 
-#[derive(Debug)]
+// rclone lsjson -R --files-only <dst>
+#[derive(Debug, Deserialize)]
+struct RcloneLsJsonItem {
+    #[serde(rename = "Path")]
+    path: String,
+    #[serde(rename = "Size")]
+    size: i64,
+}
+
+// One rclone filter rule. rclone checks the rules in order and the first one matching a file
+// decides whether it is copied, so a file excluded by an earlier rule can never be included by a
+// later one.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RcloneFilter {
+    Exclude(String),
+    Include(String),
+}
+
+impl RcloneFilter {
+    // --include and --exclude must not be mixed, so every rule is passed as a --filter
+    fn to_arg(&self) -> String {
+        match self {
+            RcloneFilter::Exclude(pattern) => format!("- {}", pattern),
+            RcloneFilter::Include(pattern) => format!("+ {}", pattern),
+        }
+    }
+}
+
+// Build the ordered filter rules: files qBittorrent has not finished writing are excluded first,
+// then the user's excludes, then the user's includes. When there are includes, everything else is
+// excluded at the end like rclone's own --include does.
+pub fn build_filters(
+    incomplete_suffixes: &[String],
+    excludes: &[String],
+    includes: &[String],
+) -> Vec<RcloneFilter> {
+    let mut filters: Vec<RcloneFilter> = incomplete_suffixes
+        .iter()
+        .map(|suffix| RcloneFilter::Exclude(format!("*.{}", suffix.trim_start_matches('.'))))
+        .collect();
+    filters.extend(excludes.iter().cloned().map(RcloneFilter::Exclude));
+    filters.extend(includes.iter().cloned().map(RcloneFilter::Include));
+    if !includes.is_empty() {
+        filters.push(RcloneFilter::Exclude(String::from("**")));
+    }
+    filters
+}
+
+// Move --exclude and --include of the user's extra_custom_flags to excludes and includes so they
+// go through build_filters, rclone does not define how they combine with rpl's --filter rules.
+// Returns the other flags. --exclude-from and --include-from cannot be translated and are refused.
+pub fn split_custom_filters(
+    flags: &[String],
+    excludes: &mut Vec<String>,
+    includes: &mut Vec<String>,
+) -> Result<Vec<String>, error::Error> {
+    let mut other = Vec::new();
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_owned())),
+            None => (flag.as_str(), None),
+        };
+        let rules = match name {
+            "--exclude" => &mut *excludes,
+            "--include" => &mut *includes,
+            "--exclude-from" | "--include-from" => {
+                return Err(error::Error::UnsupportedCustomFilter(name.to_owned()))
+            }
+            _ => {
+                other.push(flag.to_owned());
+                continue;
+            }
+        };
+        match value.or_else(|| flags.next().cloned()) {
+            Some(pattern) => rules.push(pattern),
+            None => return Err(error::Error::UnsupportedCustomFilter(name.to_owned())),
+        }
+    }
+    Ok(other)
+}
+
+// What to do with files of a chunk that are already on the remote. rclone's own default (None)
+// uploads them again when their size or modification time differs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnExistingRemote {
+    // never touch a file that exists on the remote, even with a different size (--ignore-existing)
+    Skip,
+    // upload every file again (--ignore-times)
+    Overwrite,
+    // refuse to leech when any file of the pack is already on the remote
+    Error,
+}
+
+// suffix of files rclone is still uploading when atomic_upload is on
+const PARTIAL_SUFFIX: &str = ".rpl.tmp";
+
+// name of the random file uploaded by `rpl bench`
+const BENCH_FILE: &str = "rpl-bench.bin";
+
+// rclone's exit code when the directory to list does not exist
+const RCLONE_DIR_NOT_FOUND: i32 = 3;
+
+// the last error rclone logged, or how it exited when it logged none
+fn upload_failure(code: Option<i32>, last_error: Option<String>) -> String {
+    match (last_error, code) {
+        (Some(msg), _) => msg.trim().to_owned(),
+        (None, Some(code)) => format!("exited with code {}", code),
+        (None, None) => String::from("killed by a signal"),
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct RcloneClient {
     pub variant: String,
     source: PathBuf,
     destination: String,
     transfers: u16,
+    // pick the transfers of every chunk from its file sizes, with transfers as the ceiling
+    adaptive_transfers: bool,
+    checkers: Option<u16>,
+    retries: Option<u32>,
+    low_level_retries: Option<u32>,
     drive_chunk_size: u16,
+    // pass the Google Drive only flags, off for every other kind of remote
+    drive_flags: bool,
     extra_custom_flags: Vec<String>,
+    filters: Vec<RcloneFilter>,
+    atomic_upload: bool,
+    preserve_mtime: bool,
+    user_agent: Option<String>,
+    // rclone's --bwlimit, a rate like 10M or a timetable like "08:00,512k 23:00,off"
+    bwlimit: Option<String>,
+    drive_service_account_file: Option<PathBuf>,
+    log_level: String,
+    dry_run: bool,
+    own_process_group: bool,
+    on_existing: Option<OnExistingRemote>,
+    // times a failed upload is started again before giving up
+    upload_retries: u32,
+    // log the upload progress every this many seconds (0 to disable)
+    progress_log_secs: u32,
+    // rclone move instead of rclone copy
+    move_files: bool,
+    // pid of the upload in flight, shared between clones so it can be interrupted from elsewhere
+    running: Arc<Mutex<Option<u32>>>,
+    // set by interrupt, an interrupted upload is not retried
+    interrupted: Arc<AtomicBool>,
+}
+
+// wait before the first retry of a failed upload, doubled for every retry after it
+const UPLOAD_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+// average file sizes of the chunks adaptive_transfers treats as small and large files
+const SMALL_FILE_SIZE: i64 = 16 * 1024 * 1024;
+const LARGE_FILE_SIZE: i64 = 1024 * 1024 * 1024;
+
+// The transfers of a chunk with adaptive_transfers, the configured transfers being the ceiling.
+// Small files are bound by the per-file overhead of the remote so they get every transfer, large
+// files fill the link with a few streams (each one buffering drive_chunk_size in memory) so they
+// get a quarter of them, anything in between half. Never more transfers than files.
+fn adaptive_transfers(job: &Job, ceiling: u16) -> u16 {
+    let transfers = match job.average_file_size() {
+        size if size < SMALL_FILE_SIZE => ceiling,
+        size if size >= LARGE_FILE_SIZE => ceiling / 4,
+        _ => ceiling / 2,
+    };
+    let files: u16 = job.no_files.try_into().unwrap_or(u16::MAX);
+    transfers.min(files).max(1)
 }
 
 impl RplUpload for Job {
-    fn upload(&self, client: &RcloneClient, no_jobs: usize) -> Result<(), error::Error> {
-        let stderr = client.build_stderr_capture(&client.extra_custom_flags)?;
-        let reader = BufReader::new(stderr);
-
-        let pb = ProgressBar::new(
-            self.total_size
-                .try_into()
-                .expect("Torrent size is negative?"),
-        );
-        pb.set_style(ProgressStyle::default_bar()
-            .template("{spinner:.green} {msg} [{elapsed_precise}] [{bar:20.cyan/blue}] {bytes}/{total_bytes} [{binary_bytes_per_sec}] ({eta})")
-            .progress_chars("#>-"));
-
-        pb.set_message(format!("Waiting for {}", client.variant));
-
-        reader
-            .lines()
-            .filter_map(|line| line.ok())
-            .filter(|line| line.contains("ETA"))
-            .for_each(|line| {
-                let resp: RcloneCopyResp = serde_json::from_str(&line).unwrap();
-                if let Some(stats) = resp.stats {
-                    if let Some(speed) = stats.speed {
-                        if speed > 0f64 {
-                            pb.set_message(format!("Uploading chunk {}/{}", self.chunk, no_jobs));
-                            pb.set_position(stats.bytes);
-                        }
+    fn upload(
+        &self,
+        client: &RcloneClient,
+        no_jobs: usize,
+        events: &Events,
+    ) -> Result<(), error::Error> {
+        // rclone move deletes whatever it uploads, so it only gets the files of the chunk rather
+        // than everything in source
+        let files_from = match client.move_files {
+            true => {
+                let path = std::env::temp_dir().join(format!(
+                    "rpl-files-from-{}-{}",
+                    std::process::id(),
+                    self.chunk
+                ));
+                fs::write(&path, files_from(self))?;
+                Some(path)
+            }
+            false => None,
+        };
+        let extra_args = client.chunk_args(files_from.as_deref());
+        let mut attempt = 0;
+        let result = loop {
+            let (e, retryable) = match upload_once(self, client, &extra_args, no_jobs, events) {
+                Ok(()) => break Ok(()),
+                Err(failure) => failure,
+            };
+            if !retryable
+                || attempt >= client.upload_retries
+                || client.interrupted.load(Ordering::SeqCst)
+            {
+                break Err(e);
+            }
+            let delay = UPLOAD_RETRY_DELAY * 2u32.pow(attempt);
+            attempt += 1;
+            warn!(
+                "{}, retrying in {}s ({}/{})",
+                e,
+                delay.as_secs(),
+                attempt,
+                client.upload_retries
+            );
+            std::thread::sleep(delay);
+        };
+        if let Some(path) = files_from {
+            let _ = fs::remove_file(path);
+        }
+        result
+    }
+}
+
+// the rclone variant is on PATH (or is a path to an executable file), shared with
+// `rpl config validate`
+pub fn check_installed(variant: &str) -> Result<(), error::Error> {
+    match util::find_in_path(variant) {
+        Some(path) => {
+            debug!("{} is {}", variant, path.display());
+            Ok(())
+        }
+        None => Err(error::Error::UploadClientNotFound(variant.to_owned())),
+    }
+}
+
+// the --files-from list of a chunk: its files relative to source, one per line
+pub fn files_from(job: &Job) -> String {
+    job.files
+        .iter()
+        .map(|file| format!("{}\n", file.to_string_lossy()))
+        .collect()
+}
+
+// run rclone once for the chunk. A failure is worth another run unless rclone reported a fatal
+// error, rclone is stopped right away then, or its last errors were not retryable (retryError)
+fn upload_once(
+    job: &Job,
+    client: &RcloneClient,
+    extra_args: &[String],
+    no_jobs: usize,
+    events: &Events,
+) -> Result<(), (error::Error, bool)> {
+    let (mut child, stderr) = client
+        .build_stderr_capture(extra_args)
+        .map_err(|e| (e, false))?;
+    let reader = BufReader::new(stderr);
+
+    let pb = events.progress_bar(
+        job.total_size
+            .try_into()
+            .expect("Torrent size is negative?"),
+    );
+    pb.set_style(ProgressStyle::default_bar()
+        .template("{spinner:.green} {msg} [{elapsed_precise}] [{bar:20.cyan/blue}] {bytes}/{total_bytes} [{binary_bytes_per_sec}] ({eta})")
+        .progress_chars("#>-"));
+
+    pb.set_message(format!("Waiting for {}", client.variant));
+
+    let mut last_error: Option<String> = None;
+    // rclone's own verdict on the errors so far, e.g. a missing source file is not retryable
+    let mut retryable = true;
+    let mut last_log = Instant::now();
+    for line in reader.lines().map_while(|line| line.ok()) {
+        let resp: RcloneCopyResp = match serde_json::from_str(&line) {
+            Ok(resp) => resp,
+            Err(e) => {
+                debug!(
+                    "Skipped a line of {} output ({}): {}",
+                    client.variant, e, line
+                );
+                continue;
+            }
+        };
+        if resp.level.as_deref() == Some("error") {
+            last_error = resp.msg;
+        }
+        if let Some(stats) = resp.stats {
+            // rclone keeps going after a fatal error (e.g. a quota that is used up) but nothing
+            // more gets uploaded, retrying cannot help either
+            if stats.fatal_error.unwrap_or(false) {
+                let _ = child.kill();
+                let _ = child.wait();
+                *client.running.lock().unwrap() = None;
+                pb.finish_and_clear();
+                let message = match last_error {
+                    Some(msg) => msg.trim().to_owned(),
+                    None => String::from("no error message"),
+                };
+                return Err((error::Error::RcloneFatalError(message), false));
+            }
+            if stats.errors.unwrap_or(0) > 0 {
+                retryable = stats.retry_error.unwrap_or(true);
+            }
+            if let Some(speed) = stats.speed {
+                if speed > 0f64 {
+                    pb.set_message(format!("Uploading chunk {}/{}", job.chunk, no_jobs));
+                    pb.set_position(stats.bytes);
+                    if client.progress_log_secs > 0
+                        && last_log.elapsed().as_secs() >= client.progress_log_secs as u64
+                    {
+                        last_log = Instant::now();
+                        info!(
+                            "Chunk {}/{} uploading: {}",
+                            job.chunk,
+                            no_jobs,
+                            util::progress_summary(
+                                stats.bytes,
+                                job.total_size as u64,
+                                speed as u64
+                            )
+                        );
                     }
+                    events.send(ProgressEvent::UploadProgress {
+                        chunk: job.chunk,
+                        uploaded: stats.bytes,
+                        total: job.total_size as u64,
+                    });
                 }
-            });
+            }
+        }
+    }
+    // rclone exits non-zero when some files are still not on the remote after every retry,
+    // the chunk must not count as uploaded then
+    let status = child.wait().map_err(|e| (e.into(), false))?;
+    *client.running.lock().unwrap() = None;
+    if !status.success() {
+        pb.finish_and_clear();
+        if !retryable {
+            debug!("{} reported its errors as not retryable", client.variant);
+        }
+        return Err((
+            error::Error::RcloneUploadFailed(job.chunk, upload_failure(status.code(), last_error)),
+            retryable,
+        ));
+    }
+
+    Ok(())
+}
+
+#[async_trait]
+impl RplUploadClient for RcloneClient {
+    fn name(&self) -> &str {
+        &self.variant
+    }
+
+    fn get_source(&self) -> &Path {
+        &self.source
+    }
+
+    fn set_source(&mut self, source: PathBuf) {
+        self.source = source;
+    }
+
+    fn get_destination(&self) -> &str {
+        &self.destination
+    }
+
+    fn get_on_existing_remote(&self) -> Option<OnExistingRemote> {
+        self.on_existing
+    }
+
+    async fn list_remote(
+        &self,
+        path: &str,
+        is_file: bool,
+    ) -> Result<HashMap<PathBuf, i64>, error::Error> {
+        RcloneClient::list_remote(self, path, is_file)
+    }
+
+    async fn upload_chunk(
+        &self,
+        job: &Job,
+        no_jobs: usize,
+        events: &Events,
+    ) -> Result<(), error::Error> {
+        // rclone is read on a blocking thread so signals are still handled while it uploads
+        let (job, client, events) = (job.clone(), self.for_chunk(job, no_jobs), events.clone());
+        tokio::task::spawn_blocking(move || job.upload(&client, no_jobs, &events))
+            .await
+            .expect("The upload task panicked")
+    }
+
+    fn moves_source(&self) -> bool {
+        self.move_files
+    }
 
-        Ok(())
+    fn interrupt(&self) {
+        RcloneClient::interrupt(self)
+    }
+
+    fn boxed(&self) -> Box<dyn RplUploadClient> {
+        Box::new(self.clone())
     }
 }
 
@@ -121,36 +490,365 @@ impl RcloneClient {
             source,
             destination,
             transfers,
+            adaptive_transfers: false,
+            checkers: None,
+            retries: None,
+            low_level_retries: None,
             drive_chunk_size,
+            drive_flags: true,
             extra_custom_flags,
+            filters: build_filters(&[String::from("parts"), String::from("!qB")], &[], &[]),
+            atomic_upload: false,
+            preserve_mtime: false,
+            user_agent: None,
+            bwlimit: None,
+            drive_service_account_file: None,
+            log_level: String::from("INFO"),
+            dry_run: false,
+            own_process_group: false,
+            on_existing: None,
+            upload_retries: 0,
+            progress_log_secs: 0,
+            move_files: false,
+            running: Arc::new(Mutex::new(None)),
+            interrupted: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    // TODO: implement a trait instead of hardcoding for qbittorrent like this
-    fn build_stderr_capture(&self, extra_args: &[String]) -> Result<ChildStderr, error::Error> {
-        let stderr = Command::new(self.variant.to_owned())
-            .arg("copy")
-            .arg("--exclude")
-            .arg("*.parts")
-            .arg("--exclude")
-            .arg("*.!qB")
-            .arg("--verbose")
-            .arg("--stats")
-            .arg("1s")
-            .arg("--use-json-log")
-            .arg("--transfers")
-            .arg(self.transfers.to_string())
-            .arg("--drive-chunk-size")
-            .arg(format!("{}M", self.drive_chunk_size))
-            .args(extra_args)
-            .arg(&self.source.to_str().unwrap())
+    // On remotes that support it (local, sftp, smb, ...) rclone uploads every file under a
+    // temporary name and renames it once complete. When true rpl names these files
+    // <name>.rpl.tmp (passes --partial-suffix, rclone >= 1.64), when false nothing is passed and
+    // the rclone variant keeps its own behaviour.
+    pub fn atomic_upload(mut self, atomic: bool) -> Self {
+        self.atomic_upload = atomic;
+        self
+    }
+
+    // see adaptive_transfers
+    pub fn adaptive_transfers(mut self, adaptive: bool) -> Self {
+        self.adaptive_transfers = adaptive;
+        self
+    }
+
+    // pass --checkers, rclone's own default is used when this is None
+    pub fn checkers(mut self, checkers: Option<u16>) -> Self {
+        self.checkers = checkers;
+        self
+    }
+
+    // pass --retries and --low-level-retries, rclone's own defaults are used when these are None
+    pub fn retries(mut self, retries: Option<u32>, low_level_retries: Option<u32>) -> Self {
+        self.retries = retries;
+        self.low_level_retries = low_level_retries;
+        self
+    }
+
+    // filter rules passed to rclone, in order, before any custom flag
+    pub fn filters(mut self, filters: Vec<RcloneFilter>) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    // start rclone in a process group of its own, so a Ctrl-C in the terminal does not stop the
+    // upload in flight (unix only)
+    pub fn own_process_group(mut self, own: bool) -> Self {
+        self.own_process_group = own;
+        self
+    }
+
+    // start a failed upload again up to this many times, waiting longer before every retry.
+    // rclone's own --retries only retry within one run, this also covers rclone exiting early
+    pub fn upload_retries(mut self, retries: u32) -> Self {
+        self.upload_retries = retries;
+        self
+    }
+
+    // log the percentage, speed and ETA of the upload every secs seconds, for logs captured with
+    // --log where the progress bars do not show (0 to disable)
+    pub fn progress_log(mut self, secs: u32) -> Self {
+        self.progress_log_secs = secs;
+        self
+    }
+
+    // upload with rclone move, which deletes every local file once it is on the remote, instead
+    // of rclone copy
+    pub fn move_files(mut self, move_files: bool) -> Self {
+        self.move_files = move_files;
+        self
+    }
+
+    // send SIGINT to the upload in flight, if any
+    pub fn interrupt(&self) {
+        self.interrupted.store(true, Ordering::SeqCst);
+        let pid = match *self.running.lock().unwrap() {
+            Some(pid) => pid,
+            None => return,
+        };
+        match Command::new("kill")
+            .args(["-INT", &pid.to_string()])
+            .status()
+        {
+            Ok(status) if status.success() => debug!("Interrupted {} (pid {})", self.variant, pid),
+            Ok(_) => debug!("{} (pid {}) has already exited", self.variant, pid),
+            Err(e) => warn!("Could not interrupt {} (pid {}): {}", self.variant, pid, e),
+        }
+    }
+
+    // how files already on the remote are treated, rclone's default is used when this is None
+    pub fn on_existing_remote(mut self, on_existing: Option<OnExistingRemote>) -> Self {
+        self.on_existing = on_existing;
+        self
+    }
+
+    pub fn get_on_existing_remote(&self) -> Option<OnExistingRemote> {
+        self.on_existing
+    }
+
+    // pass --dry-run so rclone goes through the upload without copying anything
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    // rclone's own --log-level, one of DEBUG, INFO, NOTICE or ERROR
+    pub fn log_level(mut self, level: String) -> Self {
+        self.log_level = level;
+        self
+    }
+
+    // pass --metadata so rclone keeps modification times (and other metadata) where the remote
+    // supports it
+    pub fn preserve_mtime(mut self, preserve: bool) -> Self {
+        self.preserve_mtime = preserve;
+        self
+    }
+
+    // pass --user-agent, rclone's own user agent is used when this is None
+    pub fn user_agent(mut self, user_agent: Option<String>) -> Self {
+        self.user_agent = user_agent;
+        self
+    }
+
+    // pass --bwlimit, uploads are not limited when this is None
+    pub fn bwlimit(mut self, bwlimit: Option<String>) -> Self {
+        self.bwlimit = bwlimit.filter(|limit| !limit.trim().is_empty());
+        self
+    }
+
+    // pass --drive-chunk-size and --drive-service-account-file, only true when the remote is a
+    // Google Drive
+    pub fn drive_flags(mut self, drive: bool) -> Self {
+        self.drive_flags = drive;
+        self
+    }
+
+    // pass --drive-service-account-file, so every run can upload with a different service
+    // account of a rotation
+    pub fn drive_service_account_file(mut self, file: Option<PathBuf>) -> Self {
+        self.drive_service_account_file = file;
+        self
+    }
+
+    pub fn get_destination(&self) -> &str {
+        &self.destination
+    }
+
+    pub fn get_source(&self) -> &Path {
+        &self.source
+    }
+
+    pub fn destination(mut self, destination: &str) -> Self {
+        self.destination = String::from(destination);
+        self
+    }
+
+    pub fn source(mut self, source: PathBuf) -> Self {
+        self.source = source;
+        self
+    }
+
+    // upload a file of size bytes of random data from source to destination through the same
+    // path as a chunk and return how long it took. The local file is removed afterwards.
+    pub fn bench(&self, size: u64) -> Result<Duration, error::Error> {
+        std::fs::create_dir_all(&self.source)?;
+        let file = PathBuf::from(BENCH_FILE);
+        util::write_random_file(&self.source.join(&file), size)?;
+
+        let job = Job::new(1, size as i64, 1, vec![file], vec![0], vec![size as i64]);
+        let started = Instant::now();
+        let result = job.upload(self, 1, &Events::default());
+        let elapsed = started.elapsed();
+        std::fs::remove_dir_all(&self.source)?;
+        result.map(|_| elapsed)
+    }
+
+    // delete destination and everything under it
+    pub fn purge_destination(&self) -> Result<(), error::Error> {
+        let status = Command::new(&self.variant)
+            .arg("purge")
             .arg(&self.destination)
-            .stderr(Stdio::piped())
-            .spawn()?
-            .stderr;
+            .status()?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(error::Error::RclonePurgeError(self.destination.to_owned())),
+        }
+    }
+
+    // TODO: implement a trait instead of hardcoding for qbittorrent like this
+    pub fn build_args(&self, extra_args: &[String]) -> Vec<String> {
+        let mut args: Vec<String> = vec![String::from(match self.move_files {
+            true => "move",
+            false => "copy",
+        })];
+        for filter in &self.filters {
+            args.push(String::from("--filter"));
+            args.push(filter.to_arg());
+        }
+        args.extend(vec![
+            String::from("--log-level"),
+            self.log_level.to_owned(),
+            String::from("--stats"),
+            String::from("1s"),
+            // the upload progress is read from the stats, which rclone logs at INFO by default
+            String::from("--stats-log-level"),
+            match self.log_level.as_str() {
+                "NOTICE" | "ERROR" => self.log_level.to_owned(),
+                _ => String::from("INFO"),
+            },
+            String::from("--use-json-log"),
+            String::from("--transfers"),
+            self.transfers.to_string(),
+        ]);
+        if self.drive_flags {
+            args.push(String::from("--drive-chunk-size"));
+            args.push(format!("{}M", self.drive_chunk_size));
+        }
+        if self.atomic_upload {
+            args.push(String::from("--partial-suffix"));
+            args.push(String::from(PARTIAL_SUFFIX));
+        }
+        if let Some(checkers) = self.checkers {
+            args.push(String::from("--checkers"));
+            args.push(checkers.to_string());
+        }
+        if let Some(retries) = self.retries {
+            args.push(String::from("--retries"));
+            args.push(retries.to_string());
+        }
+        if let Some(retries) = self.low_level_retries {
+            args.push(String::from("--low-level-retries"));
+            args.push(retries.to_string());
+        }
+        if self.preserve_mtime {
+            args.push(String::from("--metadata"));
+        }
+        match self.on_existing {
+            Some(OnExistingRemote::Skip) => args.push(String::from("--ignore-existing")),
+            Some(OnExistingRemote::Overwrite) => args.push(String::from("--ignore-times")),
+            Some(OnExistingRemote::Error) | None => (),
+        }
+        if let Some(user_agent) = &self.user_agent {
+            args.push(String::from("--user-agent"));
+            args.push(user_agent.to_owned());
+        }
+        if let Some(bwlimit) = &self.bwlimit {
+            args.push(String::from("--bwlimit"));
+            args.push(bwlimit.trim().to_owned());
+        }
+        if let (true, Some(file)) = (self.drive_flags, &self.drive_service_account_file) {
+            args.push(String::from("--drive-service-account-file"));
+            args.push(file.to_string_lossy().into_owned());
+        }
+        if self.dry_run {
+            args.push(String::from("--dry-run"));
+        }
+        args.extend(extra_args.iter().cloned());
+        args.push(String::from(self.source.to_str().unwrap()));
+        args.push(self.destination.to_owned());
+        args
+    }
 
-        match stderr {
-            Some(stderr) => Ok(stderr),
+    // the client that uploads the chunk, with its own transfers under adaptive_transfers
+    pub fn for_chunk(&self, job: &Job, no_jobs: usize) -> RcloneClient {
+        let mut client = self.clone();
+        if self.adaptive_transfers {
+            client.transfers = adaptive_transfers(job, self.transfers);
+            debug!(
+                "Uploading chunk {}/{} with {} transfers",
+                job.chunk, no_jobs, client.transfers
+            );
+        }
+        client
+    }
+
+    pub fn extra_custom_flags(&self) -> &[String] {
+        &self.extra_custom_flags
+    }
+
+    // the custom flags of a chunk's upload, limited to the files listed in files_from if any
+    pub fn chunk_args(&self, files_from: Option<&Path>) -> Vec<String> {
+        let mut args = self.extra_custom_flags.clone();
+        if let Some(path) = files_from {
+            args.push(String::from("--files-from"));
+            args.push(path.to_string_lossy().into_owned());
+        }
+        args
+    }
+
+    // every file under <destination>/<path> with its size, keyed by its path relative to the
+    // destination. A path that does not exist on the remote lists as empty. is_file must be set
+    // when path is a single file rather than a directory.
+    pub fn list_remote(
+        &self,
+        path: &str,
+        is_file: bool,
+    ) -> Result<HashMap<PathBuf, i64>, error::Error> {
+        let target = format!("{}/{}", self.destination.trim_end_matches('/'), path);
+        let output = Command::new(&self.variant)
+            .args(["lsjson", "-R", "--files-only"])
+            .arg(&target)
+            .output()?;
+        if output.status.code() == Some(RCLONE_DIR_NOT_FOUND) {
+            return Ok(HashMap::new());
+        }
+        if !output.status.success() {
+            return Err(error::Error::RcloneListError(target));
+        }
+
+        let items: Vec<RcloneLsJsonItem> = serde_json::from_slice(&output.stdout)?;
+        Ok(items
+            .into_iter()
+            // listing a single file gives its name rather than a path under it
+            .map(|item| match is_file {
+                true => (PathBuf::from(path), item.size),
+                false => (Path::new(path).join(item.path), item.size),
+            })
+            .collect())
+    }
+
+    // a variant missing from PATH fails the run before anything is downloaded, instead of when
+    // the first chunk is uploaded
+    pub fn check_installed(&self) -> Result<(), error::Error> {
+        check_installed(&self.variant)
+    }
+
+    fn build_stderr_capture(
+        &self,
+        extra_args: &[String],
+    ) -> Result<(Child, ChildStderr), error::Error> {
+        let mut command = Command::new(&self.variant);
+        command
+            .args(self.build_args(extra_args))
+            .stderr(Stdio::piped());
+        #[cfg(unix)]
+        if self.own_process_group {
+            command.process_group(0);
+        }
+        let mut child = command.spawn()?;
+        *self.running.lock().unwrap() = Some(child.id());
+
+        match child.stderr.take() {
+            Some(stderr) => Ok((child, stderr)),
             None => Err(error::Error::RcloneStderrCaptureError),
         }
     }
@@ -160,60 +858,195 @@ impl RcloneClient {
 mod tests {
     use super::*;
 
+    #[test]
+    fn custom_filters() {
+        let flags: Vec<String> = ["--exclude", "*.exe", "--fast-list", "--include=*.mkv"]
+            .iter()
+            .map(|flag| flag.to_string())
+            .collect();
+        let mut excludes = vec![String::from("*.nfo")];
+        let mut includes = Vec::new();
+        let other = split_custom_filters(&flags, &mut excludes, &mut includes).unwrap();
+        assert_eq!(other, ["--fast-list"]);
+        assert_eq!(excludes, ["*.nfo", "*.exe"]);
+        assert_eq!(includes, ["*.mkv"]);
+        for flags in [vec!["--exclude-from", "list.txt"], vec!["--exclude"]] {
+            let flags: Vec<String> = flags.iter().map(|flag| flag.to_string()).collect();
+            assert!(matches!(
+                split_custom_filters(&flags, &mut excludes, &mut includes),
+                Err(error::Error::UnsupportedCustomFilter(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn filter_order() {
+        let client = RcloneClient::new(
+            String::from("rclone"),
+            PathBuf::from("/data/rpl"),
+            String::from("nugu:/rpl"),
+            8,
+            64,
+            vec![String::from("--fast-list")],
+        )
+        .filters(build_filters(
+            &[String::from("parts"), String::from(".!qB")],
+            &[String::from("*.nfo")],
+            &[String::from("*.mkv")],
+        ));
+        let args = client.build_args(client.extra_custom_flags());
+        let filters: Vec<&str> = args
+            .windows(2)
+            .filter(|pair| pair[0] == "--filter")
+            .map(|pair| pair[1].as_str())
+            .collect();
+        assert_eq!(
+            filters,
+            vec!["- *.parts", "- *.!qB", "- *.nfo", "+ *.mkv", "- **"]
+        );
+        // custom flags come after rpl's filters
+        let custom = args.iter().position(|arg| arg == "--fast-list").unwrap();
+        let last_filter = args.iter().rposition(|arg| arg == "--filter").unwrap();
+        assert!(custom > last_filter);
+        assert_eq!(args[0], "copy");
+
+        assert!(args
+            .windows(2)
+            .any(|pair| pair == ["--drive-chunk-size", "64M"]));
+
+        // older rclone, fclone and gclone know neither flag
+        assert!(!args
+            .iter()
+            .any(|arg| arg == "--partial-suffix" || arg == "--inplace"));
+        let atomic = client.clone().atomic_upload(true).build_args(&[]);
+        assert!(atomic
+            .windows(2)
+            .any(|pair| pair == ["--partial-suffix", ".rpl.tmp"]));
+
+        let client = client.move_files(true);
+        assert_eq!(client.build_args(&[])[0], "move");
+        let job = Job::new(
+            2,
+            30,
+            2,
+            vec![PathBuf::from("Pack/e01.mkv"), PathBuf::from("Pack/e02.mkv")],
+            vec![0, 1],
+            vec![10, 20],
+        );
+        assert_eq!(files_from(&job), "Pack/e01.mkv\nPack/e02.mkv\n");
+        let args = client.chunk_args(Some(Path::new("/tmp/rpl-files-from")));
+        assert!(args
+            .windows(2)
+            .any(|pair| pair == ["--files-from", "/tmp/rpl-files-from"]));
+        assert_eq!(args[0], "--fast-list");
+
+        assert!(!client.build_args(&[]).contains(&String::from("--bwlimit")));
+        let client = client.bwlimit(Some(String::from(" ")));
+        assert!(!client.build_args(&[]).contains(&String::from("--bwlimit")));
+        let client = client.bwlimit(Some(String::from("08:00,512k 23:00,off")));
+        assert!(client
+            .build_args(&[])
+            .windows(2)
+            .any(|pair| pair == ["--bwlimit", "08:00,512k 23:00,off"]));
+
+        let client = client
+            .drive_service_account_file(Some(PathBuf::from("/sa/1.json")))
+            .drive_flags(false);
+        assert!(!client
+            .build_args(&[])
+            .iter()
+            .any(|arg| arg.starts_with("--drive")));
+    }
+
+    #[test]
+    fn transfers_by_file_size() {
+        const MIB: i64 = 1024 * 1024;
+        let job = |lengths: Vec<i64>| {
+            let files = (0..lengths.len())
+                .map(|i| PathBuf::from(i.to_string()))
+                .collect();
+            let indices = (0..lengths.len() as i32).collect();
+            Job::new(
+                1,
+                lengths.iter().sum(),
+                lengths.len() as i32,
+                files,
+                indices,
+                lengths,
+            )
+        };
+        assert_eq!(adaptive_transfers(&job(vec![MIB; 100]), 8), 8);
+        assert_eq!(adaptive_transfers(&job(vec![100 * MIB; 10]), 8), 4);
+        assert_eq!(adaptive_transfers(&job(vec![4096 * MIB; 10]), 8), 2);
+        // never more transfers than files, and at least one
+        assert_eq!(adaptive_transfers(&job(vec![MIB, MIB, 0]), 8), 3);
+        assert_eq!(adaptive_transfers(&job(vec![4096 * MIB]), 2), 1);
+    }
+
+    #[test]
+    fn missing_variant() {
+        let client = RcloneClient::new(
+            String::from("rpl-missing-rclone"),
+            PathBuf::from("/data/rpl"),
+            String::from("nugu:/rpl"),
+            8,
+            64,
+            Vec::new(),
+        );
+        assert!(matches!(
+            client.check_installed(),
+            Err(error::Error::UploadClientNotFound(variant)) if variant == "rpl-missing-rclone"
+        ));
+    }
+
+    #[test]
+    fn failure_reason() {
+        assert_eq!(upload_failure(Some(1), None), "exited with code 1");
+        assert_eq!(upload_failure(None, None), "killed by a signal");
+        let error = r#"{"level":"error","msg":"a.mkv: Failed to copy: googleapi: Error 403: User rate limit exceeded\n","source":"operations/copy.go:332","time":"2021-06-07T08:38:55.370816+07:00"}"#;
+        let resp: RcloneCopyResp = serde_json::from_str(error).unwrap();
+        assert_eq!(
+            upload_failure(Some(7), resp.msg),
+            "a.mkv: Failed to copy: googleapi: Error 403: User rate limit exceeded"
+        );
+    }
+
     #[test]
     fn deser() {
         let limiter_json = r#"{"level":"info","msg":"Starting bandwidth limiter at 5MBytes/s","source":"accounting/token_bucket.go:95","time":"2021-06-07T08:38:21.80782+07:00"}"#;
 
-        match limiter_json.find("ETA") {
-            Some(_pos) => {
-                let _resp: RcloneCopyResp = serde_json::from_str(limiter_json).unwrap();
-            }
-            None => (),
+        if limiter_json.contains("ETA") {
+            let _resp: RcloneCopyResp = serde_json::from_str(limiter_json).unwrap();
         }
 
         let waiting_json = r#"{"level":"info","msg":"\nTransferred:   \t         0 / 0 Bytes, -, 0 Bytes/s, ETA -\nTransferred:            0 / 1, 0%\nElapsed time:         5.3s\nTransferring:\n *                                   brazjson.7z: transferring\n\n","source":"accounting/stats.go:417","stats":{"bytes":0,"checks":0,"deletedDirs":0,"deletes":0,"elapsedTime":5.320457947,"errors":0,"eta":null,"fatalError":false,"renames":0,"retryError":false,"speed":0,"totalBytes":0,"totalChecks":0,"totalTransfers":1,"transferTime":3.419050329,"transferring":[{"name":"brazjson.7z","size":14067793}],"transfers":0},"time":"2021-06-07T08:38:27.083348+07:00"}"#;
 
-        match waiting_json.find("ETA") {
-            Some(_pos) => {
-                let _resp: RcloneCopyResp = serde_json::from_str(waiting_json).unwrap();
-            }
-            None => (),
+        if waiting_json.contains("ETA") {
+            let _resp: RcloneCopyResp = serde_json::from_str(waiting_json).unwrap();
         }
 
         let transferring_json = r#"{"level":"info","msg":"\nTransferred:   \t   11.996M / 13.416 MBytes, 89%, 390.982 kBytes/s, ETA 3s\nTransferred:            0 / 1, 0%\nElapsed time:        33.3s\nTransferring:\n *                                   brazjson.7z: 89% /13.416M, 6.991M/s, 0s\n\n","source":"accounting/stats.go:417","stats":{"bytes":12578816,"checks":0,"deletedDirs":0,"deletes":0,"elapsedTime":33.319626717,"errors":0,"eta":3,"fatalError":false,"renames":0,"retryError":false,"speed":400366.9062339992,"totalBytes":14067793,"totalChecks":0,"totalTransfers":1,"transferTime":31.418221147,"transferring":[{"bytes":12578816,"eta":0,"group":"global_stats","name":"brazjson.7z","percentage":89,"size":14067793,"speed":6519462.239440185,"speedAvg":7330192.215314117}],"transfers":0},"time":"2021-06-07T08:38:55.082601+07:00"}"#;
 
-        match transferring_json.find("ETA") {
-            Some(_pos) => {
-                let _resp: RcloneCopyResp = serde_json::from_str(transferring_json).unwrap();
-            }
-            None => (),
+        if transferring_json.contains("ETA") {
+            let _resp: RcloneCopyResp = serde_json::from_str(transferring_json).unwrap();
         }
 
         let finished_json = r#"{"level":"info","msg":"\nTransferred:   \t   13.416M / 13.416 MBytes, 100%, 433.291 kBytes/s, ETA 0s\nTransferred:            1 / 1, 100%\nElapsed time:        33.6s\n\n","source":"accounting/stats.go:417","stats":{"bytes":14067793,"checks":0,"deletedDirs":0,"deletes":0,"elapsedTime":33.607828572,"errors":0,"eta":0,"fatalError":false,"renames":0,"retryError":false,"speed":443690.55219237105,"totalBytes":14067793,"totalChecks":0,"totalTransfers":1,"transferTime":31.706316329,"transfers":1},"time":"2021-06-07T08:38:55.370816+07:00"}"#;
 
-        match finished_json.find("ETA") {
-            Some(_pos) => {
-                let _resp: RcloneCopyResp = serde_json::from_str(finished_json).unwrap();
-            }
-            None => (),
+        if finished_json.contains("ETA") {
+            let _resp: RcloneCopyResp = serde_json::from_str(finished_json).unwrap();
         }
 
         let gclone_json = r#"{"level":"info","msg":"\nTransferred:   \t         0 / 26.353 MBytes, 0%, 0 Bytes/s, ETA -\nTransferred:            0 / 6, 0%, 0.00 Files/s\nElapsed time:         1.4s\nTransferring:\n * MP3-daily-2021-June-11…-keep_looking_down.mp3:  0% /8.424M, 0/s, -\n * MP3-daily-2021-June-11…king_down-web-2021.m3u:  0% /96, 0/s, -\n * MP3-daily-2021-June-11…king_down-web-2021.nfo:  0% /813, 0/s, -\n * MP3-daily-2021-June-11…own-web-2021-cover.jpg:  0% /1.567M, 0/s, -\n\n","source":"accounting/stats.go:388","stats":{"bytes":0,"checks":0,"deletes":0,"elapsedTime":1.479027341,"errors":0,"fatalError":false,"renames":0,"retryError":false,"speed":0,"transferring":[{"bytes":0,"eta":null,"group":"global_stats","name":"MP3-daily-2021-June-11-Synthpop/Ritual_Veil-Keep_Looking_Down-WEB-2021-AMOK/00-ritual_veil-keep_looking_down-web-2021-cover.jpg","percentage":0,"size":1643197,"speed":0,"speedAvg":0},{"bytes":0,"eta":null,"group":"global_stats","name":"MP3-daily-2021-June-11-Synthpop/Ritual_Veil-Keep_Looking_Down-WEB-2021-AMOK/00-ritual_veil-keep_looking_down-web-2021.m3u","percentage":0,"size":96,"speed":0,"speedAvg":0},{"bytes":0,"eta":null,"group":"global_stats","name":"MP3-daily-2021-June-11-Synthpop/Ritual_Veil-Keep_Looking_Down-WEB-2021-AMOK/00-ritual_veil-keep_looking_down-web-2021.nfo","percentage":0,"size":813,"speed":0,"speedAvg":0},{"bytes":0,"eta":null,"group":"global_stats","name":"MP3-daily-2021-June-11-Synthpop/Ritual_Veil-Keep_Looking_Down-WEB-2021-AMOK/01-ritual_veil-keep_looking_down.mp3","percentage":0,"size":8833095,"speed":0,"speedAvg":0}],"transfers":0},"time":"2021-06-13T01:00:52.115318+07:00"}"#;
 
-        match gclone_json.find("ETA") {
-            Some(_pos) => {
-                let _resp: RcloneCopyResp = serde_json::from_str(gclone_json).unwrap();
-            }
-            None => (),
+        if gclone_json.contains("ETA") {
+            let _resp: RcloneCopyResp = serde_json::from_str(gclone_json).unwrap();
         }
 
         let gclone_finish = r#"{"level":"info","msg":"\nTransferred:   \t   26.353M / 26.353 MBytes, 100%, 3.640 MBytes/s, ETA 0s\nTransferred:            6 / 6, 100%, 0.83 Files/s\nElapsed time:         7.2s\n\n","source":"accounting/stats.go:388","stats":{"bytes":27633266,"checks":0,"deletes":0,"elapsedTime":7.240487058,"errors":0,"fatalError":false,"renames":0,"retryError":false,"speed":3816492.69982025,"transfers":6},"time":"2021-06-13T01:00:57.876773+07:00"}"#;
 
-        match gclone_finish.find("ETA") {
-            Some(_pos) => {
-                let _resp: RcloneCopyResp = serde_json::from_str(gclone_finish).unwrap();
-            }
-            None => (),
+        if gclone_finish.contains("ETA") {
+            let _resp: RcloneCopyResp = serde_json::from_str(gclone_finish).unwrap();
         }
     }
 }
@@ -0,0 +1,641 @@
+use async_trait::async_trait;
+use backoff::future::retry;
+use backoff::ExponentialBackoff;
+use indicatif::ProgressStyle;
+use lava_torrent::torrent::v1::Torrent;
+use log::{debug, info, warn};
+use std::cmp::min;
+use std::path::PathBuf;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Duration};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+use crate::librpl::leech_steps;
+use crate::librpl::torrent_parser::TorrentPack;
+use crate::librpl::util;
+use crate::librpl::SeedSettings;
+use crate::librpl::{build_queue, error, Job, RplChunk, RplClient, RplLeech, RplPackConfig};
+use crate::librpl::{Events, LeechFuture, LeechSettings, ProgressEvent, RplUploadClient};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// load.raw returns before the torrent is in rTorrent's view
+const LOAD_TIMEOUT_SECS: u32 = 30;
+
+// A param of an XML-RPC call
+#[derive(Debug, Clone)]
+enum XmlRpcParam {
+    Str(String),
+    Int(i64),
+    Base64(Vec<u8>),
+}
+
+impl From<&str> for XmlRpcParam {
+    fn from(value: &str) -> Self {
+        XmlRpcParam::Str(value.to_owned())
+    }
+}
+
+impl From<String> for XmlRpcParam {
+    fn from(value: String) -> Self {
+        XmlRpcParam::Str(value)
+    }
+}
+
+impl From<i64> for XmlRpcParam {
+    fn from(value: i64) -> Self {
+        XmlRpcParam::Int(value)
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn method_call(method: &str, params: &[XmlRpcParam]) -> String {
+    let mut body = format!(
+        "<?xml version=\"1.0\"?><methodCall><methodName>{}</methodName><params>",
+        method
+    );
+    for param in params {
+        let value = match param {
+            XmlRpcParam::Str(text) => format!("<string>{}</string>", escape_xml(text)),
+            XmlRpcParam::Int(number) => format!("<i8>{}</i8>", number),
+            XmlRpcParam::Base64(data) => format!("<base64>{}</base64>", base64::encode(data)),
+        };
+        body.push_str(&format!("<param><value>{}</value></param>", value));
+    }
+    body.push_str("</params></methodCall>");
+    body
+}
+
+// text of the first scalar <value> after start, whatever its type
+fn scalar_after(body: &str, start: usize) -> Option<String> {
+    let open = start + body[start..].find("<value>")? + "<value>".len();
+    let close = open + body[open..].find("</value>")?;
+    let inner = body[open..close].trim();
+    let text = match inner.strip_prefix('<') {
+        // <i8>42</i8>, <string>abc</string>, an empty <string/>, ...
+        Some(tagged) => match tagged.find('>') {
+            Some(end) if tagged[..end].ends_with('/') => "",
+            Some(end) => {
+                let rest = &tagged[end + 1..];
+                &rest[..rest.find("</").unwrap_or(rest.len())]
+            }
+            None => return None,
+        },
+        // a value without a type is a string
+        None => inner,
+    };
+    Some(unescape_xml(text))
+}
+
+// the single scalar result of a methodResponse, or the faultString of a fault
+fn parse_response(body: &str) -> Result<String, String> {
+    if body.contains("<fault>") {
+        let message = body
+            .find("<name>faultString</name>")
+            .and_then(|start| scalar_after(body, start));
+        return Err(message.unwrap_or_else(|| String::from("unknown fault")));
+    }
+    let params = body
+        .find("<params>")
+        .ok_or_else(|| String::from("malformed response"))?;
+    scalar_after(body, params).ok_or_else(|| String::from("malformed response"))
+}
+
+pub struct RtorrentConfig {
+    client: reqwest::Client,
+    // the XML-RPC endpoint, e.g. <address>/RPC2
+    address: String,
+    username: String,
+    password: String,
+    pub no_op: bool,
+}
+
+impl RplClient for RtorrentConfig {}
+impl RplPackConfig for RtorrentTorrent {}
+
+impl RtorrentConfig {
+    // username and password are for the HTTP basic auth of the web server in front of rTorrent's
+    // SCGI port (leave them empty without one)
+    pub async fn new(address: &str, username: &str, password: &str) -> Result<Self, error::Error> {
+        let config = Self {
+            client: reqwest::Client::new(),
+            address: address.to_owned(),
+            username: username.to_owned(),
+            password: password.to_owned(),
+            no_op: false,
+        };
+        let version = config.version().await?;
+        debug!("rTorrent version is {}", version);
+        Ok(config)
+    }
+
+    // only let rTorrent start downloading each chunk, see QbitConfig::no_op
+    pub fn no_op(mut self, no_op: bool) -> Self {
+        self.no_op = no_op;
+        self
+    }
+
+    async fn rpc(&self, method: &str, params: &[XmlRpcParam]) -> Result<String, error::Error> {
+        let body = method_call(method, params);
+        let res = retry(ExponentialBackoff::default(), || async {
+            let mut req = self
+                .client
+                .post(&self.address)
+                .header("Content-Type", "text/xml")
+                .body(body.clone());
+            if !self.username.is_empty() {
+                req = req.basic_auth(&self.username, Some(&self.password));
+            }
+            Ok(req.send().await?)
+        })
+        .await?;
+
+        if res.status() == reqwest::StatusCode::UNAUTHORIZED {
+            return Err(error::Error::RtorrentLoginFailed);
+        }
+        let text = res.error_for_status()?.text().await?;
+        parse_response(&text).map_err(|e| error::Error::RtorrentRpcError(method.to_owned(), e))
+    }
+
+    async fn rpc_int(&self, method: &str, params: &[XmlRpcParam]) -> Result<i64, error::Error> {
+        let text = self.rpc(method, params).await?;
+        text.trim().parse().map_err(|_| {
+            error::Error::RtorrentRpcError(method.to_owned(), format!("not a number: {}", text))
+        })
+    }
+
+    pub async fn version(&self) -> Result<String, error::Error> {
+        self.rpc("system.client_version", &[]).await
+    }
+
+    // load the torrent, stopped until d.start unless it is added for seeding
+    pub async fn add_new_torrent(&self, data: &RtorrentTorrent) -> Result<(), error::Error> {
+        let mut params = vec![
+            XmlRpcParam::from(""),
+            XmlRpcParam::Base64(data.metainfo.clone().expect("No torrent to add")),
+        ];
+        if let Some(path) = &data.directory {
+            params.push(format!("d.directory.set={}", path.to_string_lossy()).into());
+        }
+        let method = match data.start {
+            true => "load.raw_start",
+            false => "load.raw",
+        };
+        self.rpc(method, &params).await?;
+
+        // the torrent shows up once rTorrent has parsed it
+        for _ in 0..LOAD_TIMEOUT_SECS {
+            if self
+                .rpc("d.name", &[data.hash.as_str().into()])
+                .await
+                .is_ok()
+            {
+                return Ok(());
+            }
+            sleep(POLL_INTERVAL).await;
+        }
+        Err(error::Error::RtorrentRpcError(
+            String::from(method),
+            String::from("the torrent never showed up"),
+        ))
+    }
+
+    // download only the files with these indices, every other file is off
+    pub async fn select_files(&self, hash: &str, indices: &[i32]) -> Result<(), error::Error> {
+        self.rpc(
+            "f.multicall",
+            &[hash.into(), "".into(), "f.priority.set=0".into()],
+        )
+        .await?;
+        for index in indices {
+            self.rpc(
+                "f.priority.set",
+                &[format!("{}:f{}", hash, index).into(), 1.into()],
+            )
+            .await?;
+        }
+        self.rpc("d.update_priorities", &[hash.into()]).await?;
+        Ok(())
+    }
+
+    pub async fn start(&self, hash: &str) -> Result<(), error::Error> {
+        self.rpc("d.start", &[hash.into()]).await?;
+        Ok(())
+    }
+
+    // stop the torrent and forget it, d.erase never deletes the downloaded files
+    pub async fn remove_torrent(&self, hash: &str) -> Result<(), error::Error> {
+        self.rpc("d.erase", &[hash.into()]).await?;
+        Ok(())
+    }
+
+    pub async fn bytes_done(&self, hash: &str) -> Result<i64, error::Error> {
+        self.rpc_int("d.bytes_done", &[hash.into()]).await
+    }
+
+    // d.complete only turns 1 once every file is done, so the files of a chunk are checked
+    // one by one
+    pub async fn files_complete(&self, hash: &str, indices: &[i32]) -> Result<bool, error::Error> {
+        if self.rpc_int("d.complete", &[hash.into()]).await? == 1 {
+            return Ok(true);
+        }
+        for index in indices {
+            let target: XmlRpcParam = format!("{}:f{}", hash, index).into();
+            let done = self
+                .rpc_int("f.completed_chunks", std::slice::from_ref(&target))
+                .await?;
+            let total = self.rpc_int("f.size_chunks", &[target]).await?;
+            if done < total {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    pub async fn is_active(&self, hash: &str) -> Result<bool, error::Error> {
+        Ok(self.rpc_int("d.is_active", &[hash.into()]).await? == 1)
+    }
+
+    pub async fn directory(&self, hash: &str) -> Result<String, error::Error> {
+        self.rpc("d.directory", &[hash.into()]).await
+    }
+
+    // load the torrent back pointing at the upload client's mount, rTorrent checks the data
+    // there before seeding
+    pub async fn seed_torrent(
+        &self,
+        config: RtorrentTorrent,
+        seed: &SeedSettings,
+    ) -> Result<(), error::Error> {
+        let seed_config = config.start(true).save_path(PathBuf::from(
+            shellexpand::full(seed.seed_path()).unwrap().into_owned(),
+        ));
+        self.add_new_torrent(&seed_config).await
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RtorrentTorrent {
+    metainfo: Option<Vec<u8>>,
+    // rTorrent calls torrents by their info hash in upper case
+    hash: String,
+    directory: Option<PathBuf>,
+    start: bool,
+}
+
+impl RtorrentTorrent {
+    pub fn torrents(mut self, torrent: Torrent) -> Self {
+        self.hash = torrent.info_hash().to_uppercase();
+        self.metainfo = Some(
+            torrent
+                .encode()
+                .expect("Could not encode Torrent to bencode. Is torrent file corrupted?"),
+        );
+        self
+    }
+
+    // the torrent's data goes to <path>/<name> for a pack, <path> for a single file
+    pub fn save_path(mut self, path: PathBuf) -> Self {
+        self.directory = Some(path);
+        self
+    }
+
+    pub fn start(mut self, start: bool) -> Self {
+        self.start = start;
+        self
+    }
+}
+
+#[async_trait]
+impl<'a> RplLeech<'a, TorrentPack, RtorrentTorrent, RtorrentConfig> for TorrentPack {
+    async fn leech_torrent(
+        &'a mut self,
+        torrent: Torrent,
+        config: RtorrentTorrent,
+        torrent_client: RtorrentConfig,
+        upload_client: Box<dyn RplUploadClient>,
+        seed: SeedSettings,
+        settings: LeechSettings,
+    ) -> Result<(), error::Error> {
+        leech(
+            self,
+            torrent,
+            config,
+            torrent_client,
+            upload_client,
+            seed,
+            settings,
+            Events::default(),
+        )
+        .await
+    }
+
+    fn leech_torrent_with_events(
+        &'a mut self,
+        torrent: Torrent,
+        config: RtorrentTorrent,
+        torrent_client: RtorrentConfig,
+        upload_client: Box<dyn RplUploadClient>,
+        seed: SeedSettings,
+        settings: LeechSettings,
+    ) -> (UnboundedReceiverStream<ProgressEvent>, LeechFuture<'a>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let events = Events::new(sender);
+        let name = torrent.name.to_owned();
+        let run = Box::pin(async move {
+            let result = leech(
+                self,
+                torrent,
+                config,
+                torrent_client,
+                upload_client,
+                seed,
+                settings,
+                events.clone(),
+            )
+            .await;
+            if let Err(e) = &result {
+                events.send(ProgressEvent::Failed {
+                    name,
+                    error: e.to_string(),
+                });
+            }
+            result
+        });
+        (UnboundedReceiverStream::new(receiver), run)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn leech(
+    pack: &mut TorrentPack,
+    torrent: Torrent,
+    config: RtorrentTorrent,
+    torrent_client: RtorrentConfig,
+    mut upload_client: Box<dyn RplUploadClient>,
+    seed: SeedSettings,
+    mut settings: LeechSettings,
+    events: Events,
+) -> Result<(), error::Error> {
+    info!(
+        "The pack size is {}, maximum size per chunk is {}. Private torrent = {}.",
+        &pack.get_pack_size_human(),
+        &pack.get_max_size_chunk_human(),
+        &pack.is_private()
+    );
+    info!("rTorrent Version: {}", torrent_client.version().await?);
+
+    let is_pack = torrent.files.is_some();
+    let chunks = pack.chunks()?;
+    let queue = build_queue(chunks, torrent)?;
+    let size = queue.leech_size(settings.skip, settings.only.as_deref());
+    let jobs = queue.job;
+    let no_jobs = jobs.len();
+    events.send(ProgressEvent::Started {
+        name: pack.torrent.name.to_owned(),
+        chunks: no_jobs,
+        size,
+    });
+
+    leech_steps::refuse_existing(pack, &jobs, settings.skip, upload_client.as_ref()).await?;
+
+    let mut skipped = settings.skip;
+    let mut uploaded: u64 = 0;
+    let mut failed = leech_steps::FailedChunks::default();
+    let hash = config.hash.to_owned();
+    let chunk_config = config.clone().start(false);
+
+    for job in jobs {
+        leech_steps::check_stop(&settings, &job, no_jobs)?;
+        job.info();
+        if skipped > 0 {
+            info!("Chunk {}/{} has been skipped", job.chunk, no_jobs);
+            events.send(ProgressEvent::ChunkSkipped { chunk: job.chunk });
+            skipped -= 1;
+            continue;
+        }
+        if leech_steps::not_selected(&settings, &job, no_jobs) {
+            events.send(ProgressEvent::ChunkSkipped { chunk: job.chunk });
+            continue;
+        }
+        if leech_steps::over_budget(&settings, &job, uploaded, no_jobs)? {
+            return failed.check();
+        }
+        match leech_chunk(
+            pack,
+            &job,
+            no_jobs,
+            is_pack,
+            &chunk_config,
+            &torrent_client,
+            &mut upload_client,
+            &mut settings,
+            &events,
+        )
+        .await
+        {
+            Ok(()) => uploaded += job.total_size as u64,
+            Err(e) => {
+                failed.record(&mut settings, &job, no_jobs, e, &events)?;
+                // the next chunk starts from a fresh torrent
+                if let Err(e) = torrent_client.remove_torrent(&hash).await {
+                    debug!("Could not remove the torrent of chunk {}: {}", job.chunk, e);
+                }
+                if let Err(e) = job.remove_files(upload_client.get_source()) {
+                    debug!("Could not remove the files of chunk {}: {}", job.chunk, e);
+                }
+            }
+        }
+    }
+    failed.check()?;
+
+    if settings.final_verify_count && !torrent_client.no_op {
+        leech_steps::verify_remote_count(pack, &settings, upload_client.as_ref()).await?;
+    }
+
+    if *seed.seed_enable() && torrent_client.no_op {
+        info!("[no-op] Not adding the torrent back for seeding, nothing has been uploaded");
+    } else if *seed.seed_enable() {
+        info!(
+            "Waiting for {} to refresh mount point...",
+            upload_client.name()
+        );
+        util::wait_with_progress(*seed.seed_wait()).await;
+        info!(
+            "Adding the torrent back to rTorrent for seeding through {}'s mount",
+            upload_client.name()
+        );
+        torrent_client.seed_torrent(config, &seed).await?;
+        events.send(ProgressEvent::Seeding {
+            name: pack.torrent.name.to_owned(),
+        });
+    }
+
+    events.send(ProgressEvent::Finished {
+        name: pack.torrent.name.to_owned(),
+    });
+
+    Ok(())
+}
+
+// add the torrent, download and upload one chunk, then remove the torrent and the chunk's files
+#[allow(clippy::too_many_arguments)]
+async fn leech_chunk(
+    pack: &mut TorrentPack,
+    job: &Job,
+    no_jobs: usize,
+    is_pack: bool,
+    config: &RtorrentTorrent,
+    torrent_client: &RtorrentConfig,
+    upload_client: &mut Box<dyn RplUploadClient>,
+    settings: &mut LeechSettings,
+    events: &Events,
+) -> Result<(), error::Error> {
+    let hash = &config.hash;
+    leech_steps::check_output_space(settings, job, upload_client.get_source(), no_jobs)?;
+    if let Some(cross_seed) = &settings.cross_seed {
+        cross_seed.link_chunk(&pack.torrent, job, upload_client.get_source())?;
+    }
+    torrent_client.add_new_torrent(config).await?;
+    torrent_client.select_files(hash, &job.indices).await?;
+    info!("Downloading chunk {}/{}", job.chunk, no_jobs);
+    events.send(ProgressEvent::DownloadStarted { chunk: job.chunk });
+    job.download(torrent_client, hash, no_jobs, events).await?;
+    info!("Finished downloading chunk {}/{}", job.chunk, no_jobs);
+    events.send(ProgressEvent::DownloadFinished { chunk: job.chunk });
+    // d.directory of a pack already ends with its name, files are listed with it
+    let mut dir = PathBuf::from(torrent_client.directory(hash).await?);
+    if is_pack {
+        dir.pop();
+    }
+    if dir != upload_client.get_source() {
+        debug!(
+            "Uploading chunk {}/{} from rTorrent's directory {}",
+            job.chunk,
+            no_jobs,
+            dir.display()
+        );
+        upload_client.set_source(dir);
+    }
+    leech_steps::upload_chunk(
+        pack,
+        job,
+        no_jobs,
+        upload_client.as_ref(),
+        settings,
+        events,
+        torrent_client.no_op,
+    )
+    .await?;
+
+    torrent_client.remove_torrent(hash).await?;
+    job.remove_files(upload_client.get_source())
+}
+
+#[async_trait]
+trait RplRtorrent {
+    async fn download(
+        &self,
+        client: &RtorrentConfig,
+        hash: &str,
+        no_jobs: usize,
+        events: &Events,
+    ) -> Result<(), error::Error>;
+}
+
+#[async_trait]
+impl RplRtorrent for Job {
+    async fn download(
+        &self,
+        client: &RtorrentConfig,
+        hash: &str,
+        no_jobs: usize,
+        events: &Events,
+    ) -> Result<(), error::Error> {
+        client.start(hash).await?;
+        let size = self.total_size;
+
+        let pb = events.progress_bar(size as u64);
+        pb.set_style(ProgressStyle::default_bar()
+            .template("{spinner:.green} {msg} [{elapsed_precise}] [{bar:20.cyan/blue}] {bytes}/{total_bytes} [{binary_bytes_per_sec}] ({eta})")
+            .progress_chars("#>-"));
+        pb.set_message(format!("Downloading chunk {}/{}", self.chunk, no_jobs));
+
+        loop {
+            // pieces shared with the files of other chunks count too, never past the chunk size
+            let downloaded = min(client.bytes_done(hash).await?, size);
+            // only ask for the files once the chunk may be done, that is one call per file
+            if downloaded >= size && client.files_complete(hash, &self.indices).await? {
+                pb.finish_and_clear();
+                return Ok(());
+            }
+            if client.no_op && downloaded > 0 {
+                pb.finish_and_clear();
+                info!(
+                    "[no-op] Chunk {}/{} started downloading, not waiting for it to finish",
+                    self.chunk, no_jobs
+                );
+                return Ok(());
+            }
+            if !client.is_active(hash).await? {
+                warn!("rTorrent stopped the torrent, starting it again...");
+                client.start(hash).await?;
+            }
+            pb.set_position(downloaded as u64);
+            events.send(ProgressEvent::DownloadProgress {
+                chunk: self.chunk,
+                downloaded: downloaded as u64,
+                total: size as u64,
+            });
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xml_rpc() {
+        let call = method_call(
+            "f.priority.set",
+            &[XmlRpcParam::from("ABC:f2"), XmlRpcParam::from(1)],
+        );
+        assert_eq!(
+            call,
+            "<?xml version=\"1.0\"?><methodCall><methodName>f.priority.set</methodName><params><param><value><string>ABC:f2</string></value></param><param><value><i8>1</i8></value></param></params></methodCall>"
+        );
+        assert!(
+            method_call("load.raw", &[XmlRpcParam::Base64(b"d4:infoe".to_vec())])
+                .contains("<base64>ZDQ6aW5mb2U=</base64>")
+        );
+
+        let response = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<methodResponse>\n<params>\n<param><value><i8>1048576</i8></value></param>\n</params>\n</methodResponse>";
+        assert_eq!(parse_response(response).unwrap(), "1048576");
+
+        let response = "<methodResponse><params><param><value>/data/rpl/Pack &amp; Co</value></param></params></methodResponse>";
+        assert_eq!(parse_response(response).unwrap(), "/data/rpl/Pack & Co");
+
+        let response = "<methodResponse><params><param><value><string/></value></param></params></methodResponse>";
+        assert_eq!(parse_response(response).unwrap(), "");
+
+        let response = "<methodResponse><fault><value><struct><member><name>faultCode</name><value><i4>-501</i4></value></member><member><name>faultString</name><value><string>Could not find info-hash.</string></value></member></struct></value></fault></methodResponse>";
+        assert_eq!(
+            parse_response(response).unwrap_err(),
+            "Could not find info-hash."
+        );
+    }
+}
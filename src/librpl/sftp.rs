@@ -0,0 +1,406 @@
+use async_trait::async_trait;
+use indicatif::ProgressStyle;
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+use crate::librpl::error;
+use crate::librpl::rclone::OnExistingRemote;
+use crate::librpl::util;
+use crate::librpl::{Events, Job, ProgressEvent, RplUploadClient};
+
+// sftp echoes every command of a batch before running it
+const BATCH_PROMPT: &str = "sftp> put ";
+
+// Uploads through OpenSSH's sftp and ssh, so it needs nothing but key based access to the remote
+// box, and ~/.ssh/config, known_hosts and the agent apply as they do for the user's own ssh. Files
+// go to <destination>/<path in the pack>.
+#[derive(Debug, Clone)]
+pub struct SftpClient {
+    host: String,
+    port: u16,
+    username: String,
+    identity_file: Option<PathBuf>,
+    source: PathBuf,
+    // directory on the remote box
+    destination: String,
+    on_existing: Option<OnExistingRemote>,
+    // pid of the upload in flight, shared between clones so it can be interrupted from elsewhere
+    running: Arc<Mutex<Option<u32>>>,
+}
+
+impl SftpClient {
+    pub fn new(host: &str, port: u16, username: &str, source: PathBuf, destination: &str) -> Self {
+        Self {
+            host: host.to_owned(),
+            port,
+            username: username.to_owned(),
+            identity_file: None,
+            source,
+            destination: destination.trim_end_matches('/').to_owned(),
+            on_existing: None,
+            running: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // private key passed with -i, ssh's own keys and agent are used when this is None
+    pub fn identity_file(mut self, file: Option<PathBuf>) -> Self {
+        self.identity_file = file;
+        self
+    }
+
+    pub fn on_existing_remote(mut self, on_existing: Option<OnExistingRemote>) -> Self {
+        self.on_existing = on_existing;
+        self
+    }
+
+    fn target(&self) -> String {
+        match self.username.is_empty() {
+            true => self.host.to_owned(),
+            false => format!("{}@{}", self.username, self.host),
+        }
+    }
+
+    // options shared by ssh and sftp, a password prompt would hang rpl so only keys are tried
+    fn options(&self) -> Vec<String> {
+        let mut options = vec![String::from("-o"), String::from("BatchMode=yes")];
+        if let Some(file) = &self.identity_file {
+            options.push(String::from("-i"));
+            options.push(file.to_string_lossy().into_owned());
+        }
+        options
+    }
+
+    // sizes of every file under destination, keyed by their path relative to it
+    fn list(&self) -> Result<HashMap<PathBuf, i64>, error::Error> {
+        let dir = match self.destination.is_empty() {
+            true => String::from("."),
+            false => self.destination.to_owned(),
+        };
+        let output = Command::new("ssh")
+            .args(self.options())
+            .arg("-p")
+            .arg(self.port.to_string())
+            .arg(self.target())
+            .arg(list_command(&dir))
+            .output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            debug!("Listing {} failed: {}", dir, stderr.trim());
+            return Err(error::Error::SftpListError(dir));
+        }
+        Ok(parse_listing(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn upload(
+        &self,
+        job: &Job,
+        no_jobs: usize,
+        events: &Events,
+        existing: &HashMap<PathBuf, i64>,
+    ) -> Result<(), error::Error> {
+        let files: Vec<(&PathBuf, i64)> = job
+            .files
+            .iter()
+            .zip(job.lengths.iter().copied())
+            .filter(|(file, length)| {
+                let skip = existing.get(*file) == Some(length);
+                if skip {
+                    info!(
+                        "`{}` is already on the remote, it will be skipped",
+                        file.display()
+                    );
+                }
+                !skip
+            })
+            .collect();
+        if files.is_empty() {
+            return Ok(());
+        }
+
+        let batch = batch_script(&self.source, &self.destination, &files);
+        let batch_path =
+            std::env::temp_dir().join(format!("rpl-sftp-{}-{}", std::process::id(), job.chunk));
+        fs::write(&batch_path, batch)?;
+
+        let pb = events.progress_bar(job.total_size as u64);
+        pb.set_style(ProgressStyle::default_bar()
+            .template("{spinner:.green} {msg} [{elapsed_precise}] [{bar:20.cyan/blue}] {bytes}/{total_bytes} [{binary_bytes_per_sec}] ({eta})")
+            .progress_chars("#>-"));
+        pb.set_message(format!("Uploading chunk {}/{}", job.chunk, no_jobs));
+
+        let mut child = Command::new("sftp")
+            .args(self.options())
+            .arg("-P")
+            .arg(self.port.to_string())
+            .arg("-b")
+            .arg(&batch_path)
+            .arg(self.target())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        *self.running.lock().unwrap() = Some(child.id());
+        // stderr is drained alongside stdout, a full stderr pipe would block sftp
+        let stderr = child.stderr.take().map(|stderr| {
+            std::thread::spawn(move || {
+                let mut text = String::new();
+                let _ = BufReader::new(stderr).read_to_string(&mut text);
+                text
+            })
+        });
+
+        // a file is on the remote once sftp moves on to the next one
+        let mut done: u64 = job.total_size as u64 - files.iter().map(|f| f.1 as u64).sum::<u64>();
+        let mut started = 0;
+        if let Some(stdout) = child.stdout.take() {
+            for line in BufReader::new(stdout).lines().map_while(|line| line.ok()) {
+                if !line.starts_with(BATCH_PROMPT) {
+                    continue;
+                }
+                if started > 0 {
+                    done += files[started - 1].1 as u64;
+                }
+                started += 1;
+                pb.set_position(done);
+                events.send(ProgressEvent::UploadProgress {
+                    chunk: job.chunk,
+                    uploaded: done,
+                    total: job.total_size as u64,
+                });
+            }
+        }
+        let status = child.wait()?;
+        let stderr = stderr
+            .map(|reader| reader.join().unwrap_or_default())
+            .unwrap_or_default();
+        *self.running.lock().unwrap() = None;
+        let _ = fs::remove_file(&batch_path);
+        pb.finish_and_clear();
+        if !status.success() {
+            return Err(error::Error::SftpUploadFailed(
+                job.chunk,
+                match stderr.trim().lines().last() {
+                    Some(line) => line.to_owned(),
+                    None => format!("sftp exited with {}", status),
+                },
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RplUploadClient for SftpClient {
+    fn name(&self) -> &str {
+        "sftp"
+    }
+
+    fn get_source(&self) -> &Path {
+        &self.source
+    }
+
+    fn set_source(&mut self, source: PathBuf) {
+        self.source = source;
+    }
+
+    fn get_destination(&self) -> &str {
+        &self.destination
+    }
+
+    fn get_on_existing_remote(&self) -> Option<OnExistingRemote> {
+        self.on_existing
+    }
+
+    async fn list_remote(
+        &self,
+        path: &str,
+        is_file: bool,
+    ) -> Result<HashMap<PathBuf, i64>, error::Error> {
+        let client = self.clone();
+        let all = tokio::task::spawn_blocking(move || client.list())
+            .await
+            .expect("The listing task panicked")?;
+        Ok(all
+            .into_iter()
+            .filter(|(file, _)| match is_file {
+                true => file == Path::new(path),
+                false => file.starts_with(path),
+            })
+            .collect())
+    }
+
+    async fn upload_chunk(
+        &self,
+        job: &Job,
+        no_jobs: usize,
+        events: &Events,
+    ) -> Result<(), error::Error> {
+        // sftp overwrites by default, so only skip needs to look at the remote
+        let existing = match self.on_existing {
+            Some(OnExistingRemote::Skip) => self.list_remote("", false).await?,
+            _ => HashMap::new(),
+        };
+        let (job, client, events) = (job.clone(), self.clone(), events.clone());
+        tokio::task::spawn_blocking(move || client.upload(&job, no_jobs, &events, &existing))
+            .await
+            .expect("The upload task panicked")
+    }
+
+    // send SIGINT to the upload in flight, if any
+    fn interrupt(&self) {
+        let pid = match *self.running.lock().unwrap() {
+            Some(pid) => pid,
+            None => return,
+        };
+        match Command::new("kill")
+            .args(["-INT", &pid.to_string()])
+            .status()
+        {
+            Ok(status) if status.success() => debug!("Interrupted sftp (pid {})", pid),
+            Ok(_) => debug!("sftp (pid {}) has already exited", pid),
+            Err(e) => warn!("Could not interrupt sftp (pid {}): {}", pid, e),
+        }
+    }
+
+    fn boxed(&self) -> Box<dyn RplUploadClient> {
+        Box::new(self.clone())
+    }
+}
+
+// Commands of an sftp batch uploading files (relative to source, with their sizes) under
+// destination. A leading `-` lets sftp go on when a directory already exists.
+fn batch_script(source: &Path, destination: &str, files: &[(&PathBuf, i64)]) -> String {
+    let mut dirs: Vec<String> = Vec::new();
+    let mut puts = String::new();
+    for (file, _) in files {
+        let mut dir = String::from(destination);
+        for component in file.parent().into_iter().flat_map(|parent| parent.iter()) {
+            dir = match dir.is_empty() {
+                true => component.to_string_lossy().into_owned(),
+                false => format!("{}/{}", dir, component.to_string_lossy()),
+            };
+            if !dirs.contains(&dir) {
+                dirs.push(dir.to_owned());
+            }
+        }
+        let remote = match destination.is_empty() {
+            true => file.to_string_lossy().into_owned(),
+            false => format!("{}/{}", destination, file.to_string_lossy()),
+        };
+        puts.push_str(&format!(
+            "put {} {}\n",
+            batch_quote(&source.join(file).to_string_lossy()),
+            batch_quote(&remote)
+        ));
+    }
+    let mut script = String::new();
+    // every folder of destination itself, in case it does not exist yet
+    let mut parent = String::new();
+    for component in destination.split('/') {
+        if component.is_empty() {
+            parent.push('/');
+            continue;
+        }
+        parent.push_str(component);
+        script.push_str(&format!("-mkdir {}\n", batch_quote(&parent)));
+        parent.push('/');
+    }
+    for dir in dirs {
+        script.push_str(&format!("-mkdir {}\n", batch_quote(&dir)));
+    }
+    script.push_str(&puts);
+    script
+}
+
+// sftp splits batch commands on spaces and expands globs, in quotes only `\` and `"` are special
+fn batch_quote(path: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in path.chars() {
+        if matches!(c, '\\' | '"' | '*' | '?' | '[' | ']') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+// The remote command printing the size and path of every file under dir, one per line. Only POSIX
+// sh, find and wc are used so it runs on BSD and macOS boxes too, and it goes through sh as the
+// login shell of the user may be anything. A dir that does not exist lists as empty.
+fn list_command(dir: &str) -> String {
+    let script = format!(
+        "if [ -d {dir} ]; then cd {dir} && find . -type f -exec sh -c 'for f do printf \"%s %s\\n\" \"$(wc -c < \"$f\")\" \"$f\"; done' sh {{}} +; fi",
+        dir = util::shell_quote(dir)
+    );
+    format!("sh -c {}", util::shell_quote(&script))
+}
+
+// lines of list_command: the size (BSD wc pads it with spaces) and the path relative to the listed
+// folder, starting with ./
+fn parse_listing(output: &str) -> HashMap<PathBuf, i64> {
+    output
+        .lines()
+        .filter_map(|line| line.trim_start().split_once(' '))
+        .filter_map(|(size, path)| {
+            let path = path.strip_prefix("./")?;
+            Some((PathBuf::from(path), size.parse().ok()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch() {
+        let (a, b) = (
+            PathBuf::from("Pack/Season 1/e01.mkv"),
+            PathBuf::from("Pack/Season 1/\"e02\"*.mkv"),
+        );
+        let script = batch_script(Path::new("/data"), "/srv/rpl", &[(&a, 10), (&b, 20)]);
+        assert_eq!(
+            script,
+            "-mkdir \"/srv\"\n\
+             -mkdir \"/srv/rpl\"\n\
+             -mkdir \"/srv/rpl/Pack\"\n\
+             -mkdir \"/srv/rpl/Pack/Season 1\"\n\
+             put \"/data/Pack/Season 1/e01.mkv\" \"/srv/rpl/Pack/Season 1/e01.mkv\"\n\
+             put \"/data/Pack/Season 1/\\\"e02\\\"\\*.mkv\" \"/srv/rpl/Pack/Season 1/\\\"e02\\\"\\*.mkv\"\n"
+        );
+
+        let listed = parse_listing("10 ./Pack/e01.mkv\n      20 ./Pack/a b.mkv\nbroken\n");
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed.get(Path::new("Pack/a b.mkv")), Some(&20));
+        assert_eq!(listed.get(Path::new("Pack/e01.mkv")), Some(&10));
+    }
+
+    #[test]
+    fn listing() {
+        let dir = std::env::temp_dir().join(format!("rpl-sftp-list-{}", std::process::id()));
+        fs::create_dir_all(dir.join("Pack/Season 1")).unwrap();
+        fs::write(dir.join("Pack/Season 1/it's.mkv"), [0; 10]).unwrap();
+        fs::write(dir.join("Pack/e02.mkv"), [0; 20]).unwrap();
+        // ssh hands the command to the login shell of the remote user
+        let run = |dir: &Path| {
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(list_command(&dir.to_string_lossy()))
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            parse_listing(&String::from_utf8_lossy(&output.stdout))
+        };
+        let listed = run(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed.get(Path::new("Pack/Season 1/it's.mkv")), Some(&10));
+        assert_eq!(listed.get(Path::new("Pack/e02.mkv")), Some(&20));
+        assert!(run(&dir).is_empty());
+    }
+}
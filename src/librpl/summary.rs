@@ -0,0 +1,246 @@
+use log::debug;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::librpl::error;
+use crate::librpl::{ProgressEvent, Queue};
+use lava_torrent::torrent::v1::Torrent;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkStatus {
+    Uploaded,
+    Skipped,
+    Failed,
+    // the run ended before getting to the chunk
+    NotLeeched,
+}
+
+#[derive(Debug, Default)]
+struct ChunkRecord {
+    download_started: Option<Instant>,
+    upload_started: Option<Instant>,
+    download_secs: Option<f64>,
+    upload_secs: Option<f64>,
+    status: Option<ChunkStatus>,
+    error: Option<String>,
+}
+
+// When every chunk of a run was downloaded and uploaded, read from the progress events of the run
+// so every torrent client is covered
+#[derive(Debug, Default)]
+pub struct ChunkTimings {
+    chunks: BTreeMap<i32, ChunkRecord>,
+}
+
+impl ChunkTimings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, event: &ProgressEvent) {
+        self.record_at(event, Instant::now());
+    }
+
+    fn record_at(&mut self, event: &ProgressEvent, now: Instant) {
+        let secs_since = |started: Option<Instant>| {
+            started.map(|started| now.duration_since(started).as_secs_f64())
+        };
+        match event {
+            ProgressEvent::DownloadStarted { chunk } => {
+                self.chunks.entry(*chunk).or_default().download_started = Some(now);
+            }
+            ProgressEvent::DownloadFinished { chunk } => {
+                let record = self.chunks.entry(*chunk).or_default();
+                record.download_secs = secs_since(record.download_started);
+            }
+            ProgressEvent::UploadStarted { chunk } => {
+                self.chunks.entry(*chunk).or_default().upload_started = Some(now);
+            }
+            ProgressEvent::UploadFinished { chunk } => {
+                let record = self.chunks.entry(*chunk).or_default();
+                record.upload_secs = secs_since(record.upload_started);
+                record.status = Some(ChunkStatus::Uploaded);
+            }
+            ProgressEvent::ChunkSkipped { chunk } => {
+                self.chunks.entry(*chunk).or_default().status = Some(ChunkStatus::Skipped);
+            }
+            ProgressEvent::ChunkFailed { chunk, error } => {
+                let record = self.chunks.entry(*chunk).or_default();
+                record.status = Some(ChunkStatus::Failed);
+                record.error = Some(error.to_owned());
+            }
+            _ => (),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ChunkSummary {
+    chunk: i32,
+    files: usize,
+    size: i64,
+    status: ChunkStatus,
+    download_secs: Option<f64>,
+    upload_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// Machine readable record of a finished or failed run, written for cron wrappers and audits
+#[derive(Debug, Serialize)]
+pub struct RunSummary {
+    name: String,
+    info_hash: String,
+    // "finished" or "failed"
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    total_size: i64,
+    elapsed_secs: u64,
+    finished_at: String,
+    chunks: Vec<ChunkSummary>,
+    // files of the torrent no chunk holds (larger than max_size, excluded or already uploaded)
+    skipped_files: Vec<PathBuf>,
+}
+
+impl RunSummary {
+    pub fn new(
+        torrent: &Torrent,
+        queue: &Queue,
+        timings: &ChunkTimings,
+        elapsed_secs: u64,
+        error: Option<&str>,
+    ) -> Self {
+        let chunks = queue
+            .jobs()
+            .iter()
+            .map(|job| {
+                let record = timings.chunks.get(&job.chunk);
+                ChunkSummary {
+                    chunk: job.chunk,
+                    files: job.files.len(),
+                    size: job.total_size,
+                    status: record
+                        .and_then(|record| record.status)
+                        .unwrap_or(ChunkStatus::NotLeeched),
+                    download_secs: record.and_then(|record| record.download_secs),
+                    upload_secs: record.and_then(|record| record.upload_secs),
+                    error: record.and_then(|record| record.error.to_owned()),
+                }
+            })
+            .collect();
+        Self {
+            name: torrent.name.to_owned(),
+            info_hash: torrent.info_hash(),
+            status: match error {
+                None => "finished",
+                Some(_) => "failed",
+            },
+            error: error.map(String::from),
+            total_size: queue.total_size(),
+            elapsed_secs,
+            finished_at: chrono::Local::now().to_rfc3339(),
+            chunks,
+            skipped_files: skipped_files(torrent, queue),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), error::Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, path)?;
+        debug!("Wrote the run summary to {}", path.display());
+        Ok(())
+    }
+}
+
+fn skipped_files(torrent: &Torrent, queue: &Queue) -> Vec<PathBuf> {
+    let queued: BTreeSet<i32> = queue
+        .jobs()
+        .iter()
+        .flat_map(|job| job.indices.iter().copied())
+        .collect();
+    match &torrent.files {
+        Some(files) => files
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !queued.contains(&(*i as i32)))
+            .map(|(_, file)| file.path.to_owned())
+            .collect(),
+        None if queued.is_empty() => vec![PathBuf::from(&torrent.name)],
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::librpl::fixtures::pack;
+    use crate::librpl::Job;
+    use std::time::Duration;
+
+    #[test]
+    fn run_summary() {
+        let start = Instant::now();
+        let mut timings = ChunkTimings::new();
+        let events = [
+            (ProgressEvent::DownloadStarted { chunk: 1 }, 0),
+            (ProgressEvent::DownloadFinished { chunk: 1 }, 10),
+            (ProgressEvent::UploadStarted { chunk: 1 }, 10),
+            (ProgressEvent::UploadFinished { chunk: 1 }, 15),
+            (
+                ProgressEvent::ChunkFailed {
+                    chunk: 2,
+                    error: String::from("rclone exited with code 1"),
+                },
+                20,
+            ),
+        ];
+        for (event, secs) in &events {
+            timings.record_at(event, start + Duration::from_secs(*secs));
+        }
+
+        let torrent = pack(&[
+            ("1.mkv", 100),
+            ("2.mkv", 100),
+            ("3.mkv", 100),
+            ("4.mkv", 100),
+        ]);
+        // the fourth file is in no chunk
+        let queue = Queue::new(
+            4,
+            (1..=3)
+                .map(|chunk| {
+                    Job::new(
+                        chunk,
+                        100,
+                        1,
+                        vec![PathBuf::from(format!("{}.mkv", chunk))],
+                        vec![chunk - 1],
+                        vec![100],
+                    )
+                })
+                .collect(),
+        );
+        let summary = RunSummary::new(&torrent, &queue, &timings, 20, Some("1 chunk failed"));
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["total_size"], 300);
+        assert_eq!(json["skipped_files"], serde_json::json!(["4.mkv"]));
+        let chunks = json["chunks"].as_array().unwrap();
+        assert_eq!(chunks[0]["status"], "uploaded");
+        assert_eq!(chunks[0]["download_secs"], 10.0);
+        assert_eq!(chunks[0]["upload_secs"], 5.0);
+        assert_eq!(chunks[1]["status"], "failed");
+        assert_eq!(chunks[1]["error"], "rclone exited with code 1");
+        assert_eq!(chunks[2]["status"], "not_leeched");
+        assert!(chunks[2].get("error").is_none());
+    }
+}
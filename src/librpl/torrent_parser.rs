@@ -1,14 +1,20 @@
+use glob::Pattern;
 use humansize::{file_size_opts, FileSize};
-use lava_torrent::torrent::v1::Torrent;
-use log::{debug, error, warn};
-use std::collections::HashMap;
+use lava_torrent::bencode::BencodeElem;
+use lava_torrent::torrent::v1::{File, Torrent};
+use log::{debug, error, info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
 
 use crate::librpl::error;
+use crate::librpl::manifest::Manifest;
+use crate::librpl::util;
+use crate::librpl::Job;
 use crate::librpl::RplChunk;
 use crate::librpl::RplFile;
 
-pub fn get_largest_filesize(torrent: Torrent) -> i64 {
-    match torrent.files {
+pub fn get_largest_filesize(torrent: &Torrent) -> i64 {
+    match &torrent.files {
         None => torrent.length,
         Some(vec_files) => {
             vec_files
@@ -20,10 +26,281 @@ pub fn get_largest_filesize(torrent: Torrent) -> i64 {
     }
 }
 
+// Reject torrents whose sizes would break the chunker: a non-positive total, a negative file or a
+// total that does not match the sum of its files.
+pub fn validate_torrent(torrent: &Torrent) -> Result<(), error::Error> {
+    if torrent.length <= 0 {
+        return Err(error::Error::CorruptTorrent(format!(
+            "total size is {}",
+            torrent.length
+        )));
+    }
+    if let Some(vecs) = &torrent.files {
+        let mut sum: i64 = 0;
+        for file in vecs {
+            if file.length < 0 {
+                return Err(error::Error::CorruptTorrent(format!(
+                    "file `{}` has size {}",
+                    file.path.display(),
+                    file.length
+                )));
+            }
+            sum = sum.checked_add(file.length).ok_or_else(|| {
+                error::Error::CorruptTorrent(String::from("the file sizes overflow"))
+            })?;
+        }
+        if sum != torrent.length {
+            return Err(error::Error::CorruptTorrent(format!(
+                "total size is {} but its files add up to {}",
+                torrent.length, sum
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Announce the torrent to url first. The announce fields live outside the info dictionary, so the
+// info hash does not change.
+pub fn override_announce(torrent: &mut Torrent, url: &str) {
+    torrent.announce = Some(String::from(url));
+    if let Some(announce_list) = &mut torrent.announce_list {
+        for tier in announce_list.iter_mut() {
+            tier.retain(|tracker| tracker != url);
+        }
+        announce_list.retain(|tier| !tier.is_empty());
+        announce_list.insert(0, vec![String::from(url)]);
+    }
+}
+
+// The SHA-256 info hash of a hybrid torrent (BEP 52), None for a v1 only one. lava_torrent cannot
+// read v2 only torrents, so a parsed torrent is at least v1.
+pub fn info_hash_v2(torrent: &Torrent) -> Option<String> {
+    match torrent.extra_info_fields.as_ref()?.get("meta version") {
+        Some(BencodeElem::Integer(2)) => Some(util::sha256_hex(&torrent.construct_info().encode())),
+        _ => None,
+    }
+}
+
+// magnet:?xt=urn:btih:<info hash>&dn=<name>&tr=<tracker>...
+pub fn magnet_link(torrent: &Torrent) -> String {
+    let encode = |s: &str| url::form_urlencoded::byte_serialize(s.as_bytes()).collect::<String>();
+    let mut trackers: Vec<&str> = Vec::new();
+    let announce = torrent.announce.iter().map(|tracker| tracker.as_str());
+    let announce_list = torrent
+        .announce_list
+        .iter()
+        .flatten()
+        .flatten()
+        .map(|tracker| tracker.as_str());
+    for tracker in announce.chain(announce_list) {
+        if !trackers.contains(&tracker) {
+            trackers.push(tracker);
+        }
+    }
+
+    let mut magnet = format!(
+        "magnet:?xt=urn:btih:{}&dn={}",
+        torrent.info_hash(),
+        encode(&torrent.name)
+    );
+    for tracker in trackers {
+        magnet.push_str(&format!("&tr={}", encode(tracker)));
+    }
+    magnet
+}
+
+// The file indices a magnet link selects with `so=` (BEP 53), e.g. `so=0,2,4-6`. None when the
+// magnet does not select files, in which case the whole pack is leeched. Ranges are clamped to the
+// `no_files` files of the torrent, so `so=0-4294967295` selects every file instead of allocating.
+pub fn magnet_selected_files(
+    magnet: &str,
+    no_files: usize,
+) -> Result<Option<BTreeSet<usize>>, error::Error> {
+    let url = match url::Url::parse(magnet) {
+        Ok(url) => url,
+        Err(_) => return Ok(None),
+    };
+    let so = match url.query_pairs().find(|(key, _)| key == "so") {
+        Some((_, value)) => value.into_owned(),
+        None => return Ok(None),
+    };
+    let invalid = || error::Error::InvalidMagnetSelection(so.to_owned());
+    let mut selected = BTreeSet::new();
+    for part in so.split(',').filter(|part| !part.is_empty()) {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let first: usize = first.trim().parse().map_err(|_| invalid())?;
+                let last: usize = last.trim().parse().map_err(|_| invalid())?;
+                if first > last {
+                    return Err(invalid());
+                }
+                selected.extend(first..=last.min(no_files.saturating_sub(1)));
+            }
+            None => {
+                let index: usize = part.trim().parse().map_err(|_| invalid())?;
+                if index < no_files {
+                    selected.insert(index);
+                }
+            }
+        }
+    }
+    if selected.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Some(selected))
+}
+
+// The v1 info hash of a magnet link as lowercase hex, the form qBittorrent uses. The hash may be
+// given in hex or in base32 (`xt=urn:btih:<32 chars>`).
+pub fn magnet_info_hash(magnet: &str) -> Result<String, error::Error> {
+    let url = url::Url::parse(magnet).map_err(|_| error::Error::RplInvalidInput)?;
+    let hash = url
+        .query_pairs()
+        .find_map(|(key, value)| match key == "xt" {
+            true => value.strip_prefix("urn:btih:").map(str::to_owned),
+            false => None,
+        })
+        .ok_or(error::Error::RplInvalidInput)?;
+    match hash.len() {
+        40 if hash.chars().all(|c| c.is_ascii_hexdigit()) => Ok(hash.to_lowercase()),
+        32 => {
+            let mut bits: u64 = 0;
+            let mut no_bits = 0;
+            let mut hex = String::with_capacity(40);
+            for c in hash.to_ascii_uppercase().chars() {
+                let value = match c {
+                    'A'..='Z' => c as u64 - 'A' as u64,
+                    '2'..='7' => c as u64 - '2' as u64 + 26,
+                    _ => return Err(error::Error::RplInvalidInput),
+                };
+                bits = bits << 5 | value;
+                no_bits += 5;
+                if no_bits >= 8 {
+                    no_bits -= 8;
+                    hex.push_str(&format!("{:02x}", (bits >> no_bits) & 0xff));
+                }
+            }
+            Ok(hex)
+        }
+        _ => Err(error::Error::RplInvalidInput),
+    }
+}
+
+// How files are spread over chunks. Sequential keeps the torrent's order, first-fit-decreasing
+// packs the largest files first into the first chunk with room left and usually needs fewer chunks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChunkStrategy {
+    Sequential,
+    FirstFitDecreasing,
+}
+
+// the chunk of every file, counted from 0, for files of these lengths packed largest first
+fn first_fit_decreasing(lengths: &[i64], max_size: i64) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..lengths.len()).collect();
+    order.sort_by_key(|i| std::cmp::Reverse(lengths[*i]));
+    let mut sizes: Vec<i64> = Vec::new();
+    let mut bins = vec![0; lengths.len()];
+    for i in order {
+        let bin = match sizes.iter().position(|size| size + lengths[i] <= max_size) {
+            Some(bin) => bin,
+            None => {
+                sizes.push(0);
+                sizes.len() - 1
+            }
+        };
+        sizes[bin] += lengths[i];
+        bins[i] = bin;
+    }
+    bins
+}
+
+// Fills chunks one after another: a file that does not fit in the current chunk starts the next
+// one.
+struct Packer {
+    max_size: i64,
+    chunk: i32,
+    size: i64,
+}
+
+impl Packer {
+    fn new(max_size: i64) -> Self {
+        Self {
+            max_size,
+            chunk: 1,
+            size: 0,
+        }
+    }
+
+    // the chunk the file goes into
+    fn add(&mut self, length: i64) -> i32 {
+        if self.size > 0 && self.size + length > self.max_size {
+            self.next_chunk();
+        }
+        self.size += length;
+        self.chunk
+    }
+
+    fn next_chunk(&mut self) {
+        self.chunk += 1;
+        self.size = 0;
+    }
+}
+
+// number of chunks the chunker makes out of files with max_size per chunk
+fn count_chunks(lengths: &[i64], max_size: i64) -> u32 {
+    let mut packer = Packer::new(max_size);
+    for length in lengths {
+        packer.add(*length);
+    }
+    packer.chunk as u32
+}
+
+// The smallest max_size that splits the pack into at most num_chunks chunks. It is never below an
+// even share of the pack (rounded up) nor below the largest file.
+pub fn max_size_for_chunks(torrent: &Torrent, num_chunks: u32) -> Result<i64, error::Error> {
+    let lengths: Vec<i64> = match &torrent.files {
+        Some(vecs) => vecs.iter().map(|file| file.length).collect(),
+        None => vec![torrent.length],
+    };
+    let num_chunks = num_chunks as i64;
+    if num_chunks == 0 {
+        return Err(error::Error::InfeasibleNumChunks(0));
+    }
+    let share = (torrent.length + num_chunks - 1) / num_chunks;
+    let largest = lengths.iter().copied().max().unwrap_or(0);
+    if largest > share {
+        error!(
+            "The largest file in the pack is {}, larger than a 1/{} share of the pack ({})",
+            largest.file_size(file_size_opts::BINARY).unwrap(),
+            num_chunks,
+            share.file_size(file_size_opts::BINARY).unwrap(),
+        );
+        return Err(error::Error::InfeasibleNumChunks(num_chunks as u32));
+    }
+
+    // files are chunked in order, so an even share may still spill into one more chunk
+    let (mut low, mut high) = (share, std::cmp::max(torrent.length, share));
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if count_chunks(&lengths, mid) as i64 <= num_chunks {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    Ok(low)
+}
+
 pub struct TorrentPack {
     max_size_allow: i64,
     pub torrent: Torrent,
     ignore_warning: bool,
+    manifest: Option<Manifest>,
+    biggest_first: usize,
+    largest_chunks_first: bool,
+    selected: Option<BTreeSet<usize>>,
+    exclude_globs: Vec<Pattern>,
+    strategy: ChunkStrategy,
 }
 
 impl TorrentPack {
@@ -32,7 +309,126 @@ impl TorrentPack {
             max_size_allow: 0,
             torrent,
             ignore_warning,
+            manifest: None,
+            biggest_first: 0,
+            largest_chunks_first: false,
+            selected: None,
+            exclude_globs: Vec::new(),
+            strategy: ChunkStrategy::Sequential,
+        }
+    }
+
+    // skip files already recorded in the manifest and record every uploaded chunk in it
+    pub fn manifest(mut self, manifest: Manifest) -> Self {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    // leech the n largest files of the pack before everything else, in chunks of their own
+    pub fn biggest_first(mut self, n: usize) -> Self {
+        self.biggest_first = n;
+        self
+    }
+
+    // leech the chunks from the largest to the smallest, so a chunk that fails (e.g. on a remote
+    // quota) fails early in the run
+    pub fn largest_chunks_first(mut self, enable: bool) -> Self {
+        self.largest_chunks_first = enable;
+        self
+    }
+
+    pub fn chunk_strategy(mut self, strategy: ChunkStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    // only leech the files with these indices, every other file is skipped
+    pub fn select_files(mut self, indices: BTreeSet<usize>) -> Self {
+        if let Some(count) = self.torrent.files.as_ref().map(|files| files.len()) {
+            for index in indices.iter().filter(|index| **index >= count) {
+                warn!(
+                    "The pack has {} files, selected file index {} does not exist",
+                    count, index
+                );
+            }
         }
+        self.selected = Some(indices);
+        self
+    }
+
+    fn is_selected(&self, index: usize) -> bool {
+        self.selected
+            .as_ref()
+            .is_none_or(|selected| selected.contains(&index))
+    }
+
+    // never leech the files matching any of these globs, see util::glob_matches
+    pub fn exclude_globs(mut self, globs: Vec<Pattern>) -> Self {
+        self.exclude_globs = globs;
+        self
+    }
+
+    fn is_excluded(&self, path: &Path) -> bool {
+        self.exclude_globs
+            .iter()
+            .any(|glob| util::glob_matches(glob, path))
+    }
+
+    // indices of the biggest_first largest files that will be leeched, largest first. Files
+    // that are skipped anyway (already uploaded or larger than max_size) are left out.
+    fn biggest_files(&self, files: &[File]) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..files.len())
+            .filter(|index| {
+                let file = &files[*index];
+                file.length > 0
+                    && self.is_selected(*index)
+                    && !self.is_excluded(&file.path)
+                    && file.length <= self.max_size_allow
+                    && self
+                        .manifest
+                        .as_ref()
+                        .and_then(|m| m.get(&file.path, file.length))
+                        .is_none()
+            })
+            .collect();
+        indices.sort_by_key(|index| std::cmp::Reverse(files[*index].length));
+        indices.truncate(self.biggest_first);
+        indices
+    }
+
+    fn already_uploaded(&self, path: &Path, length: i64) -> bool {
+        match self.manifest.as_ref().and_then(|m| m.get(path, length)) {
+            Some(remote) => {
+                info!(
+                    "File `{}` was already uploaded to `{}`. This file will be skipped.",
+                    path.display(),
+                    remote
+                );
+                true
+            }
+            None => false,
+        }
+    }
+
+    // record the files of an uploaded chunk in the manifest, if there is one
+    pub fn record_uploaded(&mut self, job: &Job, destination: &str) -> Result<(), error::Error> {
+        let manifest = match &mut self.manifest {
+            Some(manifest) => manifest,
+            None => return Ok(()),
+        };
+        for (path, index) in job.files.iter().zip(&job.indices) {
+            let length = match &self.torrent.files {
+                Some(vecs) => vecs[*index as usize].length,
+                None => self.torrent.length,
+            };
+            let remote = format!(
+                "{}/{}",
+                destination.trim_end_matches('/'),
+                path.to_string_lossy()
+            );
+            manifest.record(path, length, remote);
+        }
+        manifest.save()
     }
 
     pub fn max_size(mut self, size: i64) -> Self {
@@ -63,7 +459,7 @@ impl TorrentPack {
 }
 
 impl<'a> RplChunk<'a> for TorrentPack {
-    fn chunks(&'a mut self) -> Result<HashMap<&str, RplFile<'a>>, error::Error> {
+    fn chunks(&'a mut self) -> Result<HashMap<&'a str, RplFile<'a>>, error::Error> {
         let mut chunks: HashMap<&str, RplFile> = HashMap::new();
         let file_vecs;
         match &self.torrent.files {
@@ -72,6 +468,26 @@ impl<'a> RplChunk<'a> for TorrentPack {
                 warn!("This torrent \"pack\" has only 1 file");
                 let path = &self.torrent.name;
                 let size = self.torrent.length;
+                if !self.is_selected(0) {
+                    info!(
+                        "File `{}` is not selected. This file will be skipped.",
+                        path
+                    );
+                    chunks.insert(path, RplFile::new(path, size, -1));
+                    return Ok(chunks);
+                }
+                if self.is_excluded(Path::new(path)) {
+                    info!(
+                        "File `{}` matches an exclude glob. This file will be skipped.",
+                        path
+                    );
+                    chunks.insert(path, RplFile::new(path, size, -1));
+                    return Ok(chunks);
+                }
+                if self.already_uploaded(Path::new(path), size) {
+                    chunks.insert(path, RplFile::new(path, size, -1));
+                    return Ok(chunks);
+                }
                 if size > self.max_size_allow {
                     if self.ignore_warning {
                         warn!(
@@ -103,12 +519,88 @@ impl<'a> RplChunk<'a> for TorrentPack {
             }
         };
 
-        let files_in_pack = file_vecs.len();
-
-        let mut current_chunk: i32 = 1;
+        // the largest files are packed into the first chunks, the rest follows in torrent order
+        let priority = self.biggest_files(file_vecs);
+        let mut packer = Packer::new(self.max_size_allow);
+        for index in &priority {
+            let file = &file_vecs[*index];
+            let chunk = packer.add(file.length);
+            debug!(
+                "Added {} size {} index {} chunk {} (biggest first)",
+                file.path.to_str().unwrap(),
+                file.length,
+                index,
+                chunk,
+            );
+            chunks.insert(
+                file.path.to_str().unwrap(),
+                RplFile::new(file.path.to_str().unwrap(), file.length, chunk),
+            );
+        }
+        if !priority.is_empty() {
+            info!(
+                "The {} largest files are leeched first, in chunks 1-{}",
+                priority.len(),
+                packer.chunk
+            );
+            packer.next_chunk();
+        }
 
-        let mut current_sum_size: i64 = 0;
+        // files left for first-fit-decreasing, packed once all of them are known
+        let mut remaining: Vec<&File> = Vec::new();
         for (index, file) in file_vecs.iter().enumerate() {
+            if priority.contains(&index) {
+                continue;
+            }
+            if !self.is_selected(index) {
+                debug!(
+                    "Skipped {} index {}, it is not selected",
+                    file.path.to_str().unwrap(),
+                    index
+                );
+                chunks.insert(
+                    file.path.to_str().unwrap(),
+                    RplFile::new(file.path.to_str().unwrap(), file.length, -1),
+                );
+                continue;
+            }
+            if self.is_excluded(&file.path) {
+                info!(
+                    "File `{}` matches an exclude glob. This file will be skipped.",
+                    file.path.display()
+                );
+                chunks.insert(
+                    file.path.to_str().unwrap(),
+                    RplFile::new(file.path.to_str().unwrap(), file.length, -1),
+                );
+                continue;
+            }
+            if self.strategy == ChunkStrategy::FirstFitDecreasing && file.length == 0 {
+                remaining.push(file);
+                continue;
+            }
+            // zero-byte placeholders always fit, keep them in the current chunk so they still get
+            // uploaded without touching the size accounting
+            if file.length == 0 {
+                debug!(
+                    "Added zero-byte {} index {} chunk {}",
+                    file.path.to_str().unwrap(),
+                    index,
+                    packer.chunk,
+                );
+                chunks.insert(
+                    file.path.to_str().unwrap(),
+                    RplFile::new(file.path.to_str().unwrap(), 0, packer.chunk),
+                );
+                continue;
+            }
+            if self.already_uploaded(&file.path, file.length) {
+                chunks.insert(
+                    file.path.to_str().unwrap(),
+                    RplFile::new(file.path.to_str().unwrap(), file.length, -1),
+                );
+                continue;
+            }
             if file.length > self.max_size_allow {
                 chunks.insert(
                     file.path.to_str().unwrap(),
@@ -140,54 +632,361 @@ impl<'a> RplChunk<'a> for TorrentPack {
 
                     return Err(error::Error::MaxSizeAllowedTooSmall);
                 }
-            // last file case
-            } else if index + 1 == files_in_pack {
-                if current_sum_size + file.length > self.max_size_allow {
-                    current_chunk += 1;
-                }
-
-                chunks.insert(
-                    file.path.to_str().unwrap(),
-                    RplFile::new(file.path.to_str().unwrap(), file.length, current_chunk),
-                );
-                debug!(
-                    "Added {} size {} index {} chunk {}",
-                    file.path.to_str().unwrap(),
-                    file.length,
-                    index,
-                    current_chunk,
-                );
-            } else if current_sum_size + file.length <= self.max_size_allow {
-                debug!(
-                    "Added {} size {} index {} chunk {}",
-                    file.path.to_str().unwrap(),
-                    file.length,
-                    index,
-                    current_chunk,
-                );
-                chunks.insert(
-                    file.path.to_str().unwrap(),
-                    RplFile::new(file.path.to_str().unwrap(), file.length, current_chunk),
-                );
-                current_sum_size += file.length;
+            } else if self.strategy == ChunkStrategy::FirstFitDecreasing {
+                remaining.push(file);
             } else {
-                current_chunk += 1;
-                current_sum_size = 0;
-                current_sum_size += file.length;
+                let chunk = packer.add(file.length);
                 debug!(
                     "Added {} size {} index {} chunk {}",
                     file.path.to_str().unwrap(),
                     file.length,
                     index,
-                    current_chunk,
+                    chunk,
                 );
                 chunks.insert(
                     file.path.to_str().unwrap(),
-                    RplFile::new(file.path.to_str().unwrap(), file.length, current_chunk),
+                    RplFile::new(file.path.to_str().unwrap(), file.length, chunk),
                 );
             }
         }
 
+        let lengths: Vec<i64> = remaining.iter().map(|file| file.length).collect();
+        let bins = first_fit_decreasing(&lengths, self.max_size_allow);
+        for (file, bin) in remaining.iter().zip(bins) {
+            let chunk = packer.chunk + bin as i32;
+            debug!(
+                "Added {} size {} chunk {} (first-fit-decreasing)",
+                file.path.to_str().unwrap(),
+                file.length,
+                chunk,
+            );
+            chunks.insert(
+                file.path.to_str().unwrap(),
+                RplFile::new(file.path.to_str().unwrap(), file.length, chunk),
+            );
+        }
+
+        if self.largest_chunks_first {
+            renumber_largest_first(&mut chunks);
+        }
         Ok(chunks)
     }
 }
+
+// Chunks are leeched in the order of their numbers, so number them from the largest to the
+// smallest, equal sizes keeping their order. Every job still lists the torrent indices of its own
+// files, which no longer follow each other from one chunk to the next.
+fn renumber_largest_first(chunks: &mut HashMap<&str, RplFile<'_>>) {
+    let mut sizes: BTreeMap<i32, i64> = BTreeMap::new();
+    for file in chunks.values().filter(|file| file.chunk > 0) {
+        *sizes.entry(file.chunk).or_default() += file.length;
+    }
+    let mut order: Vec<i32> = sizes.keys().copied().collect();
+    order.sort_by_key(|chunk| std::cmp::Reverse(sizes[chunk]));
+    let numbers: HashMap<i32, i32> = order
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| (*chunk, i as i32 + 1))
+        .collect();
+    for chunk in &order {
+        debug!(
+            "Chunk {} is leeched as chunk {} (largest first)",
+            chunk, numbers[chunk]
+        );
+    }
+    for file in chunks.values_mut().filter(|file| file.chunk > 0) {
+        file.chunk = numbers[&file.chunk];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::librpl::build_queue;
+    use crate::librpl::fixtures::pack;
+    use std::path::PathBuf;
+
+    #[test]
+    fn validate() {
+        assert!(validate_torrent(&pack(&[("a", 1), ("b", 0)])).is_ok());
+        assert!(validate_torrent(&pack(&[("a", 0)])).is_err());
+        assert!(validate_torrent(&pack(&[("a", 10), ("b", -5)])).is_err());
+        let mut mismatch = pack(&[("a", 10)]);
+        mismatch.length = 11;
+        assert!(validate_torrent(&mismatch).is_err());
+    }
+
+    #[test]
+    fn announce() {
+        let mut torrent = pack(&[("a.mkv", 1)]);
+        torrent.announce = Some(String::from("http://old/announce"));
+        torrent.announce_list = Some(vec![
+            vec![String::from("http://old/announce")],
+            vec![
+                String::from("http://new/announce"),
+                String::from("http://backup/announce"),
+            ],
+        ]);
+        let hash = torrent.info_hash();
+        override_announce(&mut torrent, "http://new/announce");
+        assert_eq!(torrent.announce.as_deref(), Some("http://new/announce"));
+        assert_eq!(
+            torrent.announce_list,
+            Some(vec![
+                vec![String::from("http://new/announce")],
+                vec![String::from("http://old/announce")],
+                vec![String::from("http://backup/announce")],
+            ])
+        );
+        assert_eq!(torrent.info_hash(), hash);
+    }
+
+    #[test]
+    fn magnet() {
+        let mut torrent = pack(&[("a.mkv", 1)]);
+        torrent.name = String::from("My Pack");
+        torrent.announce = Some(String::from("http://a/announce"));
+        torrent.announce_list = Some(vec![
+            vec![String::from("http://a/announce")],
+            vec![String::from("udp://b:80")],
+        ]);
+        assert_eq!(
+            magnet_link(&torrent),
+            format!(
+                "magnet:?xt=urn:btih:{}&dn=My+Pack&tr=http%3A%2F%2Fa%2Fannounce&tr=udp%3A%2F%2Fb%3A80",
+                torrent.info_hash()
+            )
+        );
+    }
+
+    #[test]
+    fn hybrid_hash() {
+        let mut torrent = pack(&[("a.mkv", 1)]);
+        assert_eq!(info_hash_v2(&torrent), None);
+        torrent.extra_info_fields = Some(HashMap::from([(
+            String::from("meta version"),
+            BencodeElem::Integer(2),
+        )]));
+        assert_eq!(
+            info_hash_v2(&torrent),
+            Some(util::sha256_hex(&torrent.construct_info().encode()))
+        );
+        assert_eq!(info_hash_v2(&torrent).unwrap().len(), 64);
+    }
+
+    #[test]
+    fn magnet_selection() {
+        let magnet = "magnet:?xt=urn:btih:abc&dn=Pack&so=0,2,4-6";
+        let selected = magnet_selected_files(magnet, 10).unwrap().unwrap();
+        assert_eq!(
+            selected.into_iter().collect::<Vec<_>>(),
+            vec![0, 2, 4, 5, 6]
+        );
+        assert_eq!(
+            magnet_selected_files("magnet:?xt=urn:btih:abc", 10).unwrap(),
+            None
+        );
+        assert!(magnet_selected_files("magnet:?xt=urn:btih:abc&so=3-1", 10).is_err());
+        assert!(magnet_selected_files("magnet:?xt=urn:btih:abc&so=a", 10).is_err());
+
+        let clamped = magnet_selected_files("magnet:?xt=urn:btih:abc&so=1,3-4294967295", 5);
+        assert_eq!(
+            clamped.unwrap().unwrap().into_iter().collect::<Vec<_>>(),
+            vec![1, 3, 4]
+        );
+        assert!(magnet_selected_files("magnet:?xt=urn:btih:abc&so=5,7-9", 5).is_err());
+
+        let hex = "c12fe1c06bba254a9dc9f519b335aa7c1367a88a";
+        assert_eq!(
+            magnet_info_hash(&format!(
+                "magnet:?xt=urn:btih:{}&dn=Pack",
+                hex.to_uppercase()
+            ))
+            .unwrap(),
+            hex
+        );
+        assert_eq!(
+            magnet_info_hash("magnet:?dn=Pack&xt=urn:btih:YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKEK")
+                .unwrap(),
+            hex
+        );
+        assert!(magnet_info_hash("magnet:?xt=urn:btih:abc").is_err());
+
+        let mut pack = TorrentPack::new(pack(&[("a", 40), ("b", 40), ("c", 40)]), false)
+            .max_size(100)
+            .select_files([0, 2].iter().copied().collect());
+        let chunks = pack.chunks().unwrap();
+        let chunk_of = |path: &str| chunks.get(path).unwrap().chunk;
+        assert_eq!(chunk_of("a"), 1);
+        assert_eq!(chunk_of("b"), -1);
+        assert_eq!(chunk_of("c"), 1);
+    }
+
+    #[test]
+    fn first_fit_decreasing_chunks() {
+        let files = [
+            ("a", 60),
+            ("b", 50),
+            ("c", 40),
+            ("d", 30),
+            ("e", 20),
+            ("f", 0),
+        ];
+        let mut sequential = TorrentPack::new(pack(&files), false).max_size(100);
+        let chunks = sequential.chunks().unwrap();
+        assert_eq!(chunks.values().map(|file| file.chunk).max(), Some(3));
+
+        let mut packed = TorrentPack::new(pack(&files), false)
+            .max_size(100)
+            .chunk_strategy(ChunkStrategy::FirstFitDecreasing);
+        let chunks = packed.chunks().unwrap();
+        let chunk_of = |path: &str| chunks.get(path).unwrap().chunk;
+        assert_eq!(
+            ["a", "b", "c", "d", "e", "f"].map(chunk_of),
+            [1, 2, 1, 2, 2, 1]
+        );
+
+        // the largest files still come first, and too large files are still skipped
+        let mut packed =
+            TorrentPack::new(pack(&[("a", 60), ("b", 150), ("c", 40), ("d", 70)]), true)
+                .max_size(100)
+                .biggest_first(1)
+                .chunk_strategy(ChunkStrategy::FirstFitDecreasing);
+        let chunks = packed.chunks().unwrap();
+        let chunk_of = |path: &str| chunks.get(path).unwrap().chunk;
+        assert_eq!(["a", "b", "c", "d"].map(chunk_of), [2, -1, 2, 1]);
+    }
+
+    #[test]
+    fn single_file_queue() {
+        let mut torrent = pack(&[]);
+        torrent.files = None;
+        torrent.name = String::from("movie.mkv");
+        torrent.length = 80;
+
+        let mut single = TorrentPack::new(torrent.clone(), false).max_size(100);
+        let queue = build_queue(single.chunks().unwrap(), torrent.clone()).unwrap();
+        assert_eq!(queue.no_jobs(), 1);
+        let job = &queue.jobs()[0];
+        assert_eq!(job.files(), &vec![PathBuf::from("movie.mkv")]);
+        assert_eq!(job.indices(), &vec![0]);
+        assert_eq!(*job.total_size(), 80);
+
+        let mut too_large = TorrentPack::new(torrent.clone(), true).max_size(50);
+        assert!(build_queue(too_large.chunks().unwrap(), torrent).is_err());
+    }
+
+    #[test]
+    fn excluded_files() {
+        let mut pack = TorrentPack::new(
+            pack(&[
+                ("Show/e01.mkv", 60),
+                ("Show/Show.nfo", 1),
+                ("Show/Sample/s.mkv", 10),
+                ("Show/e02.mkv", 40),
+            ]),
+            false,
+        )
+        .max_size(100)
+        .exclude_globs(vec![
+            Pattern::new("*.nfo").unwrap(),
+            Pattern::new("Sample").unwrap(),
+        ]);
+        let chunks = pack.chunks().unwrap();
+        let chunk_of = |path: &str| chunks.get(path).unwrap().chunk;
+        assert_eq!(
+            [
+                "Show/e01.mkv",
+                "Show/Show.nfo",
+                "Show/Sample/s.mkv",
+                "Show/e02.mkv"
+            ]
+            .map(chunk_of),
+            [1, -1, -1, 1]
+        );
+    }
+
+    #[test]
+    fn num_chunks() {
+        let torrent = pack(&[("a", 40), ("b", 40), ("c", 20), ("d", 60), ("e", 40)]);
+        // an even share is 67, but then d would spill into a fourth chunk
+        assert_eq!(max_size_for_chunks(&torrent, 3).unwrap(), 80);
+        assert_eq!(count_chunks(&[40, 40, 20, 60, 40], 80), 3);
+        assert_eq!(max_size_for_chunks(&torrent, 1).unwrap(), 200);
+        assert!(max_size_for_chunks(&torrent, 4).is_err());
+        assert!(max_size_for_chunks(&torrent, 0).is_err());
+    }
+
+    #[test]
+    fn zero_byte_files() {
+        let mut pack = TorrentPack::new(
+            pack(&[
+                ("empty1", 0),
+                ("a.mkv", 60),
+                ("empty2", 0),
+                ("b.mkv", 40),
+                ("c.mkv", 50),
+                ("empty3", 0),
+            ]),
+            false,
+        )
+        .max_size(100);
+        let chunks = pack.chunks().unwrap();
+        let chunk_of = |path: &str| chunks.get(path).unwrap().chunk;
+        assert_eq!(chunk_of("empty1"), 1);
+        assert_eq!(chunk_of("a.mkv"), 1);
+        assert_eq!(chunk_of("empty2"), 1);
+        assert_eq!(chunk_of("b.mkv"), 1);
+        assert_eq!(chunk_of("c.mkv"), 2);
+        assert_eq!(chunk_of("empty3"), 2);
+    }
+
+    #[test]
+    fn biggest_first() {
+        let torrent = pack(&[
+            ("a.nfo", 10),
+            ("b.mkv", 90),
+            ("c.nfo", 20),
+            ("d.mkv", 80),
+            ("e.mkv", 70),
+        ]);
+        let mut pack = TorrentPack::new(torrent.clone(), false)
+            .max_size(100)
+            .biggest_first(2);
+        let queue = crate::librpl::build_queue(pack.chunks().unwrap(), torrent).unwrap();
+        let chunks: Vec<Vec<i32>> = queue.job.iter().map(|job| job.indices.clone()).collect();
+        assert_eq!(chunks, vec![vec![1], vec![3], vec![0, 2, 4]]);
+    }
+
+    #[test]
+    fn largest_chunks_first() {
+        let torrent = pack(&[
+            ("a", 30),
+            ("b", 40),
+            ("c", 90),
+            ("d", 60),
+            ("e", 20),
+            ("f", 150),
+            ("g", 50),
+        ]);
+        // sequential chunks are [a, b], [c], [d, e], [g] with f skipped
+        let mut pack = TorrentPack::new(torrent.clone(), true)
+            .max_size(100)
+            .largest_chunks_first(true);
+        let queue = build_queue(pack.chunks().unwrap(), torrent).unwrap();
+        let chunks: Vec<(i32, Vec<i32>, i64)> = queue
+            .jobs()
+            .iter()
+            .map(|job| (job.chunk, job.indices.clone(), job.total_size))
+            .collect();
+        assert_eq!(
+            chunks,
+            vec![
+                (1, vec![2], 90),
+                (2, vec![3, 4], 80),
+                (3, vec![0, 1], 70),
+                (4, vec![6], 50)
+            ]
+        );
+    }
+}
@@ -0,0 +1,666 @@
+use async_trait::async_trait;
+use backoff::future::retry;
+use backoff::ExponentialBackoff;
+use indicatif::ProgressStyle;
+use lava_torrent::torrent::v1::Torrent;
+use log::{debug, error, info, warn};
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::cmp::min;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Duration};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+use crate::librpl::leech_steps;
+use crate::librpl::torrent_parser::TorrentPack;
+use crate::librpl::util;
+use crate::librpl::SeedSettings;
+use crate::librpl::{build_queue, error, Job, RplChunk, RplClient, RplLeech, RplPackConfig};
+use crate::librpl::{Events, LeechFuture, LeechSettings, ProgressEvent, RplUploadClient};
+
+// Transmission rejects requests without the session id it handed out last, answering 409 with a
+// new one
+const SESSION_ID_HEADER: &str = "X-Transmission-Session-Id";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// status field of torrent-get
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Status {
+    Stopped,
+    CheckWait,
+    Check,
+    DownloadWait,
+    Download,
+    SeedWait,
+    Seed,
+    Unknown,
+}
+
+impl From<i64> for Status {
+    fn from(status: i64) -> Self {
+        match status {
+            0 => Status::Stopped,
+            1 => Status::CheckWait,
+            2 => Status::Check,
+            3 => Status::DownloadWait,
+            4 => Status::Download,
+            5 => Status::SeedWait,
+            6 => Status::Seed,
+            _ => Status::Unknown,
+        }
+    }
+}
+
+// error field of torrent-get, tracker warnings and errors do not stop the download
+const LOCAL_ERROR: i64 = 3;
+
+#[derive(Debug, Deserialize)]
+struct RpcResp<T> {
+    result: String,
+    arguments: Option<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SessionInfo {
+    version: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TorrentGetResp {
+    torrents: Vec<TransmissionTorrentInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransmissionTorrentInfo {
+    status: i64,
+    error: i64,
+    error_string: String,
+    pub left_until_done: i64,
+    pub download_dir: String,
+}
+
+impl TransmissionTorrentInfo {
+    fn status(&self) -> Status {
+        Status::from(self.status)
+    }
+}
+
+pub struct TransmissionConfig {
+    client: reqwest::Client,
+    // <address>/transmission/rpc
+    rpc_url: String,
+    username: String,
+    password: String,
+    session_id: Mutex<String>,
+    pub no_op: bool,
+}
+
+impl RplClient for TransmissionConfig {}
+impl RplPackConfig for TransmissionTorrent {}
+
+impl TransmissionConfig {
+    pub async fn new(address: &str, username: &str, password: &str) -> Result<Self, error::Error> {
+        let config = Self {
+            client: reqwest::Client::new(),
+            rpc_url: format!("{}/transmission/rpc", address.trim_end_matches('/')),
+            username: username.to_owned(),
+            password: password.to_owned(),
+            session_id: Mutex::new(String::new()),
+            no_op: false,
+        };
+        let version = config.version().await?;
+        debug!("Transmission version is {}", version);
+        Ok(config)
+    }
+
+    // only let Transmission start downloading each chunk, see QbitConfig::no_op
+    pub fn no_op(mut self, no_op: bool) -> Self {
+        self.no_op = no_op;
+        self
+    }
+
+    async fn rpc<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        arguments: serde_json::Value,
+    ) -> Result<Option<T>, error::Error> {
+        let body = serde_json::json!({ "method": method, "arguments": arguments });
+        // the first request of a session always gets a 409 with the session id to use
+        for _ in 0..2 {
+            let session_id = self.session_id.lock().unwrap().to_owned();
+            let res = retry(ExponentialBackoff::default(), || async {
+                let mut request = self
+                    .client
+                    .post(&self.rpc_url)
+                    .header(SESSION_ID_HEADER, &session_id)
+                    .json(&body);
+                if !self.username.is_empty() {
+                    request = request.basic_auth(&self.username, Some(&self.password));
+                }
+                Ok(request.send().await?)
+            })
+            .await?;
+
+            match res.status() {
+                StatusCode::CONFLICT => {
+                    let session_id = res
+                        .headers()
+                        .get(SESSION_ID_HEADER)
+                        .ok_or(error::Error::MissingHeaders)?
+                        .to_str()?;
+                    *self.session_id.lock().unwrap() = session_id.to_owned();
+                }
+                StatusCode::UNAUTHORIZED => return Err(error::Error::TransmissionLoginFailed),
+                _ => {
+                    let resp: RpcResp<T> = res.error_for_status()?.json().await?;
+                    return match resp.result.as_str() {
+                        "success" => Ok(resp.arguments),
+                        _ => Err(error::Error::TransmissionRpcError(
+                            method.to_owned(),
+                            resp.result,
+                        )),
+                    };
+                }
+            }
+        }
+        Err(error::Error::MissingHeaders)
+    }
+
+    // rpc call whose arguments in the response are not needed
+    async fn call(&self, method: &str, arguments: serde_json::Value) -> Result<(), error::Error> {
+        self.rpc::<serde_json::Value>(method, arguments).await?;
+        Ok(())
+    }
+
+    pub async fn version(&self) -> Result<String, error::Error> {
+        let info: Option<SessionInfo> = self
+            .rpc("session-get", serde_json::json!({ "fields": ["version"] }))
+            .await?;
+        Ok(info.map(|info| info.version).unwrap_or_default())
+    }
+
+    pub async fn add_new_torrent(&self, data: &TransmissionTorrent) -> Result<(), error::Error> {
+        let mut arguments = serde_json::json!({
+            "metainfo": base64::encode(data.metainfo.as_ref().expect("No torrent to add")),
+            "paused": data.paused,
+        });
+        if let Some(path) = &data.download_dir {
+            arguments["download-dir"] = path.to_string_lossy().into();
+        }
+        self.call("torrent-add", arguments).await
+    }
+
+    // a limit in KiB/s for the torrent, 0 for unlimited
+    pub async fn set_download_limit(&self, hash: &str, limit: i64) -> Result<(), error::Error> {
+        self.call(
+            "torrent-set",
+            serde_json::json!({
+                "ids": [hash],
+                "downloadLimit": limit,
+                "downloadLimited": limit > 0,
+            }),
+        )
+        .await
+    }
+
+    pub async fn set_unwanted(&self, hash: &str, indices: &[i32]) -> Result<(), error::Error> {
+        self.call(
+            "torrent-set",
+            serde_json::json!({ "ids": [hash], "files-unwanted": indices }),
+        )
+        .await
+    }
+
+    pub async fn start_torrent(&self, hash: &str) -> Result<(), error::Error> {
+        self.call("torrent-start", serde_json::json!({ "ids": [hash] }))
+            .await
+    }
+
+    pub async fn delete_torrent(&self, hash: &str, delete_files: bool) -> Result<(), error::Error> {
+        self.call(
+            "torrent-remove",
+            serde_json::json!({ "ids": [hash], "delete-local-data": delete_files }),
+        )
+        .await
+    }
+
+    pub async fn get_torrent_info(
+        &self,
+        hash: &str,
+    ) -> Result<TransmissionTorrentInfo, error::Error> {
+        let resp: Option<TorrentGetResp> = self
+            .rpc(
+                "torrent-get",
+                serde_json::json!({
+                    "ids": [hash],
+                    "fields": [
+                        "status",
+                        "error",
+                        "errorString",
+                        "leftUntilDone",
+                        "downloadDir",
+                    ],
+                }),
+            )
+            .await?;
+        resp.and_then(|resp| resp.torrents.into_iter().next())
+            .ok_or(error::Error::TransmissionTorrentNotFound)
+    }
+
+    // add the torrent back pointing at the upload client's mount, Transmission checks the data
+    // there before seeding
+    pub async fn seed_torrent(
+        &self,
+        config: TransmissionTorrent,
+        seed: &SeedSettings,
+    ) -> Result<(), error::Error> {
+        let seed_config = config.paused(false).save_path(PathBuf::from(
+            shellexpand::full(seed.seed_path()).unwrap().into_owned(),
+        ));
+        self.add_new_torrent(&seed_config).await
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TransmissionTorrent {
+    metainfo: Option<Vec<u8>>,
+    download_dir: Option<PathBuf>,
+    paused: bool,
+    download_limit: i64,
+}
+
+impl TransmissionTorrent {
+    pub fn torrents(mut self, torrent: Torrent) -> Self {
+        self.metainfo = Some(
+            torrent
+                .encode()
+                .expect("Could not encode Torrent to bencode. Is torrent file corrupted?"),
+        );
+        self
+    }
+
+    pub fn save_path(mut self, path: PathBuf) -> Self {
+        self.download_dir = Some(path);
+        self
+    }
+
+    pub fn paused(mut self, paused: bool) -> Self {
+        self.paused = paused;
+        self
+    }
+
+    // KiB/s, 0 for unlimited
+    pub fn download_limit(mut self, limit: i64) -> Self {
+        self.download_limit = limit;
+        self
+    }
+}
+
+#[async_trait]
+impl<'a> RplLeech<'a, TorrentPack, TransmissionTorrent, TransmissionConfig> for TorrentPack {
+    async fn leech_torrent(
+        &'a mut self,
+        torrent: Torrent,
+        config: TransmissionTorrent,
+        torrent_client: TransmissionConfig,
+        upload_client: Box<dyn RplUploadClient>,
+        seed: SeedSettings,
+        settings: LeechSettings,
+    ) -> Result<(), error::Error> {
+        leech(
+            self,
+            torrent,
+            config,
+            torrent_client,
+            upload_client,
+            seed,
+            settings,
+            Events::default(),
+        )
+        .await
+    }
+
+    fn leech_torrent_with_events(
+        &'a mut self,
+        torrent: Torrent,
+        config: TransmissionTorrent,
+        torrent_client: TransmissionConfig,
+        upload_client: Box<dyn RplUploadClient>,
+        seed: SeedSettings,
+        settings: LeechSettings,
+    ) -> (UnboundedReceiverStream<ProgressEvent>, LeechFuture<'a>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let events = Events::new(sender);
+        let name = torrent.name.to_owned();
+        let run = Box::pin(async move {
+            let result = leech(
+                self,
+                torrent,
+                config,
+                torrent_client,
+                upload_client,
+                seed,
+                settings,
+                events.clone(),
+            )
+            .await;
+            if let Err(e) = &result {
+                events.send(ProgressEvent::Failed {
+                    name,
+                    error: e.to_string(),
+                });
+            }
+            result
+        });
+        (UnboundedReceiverStream::new(receiver), run)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn leech(
+    pack: &mut TorrentPack,
+    torrent: Torrent,
+    config: TransmissionTorrent,
+    torrent_client: TransmissionConfig,
+    mut upload_client: Box<dyn RplUploadClient>,
+    seed: SeedSettings,
+    mut settings: LeechSettings,
+    events: Events,
+) -> Result<(), error::Error> {
+    let hash = pack.info_hash();
+
+    info!(
+        "The pack size is {}, maximum size per chunk is {}. Private torrent = {}.",
+        &pack.get_pack_size_human(),
+        &pack.get_max_size_chunk_human(),
+        &pack.is_private()
+    );
+    info!("Transmission Version: {}", torrent_client.version().await?);
+
+    let chunks = pack.chunks()?;
+    let queue = build_queue(chunks, torrent)?;
+    let no_all_files = queue.no_all_files;
+    let size = queue.leech_size(settings.skip, settings.only.as_deref());
+    let jobs = queue.job;
+    let no_jobs = jobs.len();
+    events.send(ProgressEvent::Started {
+        name: pack.torrent.name.to_owned(),
+        chunks: no_jobs,
+        size,
+    });
+
+    leech_steps::refuse_existing(pack, &jobs, settings.skip, upload_client.as_ref()).await?;
+
+    let mut skipped = settings.skip;
+    let mut uploaded: u64 = 0;
+    let mut failed = leech_steps::FailedChunks::default();
+
+    for job in jobs {
+        leech_steps::check_stop(&settings, &job, no_jobs)?;
+        job.info();
+        if skipped > 0 {
+            info!("Chunk {}/{} has been skipped", job.chunk, no_jobs);
+            events.send(ProgressEvent::ChunkSkipped { chunk: job.chunk });
+            skipped -= 1;
+            continue;
+        }
+        if leech_steps::not_selected(&settings, &job, no_jobs) {
+            events.send(ProgressEvent::ChunkSkipped { chunk: job.chunk });
+            continue;
+        }
+        if leech_steps::over_budget(&settings, &job, uploaded, no_jobs)? {
+            return failed.check();
+        }
+        match leech_chunk(
+            pack,
+            &job,
+            no_jobs,
+            no_all_files,
+            &config,
+            &torrent_client,
+            &mut upload_client,
+            &mut settings,
+            &events,
+        )
+        .await
+        {
+            Ok(()) => uploaded += job.total_size as u64,
+            Err(e) => {
+                failed.record(&mut settings, &job, no_jobs, e, &events)?;
+                // the next chunk starts from a fresh torrent
+                if let Err(e) = torrent_client.delete_torrent(&hash, true).await {
+                    debug!("Could not remove the torrent of chunk {}: {}", job.chunk, e);
+                }
+            }
+        }
+    }
+    failed.check()?;
+
+    if settings.final_verify_count && !torrent_client.no_op {
+        leech_steps::verify_remote_count(pack, &settings, upload_client.as_ref()).await?;
+    }
+
+    if *seed.seed_enable() && torrent_client.no_op {
+        info!("[no-op] Not adding the torrent back for seeding, nothing has been uploaded");
+    } else if *seed.seed_enable() {
+        info!(
+            "Waiting for {} to refresh mount point...",
+            upload_client.name()
+        );
+        util::wait_with_progress(*seed.seed_wait()).await;
+        info!(
+            "Adding the torrent back to Transmission for seeding through {}'s mount",
+            upload_client.name()
+        );
+        torrent_client.seed_torrent(config, &seed).await?;
+        events.send(ProgressEvent::Seeding {
+            name: pack.torrent.name.to_owned(),
+        });
+    }
+
+    events.send(ProgressEvent::Finished {
+        name: pack.torrent.name.to_owned(),
+    });
+
+    Ok(())
+}
+
+// add the torrent, download and upload one chunk, then remove the torrent
+#[allow(clippy::too_many_arguments)]
+async fn leech_chunk(
+    pack: &mut TorrentPack,
+    job: &Job,
+    no_jobs: usize,
+    no_all_files: i32,
+    config: &TransmissionTorrent,
+    torrent_client: &TransmissionConfig,
+    upload_client: &mut Box<dyn RplUploadClient>,
+    settings: &mut LeechSettings,
+    events: &Events,
+) -> Result<(), error::Error> {
+    let hash = pack.info_hash();
+    leech_steps::check_output_space(settings, job, upload_client.get_source(), no_jobs)?;
+    if let Some(cross_seed) = &settings.cross_seed {
+        cross_seed.link_chunk(&pack.torrent, job, upload_client.get_source())?;
+    }
+    torrent_client.add_new_torrent(config).await?;
+    if config.download_limit > 0 {
+        torrent_client
+            .set_download_limit(&hash, config.download_limit)
+            .await?;
+    }
+    let unwanted = job.unwanted(no_all_files);
+    if !unwanted.is_empty() {
+        torrent_client.set_unwanted(&hash, &unwanted).await?;
+    }
+    info!("Downloading chunk {}/{}", job.chunk, no_jobs);
+    events.send(ProgressEvent::DownloadStarted { chunk: job.chunk });
+    job.download(torrent_client, &hash, no_jobs, events).await?;
+    info!("Finished downloading chunk {}/{}", job.chunk, no_jobs);
+    events.send(ProgressEvent::DownloadFinished { chunk: job.chunk });
+    // with an incomplete-dir the files only end up in the download dir once complete
+    let download_dir = PathBuf::from(torrent_client.get_torrent_info(&hash).await?.download_dir);
+    if download_dir != upload_client.get_source() {
+        debug!(
+            "Uploading chunk {}/{} from Transmission's download dir {}",
+            job.chunk,
+            no_jobs,
+            download_dir.display()
+        );
+        upload_client.set_source(download_dir);
+    }
+    leech_steps::upload_chunk(
+        pack,
+        job,
+        no_jobs,
+        upload_client.as_ref(),
+        settings,
+        events,
+        torrent_client.no_op,
+    )
+    .await?;
+
+    torrent_client
+        .delete_torrent(&hash, !upload_client.moves_source())
+        .await
+}
+
+#[async_trait]
+trait RplTransmission {
+    fn unwanted(&self, no_all_files: i32) -> Vec<i32>;
+    async fn download(
+        &self,
+        client: &TransmissionConfig,
+        hash: &str,
+        no_jobs: usize,
+        events: &Events,
+    ) -> Result<(), error::Error>;
+}
+
+#[async_trait]
+impl RplTransmission for Job {
+    // files-unwanted of every file outside of this chunk
+    fn unwanted(&self, no_all_files: i32) -> Vec<i32> {
+        (0..no_all_files)
+            .filter(|i| !self.indices.contains(i))
+            .collect()
+    }
+
+    async fn download(
+        &self,
+        client: &TransmissionConfig,
+        hash: &str,
+        no_jobs: usize,
+        events: &Events,
+    ) -> Result<(), error::Error> {
+        let mut retry = 1;
+        client.start_torrent(hash).await?;
+        let size = self.total_size;
+
+        let pb = events.progress_bar(size as u64);
+        pb.set_style(ProgressStyle::default_bar()
+            .template("{spinner:.green} {msg} [{elapsed_precise}] [{bar:20.cyan/blue}] {bytes}/{total_bytes} [{binary_bytes_per_sec}] ({eta})")
+            .progress_chars("#>-"));
+        pb.set_message(format!(
+            "Waiting to download chunk {}/{}",
+            self.chunk, no_jobs
+        ));
+
+        loop {
+            let info = client.get_torrent_info(hash).await?;
+            let status = info.status();
+            let downloaded = min(size - info.left_until_done, size);
+            if info.error == LOCAL_ERROR {
+                pb.finish_and_clear();
+                error!(
+                    "Transmission could not download chunk {}/{}: {}",
+                    self.chunk, no_jobs, info.error_string
+                );
+                return Err(error::Error::TransmissionTorrentErrored(info.error_string));
+            }
+            if client.no_op && status == Status::Download {
+                pb.finish_and_clear();
+                info!(
+                    "[no-op] Chunk {}/{} started downloading, not waiting for it to finish",
+                    self.chunk, no_jobs
+                );
+                return Ok(());
+            }
+            match status {
+                Status::CheckWait | Status::Check => {
+                    pb.set_message(format!(
+                        "[Checking] Downloading chunk {}/{}",
+                        self.chunk, no_jobs
+                    ));
+                }
+                _ if info.left_until_done == 0 => {
+                    pb.finish_and_clear();
+                    return Ok(());
+                }
+                Status::Stopped => {
+                    if retry > 3 {
+                        pb.finish_and_clear();
+                        error!("The torrent was stopped and did not start again, maybe it has been stopped by the user!");
+                        return Err(error::Error::TransmissionTorrentErrored(String::from(
+                            "the torrent is stopped",
+                        )));
+                    }
+                    warn!(
+                        "Transmission stopped the torrent. Waiting 5s before starting it again..."
+                    );
+                    sleep(Duration::from_millis(5000)).await;
+                    info!("Retrying {}/3 times", retry);
+                    retry += 1;
+                    client.start_torrent(hash).await?;
+                    continue;
+                }
+                Status::DownloadWait => {
+                    pb.set_message(format!(
+                        "[Queued] Downloading chunk {}/{}",
+                        self.chunk, no_jobs
+                    ));
+                }
+                Status::Download | Status::SeedWait | Status::Seed | Status::Unknown => {
+                    pb.set_message(format!("Downloading chunk {}/{}", self.chunk, no_jobs));
+                }
+            }
+            pb.set_position(downloaded as u64);
+            events.send(ProgressEvent::DownloadProgress {
+                chunk: self.chunk,
+                downloaded: downloaded as u64,
+                total: size as u64,
+            });
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn torrent_get() {
+        let json = r#"{"arguments":{"torrents":[{"downloadDir":"/data/rpl","error":0,"errorString":"","leftUntilDone":1048576,"status":4}]},"result":"success"}"#;
+        let resp: RpcResp<TorrentGetResp> = serde_json::from_str(json).unwrap();
+        let info = &resp.arguments.unwrap().torrents[0];
+        assert_eq!(info.status(), Status::Download);
+        assert_eq!(info.left_until_done, 1048576);
+        assert_eq!(info.download_dir, "/data/rpl");
+
+        let job = Job::new(
+            2,
+            200,
+            2,
+            vec![PathBuf::from("Pack/b"), PathBuf::from("Pack/c")],
+            vec![1, 2],
+            vec![100, 100],
+        );
+        assert_eq!(job.unwanted(4), vec![0, 3]);
+    }
+}
@@ -1,9 +1,18 @@
 use directories::ProjectDirs;
+use glob::{MatchOptions, Pattern};
+use humansize::{file_size_opts, FileSize};
 use indicatif::{ProgressBar, ProgressStyle};
-use log::debug;
-use std::path::PathBuf;
+use log::{debug, warn};
+use parse_size::parse_size;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::time::{sleep, Duration};
 
+use crate::librpl::error;
+
 pub fn get_conf_dir(
     qualifier: &str,
     organization: &str,
@@ -47,3 +56,369 @@ pub async fn wait_with_progress(wait_time: u32) {
         pb.tick();
     }
 }
+
+// quote a string so it can be pasted into a POSIX shell script as a single word
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+// rename from to to, falling back to copy and remove when they are on different filesystems
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(_) => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+    }
+}
+
+// whether both paths live on the same filesystem, assumed true where this cannot be told
+#[cfg(unix)]
+pub fn same_filesystem(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(fs::metadata(a)?.dev() == fs::metadata(b)?.dev())
+}
+
+#[cfg(not(unix))]
+pub fn same_filesystem(_a: &Path, _b: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
+// environment variables named RPL_<SECTION>__<KEY> override a key of the config file
+pub const ENV_PREFIX: &str = "RPL_";
+
+// Apply RPL_<SECTION>__<KEY> overrides (e.g. RPL_RPL__MAX_SIZE, RPL_QBITTORRENT__ADDRESS) to a
+// parsed config. Values are read as TOML so numbers, booleans, arrays and inline tables work, but
+// a key that already holds a string keeps the raw value, so a password of digits stays a string.
+// Returns the names of the variables that were applied.
+pub fn apply_env_overrides(
+    config: &mut toml::Value,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Vec<String> {
+    let mut applied = Vec::new();
+    for (name, raw) in vars {
+        let (section, key) = match name
+            .strip_prefix(ENV_PREFIX)
+            .and_then(|rest| rest.split_once("__"))
+        {
+            Some((section, key)) => (section.to_lowercase(), key.to_lowercase()),
+            None => continue,
+        };
+        let table = match config.get_mut(&section).and_then(|s| s.as_table_mut()) {
+            Some(table) => table,
+            None => {
+                warn!(
+                    "{} does not match any section of the config, ignoring it",
+                    name
+                );
+                continue;
+            }
+        };
+        let value = match table.get(&key) {
+            Some(toml::Value::String(_)) => toml::Value::String(raw),
+            _ => toml::from_str::<toml::Value>(&format!("value = {}", raw))
+                .ok()
+                .and_then(|parsed| parsed.get("value").cloned())
+                .unwrap_or(toml::Value::String(raw)),
+        };
+        table.insert(key, value);
+        applied.push(name);
+    }
+    applied
+}
+
+// Parse a transfer speed into bytes per second. Bit rates use decimal units ("50Mbps"),
+// anything else is read as a size per second ("10MiB/s", "10 MB/s").
+pub fn parse_speed(speed: &str) -> Option<u64> {
+    let speed = speed.trim();
+    let lower = speed.to_lowercase();
+    if let Some(value) = lower.strip_suffix("bps") {
+        let (number, multiplier) = match value.trim_end().chars().last()? {
+            'k' => (&value[..value.len() - 1], 1_000f64),
+            'm' => (&value[..value.len() - 1], 1_000_000f64),
+            'g' => (&value[..value.len() - 1], 1_000_000_000f64),
+            _ => (value, 1f64),
+        };
+        let bits = number.trim().parse::<f64>().ok()? * multiplier;
+        return Some((bits / 8f64) as u64);
+    }
+    let size = speed
+        .strip_suffix("/s")
+        .or_else(|| speed.strip_suffix("ps"))
+        .unwrap_or(speed);
+    parse_size(size).ok()
+}
+
+// Parse a size such as "5 GiB". Binary ("GiB") and decimal ("GB") units are read as written, a
+// bare prefix ("5G") is ambiguous so it is read as binary with a warning.
+pub fn parse_size_checked(size: &str) -> Result<u64, error::Error> {
+    let size = size.trim();
+    let unit_start = size
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(size.len());
+    let number = size[..unit_start].trim_end();
+    let unit = size[unit_start..].to_ascii_uppercase();
+    let normalized = match unit.as_str() {
+        "K" | "M" | "G" | "T" | "P" | "E" => {
+            let binary = format!("{} {}iB", number, unit);
+            warn!(
+                "Size `{}` has an ambiguous unit and is read as `{}`. Use `{}iB` or `{}B` to be explicit.",
+                size, binary, unit, unit
+            );
+            binary
+        }
+        _ => String::from(size),
+    };
+    parse_size(&normalized).map_err(|_| error::Error::InvalidSizeFormat(String::from(size)))
+}
+
+// fill path with size bytes of xorshift noise, so remotes that compress or deduplicate uploads
+// cannot make the transfer look faster than it is
+pub fn write_random_file(path: &Path, size: u64) -> io::Result<()> {
+    let mut state = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_nanos() as u64)
+        .unwrap_or(1)
+        | 1;
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    let mut written = 0;
+    while written < size {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        let bytes = state.to_le_bytes();
+        let len = std::cmp::min(size - written, bytes.len() as u64) as usize;
+        writer.write_all(&bytes[..len])?;
+        written += len as u64;
+    }
+    writer.flush()
+}
+
+// the executable name would run as, looked up in PATH unless it is a path already
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    if name.contains(std::path::MAIN_SEPARATOR) {
+        let path = PathBuf::from(name);
+        return Some(path).filter(|path| path.is_file());
+    }
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    })
+}
+
+// 22325 -> "6h 12m 5s"
+pub fn format_duration(secs: u64) -> String {
+    let (hours, minutes, seconds) = (secs / 3600, secs % 3600 / 60, secs % 60);
+    if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+// "3,5,7-9" -> [3, 5, 7, 8, 9], chunk numbers start at 1. None when the list does not parse
+pub fn parse_chunk_list(list: &str) -> Option<Vec<i32>> {
+    let mut chunks = Vec::new();
+    for part in list
+        .split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+    {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let first: i32 = first.trim().parse().ok()?;
+                let last: i32 = last.trim().parse().ok()?;
+                if first < 1 || first > last {
+                    return None;
+                }
+                chunks.extend(first..=last);
+            }
+            None => chunks.push(part.parse().ok().filter(|chunk| *chunk >= 1)?),
+        }
+    }
+    chunks.sort_unstable();
+    chunks.dedup();
+    Some(chunks).filter(|chunks| !chunks.is_empty())
+}
+
+// [3, 5, 7] -> "3,5,7", the form --only takes
+pub fn chunk_list(chunks: &[i32]) -> String {
+    chunks
+        .iter()
+        .map(|chunk| chunk.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// "45.0% of 10 GiB at 20 MiB/s, ETA 8m 32s", the ETA is left out while nothing moves
+pub fn progress_summary(done: u64, total: u64, speed: u64) -> String {
+    let mut summary = format!(
+        "{:.1}% of {} at {}/s",
+        done as f64 * 100f64 / total.max(1) as f64,
+        total.file_size(file_size_opts::BINARY).unwrap(),
+        speed.file_size(file_size_opts::BINARY).unwrap(),
+    );
+    if let Some(eta) = total.saturating_sub(done).checked_div(speed) {
+        summary.push_str(&format!(", ETA {}", format_duration(eta)));
+    }
+    summary
+}
+
+// a malformed glob is a config error rather than a glob that never matches
+pub fn parse_globs(globs: &[String]) -> Result<Vec<Pattern>, error::Error> {
+    globs
+        .iter()
+        .map(|glob| Pattern::new(glob).map_err(|e| error::Error::InvalidGlob(glob.to_owned(), e)))
+        .collect()
+}
+
+// Match a file path inside the pack: `*` and `?` never cross a `/`, `**/` matches any number of
+// folders. A pattern without `/` is matched against every component, so `*.nfo` matches any nfo
+// file and `Sample` any file in a Sample folder.
+pub fn glob_matches(pattern: &Pattern, path: &Path) -> bool {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    match pattern.as_str().contains('/') {
+        true => pattern.matches_path_with(path, options),
+        false => path
+            .iter()
+            .any(|component| pattern.matches_path_with(Path::new(component), options)),
+    }
+}
+
+// SHA-256 as lowercase hex, the v2 info hash of hybrid torrents
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256() {
+        assert_eq!(
+            sha256_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // two blocks
+        assert_eq!(
+            sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn speed() {
+        assert_eq!(parse_speed("50Mbps"), Some(6_250_000));
+        assert_eq!(parse_speed("800 kbps"), Some(100_000));
+        assert_eq!(parse_speed("10MiB/s"), Some(10 * 1024 * 1024));
+        assert_eq!(parse_speed("10 MB/s"), Some(10_000_000));
+        assert_eq!(parse_speed("fast"), None);
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(parse_size_checked("5 GiB").unwrap(), 5 * 1024 * 1024 * 1024);
+        assert_eq!(parse_size_checked("5GB").unwrap(), 5_000_000_000);
+        assert_eq!(parse_size_checked("5G").unwrap(), 5 * 1024 * 1024 * 1024);
+        assert_eq!(parse_size_checked("0 MiB").unwrap(), 0);
+        assert_eq!(parse_size_checked("1024").unwrap(), 1024);
+        assert!(parse_size_checked("5 GX").is_err());
+        assert!(parse_size_checked("").is_err());
+    }
+
+    #[test]
+    fn env_overrides() {
+        let mut config: toml::Value =
+            toml::from_str("[rpl]\nmax_size = \"5 GiB\"\n[qbittorrent]\npassword = \"\"\n")
+                .unwrap();
+        let vars = vec![
+            ("RPL_RPL__MAX_SIZE", "10 GiB"),
+            ("RPL_RPL__VERIFY_PIECES", "true"),
+            ("RPL_QBITTORRENT__PASSWORD", "1234"),
+            ("RPL_RCLONE__TRANSFERS", "4"),
+            ("RPL_VERSION", "1"),
+        ];
+        let applied = apply_env_overrides(
+            &mut config,
+            vars.into_iter().map(|(k, v)| (k.to_owned(), v.to_owned())),
+        );
+        assert_eq!(applied.len(), 3);
+        assert_eq!(config["rpl"]["max_size"].as_str(), Some("10 GiB"));
+        assert_eq!(config["rpl"]["verify_pieces"].as_bool(), Some(true));
+        assert_eq!(config["qbittorrent"]["password"].as_str(), Some("1234"));
+        assert!(config.get("rclone").is_none());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn executable_lookup() {
+        assert!(find_in_path("sh").is_some());
+        assert_eq!(find_in_path("/bin/sh"), Some(PathBuf::from("/bin/sh")));
+        assert!(find_in_path("rpl-no-such-binary").is_none());
+    }
+
+    #[test]
+    fn duration() {
+        assert_eq!(format_duration(22325), "6h 12m 5s");
+        assert_eq!(format_duration(61), "1m 1s");
+        assert_eq!(format_duration(0), "0s");
+    }
+
+    #[test]
+    fn progress() {
+        assert_eq!(
+            progress_summary(512, 2048, 256),
+            "25.0% of 2 KiB at 256 B/s, ETA 6s"
+        );
+        assert_eq!(progress_summary(0, 0, 0), "0.0% of 0 B at 0 B/s");
+    }
+
+    #[test]
+    fn chunk_lists() {
+        assert_eq!(parse_chunk_list("3,5,7-9"), Some(vec![3, 5, 7, 8, 9]));
+        assert_eq!(parse_chunk_list(" 2 , 1,2 "), Some(vec![1, 2]));
+        assert_eq!(parse_chunk_list("0"), None);
+        assert_eq!(parse_chunk_list("4-2"), None);
+        assert_eq!(parse_chunk_list("a"), None);
+        assert_eq!(parse_chunk_list(""), None);
+        assert_eq!(chunk_list(&[3, 5, 7]), "3,5,7");
+    }
+
+    #[test]
+    fn globs() {
+        let glob = |pattern| Pattern::new(pattern).unwrap();
+        assert!(glob_matches(&glob("*.nfo"), Path::new("Show/Show.S01.nfo")));
+        assert!(glob_matches(&glob("RARBG*"), Path::new("RARBG.txt")));
+        assert!(glob_matches(
+            &glob("Sample"),
+            Path::new("Show/Sample/s.mkv")
+        ));
+        assert!(glob_matches(&glob("Show/*.mkv"), Path::new("Show/e01.mkv")));
+        assert!(!glob_matches(
+            &glob("Show/*.mkv"),
+            Path::new("Show/Extras/e01.mkv")
+        ));
+        assert!(glob_matches(
+            &glob("Show/**/*.mkv"),
+            Path::new("Show/Extras/e01.mkv")
+        ));
+        assert!(glob_matches(&glob("e0?.mkv"), Path::new("e01.mkv")));
+        assert!(!glob_matches(&glob("*.nfo"), Path::new("Show/e01.mkv")));
+        assert!(parse_globs(&[String::from("Show/**.mkv")]).is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use humansize::{file_size_opts, FileSize};
+use log::{debug, warn};
+use notify_rust::Notification;
+use serde::Serialize;
+use serde_json::json;
+use tokio_stream::{Stream, StreamExt};
+
+use crate::librpl::util;
+use crate::librpl::ProgressEvent;
+
+const DISCORD_GREEN: u32 = 0x2ecc71;
+const DISCORD_RED: u32 = 0xe74c3c;
+
+// POSTs a JSON payload to a user defined url on every state transition of a run. Failures are
+// logged and otherwise ignored, a broken receiver must never stop a leech.
+pub struct Webhook {
+    url: String,
+    // shown in the logs instead of url, which may hold a secret
+    name: String,
+    client: reqwest::Client,
+}
+
+impl Webhook {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: String::from(url),
+            name: String::from(url),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    // sendMessage of a Telegram bot, the token is part of the url so it is never logged
+    pub fn telegram(bot_token: &str) -> Self {
+        Self {
+            url: format!("https://api.telegram.org/bot{}/sendMessage", bot_token),
+            name: String::from("Telegram"),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    // share one connection pool between several webhooks
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub async fn post<T: Serialize>(&self, payload: &T) {
+        let res = self
+            .client
+            .post(&self.url)
+            .json(payload)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        match res {
+            Ok(_) => debug!("Sent webhook to {}", self.name),
+            // reqwest errors carry the url
+            Err(e) => warn!(
+                "Could not send webhook to {}: {}",
+                self.name,
+                e.to_string().replace(&self.url, &self.name)
+            ),
+        }
+    }
+}
+
+// Post every event of the stream except the progress updates to webhook, until the stream ends.
+// Without a webhook the stream is only drained so events do not pile up.
+pub async fn forward(
+    webhook: Option<Webhook>,
+    mut events: impl Stream<Item = ProgressEvent> + Unpin,
+) {
+    while let Some(event) = events.next().await {
+        if let Some(webhook) = &webhook {
+            if !event.is_progress() {
+                webhook.post(&event).await;
+            }
+        }
+    }
+}
+
+// Discord embed summarizing a finished run, error is None when it succeeded
+pub fn discord_summary(
+    name: &str,
+    size: i64,
+    chunks: usize,
+    elapsed_secs: u64,
+    error: Option<&str>,
+) -> serde_json::Value {
+    let mut fields = vec![
+        json!({
+            "name": "Size",
+            "value": size.file_size(file_size_opts::BINARY).expect("File size is a negative number?"),
+            "inline": true,
+        }),
+        json!({ "name": "Chunks", "value": chunks.to_string(), "inline": true }),
+        json!({
+            "name": "Elapsed",
+            "value": util::format_duration(elapsed_secs),
+            "inline": true,
+        }),
+    ];
+    if let Some(error) = error {
+        fields.push(json!({ "name": "Error", "value": error }));
+    }
+    let (title, color) = match error {
+        None => ("Finished leeching", DISCORD_GREEN),
+        Some(_) => ("Leeching failed", DISCORD_RED),
+    };
+    json!({
+        "embeds": [{
+            "title": title,
+            "description": name,
+            "color": color,
+            "fields": fields,
+        }]
+    })
+}
+
+// sendMessage payload summarizing a finished run for the Telegram chat, error is None when it
+// succeeded
+pub fn telegram_summary(
+    chat_id: &str,
+    name: &str,
+    size: i64,
+    chunks: usize,
+    elapsed_secs: u64,
+    error: Option<&str>,
+) -> serde_json::Value {
+    let mut text = format!(
+        "{}\n{}\nSize: {}\nChunks: {}\nElapsed: {}",
+        match error {
+            None => "Finished leeching",
+            Some(_) => "Leeching failed",
+        },
+        name,
+        size.file_size(file_size_opts::BINARY)
+            .expect("File size is a negative number?"),
+        chunks,
+        util::format_duration(elapsed_secs),
+    );
+    if let Some(error) = error {
+        text.push_str(&format!("\nError: {}", error));
+    }
+    json!({ "chat_id": chat_id, "text": text })
+}
+
+// title and body of the desktop notification for a finished run, error is None when it succeeded
+pub fn desktop_summary(name: &str, elapsed_secs: u64, error: Option<&str>) -> (String, String) {
+    match error {
+        None => (
+            String::from("Finished leeching"),
+            format!("{} in {}", name, util::format_duration(elapsed_secs)),
+        ),
+        Some(error) => (
+            String::from("Leeching failed"),
+            format!("{}: {}", name, error),
+        ),
+    }
+}
+
+// Show a notification through the desktop's notification service. A headless server has no
+// notification daemon, so failures are only logged at debug.
+pub fn desktop_notify(title: &str, body: &str) {
+    match Notification::new()
+        .appname("rpl")
+        .summary(title)
+        .body(body)
+        .show()
+    {
+        Ok(_) => debug!("Sent the desktop notification"),
+        Err(e) => debug!("Could not send the desktop notification: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discord_embed() {
+        let embed = discord_summary(
+            "Pack",
+            3 * 1024 * 1024,
+            2,
+            3725,
+            Some("rpl was interrupted"),
+        );
+        let embed = &embed["embeds"][0];
+        assert_eq!(embed["title"], "Leeching failed");
+        assert_eq!(embed["description"], "Pack");
+        assert_eq!(embed["fields"][0]["value"], "3 MiB");
+        assert_eq!(embed["fields"][1]["value"], "2");
+        assert_eq!(embed["fields"][3]["value"], "rpl was interrupted");
+
+        let embed = discord_summary("Pack", 0, 1, 5, None);
+        assert_eq!(embed["embeds"][0]["color"], DISCORD_GREEN);
+        assert_eq!(embed["embeds"][0]["fields"].as_array().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn telegram_message() {
+        let message = telegram_summary("42", "Pack", 3 * 1024 * 1024, 2, 3725, None);
+        assert_eq!(message["chat_id"], "42");
+        assert_eq!(
+            message["text"],
+            "Finished leeching\nPack\nSize: 3 MiB\nChunks: 2\nElapsed: 1h 2m 5s"
+        );
+
+        let message = telegram_summary("42", "Pack", 0, 1, 5, Some("rpl was interrupted"));
+        assert!(message["text"]
+            .as_str()
+            .unwrap()
+            .ends_with("\nError: rpl was interrupted"));
+    }
+
+    #[test]
+    fn desktop_message() {
+        assert_eq!(
+            desktop_summary("Pack", 3725, None),
+            (
+                String::from("Finished leeching"),
+                String::from("Pack in 1h 2m 5s")
+            )
+        );
+        assert_eq!(
+            desktop_summary("Pack", 5, Some("rpl was interrupted")).1,
+            "Pack: rpl was interrupted"
+        );
+    }
+}
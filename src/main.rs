@@ -2,47 +2,160 @@ use anyhow::Result;
 use chrono::{Local, Utc};
 use clap::{
     crate_authors, crate_description, crate_version, value_t, App, AppSettings, Arg, ArgMatches,
+    SubCommand,
 };
 use derive_getters::Getters;
 use fern::colors::{Color, ColoredLevelConfig};
 use fs2::FileExt;
+use glob::Pattern;
 use humansize::{file_size_opts, FileSize};
 use lava_torrent::torrent::v1::Torrent;
 use log::{debug, error, info, warn, LevelFilter};
-use parse_size::parse_size;
 use serde::{Deserialize, Serialize};
+use std::cmp::max;
+use std::collections::{BTreeSet, HashMap};
+use std::env;
 use std::fs::{self, File, OpenOptions};
 use std::io::{stdout, Read, Write};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-mod librpl;
 use librpl::util;
+use rpl::librpl;
 
+use librpl::aria2::{Aria2Config, Aria2Torrent};
+use librpl::b2::B2Client;
+use librpl::cross_seed::CrossSeed;
 use librpl::error;
-use librpl::qbittorrent::{QbitConfig, QbitTorrent};
-use librpl::rclone::RcloneClient;
-use librpl::torrent_parser::{get_largest_filesize, TorrentPack};
-use librpl::{RplLeech, SeedSettings};
+use librpl::manifest::Manifest;
+use librpl::multi_remote::{MultiRemoteClient, UploadMode};
+use librpl::plan::Plan;
+use librpl::qbittorrent::{
+    emit_script, parse_headers, QbitConfig, QbitTorrent, DEFAULT_MAX_CONCURRENT_REQUESTS,
+    DEFAULT_POOL_MAX_IDLE_PER_HOST,
+};
+use librpl::rclone::{self, build_filters, OnExistingRemote, RcloneClient};
+use librpl::rtorrent::{RtorrentConfig, RtorrentTorrent};
+use librpl::sftp::SftpClient;
+use librpl::summary::{ChunkTimings, RunSummary};
+use librpl::torrent_parser::{
+    get_largest_filesize, magnet_info_hash, magnet_link, magnet_selected_files,
+    max_size_for_chunks, override_announce, validate_torrent, ChunkStrategy, TorrentPack,
+};
+use librpl::transmission::{TransmissionConfig, TransmissionTorrent};
+use librpl::webhook::{self, Webhook};
+use librpl::{
+    build_queue, LeechFuture, LeechSettings, ProgressEvent, Queue, RplChunk, RplLeech,
+    RplUploadClient, SeedSettings,
+};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::StreamExt;
 
 pub const PROGRAM_NAME: &str = "rpl";
-const STOCK_CONFIG: &str = r#"[rpl]
+// download limit of torrents added in --no-op mode (bytes/second)
+const NO_OP_DOWNLOAD_LIMIT: i64 = 1024;
+// exit code of `rpl verify` when some chunks are not fully on the remote
+const VERIFY_INCOMPLETE_EXIT_CODE: i32 = 2;
+// how long `rpl config validate` waits for the torrent client to accept the login
+const VALIDATE_LOGIN_TIMEOUT_SECS: u64 = 30;
+const STOCK_CONFIG: &str = r#"# every key can be overridden with an environment variable RPL_<SECTION>__<KEY>, e.g.
+# RPL_RPL__MAX_SIZE="10 GiB" or RPL_QBITTORRENT__ADDRESS="http://qbittorrent:8080"
+# without a config file, rpl runs from these defaults and the environment variables alone
+# precedence: command line flags > environment variables > this file > built-in defaults
+[rpl]
 # rpl will use this percentage of available disk space as max_size
 # value range: 1-100, or 0 to use max_size value instead (recommended to use max_size instead)
 max_size_percentage = 0
 # maximum size per chunk allowed for rpl. The bigger the value, the faster the download speed
 # if max_size_percentage is > 0 then this field will have no effect
+# "GiB"/"MiB" are binary units and "GB"/"MB" are decimal, a bare "G"/"M" is read as binary
 max_size = "5 GiB"
-# only qbittorrent is available at the moment
+# how files are spread over chunks: "sequential" fills chunks in the torrent's file order,
+# "firstfit-decreasing" packs the largest files first into the first chunk with room left,
+# which usually needs fewer chunks but downloads the files out of order
+chunk_strategy = "sequential"
+# stop before leeching anything when the pack would be split into more chunks than this, which
+# usually means max_size is set too low for the pack (0 to disable)
+max_chunks = 0
+# files never downloaded, matched against their path in the pack: `*` matches any characters and
+# `?` one character within a folder or file name, a `**` folder matches any number of folders
+# (e.g. "Show/**/*.nfo"). A glob without `/` matches any folder or file name.
+# Example: exclude_globs = ["*.nfo", "Sample", "RARBG*"]
+exclude_globs = []
+# qbittorrent, transmission, aria2 or rtorrent (see [transmission], [aria2] and [rtorrent] below)
 torrent_client = "qbittorrent"
 # rclone or other rclone's variants (fclone, gclone, xclone) used for uploading
+# or "b2" to upload straight to a Backblaze B2 bucket without rclone (see [b2] below)
+# or "sftp" to upload to a box over ssh without rclone (see [sftp] below)
 upload_client = "rclone"
 # [REQUIRED] temporary data from pack will be saved to here
 # this directory should be dedicated for rpl
 save_path = ""
 # [REQUIRED] rclone remote path for uploading. Example: "nugu:/rpl"
+# or a list of remote paths to spread the pack over. Example: ["gd1:/rpl", "gd2:/rpl"]
 remote_path = ""
+# with several remote_path: "round-robin" uploads each chunk to the next remote in turn,
+# "mirror" uploads every chunk to all of them and a chunk is only done once every remote has it
+upload_mode = "round-robin"
 # Force rpl to skip files that have size larger than max_size
 ignore_warning = false
+# json file recording every file uploaded by rpl (leave empty to disable)
+# files with the same name and size as a recorded file are skipped instead of uploaded again
+# Note: skipped files are not part of the seeded data when seed_enable is true
+manifest_path = ""
+# directory with data you already have from other torrents (leave empty to disable)
+# files with the same name and size are copied into save_path before each chunk and the torrent
+# client hash checks them, so only the missing data is downloaded. A file is hardlinked instead
+# when its pieces are checked to match first, a false match is never linked
+cross_seed_dir = ""
+# recompute the piece hashes of every downloaded chunk before uploading it and stop on mismatch
+# useful with skip_checking_on_leech, pieces shared with files of other chunks are not checked
+verify_pieces = false
+# at the end of a run, list the files under remote_path with a single rclone lsjson and stop with
+# an error when any file uploaded by this run is missing (catches missing chunks)
+final_verify_count = false
+# download the next chunk while the previous one is uploading (qbittorrent only). Faster when
+# both directions are busy, but save_path needs room for the two largest chunks in a row
+pipeline = false
+# put every pack in a folder named after the torrent under save_path, remote_path and seed_path,
+# so runs of different packs never share a folder (multi-file packs end up in <name>/<name>)
+nest_save_path = false
+# leave the files of every chunk in save_path after its upload (qbittorrent only), e.g. to check
+# them before cleaning up yourself. The torrent is still removed. rpl relies on deleting chunks to
+# stay under max_size, so save_path needs room for the whole pack
+keep_after_upload = false
+# set to true so the first Ctrl-C lets the current chunk finish uploading and then stops cleanly
+# (rerunning rpl on the pack continues after it), a second Ctrl-C aborts right away
+# either way the torrent and its partial data are then removed from qbittorrent or transmission
+graceful_shutdown = false
+# url that receives a POST with a JSON payload on every state transition of a run
+# (run start, chunk download/upload start and finish, run finished or failed)
+# Example payload: {"event": "upload_finished", "chunk": 2} (leave empty to disable)
+webhook_url = ""
+# with --log or -v, log the percentage, speed and ETA of the chunk being downloaded (qbittorrent
+# only, instead of its heartbeat_secs) and uploaded every this many seconds (0 to disable)
+progress_log_secs = 10
+# write a JSON summary of every run (name, info hash, size, every chunk with its size, status and
+# download and upload time, the files no chunk holds and the final status) for scripts to parse
+write_summary = false
+# where the summary goes (leave empty for summaries/<info hash>.json in the config dir, a file in
+# save_path would be uploaded with the pack)
+summary_path = ""
+
+[notifications]
+# Discord webhook that gets an embed with the name, size, number of chunks and elapsed time of the
+# pack once a run finishes or fails (leave empty to disable)
+discord_webhook_url = ""
+# Telegram bot that sends the same summary to a chat (leave either empty to disable)
+# the token comes from @BotFather, the chat id is the user, group or channel to message
+telegram_bot_token = ""
+telegram_chat_id = ""
+# show a desktop notification once a run finishes or fails
+# does nothing on a headless server without a notification daemon
+desktop = false
 
 [seed_settings]
 # set to true to seed the torrent through rclone's mount after rpl finishes
@@ -53,6 +166,10 @@ seed_path = ""
 # number of seconds to wait for rclone's mount to refresh the mount point
 # should be at least equal or bigger than the value of --poll-interval
 seed_wait = 20
+# stop seeding once the torrent reaches this share ratio (-1 to seed forever) (qbittorrent only)
+seed_ratio_limit = -1.0
+# stop seeding after this many minutes (-1 to seed forever) (qbittorrent only)
+seed_time_limit = -1
 
 [qbittorrent]
 # username of qbittorrent Web UI
@@ -65,21 +182,199 @@ address = "http://localhost:8080"
 upload_limit = "0 MiB"
 # download_limit for torrents added (unit: value/second) (0 for unlimited)
 download_limit = "0 MiB"
+# number of seconds to wait for qBittorrent Web UI to come up before logging in
+# useful when rpl and qbittorrent are started together (0 to disable)
+wait_for_client_secs = 0
+# category assigned to torrents added by rpl (leave empty for none)
+category = ""
+# set to true to upload from the save path defined by the category in qbittorrent
+# instead of rpl's save_path (only used when category is set)
+use_category_path = false
+# keep incomplete downloads here (e.g. a fast SSD), qbittorrent moves them to save_path once
+# complete and rpl always uploads from the completed path (leave empty to disable)
+download_path = ""
+# minimum number of seeders required before downloading a chunk (0 to disable)
+min_seeders = 0
+# number of seconds to wait for min_seeders to show up
+min_seeders_timeout = 300
+# what to do with a chunk that does not reach min_seeders in time: "fail" or "skip"
+min_seeders_policy = "fail"
+# number of seconds a chunk may stay in the checkingResumeData state before rpl gives up
+# (0 to wait forever)
+checking_resume_data_timeout = 600
+# number of seconds qbittorrent may take to fetch the metadata of a magnet link from peers
+# (0 to wait forever). rpl then works from the torrent file qbittorrent exports. Versions older
+# than 4.5 cannot export it, so rpl rebuilds it from the files and piece hashes qbittorrent
+# reports, which fails for torrents with extra keys in their info (pass the torrent file then)
+magnet_metadata_timeout = 600
+# log the state, progress and speed of the chunk being downloaded every this many seconds
+# so a log captured with --log shows rpl is still alive (0 to disable)
+heartbeat_secs = 300
+# when qbittorrent runs out of disk space mid-download, wait up to this many seconds for enough
+# space to be freed before failing the chunk (0 to fail right away, retrying never helps)
+disk_full_wait_secs = 0
+# set to true when qbittorrent runs on another machine than rpl (e.g. a seedbox behind a reverse
+# proxy): max_size_percentage and the disk space checks then use the free space qbittorrent
+# reports for its default save path instead of the free space of save_path on this machine
+remote_free_space = false
+# add the torrent of a chunk with qbittorrent's "skip hash checking" when every file of the chunk
+# is already in save_path at full size (e.g. hardlinked from cross_seed_dir), saving the check on
+# slow disks. qbittorrent then takes the whole chunk as downloaded, so any other chunk is still
+# added with a hash check. Turn on verify_pieces to catch corrupt data before it is uploaded
+skip_checking_on_leech = false
+# poll a stalled or queued chunk less often the longer it stays stalled, up to once every this many
+# seconds, to spare qbittorrent's Web UI on slow torrents (0 to always poll every second)
+stalled_poll_max_secs = 30
+# maximum number of qbittorrent Web UI requests rpl sends at once
+# lower it to protect a small or remote qbittorrent instance
+max_concurrent_requests = 16
+# torrents added by rpl are tagged "rpl", or "rpl-<instance_id>" when this is set
+# give every rpl instance sharing one qbittorrent a different instance_id
+# so they never touch each other's torrents
+instance_id = ""
+# extra comma separated tags for torrents added by rpl, on top of the one above
+# Example: tags = "anime,archive"
+tags = ""
+# pause every other active torrent in qbittorrent while rpl runs so rpl gets all the bandwidth
+# they are resumed when rpl finishes, fails or is interrupted with Ctrl-C
+exclusive_mode = false
+# turn off qbittorrent's alternative speed limits (e.g. a throttling schedule) while rpl runs
+# they are turned back on when rpl finishes, fails or is interrupted with Ctrl-C
+override_alt_limits = false
+# number of idle connections to qbittorrent Web UI kept for reuse
+# 0 opens a new connection for every request, which avoids a hyper bug with stale keep-alive
+# connections (https://github.com/hyperium/hyper/issues/2136) but costs a handshake per request
+# raise it (e.g. 4) for lower latency when polling a remote qbittorrent that does not hit the bug
+pool_max_idle_per_host = 0
+# extra headers sent with every request to qbittorrent Web UI, e.g. for an auth proxy in front of it
+# Example: extra_headers = { Authorization = "Bearer <token>" }
+extra_headers = {}
+# WARNING: set to true to skip TLS certificate verification of an https Web UI, e.g. one with a
+# self-signed certificate. Anyone between rpl and qbittorrent can then read the password
+accept_invalid_certs = false
 
 [rclone]
 # default transfers of rclone
 transfers = 8
+# set to true to pick the transfers of every chunk from its average file size, with transfers
+# above as the ceiling: chunks of small files (< 16 MiB) get every transfer, chunks of large files
+# (>= 1 GiB) a quarter of them and anything in between half, never more than the chunk has files
+adaptive_transfers = false
+# number of rclone's checkers, raise it for chunks with many small files
+# (leave it commented out to use rclone's default)
+# checkers = 8
+# number of times rclone retries a failed file (--retries) and a failed low level operation
+# such as a single http request (--low-level-retries), raise them for flaky remotes
+# (leave them commented out to use rclone's defaults of 3 and 10)
+# rclone_retries = 3
+# rclone_low_level_retries = 10
+# number of times rpl starts a failed upload of a chunk again, waiting 10s, 20s, 40s, ... before
+# each try. Unlike rclone_retries this also covers rclone giving up on the whole run
+# (0 disables it; an upload stopped by rpl itself or failing with errors rclone reports as not
+# retryable is never retried)
+upload_retries = 3
 # default drive chunk size (unit is MiB)
 # Note: with default rpl's setting (transfers = 8, drive_chunk_size = 64M)
 # rclone will consume 8*64 = 512 MiB of RAM when uploading
 drive_chunk_size = 64
+# the kind of remote uploaded to, as in rclone config (drive, s3, dropbox, sftp, ...)
+# drive_chunk_size and drive_service_account_file are only passed to rclone for "drive"
+remote_type = "drive"
 # by default rpl uses this command to upload the files
-# rclone copy --exclude "*.parts" --exclude "*.!qB" --verbose --stats 1s \
-# --use-json-log --transfers 8 --drive-chunk-size 64M <save_path> <remote_path>
+# rclone copy --filter "- *.parts" --filter "- *.!qB" --log-level INFO --stats 1s \
+# --stats-log-level INFO --use-json-log --transfers 8 --drive-chunk-size 64M <save_path> <remote_path>
 # you can add more custom flags here, but do not override rpl's flags.
 # the flags and their args must be separated, and in ther correct order
 # See example below. If no extra flags is needed, leave it as []
-extra_custom_flags = ["--exclude", "RARBG_DO_NOT_MIRROR.exe"]"#;
+extra_custom_flags = ["--exclude", "RARBG_DO_NOT_MIRROR.exe"]
+# rpl passes its filters to rclone as --filter rules in this order, before extra_custom_flags:
+# suffixes of files qbittorrent has not finished writing, then exclude, then include
+# (and "- **" after the includes so only included files are uploaded). --exclude and --include in
+# extra_custom_flags are added to exclude and include, --exclude-from and --include-from are refused
+# rclone uses the first rule matching a file, so a file matched by exclude is never included
+incomplete_suffixes = ["parts", "!qB"]
+# or list the partial-file rules as whole rclone patterns, replacing incomplete_suffixes
+# (e.g. ["*.part", "*.!qB", "*/Sample/**"])
+# exclude_patterns = ["*.parts", "*.!qB"]
+exclude = []
+include = []
+# set to true to upload each file under a temporary `.rpl.tmp` name and rename it once complete,
+# so nobody reading the remote sees half-written files (passes --partial-suffix, rclone >= 1.64,
+# leave it off for older rclone, fclone and gclone)
+# Note: only remotes that support renaming (local, sftp, smb, ...) do this, others such as
+# Google Drive and S3 only show a file once its upload is complete anyway
+atomic_upload = false
+# set to true to preserve modification times on the remote (passes --metadata, rclone >= 1.59)
+# Note: qbittorrent does not restore the original mtimes of downloaded files,
+# so the preserved mtimes are the time each file finished downloading
+preserve_mtime = false
+# "copy" uploads each chunk and then deletes it locally along with the torrent, "move" lets
+# rclone delete every file of the chunk right after it is on the remote, anything else in
+# save_path is left alone (no output_dir, pipeline,
+# keep_after_upload or upload_mode = "mirror" with it)
+rclone_command = "copy"
+# log level of rclone itself, independent of rpl's verbosity: "DEBUG", "INFO", "NOTICE" or "ERROR"
+# rclone's stats are always logged so rpl can still show the upload progress
+rclone_log_level = "INFO"
+# what to do with files that are already on remote_path, e.g. from an earlier partial run:
+# "skip" leaves them alone even if their size differs (--ignore-existing), "overwrite" uploads
+# them again (--ignore-times) and "error" refuses to leech when any file of the pack is there
+# (leave empty to let rclone upload files whose size or modification time differs)
+on_existing_remote = ""
+# user agent rclone sends to the remote (leave empty for rclone's default)
+rclone_user_agent = ""
+# limit the upload bandwidth of rclone (--bwlimit), either a rate like "10M" or a timetable like
+# "08:00,512k 23:00,off" to only limit it during the day (leave empty for no limit)
+bwlimit = ""
+# google drive service account used for uploading (leave empty to use the remote's own auth)
+# point it to another account's json file between runs to rotate service accounts
+drive_service_account_file = ""
+
+[b2]
+# only used when upload_client = "b2": files are uploaded to <remote_path>/<pack> in the bucket
+# through B2's native API, files larger than the account's recommended part size (100 MB)
+# are uploaded in parts. key_id and application_key come from B2's App Keys page
+key_id = ""
+application_key = ""
+bucket = ""
+
+[sftp]
+# only used when upload_client = "sftp": files are uploaded to <remote_path>/<pack> on the host
+# with OpenSSH's sftp, remote_path being a directory there. ssh must log in without a password
+# (a key in the agent, ~/.ssh or identity_file) and the host needs `find` to list uploaded files
+host = ""
+port = 22
+# leave empty to use ssh's default (or the User of ~/.ssh/config)
+username = ""
+# private key to log in with (leave empty to let ssh pick one)
+identity_file = ""
+
+[transmission]
+# only used when torrent_client = "transmission": rpl talks to transmission-daemon over its RPC
+# interface, the [qbittorrent] settings other than download_limit do not apply to it
+# address of transmission's RPC interface, without the /transmission/rpc path
+address = "http://localhost:9091"
+# rpc-username and rpc-password of transmission (leave empty when authentication is disabled)
+username = ""
+password = ""
+
+[aria2]
+# only used when torrent_client = "aria2": rpl talks to aria2c over its JSON-RPC interface
+# (aria2c --enable-rpc), the [qbittorrent] settings other than download_limit do not apply to it
+# address of aria2's RPC interface, without the /jsonrpc path
+address = "http://localhost:6800"
+# --rpc-secret of aria2 (leave empty when it is not set)
+secret = ""
+
+[rtorrent]
+# only used when torrent_client = "rtorrent": rpl talks to rtorrent over XML-RPC through the web
+# server in front of its SCGI port (the one rutorrent uses), the [qbittorrent] settings do not
+# apply to it. rtorrent's own throttle settings limit the download speed
+# url of the XML-RPC endpoint
+address = "http://localhost/RPC2"
+# HTTP basic auth of the web server (leave empty when it is not set)
+username = ""
+password = """#;
 
 fn setup_logging(verbosity: u64, chain: bool, log_path: Option<&str>) -> Result<Option<&str>> {
     let colors_line = ColoredLevelConfig::new()
@@ -162,46 +457,242 @@ struct Config {
     qbittorrent: RplQbitConfig,
     seed_settings: SeedSettings,
     rclone: RplRcloneConfig,
+    #[serde(default)]
+    b2: RplB2Config,
+    #[serde(default)]
+    sftp: RplSftpConfig,
+    #[serde(default)]
+    transmission: RplTransmissionConfig,
+    #[serde(default)]
+    aria2: RplAria2Config,
+    #[serde(default)]
+    rtorrent: RplRtorrentConfig,
+    #[serde(default)]
+    notifications: RplNotificationsConfig,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct RplNotificationsConfig {
+    discord_webhook_url: String,
+    #[serde(default)]
+    telegram_bot_token: String,
+    #[serde(default)]
+    telegram_chat_id: String,
+    #[serde(default)]
+    desktop: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct RplB2Config {
+    key_id: String,
+    application_key: String,
+    bucket: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct RplSftpConfig {
+    host: String,
+    port: u16,
+    username: String,
+    identity_file: String,
+}
+
+impl Default for RplSftpConfig {
+    fn default() -> Self {
+        Self {
+            host: String::new(),
+            port: 22,
+            username: String::new(),
+            identity_file: String::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct RplTransmissionConfig {
+    address: String,
+    username: String,
+    password: String,
+}
+
+impl Default for RplTransmissionConfig {
+    fn default() -> Self {
+        Self {
+            address: String::from("http://localhost:9091"),
+            username: String::new(),
+            password: String::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RplAria2Config {
+    address: String,
+    secret: String,
+}
+
+impl Default for RplAria2Config {
+    fn default() -> Self {
+        Self {
+            address: String::from("http://localhost:6800"),
+            secret: String::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RplRtorrentConfig {
+    address: String,
+    username: String,
+    password: String,
+}
+
+impl Default for RplRtorrentConfig {
+    fn default() -> Self {
+        Self {
+            address: String::from("http://localhost/RPC2"),
+            username: String::new(),
+            password: String::new(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Getters)]
 struct RplConfig {
     max_size_percentage: u8,
     max_size: String,
+    #[serde(default = "default_chunk_strategy")]
+    chunk_strategy: String,
+    #[serde(default)]
+    max_chunks: u32,
+    #[serde(default)]
+    exclude_globs: Vec<String>,
     torrent_client: String,
     upload_client: String,
     save_path: String,
-    remote_path: String,
+    remote_path: RemotePath,
+    #[serde(default = "default_upload_mode")]
+    upload_mode: String,
     ignore_warning: bool,
+    #[serde(default)]
+    manifest_path: String,
+    #[serde(default)]
+    cross_seed_dir: String,
+    #[serde(default)]
+    verify_pieces: bool,
+    #[serde(default)]
+    final_verify_count: bool,
+    #[serde(default)]
+    pipeline: bool,
+    #[serde(default)]
+    nest_save_path: bool,
+    #[serde(default)]
+    keep_after_upload: bool,
+    #[serde(default)]
+    graceful_shutdown: bool,
+    #[serde(default)]
+    webhook_url: String,
+    #[serde(default = "default_progress_log_secs")]
+    progress_log_secs: u32,
+    #[serde(default)]
+    write_summary: bool,
+    #[serde(default)]
+    summary_path: String,
+}
+
+// remote_path is either a single remote or a list of them
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RemotePath {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl RemotePath {
+    fn paths(&self) -> Vec<String> {
+        match self {
+            RemotePath::One(path) => vec![path.to_owned()],
+            RemotePath::Many(paths) => paths.to_owned(),
+        }
+        .into_iter()
+        .filter(|path| !path.is_empty())
+        .collect()
+    }
 }
 
 struct RplRunningConfig {
     max_size: u64,
-    //torrent_client: String,
+    torrent_client: String,
     upload_client: String,
     save_path: String,
+    // the first of remote_paths
     remote_path: String,
+    remote_paths: Vec<String>,
+    upload_mode: UploadMode,
     ignore_warning: bool,
+    manifest_path: Option<PathBuf>,
+    cross_seed_dir: Option<PathBuf>,
+    webhook_url: Option<String>,
+    notify_url: Option<String>,
+    // bot token and chat id
+    telegram: Option<(String, String)>,
 }
 
 impl RplRunningConfig {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         max_size: u64,
-        //torrent_client: String,
+        torrent_client: String,
         upload_client: String,
         save_path: String,
-        remote_path: String,
+        remote_paths: Vec<String>,
+        upload_mode: UploadMode,
         ignore_warning: bool,
+        manifest_path: Option<PathBuf>,
+        cross_seed_dir: Option<PathBuf>,
+        webhook_url: Option<String>,
+        notify_url: Option<String>,
+        telegram: Option<(String, String)>,
     ) -> Self {
         Self {
             max_size,
-            //torrent_client,
+            torrent_client,
             upload_client,
             save_path,
-            remote_path,
+            remote_path: remote_paths[0].to_owned(),
+            remote_paths,
+            upload_mode,
             ignore_warning,
+            manifest_path,
+            cross_seed_dir,
+            webhook_url,
+            notify_url,
+            telegram,
         }
     }
+
+    // save_path and every remote path get a folder of their own for the pack
+    fn nest(&mut self, dir: &str) {
+        self.save_path = Path::new(&self.save_path)
+            .join(dir)
+            .to_string_lossy()
+            .into_owned();
+        for path in self.remote_paths.iter_mut() {
+            *path = format!("{}/{}", path.trim_end_matches('/'), dir);
+        }
+        self.remote_path = self.remote_paths[0].to_owned();
+    }
+}
+
+// the folder of the pack under save_path and remote_path when nest_save_path is set, a torrent name
+// has no `/` but better safe than writing outside save_path
+fn nested_dir(file_config: &Config, matches: &ArgMatches, name: &str) -> Option<String> {
+    match matches.is_present("nest_save_path") || file_config.rpl.nest_save_path {
+        true => Some(name.replace('/', "_")),
+        false => None,
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -211,6 +702,94 @@ struct RplQbitConfig {
     address: String,
     upload_limit: String,
     download_limit: String,
+    #[serde(default)]
+    wait_for_client_secs: u32,
+    #[serde(default)]
+    category: String,
+    #[serde(default)]
+    use_category_path: bool,
+    #[serde(default)]
+    download_path: String,
+    #[serde(default)]
+    min_seeders: u32,
+    #[serde(default = "default_min_seeders_timeout")]
+    min_seeders_timeout: u32,
+    #[serde(default = "default_min_seeders_policy")]
+    min_seeders_policy: String,
+    #[serde(default = "default_checking_resume_data_timeout")]
+    checking_resume_data_timeout: u32,
+    #[serde(default = "default_magnet_metadata_timeout")]
+    magnet_metadata_timeout: u32,
+    #[serde(default = "default_heartbeat_secs")]
+    heartbeat_secs: u32,
+    #[serde(default)]
+    disk_full_wait_secs: u32,
+    #[serde(default)]
+    remote_free_space: bool,
+    #[serde(default)]
+    skip_checking_on_leech: bool,
+    #[serde(default = "default_stalled_poll_max_secs")]
+    stalled_poll_max_secs: u32,
+    #[serde(default = "default_max_concurrent_requests")]
+    max_concurrent_requests: usize,
+    #[serde(default)]
+    instance_id: String,
+    #[serde(default)]
+    tags: String,
+    #[serde(default)]
+    exclusive_mode: bool,
+    #[serde(default)]
+    override_alt_limits: bool,
+    #[serde(default = "default_pool_max_idle_per_host")]
+    pool_max_idle_per_host: usize,
+    #[serde(default)]
+    extra_headers: HashMap<String, String>,
+    #[serde(default)]
+    accept_invalid_certs: bool,
+}
+
+fn default_chunk_strategy() -> String {
+    String::from("sequential")
+}
+
+fn default_progress_log_secs() -> u32 {
+    10
+}
+
+fn default_upload_mode() -> String {
+    String::from("round-robin")
+}
+
+fn default_min_seeders_timeout() -> u32 {
+    300
+}
+
+fn default_min_seeders_policy() -> String {
+    String::from("fail")
+}
+
+fn default_checking_resume_data_timeout() -> u32 {
+    600
+}
+
+fn default_magnet_metadata_timeout() -> u32 {
+    600
+}
+
+fn default_heartbeat_secs() -> u32 {
+    300
+}
+
+fn default_stalled_poll_max_secs() -> u32 {
+    30
+}
+
+fn default_max_concurrent_requests() -> usize {
+    DEFAULT_MAX_CONCURRENT_REQUESTS
+}
+
+fn default_pool_max_idle_per_host() -> usize {
+    DEFAULT_POOL_MAX_IDLE_PER_HOST
 }
 
 #[derive(Serialize, Deserialize)]
@@ -220,15 +799,54 @@ struct RplRunningQbitConfig {
     address: String,
     upload_limit: i64,
     download_limit: i64,
+    wait_for_client_secs: u32,
+    category: String,
+    use_category_path: bool,
+    download_path: Option<PathBuf>,
+    min_seeders: u32,
+    min_seeders_timeout: u32,
+    skip_unseeded: bool,
+    checking_resume_data_timeout: u32,
+    magnet_metadata_timeout: u32,
+    heartbeat_secs: u32,
+    disk_full_wait_secs: u32,
+    stalled_poll_max_secs: u32,
+    max_concurrent_requests: usize,
+    tags: String,
+    exclusive_mode: bool,
+    override_alt_limits: bool,
+    pool_max_idle_per_host: usize,
+    extra_headers: HashMap<String, String>,
+    accept_invalid_certs: bool,
 }
 
 impl RplRunningQbitConfig {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         username: String,
         password: String,
         address: String,
         upload_limit: i64,
         download_limit: i64,
+        wait_for_client_secs: u32,
+        category: String,
+        use_category_path: bool,
+        download_path: Option<PathBuf>,
+        min_seeders: u32,
+        min_seeders_timeout: u32,
+        skip_unseeded: bool,
+        checking_resume_data_timeout: u32,
+        magnet_metadata_timeout: u32,
+        heartbeat_secs: u32,
+        disk_full_wait_secs: u32,
+        stalled_poll_max_secs: u32,
+        max_concurrent_requests: usize,
+        tags: String,
+        exclusive_mode: bool,
+        override_alt_limits: bool,
+        pool_max_idle_per_host: usize,
+        extra_headers: HashMap<String, String>,
+        accept_invalid_certs: bool,
     ) -> Self {
         Self {
             username,
@@ -236,6 +854,25 @@ impl RplRunningQbitConfig {
             address,
             upload_limit,
             download_limit,
+            wait_for_client_secs,
+            category,
+            use_category_path,
+            download_path,
+            min_seeders,
+            min_seeders_timeout,
+            skip_unseeded,
+            checking_resume_data_timeout,
+            magnet_metadata_timeout,
+            heartbeat_secs,
+            disk_full_wait_secs,
+            stalled_poll_max_secs,
+            max_concurrent_requests,
+            tags,
+            exclusive_mode,
+            override_alt_limits,
+            pool_max_idle_per_host,
+            extra_headers,
+            accept_invalid_certs,
         }
     }
 }
@@ -245,25 +882,176 @@ impl RplRunningQbitConfig {
 #[derive(Serialize, Deserialize, Getters)]
 struct RplRcloneConfig {
     transfers: u16,
+    #[serde(default)]
+    adaptive_transfers: bool,
+    #[serde(default)]
+    checkers: Option<u16>,
+    #[serde(default)]
+    rclone_retries: Option<u32>,
+    #[serde(default)]
+    rclone_low_level_retries: Option<u32>,
+    #[serde(default = "default_upload_retries")]
+    upload_retries: u32,
+    #[serde(default = "default_rclone_command")]
+    rclone_command: String,
     drive_chunk_size: u16,
+    #[serde(default = "default_remote_type")]
+    remote_type: String,
     extra_custom_flags: Vec<String>,
+    #[serde(default = "default_incomplete_suffixes")]
+    incomplete_suffixes: Vec<String>,
+    #[serde(default)]
+    exclude_patterns: Option<Vec<String>>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    atomic_upload: bool,
+    #[serde(default)]
+    preserve_mtime: bool,
+    #[serde(default = "default_rclone_log_level")]
+    rclone_log_level: String,
+    #[serde(default)]
+    on_existing_remote: String,
+    #[serde(default)]
+    rclone_user_agent: String,
+    #[serde(default)]
+    bwlimit: String,
+    #[serde(default)]
+    drive_service_account_file: String,
+}
+
+fn default_incomplete_suffixes() -> Vec<String> {
+    vec![String::from("parts"), String::from("!qB")]
+}
+
+fn default_upload_retries() -> u32 {
+    3
+}
+
+fn default_rclone_command() -> String {
+    String::from("copy")
+}
+
+fn default_remote_type() -> String {
+    String::from("drive")
+}
+
+fn default_rclone_log_level() -> String {
+    String::from("INFO")
 }
 
 impl RplRcloneConfig {
-    fn new(transfers: u16, drive_chunk_size: u16, extra_custom_flags: Vec<String>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        transfers: u16,
+        adaptive_transfers: bool,
+        checkers: Option<u16>,
+        rclone_retries: Option<u32>,
+        rclone_low_level_retries: Option<u32>,
+        upload_retries: u32,
+        rclone_command: String,
+        drive_chunk_size: u16,
+        remote_type: String,
+        extra_custom_flags: Vec<String>,
+        incomplete_suffixes: Vec<String>,
+        exclude_patterns: Option<Vec<String>>,
+        exclude: Vec<String>,
+        include: Vec<String>,
+        atomic_upload: bool,
+        preserve_mtime: bool,
+        rclone_log_level: String,
+        on_existing_remote: String,
+        rclone_user_agent: String,
+        bwlimit: String,
+        drive_service_account_file: String,
+    ) -> Self {
         Self {
             transfers,
+            adaptive_transfers,
+            checkers,
+            rclone_retries,
+            rclone_low_level_retries,
+            upload_retries,
+            rclone_command,
             drive_chunk_size,
+            remote_type,
             extra_custom_flags,
+            incomplete_suffixes,
+            exclude_patterns,
+            exclude,
+            include,
+            atomic_upload,
+            rclone_log_level,
+            preserve_mtime,
+            on_existing_remote,
+            rclone_user_agent,
+            bwlimit,
+            drive_service_account_file,
         }
     }
+
+    // empty config values mean rclone's own default
+    fn on_existing(&self) -> Option<OnExistingRemote> {
+        on_existing_remote(&self.on_existing_remote).expect("on_existing_remote is validated")
+    }
+
+    fn user_agent(&self) -> Option<String> {
+        Some(self.rclone_user_agent.to_owned()).filter(|agent| !agent.is_empty())
+    }
+
+    fn bandwidth_limit(&self) -> Option<String> {
+        Some(self.bwlimit.trim().to_owned()).filter(|limit| !limit.is_empty())
+    }
+
+    // the drive only flags are passed to rclone
+    fn is_drive(&self) -> bool {
+        self.remote_type == "drive"
+    }
+
+    fn service_account_file(&self) -> Option<PathBuf> {
+        Some(&self.drive_service_account_file)
+            .filter(|file| !file.is_empty())
+            .map(PathBuf::from)
+    }
+}
+
+fn get_chunk_strategy(
+    file_config: &Config,
+    matches: &ArgMatches,
+) -> Result<ChunkStrategy, error::Error> {
+    let strategy = match matches.value_of("chunk_strategy") {
+        Some(strategy) => strategy,
+        None => &file_config.rpl.chunk_strategy,
+    };
+    match strategy {
+        "sequential" => Ok(ChunkStrategy::Sequential),
+        "firstfit-decreasing" => Ok(ChunkStrategy::FirstFitDecreasing),
+        _ => Err(error::Error::InvalidChunkStrategy),
+    }
 }
 
-#[derive(Deserialize)]
-struct MagnetTorrent {
-    #[allow(dead_code)]
-    result: i64,
-    url: String,
+// the globs of the config file and the command line together
+fn get_exclude_globs(
+    file_config: &Config,
+    matches: &ArgMatches,
+) -> Result<Vec<Pattern>, error::Error> {
+    let mut globs = file_config.rpl.exclude_globs.clone();
+    if let Some(values) = matches.values_of("exclude_glob") {
+        globs.extend(values.map(String::from));
+    }
+    util::parse_globs(&globs)
+}
+
+fn on_existing_remote(value: &str) -> Result<Option<OnExistingRemote>, error::Error> {
+    match value {
+        "" => Ok(None),
+        "skip" => Ok(Some(OnExistingRemote::Skip)),
+        "overwrite" => Ok(Some(OnExistingRemote::Overwrite)),
+        "error" => Ok(Some(OnExistingRemote::Error)),
+        _ => Err(error::Error::InvalidOnExistingRemote),
+    }
 }
 
 // should always return error!
@@ -271,6 +1059,7 @@ fn write_default_config(config_path: &Path) -> Result<(), error::Error> {
     let mut file = OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(true)
         .open(config_path)
         .unwrap();
     writeln!(file, "{}", STOCK_CONFIG)
@@ -283,8 +1072,15 @@ fn write_default_config(config_path: &Path) -> Result<(), error::Error> {
 }
 
 impl Config {
-    fn from_config(config_string: &str) -> Self {
-        let config: Config = toml::from_str(config_string).expect("Could not parse config file");
+    fn from_config(config_string: &str, overrides: Vec<(String, String)>) -> Self {
+        let mut value: toml::Value =
+            toml::from_str(config_string).expect("Could not parse config file");
+        for name in util::apply_env_overrides(&mut value, overrides) {
+            debug!("{} overrides the config file", name);
+        }
+        let config: Config = value
+            .try_into()
+            .expect("Could not parse config file with its RPL_<SECTION>__<KEY> overrides");
         config
     }
 
@@ -329,7 +1125,7 @@ impl Config {
     }
 
     fn remote_path_invalid(&self) -> bool {
-        self.rpl.remote_path.is_empty()
+        self.rpl.remote_path.paths().is_empty()
     }
 
     fn max_size_percentage_used(&self) -> Result<bool, error::Error> {
@@ -345,36 +1141,66 @@ impl Config {
     }
 }
 
-fn get_rpl_config() -> Result<Config, error::Error> {
-    let mut conf_file = util::get_conf_dir("", "", PROGRAM_NAME).unwrap();
-    conf_file.push(PROGRAM_NAME);
-    conf_file.set_file_name(PROGRAM_NAME);
-    conf_file.set_extension("toml");
+// the config file given with --config, else rpl.toml in the platform's config dir
+fn get_rpl_config(path: Option<&str>) -> Result<Config, error::Error> {
+    let conf_file = match path {
+        Some(path) => PathBuf::from(shellexpand::full(path).unwrap().into_owned()),
+        None => {
+            let mut conf_file = util::get_conf_dir("", "", PROGRAM_NAME).unwrap();
+            conf_file.push(PROGRAM_NAME);
+            conf_file.set_file_name(PROGRAM_NAME);
+            conf_file.set_extension("toml");
+            conf_file
+        }
+    };
 
-    let config: Config;
-    if !conf_file.exists() {
-        util::create_proj_conf("", "", PROGRAM_NAME).unwrap();
+    let overrides: Vec<(String, String)> = std::env::vars()
+        .filter(|(name, _)| name.starts_with(util::ENV_PREFIX) && name.contains("__"))
+        .collect();
+    if !conf_file.exists() && overrides.is_empty() {
+        match conf_file.parent().filter(|_| path.is_some()) {
+            Some(dir) => fs::create_dir_all(dir)?,
+            None => util::create_proj_conf("", "", PROGRAM_NAME).unwrap(),
+        }
         write_default_config(&conf_file)?;
     }
 
-    let s = fs::read_to_string(&conf_file).unwrap();
-    config = Config::from_config(&s);
+    let s = match conf_file.exists() {
+        true => fs::read_to_string(&conf_file).unwrap(),
+        false => {
+            info!("No config file found, rpl is configured from the environment");
+            String::from(STOCK_CONFIG)
+        }
+    };
+    let config = Config::from_config(&s, overrides);
 
     Ok(config)
 }
 
+fn get_torrent_client<'a>(file_config: &'a Config, matches: &'a ArgMatches) -> &'a str {
+    match matches.value_of("torrent_client") {
+        Some(client) => client,
+        None => &file_config.rpl.torrent_client,
+    }
+}
+
+// max_size is a percentage of the free space, which then has to be known before chunking
+fn sized_by_free_space(file_config: &Config, matches: &ArgMatches) -> bool {
+    let percentage = matches.is_present("max_size_percentage")
+        || (!matches.is_present("max_size") && file_config.max_size_percentage_used().unwrap());
+    percentage && !matches.is_present("num_chunks")
+}
+
+// free_space is what qBittorrent reports with remote_free_space, see qbit_free_space
 fn get_running_config(
     file_config: &Config,
     matches: &ArgMatches,
+    free_space: Option<u64>,
 ) -> Result<RplRunningConfig, error::Error> {
-    let torrent_client = if let Some(client) = matches.value_of("torrent_client") {
-        client
-    } else {
-        &file_config.rpl.torrent_client
-    };
-
-    if torrent_client != "qbittorrent" {
-        return Err(error::Error::UnsupportedTorrentClient);
+    let torrent_client = get_torrent_client(file_config, matches);
+    match torrent_client {
+        "qbittorrent" | "transmission" | "aria2" | "rtorrent" => (),
+        _ => return Err(error::Error::UnsupportedTorrentClient),
     }
 
     let upload_client = if let Some(client) = matches.value_of("upload_client") {
@@ -385,6 +1211,17 @@ fn get_running_config(
 
     match upload_client {
         "rclone" | "fclone" | "gclone" | "xclone" => (),
+        "b2" => {
+            let b2 = &file_config.b2;
+            if b2.key_id.is_empty() || b2.application_key.is_empty() || b2.bucket.is_empty() {
+                return Err(error::Error::B2ConfigMissing);
+            }
+        }
+        "sftp" => {
+            if file_config.sftp.host.is_empty() {
+                return Err(error::Error::SftpConfigMissing);
+            }
+        }
         _ => {
             return Err(error::Error::UnsupportedRcloneVariant);
         }
@@ -392,12 +1229,9 @@ fn get_running_config(
 
     let save_path: String = if let Some(p) = matches.value_of("save_path") {
         let path = PathBuf::from(shellexpand::full(p).unwrap().into_owned());
-        match !path.exists() {
-            true => {
-                debug!("{} does not exist. I will create it now", path.display());
-                fs::create_dir_all(&path).expect("Could not create save_path from CLI arg");
-            }
-            false => (),
+        if !path.exists() {
+            debug!("{} does not exist. I will create it now", path.display());
+            fs::create_dir_all(&path).expect("Could not create save_path from CLI arg");
         }
         String::from(path.to_str().unwrap())
     } else {
@@ -409,68 +1243,153 @@ fn get_running_config(
         }
     };
 
+    let available_space = || match free_space {
+        Some(size) => Ok(size),
+        None => fs2::available_space(PathBuf::from(
+            shellexpand::full(&file_config.rpl.save_path)
+                .unwrap()
+                .into_owned(),
+        ))
+        .map_err(|_e| error::Error::DiskSpaceReadError),
+    };
     let max_size_allow: u64 = if let Some(percentage) = matches.value_of("max_size_percentage") {
         let p: u64 = percentage.parse::<u64>().unwrap();
         if p > 0 && p <= 100 {
-            let max_size_possible: u64 = match fs2::available_space(PathBuf::from(
-                shellexpand::full(&file_config.rpl.save_path)
-                    .unwrap()
-                    .into_owned(),
-            )) {
-                Ok(size) => size,
-                Err(_e) => return Err(error::Error::DiskSpaceReadError),
-            };
+            let max_size_possible: u64 = available_space()?;
 
             max_size_possible * p / 100
         } else {
             return Err(error::Error::InvalidMaxSizePercentage);
         }
     } else if let Some(size) = matches.value_of("max_size") {
-        parse_size(size).expect("Could not parse max_size from input")
+        util::parse_size_checked(size)?
     } else if file_config.max_size_percentage_used().unwrap() {
-        let max_size_possible: u64 = match fs2::available_space(PathBuf::from(
-            shellexpand::full(&file_config.rpl.save_path)
-                .unwrap()
-                .into_owned(),
-        )) {
-            Ok(size) => size,
-            Err(_e) => return Err(error::Error::DiskSpaceReadError),
-        };
+        let max_size_possible: u64 = available_space()?;
 
         max_size_possible * (file_config.rpl.max_size_percentage as u64) / 100
     } else {
-        parse_size(&file_config.rpl.max_size).expect("Could not parse max_size in file config")
+        util::parse_size_checked(&file_config.rpl.max_size)?
     };
 
-    let remote_path = if let Some(path) = matches.value_of("remote_path") {
-        path
+    let remote_paths = if let Some(paths) = matches.values_of("remote_path") {
+        paths.map(String::from).collect()
     } else {
         match &file_config.remote_path_invalid() {
             true => {
                 return Err(error::Error::RemotePathEmptyError);
             }
-            false => &file_config.rpl.remote_path,
+            false => file_config.rpl.remote_path.paths(),
         }
     };
 
+    let upload_mode = match matches
+        .value_of("upload_mode")
+        .unwrap_or(&file_config.rpl.upload_mode)
+    {
+        "round-robin" => UploadMode::RoundRobin,
+        "mirror" => UploadMode::Mirror,
+        _ => return Err(error::Error::InvalidUploadMode),
+    };
+
     let ignore_warning: bool = if matches.is_present("ignore_warning") {
         true
     } else {
         file_config.rpl.ignore_warning
     };
 
+    let manifest_path = if let Some(path) = matches.value_of("manifest") {
+        Some(path)
+    } else if !file_config.rpl.manifest_path.is_empty() {
+        Some(file_config.rpl.manifest_path.as_str())
+    } else {
+        None
+    }
+    .map(|path| PathBuf::from(shellexpand::full(path).unwrap().into_owned()));
+
+    let cross_seed_dir = if let Some(dir) = matches.value_of("cross_seed_dir") {
+        Some(dir)
+    } else if !file_config.rpl.cross_seed_dir.is_empty() {
+        Some(file_config.rpl.cross_seed_dir.as_str())
+    } else {
+        None
+    }
+    .map(|dir| PathBuf::from(shellexpand::full(dir).unwrap().into_owned()));
+
+    let webhook_url = if let Some(url) = matches.value_of("webhook_url") {
+        Some(String::from(url))
+    } else if !file_config.rpl.webhook_url.is_empty() {
+        Some(file_config.rpl.webhook_url.to_owned())
+    } else {
+        None
+    };
+
+    let notify_url = if let Some(url) = matches.value_of("notify_url") {
+        Some(String::from(url))
+    } else if !file_config.notifications.discord_webhook_url.is_empty() {
+        Some(file_config.notifications.discord_webhook_url.to_owned())
+    } else {
+        None
+    };
+
+    let notifications = &file_config.notifications;
+    let telegram = match (
+        notifications.telegram_bot_token.is_empty(),
+        notifications.telegram_chat_id.is_empty(),
+    ) {
+        (false, false) => Some((
+            notifications.telegram_bot_token.to_owned(),
+            notifications.telegram_chat_id.to_owned(),
+        )),
+        _ => None,
+    };
+
     let running_config = RplRunningConfig::new(
         max_size_allow,
-        //String::from(torrent_client),
+        String::from(torrent_client),
         String::from(upload_client),
         save_path,
-        String::from(remote_path),
+        remote_paths,
+        upload_mode,
         ignore_warning,
+        manifest_path,
+        cross_seed_dir,
+        webhook_url,
+        notify_url,
+        telegram,
     );
 
     Ok(running_config)
 }
 
+// the free space qBittorrent reports when remote_free_space is set, None to read save_path's on
+// this machine
+async fn qbit_free_space(
+    file_config: &Config,
+    matches: &ArgMatches<'_>,
+) -> Result<Option<u64>, error::Error> {
+    if !file_config.qbittorrent.remote_free_space
+        || get_torrent_client(file_config, matches) != "qbittorrent"
+    {
+        return Ok(None);
+    }
+    let qbconfig = get_qb_config(file_config, matches)?;
+    let qbit = QbitConfig::new(
+        &qbconfig.username,
+        &qbconfig.password,
+        &qbconfig.address,
+        &qbconfig.extra_headers,
+        qbconfig.pool_max_idle_per_host,
+        qbconfig.accept_invalid_certs,
+    )
+    .await?;
+    let free_space = qbit.free_space().await?;
+    debug!(
+        "qBittorrent reports {} of free space",
+        free_space.file_size(file_size_opts::BINARY).unwrap()
+    );
+    Ok(Some(free_space))
+}
+
 fn get_qb_config(
     file_config: &Config,
     matches: &ArgMatches,
@@ -494,18 +1413,79 @@ fn get_qb_config(
     };
 
     let upload_limit: u64 = if let Some(val) = matches.value_of("qbittorrent_upload_limit") {
-        parse_size(val).expect("Could not parse the value of qbittorrent upload limit")
+        util::parse_size_checked(val)?
     } else {
-        parse_size(&file_config.qbittorrent.upload_limit)
-            .expect("Could not parse qbittorrent upload limit in file config")
+        util::parse_size_checked(&file_config.qbittorrent.upload_limit)?
     };
 
     let download_limit: u64 = if let Some(val) = matches.value_of("qbittorrent_download_limit") {
-        parse_size(val).expect("Could not parse the value of qbittorrent download limit")
+        util::parse_size_checked(val)?
+    } else {
+        util::parse_size_checked(&file_config.qbittorrent.download_limit)?
+    };
+
+    let wait_for_client_secs: u32 = if let Some(val) = matches.value_of("qbittorrent_wait") {
+        val.parse()
+            .expect("Could not parse the value of qbittorrent wait time")
     } else {
-        parse_size(&file_config.qbittorrent.download_limit)
-            .expect("Could not parse qbittorrent upload limit in file config")
+        file_config.qbittorrent.wait_for_client_secs
+    };
+
+    let min_seeders: u32 = if let Some(val) = matches.value_of("min_seeders") {
+        val.parse()
+            .expect("Could not parse the value of min seeders")
+    } else {
+        file_config.qbittorrent.min_seeders
+    };
+
+    let skip_unseeded = match file_config.qbittorrent.min_seeders_policy.as_str() {
+        "fail" => false,
+        "skip" => true,
+        _ => return Err(error::Error::InvalidMinSeedersPolicy),
+    };
+
+    let download_path = match file_config.qbittorrent.download_path.is_empty() {
+        true => None,
+        false => Some(PathBuf::from(
+            shellexpand::full(&file_config.qbittorrent.download_path)
+                .expect("Could not find the correct path to download data")
+                .into_owned(),
+        )),
+    };
+
+    let heartbeat_secs: u32 = if let Some(val) = matches.value_of("heartbeat") {
+        val.parse().expect("Could not parse the value of heartbeat")
+    } else {
+        file_config.qbittorrent.heartbeat_secs
+    };
+
+    let instance_id = if let Some(id) = matches.value_of("instance_id") {
+        id
+    } else {
+        &file_config.qbittorrent.instance_id
+    };
+    let tag = match instance_id.is_empty() {
+        true => String::from(PROGRAM_NAME),
+        false => format!("{}-{}", PROGRAM_NAME, instance_id),
+    };
+
+    let category = match matches.value_of("qbittorrent_category") {
+        Some(category) => category,
+        None => &file_config.qbittorrent.category,
+    };
+    let extra_tags = match matches.value_of("qbittorrent_tags") {
+        Some(tags) => tags,
+        None => &file_config.qbittorrent.tags,
     };
+    // rpl's own tag always comes first, it is what tells rpl's torrents apart
+    let tags = std::iter::once(tag.as_str())
+        .chain(extra_tags.split(',').map(str::trim))
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<&str>>()
+        .join(",");
+
+    // fail now rather than on the first request
+    parse_headers(&file_config.qbittorrent.extra_headers)?;
 
     let config = RplRunningQbitConfig::new(
         String::from(username),
@@ -513,6 +1493,25 @@ fn get_qb_config(
         String::from(address),
         upload_limit as i64,
         download_limit as i64,
+        wait_for_client_secs,
+        String::from(category),
+        file_config.qbittorrent.use_category_path,
+        download_path,
+        min_seeders,
+        file_config.qbittorrent.min_seeders_timeout,
+        skip_unseeded,
+        file_config.qbittorrent.checking_resume_data_timeout,
+        file_config.qbittorrent.magnet_metadata_timeout,
+        heartbeat_secs,
+        file_config.qbittorrent.disk_full_wait_secs,
+        file_config.qbittorrent.stalled_poll_max_secs,
+        file_config.qbittorrent.max_concurrent_requests,
+        tags,
+        matches.is_present("exclusive") || file_config.qbittorrent.exclusive_mode,
+        file_config.qbittorrent.override_alt_limits,
+        file_config.qbittorrent.pool_max_idle_per_host,
+        file_config.qbittorrent.extra_headers.clone(),
+        matches.is_present("qbittorrent_insecure") || file_config.qbittorrent.accept_invalid_certs,
     );
 
     Ok(config)
@@ -534,79 +1533,195 @@ fn get_rclone_config(
         file_config.rclone.drive_chunk_size
     };
 
-    // TODO: find a way to not clone
-    let extra_custom_flags = file_config.rclone.extra_custom_flags.clone();
+    // exclude_patterns spells out the partial-file rules of incomplete_suffixes as whole patterns,
+    // they keep their place in front of exclude
+    let (incomplete_suffixes, mut exclude) = match &file_config.rclone.exclude_patterns {
+        Some(patterns) => (Vec::new(), patterns.clone()),
+        None => (file_config.rclone.incomplete_suffixes.clone(), Vec::new()),
+    };
+    exclude.extend(file_config.rclone.exclude.iter().cloned());
+    // the user's --exclude and --include become filter rules after the ones of [rclone]
+    let mut include = file_config.rclone.include.clone();
+    let extra_custom_flags = rclone::split_custom_filters(
+        &file_config.rclone.extra_custom_flags,
+        &mut exclude,
+        &mut include,
+    )?;
+
+    let rclone_log_level = if let Some(level) = matches.value_of("rclone_log_level") {
+        level
+    } else {
+        &file_config.rclone.rclone_log_level
+    }
+    .to_uppercase();
+    match rclone_log_level.as_str() {
+        "DEBUG" | "INFO" | "NOTICE" | "ERROR" => (),
+        _ => {
+            return Err(error::Error::InvalidRcloneLogLevel);
+        }
+    }
+
+    // fail now rather than when building the upload client
+    on_existing_remote(&file_config.rclone.on_existing_remote)?;
+
+    // negative values are already rejected when parsing the config
+    if file_config.rclone.rclone_retries == Some(0)
+        || file_config.rclone.rclone_low_level_retries == Some(0)
+    {
+        return Err(error::Error::InvalidRcloneRetries);
+    }
+
+    match file_config.rclone.rclone_command.as_str() {
+        "copy" | "move" => (),
+        _ => return Err(error::Error::InvalidRcloneCommand),
+    }
+
+    let drive_service_account_file = match file_config.rclone.drive_service_account_file.is_empty()
+    {
+        true => String::new(),
+        false => {
+            let path = shellexpand::full(&file_config.rclone.drive_service_account_file)
+                .expect("Could not find the correct path to the service account file")
+                .into_owned();
+            if !Path::new(&path).is_file() {
+                return Err(error::Error::ServiceAccountFileNotExist(path));
+            }
+            path
+        }
+    };
+    if file_config.rclone.remote_type != "drive" && !drive_service_account_file.is_empty() {
+        warn!(
+            "drive_service_account_file is ignored, remote_type is {}",
+            file_config.rclone.remote_type
+        );
+    }
 
-    let config = RplRcloneConfig::new(transfers, drive_chunk_size, extra_custom_flags);
+    let config = RplRcloneConfig::new(
+        transfers,
+        file_config.rclone.adaptive_transfers,
+        file_config.rclone.checkers,
+        file_config.rclone.rclone_retries,
+        file_config.rclone.rclone_low_level_retries,
+        file_config.rclone.upload_retries,
+        file_config.rclone.rclone_command.to_owned(),
+        drive_chunk_size,
+        file_config.rclone.remote_type.to_owned(),
+        extra_custom_flags,
+        incomplete_suffixes,
+        file_config.rclone.exclude_patterns.clone(),
+        exclude,
+        include,
+        file_config.rclone.atomic_upload,
+        file_config.rclone.preserve_mtime,
+        rclone_log_level,
+        file_config.rclone.on_existing_remote.to_owned(),
+        file_config.rclone.rclone_user_agent.to_owned(),
+        matches
+            .value_of("rclone_bwlimit")
+            .unwrap_or(&file_config.rclone.bwlimit)
+            .to_owned(),
+        drive_service_account_file,
+    );
     Ok(config)
 }
 
 struct TorrentInput {
-    raw_data: Vec<u8>,
+    torrent: Torrent,
     input_type: RplInputType,
+    // file indices selected by the `so=` parameter of a magnet link
+    selected: Option<BTreeSet<usize>>,
 }
 
 enum RplInputType {
     NormalPath,
     UrlLink,
     MagnetString,
+    Stdin,
 }
 
 impl TorrentInput {
-    fn new(raw_data: Vec<u8>, input_type: RplInputType) -> Self {
-        Self {
-            raw_data,
+    // decode the torrent once, rejecting broken torrents here instead of panicking on them later
+    fn new(raw_data: &[u8], input_type: RplInputType) -> Result<Self, error::Error> {
+        // a v2 only torrent has a meta version of 2 but none of the v1 fields lava_torrent needs
+        let torrent = Torrent::read_from_bytes(raw_data).map_err(|e| {
+            match raw_data
+                .windows(18)
+                .any(|window| window == b"12:meta versioni2e")
+            {
+                true => error::Error::V2OnlyTorrent,
+                false => error::Error::CorruptTorrent(e.to_string()),
+            }
+        })?;
+        validate_torrent(&torrent)?;
+        Ok(Self {
+            torrent,
             input_type,
-        }
+            selected: None,
+        })
     }
 }
 
-async fn parse_input(matches: &ArgMatches<'_>) -> Result<TorrentInput, error::Error> {
-    let input = matches.value_of("input").unwrap();
+// a magnet link is resolved to a torrent file by qBittorrent, with the settings of matches
+async fn parse_input(
+    file_config: &Config,
+    matches: &ArgMatches<'_>,
+    input_matches: &ArgMatches<'_>,
+) -> Result<TorrentInput, error::Error> {
+    let input = input_matches.value_of("input").unwrap();
 
     let try_path = shellexpand::full(input)
         .expect("Could not look up a variable in input")
         .into_owned();
-    if Path::new(&try_path).exists() {
+    if input == "-" {
+        debug!("User inputted `-`. Will now read the torrent from stdin and try to parse it.");
+        let mut raw_torrent = Vec::new();
+        std::io::stdin().read_to_end(&mut raw_torrent)?;
+        TorrentInput::new(&raw_torrent, RplInputType::Stdin)
+    } else if Path::new(&try_path).exists() {
         debug!("User inputted a normal path. Will now try to parse it.");
         let mut torrent_file = File::open(&try_path).unwrap();
         let mut raw_torrent = Vec::new();
         torrent_file.read_to_end(&mut raw_torrent)?;
-        Ok(TorrentInput::new(raw_torrent, RplInputType::NormalPath))
+        TorrentInput::new(&raw_torrent, RplInputType::NormalPath)
+    } else if input.starts_with("magnet:") {
+        debug!("User inputted a magnet link, will now let qBittorrent fetch its metadata");
+        match get_torrent_client(file_config, matches) {
+            "transmission" => return Err(error::Error::TransmissionUnsupported("a magnet link")),
+            "aria2" => return Err(error::Error::Aria2Unsupported("a magnet link")),
+            "rtorrent" => return Err(error::Error::RtorrentUnsupported("a magnet link")),
+            _ => (),
+        }
+        let hash = magnet_info_hash(input)?;
+        let qbconfig = get_qb_config(file_config, matches)?;
+        if qbconfig.wait_for_client_secs > 0 {
+            QbitConfig::wait_for_client(
+                &qbconfig.address,
+                qbconfig.wait_for_client_secs,
+                qbconfig.accept_invalid_certs,
+            )
+            .await?;
+        }
+        let qbit = QbitConfig::new(
+            &qbconfig.username,
+            &qbconfig.password,
+            &qbconfig.address,
+            &qbconfig.extra_headers,
+            qbconfig.pool_max_idle_per_host,
+            qbconfig.accept_invalid_certs,
+        )
+        .await?;
+        info!("Fetching the metadata of the magnet link through qBittorrent...");
+        let response = qbit
+            .fetch_magnet_metadata(input, &hash, qbconfig.magnet_metadata_timeout)
+            .await?;
+        let mut parsed = TorrentInput::new(&response, RplInputType::MagnetString)?;
+        let no_files = parsed.torrent.files.as_ref().map_or(1, Vec::len);
+        parsed.selected = magnet_selected_files(input, no_files)?;
+        Ok(parsed)
     } else if url::Url::parse(input).is_ok() {
         debug!("User inputted a url link. Will now download its content and try to parse it.");
         let response = reqwest::get(input).await?.bytes().await?;
-        Ok(TorrentInput::new(response.to_vec(), RplInputType::UrlLink))
-    } else if input.contains("magnet") {
-        // TODO: This is not ideal, maybe use a dedicated crate
-        debug!("User inputted a magnet link, will now download the torrent file first");
-        let client = reqwest::Client::new();
-        let torrent_resp = client
-            .get(format!(
-                "https://anonymiz.com/magnet2torrent/magnet2torrent.php?magnet={}",
-                input
-            ))
-            .send()
-            .await?
-            .json::<MagnetTorrent>()
-            .await?;
-
-        let re = regex::Regex::new("^(https://itorrents.org/torrent/.*.torrent)<").unwrap();
-        let caps = re
-            .captures(&torrent_resp.url)
-            .expect("Could not capture torrent file link from MagnetTorrent response");
-        let torrent_location = caps
-            .get(1)
-            .expect("Could not get capture group 1 from captures")
-            .as_str();
-
-        debug!("The torrent file location is {}", torrent_location);
-
-        let response = reqwest::get(torrent_location).await?.bytes().await?;
-        Ok(TorrentInput::new(
-            response.to_vec(),
-            RplInputType::MagnetString,
-        ))
+        TorrentInput::new(&response, RplInputType::UrlLink)
     } else {
         Err(error::Error::RplInvalidInput)
     }
@@ -615,8 +1730,9 @@ async fn parse_input(matches: &ArgMatches<'_>) -> Result<TorrentInput, error::Er
 fn get_seed_config(
     file_config: &Config,
     matches: &ArgMatches,
+    force: bool,
 ) -> Result<SeedSettings, error::Error> {
-    let seed: bool = if matches.is_present("seed_enable") {
+    let seed: bool = if force || matches.is_present("seed_enable") {
         true
     } else {
         *file_config.seed_settings.seed_enable()
@@ -631,10 +1747,10 @@ fn get_seed_config(
         seed_path = if let Some(p) = matches.value_of("seed_path") {
             let path = PathBuf::from(shellexpand::full(p).unwrap().into_owned());
             match path.exists() {
-                true => {
+                false => {
                     return Err(error::Error::MountPathNotExist);
                 }
-                false => String::from(path.to_str().unwrap()),
+                true => String::from(path.to_str().unwrap()),
             }
         } else {
             match &file_config.seed_path_invalid()? {
@@ -652,16 +1768,80 @@ fn get_seed_config(
         *file_config.seed_settings.seed_wait()
     };
 
-    let config = SeedSettings::new(seed, seed_path, seed_wait);
+    let config = SeedSettings::new(
+        seed,
+        seed_path,
+        seed_wait,
+        *file_config.seed_settings.seed_ratio_limit(),
+        *file_config.seed_settings.seed_time_limit(),
+    );
 
     Ok(config)
 }
 
+// The chunk layout of the pack: --num-chunks (which sets max_size), the manifest, the files the
+// magnet link selects, the chunk strategy, exclude globs and the chunk order. Shared by the run and
+// `rpl verify` so both number the chunks the same way.
+// value of a numeric flag, a typo is reported like any other bad input instead of panicking
+fn number_arg<T: FromStr>(matches: &ArgMatches, name: &str) -> Result<Option<T>, error::Error> {
+    matches
+        .value_of(name)
+        .map(|val| {
+            val.parse().map_err(|_| {
+                error::Error::InvalidNumber(
+                    format!("--{}", name.replace('_', "-")),
+                    String::from(val),
+                )
+            })
+        })
+        .transpose()
+}
+
+fn pack_layout(
+    file_config: &Config,
+    matches: &ArgMatches,
+    config: &mut RplRunningConfig,
+    parsed_input: &TorrentInput,
+) -> Result<TorrentPack, error::Error> {
+    if let Some(num_chunks) = number_arg::<u32>(matches, "num_chunks")? {
+        config.max_size = max_size_for_chunks(&parsed_input.torrent, num_chunks)? as u64;
+        info!(
+            "Splitting the pack into {} chunks of at most {}",
+            num_chunks,
+            config.max_size.file_size(file_size_opts::BINARY).unwrap()
+        );
+    }
+    check_max_size_requirements(config, &parsed_input.torrent)?;
+
+    let mut pack_config = TorrentPack::new(parsed_input.torrent.clone(), config.ignore_warning)
+        .max_size(config.max_size as i64);
+    if let Some(path) = &config.manifest_path {
+        pack_config = pack_config.manifest(Manifest::load(path.clone())?);
+    }
+    if let Some(selected) = parsed_input.selected.clone() {
+        info!(
+            "The magnet link selects {} files, every other file will be skipped",
+            selected.len()
+        );
+        pack_config = pack_config.select_files(selected);
+    }
+    pack_config = pack_config
+        .chunk_strategy(get_chunk_strategy(file_config, matches)?)
+        .exclude_globs(get_exclude_globs(file_config, matches)?);
+    if let Some(n) = number_arg::<usize>(matches, "biggest_first")? {
+        pack_config = pack_config.biggest_first(n);
+    }
+    if matches.is_present("largest_first") {
+        pack_config = pack_config.largest_chunks_first(true);
+    }
+    Ok(pack_config)
+}
+
 fn check_max_size_requirements(
     config: &RplRunningConfig,
-    raw_data: &[u8],
+    torrent: &Torrent,
 ) -> Result<(), error::Error> {
-    let largest_file = get_largest_filesize(Torrent::read_from_bytes(raw_data).unwrap());
+    let largest_file = get_largest_filesize(torrent);
 
     if largest_file > config.max_size as i64 {
         match config.ignore_warning {
@@ -687,23 +1867,362 @@ fn check_max_size_requirements(
     }
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<()> {
-    let matches = App::new(PROGRAM_NAME)
-        .setting(AppSettings::DisableHelpSubcommand)
-        .version(crate_version!())
-        .author(crate_authors!())
-        .about(crate_description!())
-        .arg(
+// only re-add the torrent for seeding, the data is expected to already be under seed_path
+async fn seed_only(
+    file_config: &Config,
+    matches: &ArgMatches<'_>,
+    seed_matches: &ArgMatches<'_>,
+) -> Result<()> {
+    match get_torrent_client(file_config, matches) {
+        "transmission" => return Err(error::Error::TransmissionUnsupported("rpl seed").into()),
+        "aria2" => return Err(error::Error::Aria2Unsupported("rpl seed").into()),
+        "rtorrent" => return Err(error::Error::RtorrentUnsupported("rpl seed").into()),
+        _ => (),
+    }
+    let qbconfig = get_qb_config(file_config, matches)?;
+    let mut seed_config = get_seed_config(file_config, seed_matches, true)?;
+    let torrent = parse_input(file_config, matches, seed_matches)
+        .await?
+        .torrent;
+    let name = torrent.name.to_owned();
+    // a pack leeched with nest_save_path was uploaded into a folder of its own
+    if let Some(dir) = nested_dir(file_config, matches, &name) {
+        seed_config = seed_config.nest(&dir);
+    }
+
+    let seed_path = PathBuf::from(
+        shellexpand::full(seed_config.seed_path())
+            .unwrap()
+            .into_owned(),
+    );
+    if !seed_path.join(&name).exists() {
+        warn!(
+            "`{}` was not found in {}, qBittorrent will not be able to seed it",
+            name,
+            seed_path.display()
+        );
+    }
+
+    let mut torrent_config = QbitTorrent::default()
+        .torrents(torrent.clone())
+        .paused(true)
+        .upload_limit(qbconfig.upload_limit)
+        .download_limit(qbconfig.download_limit)
+        .tags(&qbconfig.tags);
+    if !qbconfig.category.is_empty() {
+        torrent_config = torrent_config.category(&qbconfig.category);
+    }
+
+    if qbconfig.wait_for_client_secs > 0 {
+        QbitConfig::wait_for_client(
+            &qbconfig.address,
+            qbconfig.wait_for_client_secs,
+            qbconfig.accept_invalid_certs,
+        )
+        .await?;
+    }
+    let qbit = QbitConfig::new(
+        &qbconfig.username,
+        &qbconfig.password,
+        &qbconfig.address,
+        &qbconfig.extra_headers,
+        qbconfig.pool_max_idle_per_host,
+        qbconfig.accept_invalid_certs,
+    )
+    .await?;
+    let hash = qbit.torrent_id(&torrent);
+
+    info!(
+        "Adding `{}` to qBittorrent for seeding from {}",
+        name,
+        seed_path.display()
+    );
+    qbit.seed_torrent(torrent_config, &hash, &seed_config)
+        .await?;
+    info!("`{}` is now seeding!", name);
+    Ok(())
+}
+
+// compare the remote against the pack chunk by chunk, exits with VERIFY_INCOMPLETE_EXIT_CODE when
+// any chunk is not fully uploaded
+async fn verify_only(
+    file_config: &Config,
+    matches: &ArgMatches<'_>,
+    verify_matches: &ArgMatches<'_>,
+) -> Result<()> {
+    let free_space = match sized_by_free_space(file_config, matches) {
+        true => qbit_free_space(file_config, matches).await?,
+        false => None,
+    };
+    let mut config = get_running_config(file_config, matches, free_space)?;
+    let parsed_input = parse_input(file_config, matches, verify_matches).await?;
+    let torrent = &parsed_input.torrent;
+    let name = torrent.name.to_owned();
+    if let Some(dir) = nested_dir(file_config, matches, &name) {
+        config.nest(&dir);
+    }
+    let is_file = torrent.files.is_none();
+
+    let mut pack_config = pack_layout(file_config, matches, &mut config, &parsed_input)?;
+    let queue = build_queue(pack_config.chunks()?, torrent.clone())?;
+
+    let source = PathBuf::from(shellexpand::full(&config.save_path).unwrap().into_owned());
+    let upload_client = boxed_upload_client(
+        file_config,
+        RcloneClient::new(
+            config.upload_client,
+            source,
+            config.remote_path,
+            0,
+            0,
+            Vec::new(),
+        ),
+        &config.remote_paths,
+        config.upload_mode,
+    )
+    .await?;
+    let remote = upload_client.list_remote(&name, is_file).await?;
+
+    let reports = queue.verify(&remote);
+    for report in &reports {
+        println!("{}", report);
+    }
+    if !reports.iter().all(|report| report.is_complete()) {
+        std::process::exit(VERIFY_INCOMPLETE_EXIT_CODE);
+    }
+    Ok(())
+}
+
+// the B2 client of [b2], uploading from source to the folder destination of the bucket
+async fn get_b2_client(
+    file_config: &Config,
+    source: PathBuf,
+    destination: &str,
+) -> Result<B2Client, error::Error> {
+    let b2 = &file_config.b2;
+    B2Client::new(
+        &b2.key_id,
+        &b2.application_key,
+        &b2.bucket,
+        source,
+        destination,
+    )
+    .await
+}
+
+// print a checkmark or the error of a check of `rpl config validate`, true when it passed
+fn report_check(name: &str, result: Result<(), error::Error>) -> bool {
+    match result {
+        Ok(()) => {
+            println!("\x1B[32m\u{2714}\x1B[0m {}", name);
+            true
+        }
+        Err(e) => {
+            println!("\x1B[31m\u{2718}\x1B[0m {}: {}", name, e);
+            false
+        }
+    }
+}
+
+// run every check of the config a leech would, without leeching, and exit with 1 if any fails
+async fn validate_config(file_config: &Config, matches: &ArgMatches<'_>) -> Result<()> {
+    let mut passed = report_check(
+        "save_path",
+        match file_config.save_path_invalid() {
+            true => Err(error::Error::SavePathEmptyError),
+            false => Ok(()),
+        },
+    );
+    passed &= report_check(
+        "remote_path",
+        match file_config.remote_path_invalid() {
+            true => Err(error::Error::RemotePathEmptyError),
+            false => Ok(()),
+        },
+    );
+    passed &= report_check("seed_path", file_config.seed_path_invalid().map(|_| ()));
+    passed &= report_check(
+        "max_size",
+        match file_config.max_size_percentage_used() {
+            Ok(true) => Ok(()),
+            Ok(false) => util::parse_size_checked(&file_config.rpl.max_size).map(|_| ()),
+            Err(e) => Err(e),
+        },
+    );
+
+    let upload_client = matches
+        .value_of("upload_client")
+        .unwrap_or(&file_config.rpl.upload_client);
+    let upload_check = match upload_client {
+        "b2" => {
+            let paths = file_config.rpl.remote_path.paths();
+            let destination = paths.first().map_or("", String::as_str);
+            get_b2_client(file_config, PathBuf::new(), destination)
+                .await
+                .map(|_| ())
+        }
+        variant => rclone::check_installed(variant),
+    };
+    passed &= report_check(&format!("upload_client ({})", upload_client), upload_check);
+
+    let timeout = Duration::from_secs(VALIDATE_LOGIN_TIMEOUT_SECS);
+    let login = match get_torrent_client(file_config, matches) {
+        "transmission" => {
+            let transmission = &file_config.transmission;
+            let login = TransmissionConfig::new(
+                &transmission.address,
+                &transmission.username,
+                &transmission.password,
+            );
+            match tokio::time::timeout(timeout, login).await {
+                Ok(login) => login.map(|_| ()),
+                Err(_) => Err(error::Error::ClientTimeout("Transmission")),
+            }
+        }
+        "aria2" => {
+            let login = Aria2Config::new(&file_config.aria2.address, &file_config.aria2.secret);
+            match tokio::time::timeout(timeout, login).await {
+                Ok(login) => login.map(|_| ()),
+                Err(_) => Err(error::Error::ClientTimeout("aria2")),
+            }
+        }
+        "rtorrent" => {
+            let rtorrent = &file_config.rtorrent;
+            let login =
+                RtorrentConfig::new(&rtorrent.address, &rtorrent.username, &rtorrent.password);
+            match tokio::time::timeout(timeout, login).await {
+                Ok(login) => login.map(|_| ()),
+                Err(_) => Err(error::Error::ClientTimeout("rTorrent")),
+            }
+        }
+        _ => match get_qb_config(file_config, matches) {
+            Ok(qbconfig) => {
+                let login = QbitConfig::new(
+                    &qbconfig.username,
+                    &qbconfig.password,
+                    &qbconfig.address,
+                    &qbconfig.extra_headers,
+                    qbconfig.pool_max_idle_per_host,
+                    qbconfig.accept_invalid_certs,
+                );
+                match tokio::time::timeout(timeout, login).await {
+                    Ok(login) => login.map(|_| ()),
+                    Err(_) => Err(error::Error::ClientTimeout("qBittorrent")),
+                }
+            }
+            Err(e) => Err(e),
+        },
+    };
+    passed &= report_check("torrent client login", login);
+
+    if !passed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// upload a file of random data to the remote and report the throughput, to help pick max_size
+async fn bench_only(
+    file_config: &Config,
+    matches: &ArgMatches<'_>,
+    bench_matches: &ArgMatches<'_>,
+) -> Result<()> {
+    let config = get_running_config(file_config, matches, None)?;
+    match config.upload_client.as_str() {
+        "b2" => return Err(error::Error::B2Unsupported("rpl bench").into()),
+        "sftp" => return Err(error::Error::SftpUnsupported("rpl bench").into()),
+        _ => (),
+    }
+    let rclone_config = get_rclone_config(file_config, matches)?;
+    let size = util::parse_size_checked(bench_matches.value_of("size").unwrap())?;
+
+    let bench_dir = format!("{}-bench-{}", PROGRAM_NAME, std::process::id());
+    let on_existing = rclone_config.on_existing();
+    let user_agent = rclone_config.user_agent();
+    let service_account_file = rclone_config.service_account_file();
+    let drive = rclone_config.is_drive();
+    let upload_client = RcloneClient::new(
+        config.upload_client,
+        PathBuf::from(shellexpand::full(&config.save_path).unwrap().into_owned()).join(&bench_dir),
+        format!("{}/{}", config.remote_path.trim_end_matches('/'), bench_dir),
+        rclone_config.transfers,
+        rclone_config.drive_chunk_size,
+        rclone_config.extra_custom_flags,
+    )
+    .checkers(rclone_config.checkers)
+    .retries(
+        rclone_config.rclone_retries,
+        rclone_config.rclone_low_level_retries,
+    )
+    .atomic_upload(rclone_config.atomic_upload)
+    .log_level(rclone_config.rclone_log_level)
+    .on_existing_remote(on_existing)
+    .user_agent(user_agent)
+    .drive_flags(drive)
+    .drive_service_account_file(service_account_file);
+
+    info!(
+        "Uploading {} of random data to {}",
+        size.file_size(file_size_opts::BINARY).unwrap(),
+        upload_client.get_destination()
+    );
+    let elapsed = upload_client.bench(size)?;
+    let speed = (size as f64 / elapsed.as_secs_f64().max(0.001)) as u64;
+    println!(
+        "Uploaded {} in {} ({}/s)",
+        size.file_size(file_size_opts::BINARY).unwrap(),
+        util::format_duration(elapsed.as_secs()),
+        speed.file_size(file_size_opts::BINARY).unwrap()
+    );
+    println!(
+        "A chunk of max_size {} would take about {} to upload",
+        config.max_size.file_size(file_size_opts::BINARY).unwrap(),
+        util::format_duration(config.max_size / max(speed, 1))
+    );
+
+    if bench_matches.is_present("keep") {
+        info!(
+            "Keeping the test file in {}",
+            upload_client.get_destination()
+        );
+    } else {
+        upload_client.purge_destination()?;
+    }
+    Ok(())
+}
+
+fn build_app() -> App<'static, 'static> {
+    App::new(PROGRAM_NAME)
+        .setting(AppSettings::DisableHelpSubcommand)
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about(crate_description!())
+        .arg(
             Arg::with_name("input")
-                .help("Input torrent file or url link or magnet string")
+                .help(
+                    "Input torrent file or url link or magnet string, or - to read the torrent \
+                     from stdin",
+                )
                 .index(1)
                 .takes_value(true)
                 .required(true),
         )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .global(true)
+                .value_name("PATH")
+                .takes_value(true)
+                .help(
+                    "Use this config file instead of the one in the config dir (created with the \
+                     defaults if missing)",
+                ),
+        )
         .arg(
             Arg::with_name("log")
                 .long("log")
+                .global(true)
                 .takes_value(true)
                 .help("Also log output to file (for debugging)"),
         )
@@ -711,6 +2230,7 @@ async fn main() -> Result<()> {
             Arg::with_name("verbose")
                 .short("v")
                 .long("verbose")
+                .global(true)
                 .multiple(true)
                 .help("Sets the level of debug information verbosity"),
         )
@@ -730,6 +2250,24 @@ async fn main() -> Result<()> {
                 .takes_value(true)
                 .help("Set disk space allowed for rpl"),
         )
+        .arg(
+            Arg::with_name("num_chunks")
+                .long("num-chunks")
+                .value_name("VALUE")
+                .takes_value(true)
+                .conflicts_with_all(&["max_size", "max_size_percentage"])
+                .help("Split the pack into this many chunks instead of using max_size"),
+        )
+        .arg(
+            Arg::with_name("max_chunks")
+                .long("max-chunks")
+                .value_name("VALUE")
+                .takes_value(true)
+                .help(
+                    "Stop before leeching anything when the pack would be split into more chunks \
+                     than this",
+                ),
+        )
         .arg(
             Arg::with_name("torrent_client")
                 .long("torrent-client")
@@ -758,7 +2296,17 @@ async fn main() -> Result<()> {
                 .long("remote-path")
                 .value_name("PATH")
                 .takes_value(true)
-                .help("Set the remote path"),
+                .multiple(true)
+                .number_of_values(1)
+                .help("Set the remote path, repeat it to upload to several remotes"),
+        )
+        .arg(
+            Arg::with_name("upload_mode")
+                .long("upload-mode")
+                .value_name("MODE")
+                .takes_value(true)
+                .possible_values(&["round-robin", "mirror"])
+                .help("How chunks are spread over several remote paths"),
         )
         .arg(
             Arg::with_name("ignore_warning")
@@ -790,6 +2338,226 @@ async fn main() -> Result<()> {
                 .takes_value(true)
                 .help("Skip number of chunks (in case of unexpected errors)"),
         )
+        .arg(
+            Arg::with_name("only")
+                .long("only")
+                .value_name("CHUNKS")
+                .takes_value(true)
+                .conflicts_with_all(&["skip", "no_resume"])
+                .help(
+                    "Leech these chunks only, e.g. the ones that failed in an earlier run \
+                     (example: 3,5,7-9)",
+                ),
+        )
+        .arg(
+            Arg::with_name("chunk_strategy")
+                .long("chunk-strategy")
+                .value_name("STRATEGY")
+                .takes_value(true)
+                .possible_values(&["sequential", "firstfit-decreasing"])
+                .help("How files are spread over chunks"),
+        )
+        .arg(
+            Arg::with_name("exclude_glob")
+                .long("exclude-glob")
+                .value_name("GLOB")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Never download the files matching this glob (e.g. \"*.nfo\"), can be repeated",
+                ),
+        )
+        .arg(
+            Arg::with_name("biggest_first")
+                .long("biggest-first")
+                .value_name("N")
+                .takes_value(true)
+                .help("Leech the N largest files of the pack first, then the rest in order"),
+        )
+        .arg(
+            Arg::with_name("largest_first")
+                .long("largest-first")
+                .conflicts_with("biggest_first")
+                .help(
+                    "Leech the chunks from the largest to the smallest, so a problematic chunk \
+                     fails early",
+                ),
+        )
+        .arg(
+            Arg::with_name("no_resume")
+                .long("no-resume")
+                .conflicts_with("skip")
+                .help(
+                    "Start from the first chunk even if an earlier run of the pack was interrupted",
+                ),
+        )
+        .arg(
+            Arg::with_name("replan")
+                .long("replan")
+                .help("Resume even if the chunk layout changed since the pack was last leeched"),
+        )
+        .arg(
+            Arg::with_name("upload_budget")
+                .long("upload-budget")
+                .value_name("SIZE")
+                .takes_value(true)
+                .help("Stop cleanly before uploading more than SIZE in this run (e.g. 100GiB)"),
+        )
+        .arg(
+            Arg::with_name("output_dir")
+                .long("output-dir")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Keep a local copy of each chunk in this directory after uploading"),
+        )
+        .arg(
+            Arg::with_name("keep_after_upload")
+                .long("keep")
+                .conflicts_with("output_dir")
+                .help(
+                    "Leave the files of each chunk in save_path after uploading (qBittorrent only)",
+                ),
+        )
+        .arg(Arg::with_name("strict").long("strict").help(
+            "Stop before downloading anything when the run may not fit in save_path's free \
+             space",
+        ))
+        .arg(
+            Arg::with_name("graceful").long("graceful").help(
+                "Finish uploading the current chunk on the first Ctrl-C, abort on the second",
+            ),
+        )
+        .arg(
+            Arg::with_name("json_errors")
+                .long("json-errors")
+                .global(true)
+                .help(
+                    "On failure, print the error to stderr as a single JSON object with its \
+                     category and context",
+                ),
+        )
+        .arg(Arg::with_name("no_op").long("no-op").help(
+            "Run the whole flow against qBittorrent with throttled downloads and a rclone \
+             dry-run upload, for testing",
+        ))
+        .arg(
+            Arg::with_name("manifest")
+                .long("manifest")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Skip files already recorded in this upload manifest and record new uploads"),
+        )
+        .arg(
+            Arg::with_name("cross_seed_dir")
+                .long("cross-seed-dir")
+                .value_name("PATH")
+                .takes_value(true)
+                .help(
+                    "Reuse files with the same name and size found in this directory instead of \
+                     downloading them",
+                ),
+        )
+        .arg(
+            Arg::with_name("verify_pieces")
+                .long("verify-pieces")
+                .help("Check the piece hashes of every downloaded chunk before uploading it"),
+        )
+        .arg(
+            Arg::with_name("final_verify_count")
+                .long("final-verify-count")
+                .help(
+                    "Check that every file uploaded by the run is on the remote once every chunk \
+                     is uploaded",
+                ),
+        )
+        .arg(
+            Arg::with_name("pipeline")
+                .long("pipeline")
+                .help("Download the next chunk while the previous one is uploading"),
+        )
+        .arg(
+            Arg::with_name("nest_save_path")
+                .long("nest-save-path")
+                .help(
+                    "Put the pack in a folder named after the torrent under save_path and \
+                     remote_path",
+                ),
+        )
+        .arg(
+            Arg::with_name("webhook_url")
+                .long("webhook")
+                .value_name("URL")
+                .takes_value(true)
+                .help("POST a JSON payload to this url on every state transition of the run"),
+        )
+        .arg(
+            Arg::with_name("notify_url")
+                .long("notify-url")
+                .value_name("URL")
+                .takes_value(true)
+                .help(
+                    "Send a summary of the run to this Discord webhook once it finishes or fails",
+                ),
+        )
+        .arg(
+            Arg::with_name("desktop_notify")
+                .long("desktop-notify")
+                .help("Show a desktop notification once the run finishes or fails"),
+        )
+        .arg(
+            Arg::with_name("summary_path")
+                .long("summary-path")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Write a JSON summary of the run to this file once it finishes or fails"),
+        )
+        .arg(
+            Arg::with_name("estimate")
+                .long("estimate")
+                .value_name("SPEED")
+                .takes_value(true)
+                .help(
+                    "Print the estimated download and upload time at this speed and exit (e.g. \
+                     50Mbps, 10MiB/s)",
+                ),
+        )
+        .arg(
+            Arg::with_name("dry_run")
+                .long("dry-run")
+                .conflicts_with("estimate")
+                .help(
+                    "Print the files of every chunk and exit without touching the torrent or \
+                     upload client",
+                ),
+        )
+        .arg(
+            Arg::with_name("list_chunks")
+                .long("list-chunks")
+                .conflicts_with_all(&["estimate", "dry_run"])
+                .help(
+                    "Print the chunks and their files as JSON and exit without touching the \
+                     torrent or upload client",
+                ),
+        )
+        .arg(
+            Arg::with_name("to_magnet")
+                .long("to-magnet")
+                .help("Print the magnet link of the torrent and exit"),
+        )
+        .arg(Arg::with_name("emit_script").long("emit-script").help(
+            "Print a shell script of the qBittorrent API calls and rclone commands instead \
+                     of running them",
+        ))
+        .arg(
+            Arg::with_name("announce")
+                .long("announce")
+                .value_name("URL")
+                .takes_value(true)
+                .help(
+                    "Announce the torrent to this tracker first (private torrents need -f/--force)",
+                ),
+        )
         .arg(
             Arg::with_name("qbittorrent_username")
                 .long("qbu")
@@ -825,6 +2593,64 @@ async fn main() -> Result<()> {
                 .takes_value(true)
                 .help("Set the download limit for torrents in qBittorrent (value/second)"),
         )
+        .arg(
+            Arg::with_name("min_seeders")
+                .long("min-seeders")
+                .value_name("VALUE")
+                .takes_value(true)
+                .help("Set the minimum number of seeders required before downloading a chunk"),
+        )
+        .arg(
+            Arg::with_name("qbittorrent_wait")
+                .long("qb-wait")
+                .value_name("VALUE")
+                .takes_value(true)
+                .help("Wait for qBittorrent Web UI to be ready before logging in (in seconds)"),
+        )
+        .arg(
+            Arg::with_name("heartbeat")
+                .long("heartbeat")
+                .value_name("VALUE")
+                .takes_value(true)
+                .help("Log the download progress every VALUE seconds (0 to disable)"),
+        )
+        .arg(
+            Arg::with_name("exclusive")
+                .long("exclusive")
+                .help("Pause every other active torrent in qBittorrent while rpl runs"),
+        )
+        .arg(
+            Arg::with_name("instance_id")
+                .long("instance-id")
+                .value_name("ID")
+                .takes_value(true)
+                .help(
+                    "Tag added torrents as rpl-<ID> so several rpl instances can share one \
+                     qBittorrent",
+                ),
+        )
+        .arg(
+            Arg::with_name("qbittorrent_insecure")
+                .long("qb-insecure")
+                .help(
+                    "Do not verify the TLS certificate of qBittorrent Web UI (e.g. a self-signed \
+                     one)",
+                ),
+        )
+        .arg(
+            Arg::with_name("qbittorrent_category")
+                .long("qb-category")
+                .value_name("CATEGORY")
+                .takes_value(true)
+                .help("Set the category of torrents added to qBittorrent"),
+        )
+        .arg(
+            Arg::with_name("qbittorrent_tags")
+                .long("qb-tags")
+                .value_name("TAGS")
+                .takes_value(true)
+                .help("Add these comma separated tags to torrents added to qBittorrent"),
+        )
         .arg(
             Arg::with_name("rclone_transfers")
                 .short("t")
@@ -840,14 +2666,382 @@ async fn main() -> Result<()> {
                 .takes_value(true)
                 .help("Set the rclone's drive chunk size value (in MiB)"),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("rclone_log_level")
+                .long("rclone-log-level")
+                .value_name("LEVEL")
+                .takes_value(true)
+                .help("Set the rclone's own log level (DEBUG, INFO, NOTICE or ERROR)"),
+        )
+        .arg(
+            Arg::with_name("rclone_bwlimit")
+                .long("bwlimit")
+                .value_name("LIMIT")
+                .takes_value(true)
+                .help("Limit rclone's upload bandwidth, e.g. 10M or \"08:00,512k 23:00,off\""),
+        )
+        .subcommand(
+            SubCommand::with_name("seed")
+                .about("Seed a previously leeched pack from seed_path without downloading it again")
+                .arg(
+                    Arg::with_name("input")
+                        .help("Input torrent file or url link or magnet string")
+                        .index(1)
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("seed_path")
+                        .long("seed-path")
+                        .value_name("PATH")
+                        .help("Set the rclone's mount path used for seeding"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about(
+                    "Report which chunks of a pack are complete, partial or missing on the remote \
+                     (exits with 2 if any chunk is incomplete)",
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .help("Input torrent file or url link or magnet string")
+                        .index(1)
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("resume")
+                .about("List interrupted runs or continue one from the last uploaded chunk")
+                .arg(
+                    Arg::with_name("hash")
+                        .help("Info hash of the run to continue")
+                        .index(1)
+                        .takes_value(true)
+                        .required_unless("list"),
+                )
+                .arg(
+                    Arg::with_name("list")
+                        .long("list")
+                        .help("List the interrupted runs with their progress"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Inspect the config file")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("validate").about(
+                    "Check every setting and log in to the torrent client without leeching \
+                     (exits with 1 if any check fails)",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("Upload a file of random data to remote_path and report the upload speed")
+                .arg(
+                    Arg::with_name("size")
+                        .long("size")
+                        .value_name("SIZE")
+                        .default_value("256 MiB")
+                        .help("Size of the test file"),
+                )
+                .arg(
+                    Arg::with_name("keep")
+                        .long("keep")
+                        .help("Do not delete the test file from the remote afterwards"),
+                ),
+        )
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    let mut args: Vec<String> = std::env::args().collect();
+    let mut matches = build_app().get_matches_from(&args);
+    if let Some(resume_matches) = matches.subcommand_matches("resume") {
+        let config = match matches.value_of("config") {
+            Some(path) => Some(fs::canonicalize(path)?),
+            None => None,
+        };
+        args = match resume_args(resume_matches, config)? {
+            Some(args) => args,
+            None => return Ok(()),
+        };
+        matches = build_app().get_matches_from(&args);
+    }
+    let json_errors = matches.is_present("json_errors");
+    match run(matches, &args).await {
+        Err(e) if json_errors => {
+            eprintln!("{}", json_error(&e));
+            std::process::exit(1);
+        }
+        result => result,
+    }
+}
+
+// where the chunk layout and progress of every leeched pack is stored
+fn plan_dir() -> Result<PathBuf> {
+    Ok(util::get_conf_dir("", "", PROGRAM_NAME)?.join("plans"))
+}
+
+// where the run summaries go when summary_path is left empty
+fn summary_dir() -> Result<PathBuf> {
+    Ok(util::get_conf_dir("", "", PROGRAM_NAME)?.join("summaries"))
+}
+
+// `rpl resume --list` prints the interrupted runs, `rpl resume <hash>` becomes the command line that
+// continues one, with the flags of that run replayed from its working directory. A --config given
+// to `rpl resume` replaces the one of the run. Returns None when there is nothing to run.
+fn resume_args(
+    resume_matches: &ArgMatches<'_>,
+    config: Option<PathBuf>,
+) -> Result<Option<Vec<String>>> {
+    let dir = plan_dir()?;
+    if resume_matches.is_present("list") {
+        let runs: Vec<(String, Plan)> = Plan::load_all(&dir)?
+            .into_iter()
+            .filter(|(_, plan)| !plan.is_complete() && !plan.input().is_empty())
+            .collect();
+        if runs.is_empty() {
+            println!("There is no interrupted run");
+        }
+        for (hash, plan) in runs {
+            let failed = match plan.failed() {
+                [] => String::new(),
+                failed => format!(", chunks {} failed", util::chunk_list(failed)),
+            };
+            println!(
+                "{}  {}  {}/{} chunks uploaded{}",
+                hash,
+                plan.name(),
+                plan.uploaded(),
+                plan.no_chunks(),
+                failed
+            );
+        }
+        return Ok(None);
+    }
+
+    let hash = resume_matches.value_of("hash").unwrap().to_lowercase();
+    let plan = match Plan::load(&Plan::path(&dir, &hash))? {
+        Some(plan) if !plan.input().is_empty() => plan,
+        _ => return Err(error::Error::UnknownRun(hash).into()),
+    };
+    if plan.is_complete() {
+        println!("`{}` has already been fully uploaded", plan.name());
+        return Ok(None);
+    }
+    let (flag, chunks) = match plan.failed() {
+        [] => ("--skip", plan.uploaded().to_string()),
+        _ => ("--only", util::chunk_list(&plan.remaining())),
+    };
+    let mut args = vec![String::from(PROGRAM_NAME), plan.input().to_owned()];
+    let mut flags = plan.args().iter();
+    while let Some(arg) = flags.next() {
+        if config.is_some() && arg == "--config" {
+            flags.next();
+        } else if config.is_none() || !arg.starts_with("--config=") {
+            args.push(arg.clone());
+        }
+    }
+    if let Some(path) = config {
+        args.extend([
+            String::from("--config"),
+            path.to_string_lossy().into_owned(),
+        ]);
+    }
+    args.extend([String::from(flag), chunks]);
+    if !plan.dir().as_os_str().is_empty() {
+        if let Err(e) = env::set_current_dir(plan.dir()) {
+            warn!(
+                "Could not change to {}, the directory of the interrupted run: {}",
+                plan.dir().display(),
+                e
+            );
+        }
+    }
+    Ok(Some(args))
+}
+
+// one client per remote path, spread by upload_mode when there are several of them
+async fn boxed_upload_client(
+    file_config: &Config,
+    upload_client: RcloneClient,
+    remote_paths: &[String],
+    upload_mode: UploadMode,
+) -> Result<Box<dyn RplUploadClient>, error::Error> {
+    if remote_paths.len() <= 1 {
+        return single_upload_client(file_config, upload_client).await;
+    }
+    let mut remotes = Vec::with_capacity(remote_paths.len());
+    for path in remote_paths {
+        remotes.push(
+            single_upload_client(file_config, upload_client.clone().destination(path)).await?,
+        );
+    }
+    Ok(Box::new(MultiRemoteClient::new(remotes, upload_mode)))
+}
+
+// "b2" and "sftp" swap rclone for their own client, with the same source, destination and
+// on_existing_remote
+async fn single_upload_client(
+    file_config: &Config,
+    upload_client: RcloneClient,
+) -> Result<Box<dyn RplUploadClient>, error::Error> {
+    Ok(match upload_client.variant.as_str() {
+        "b2" => Box::new(
+            get_b2_client(
+                file_config,
+                upload_client.get_source().to_path_buf(),
+                upload_client.get_destination(),
+            )
+            .await?
+            .on_existing_remote(upload_client.get_on_existing_remote()),
+        ),
+        "sftp" => {
+            let sftp = &file_config.sftp;
+            let identity_file = Some(&sftp.identity_file)
+                .filter(|file| !file.is_empty())
+                .map(|file| PathBuf::from(shellexpand::full(file).unwrap().into_owned()));
+            Box::new(
+                SftpClient::new(
+                    &sftp.host,
+                    sftp.port,
+                    &sftp.username,
+                    upload_client.get_source().to_path_buf(),
+                    upload_client.get_destination(),
+                )
+                .identity_file(identity_file)
+                .on_existing_remote(upload_client.get_on_existing_remote()),
+            )
+        }
+        _ => {
+            upload_client.check_installed()?;
+            Box::new(upload_client)
+        }
+    })
+}
+
+// run the leech while forwarding its events to the webhook and timing its chunks, until it
+// finishes or Ctrl-C
+async fn drive_leech(
+    events: UnboundedReceiverStream<ProgressEvent>,
+    run: LeechFuture<'_>,
+    webhook_url: Option<&str>,
+    stop: Option<Arc<AtomicBool>>,
+    running_upload: Box<dyn RplUploadClient>,
+    timings: &mut ChunkTimings,
+) -> Result<(), error::Error> {
+    let events = events.map(|event| {
+        timings.record(&event);
+        event
+    });
+    let forward_events = webhook::forward(webhook_url.map(Webhook::new), events);
+    tokio::select! {
+        (result, _) = async { tokio::join!(run, forward_events) } => result,
+        _ = interrupted(stop) => {
+            warn!("Interrupted, stopping rpl");
+            running_upload.interrupt();
+            Err(error::Error::Interrupted)
+        }
+    }
+}
+
+// delete the torrent of an interrupted run with its partial data, so the next run starts from a
+// clean save_path. A torrent without rpl's tag was never added by this run and is left alone.
+// With keep_files the files of every chunk so far stay, partial ones included.
+async fn remove_interrupted_torrent(
+    qbconfig: &RplRunningQbitConfig,
+    hash: &str,
+    keep_files: bool,
+) -> Result<(), error::Error> {
+    let qbit = QbitConfig::new(
+        &qbconfig.username,
+        &qbconfig.password,
+        &qbconfig.address,
+        &qbconfig.extra_headers,
+        qbconfig.pool_max_idle_per_host,
+        qbconfig.accept_invalid_certs,
+    )
+    .await?;
+    let tag = qbconfig.tags.split(',').next().unwrap_or(PROGRAM_NAME);
+    match qbit.get_torrent_info(hash).await {
+        Ok(info) if info.has_tag(tag) => (),
+        Ok(_) | Err(error::Error::QbitEmptyTorrentInfo) => return Ok(()),
+        Err(e) => return Err(e),
+    }
+    qbit.delete_torrent(hash, !keep_files).await?;
+    match keep_files {
+        true => info!("Removed the interrupted torrent from qBittorrent, its data is kept"),
+        false => info!("Removed the interrupted torrent and its partial data from qBittorrent"),
+    }
+    Ok(())
+}
 
+// aria2 and rTorrent never delete data themselves, so the files of an interrupted run are removed
+// from source like after an upload. Uploaded chunks are already gone, archived ones are in
+// output_dir.
+fn remove_partial_files(queue: &Queue, source: &Path) -> Result<(), error::Error> {
+    for job in queue.jobs() {
+        job.remove_files(source)?;
+    }
+    Ok(())
+}
+
+// resolves on Ctrl-C, or on the second one when stop is set by the first one
+async fn interrupted(stop: Option<Arc<AtomicBool>>) {
+    if let Some(stop) = stop {
+        let _ = tokio::signal::ctrl_c().await;
+        warn!("Stopping once the current chunk is uploaded, press Ctrl-C again to abort now");
+        stop.store(true, Ordering::SeqCst);
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+// {"category": "disk", "message": "...", ...} with the context fields of the error, for wrappers
+// that need to tell failures apart without parsing the message
+fn json_error(e: &anyhow::Error) -> serde_json::Value {
+    let mut object = serde_json::Map::new();
+    match e.downcast_ref::<error::Error>() {
+        Some(rpl_error) => {
+            object.insert(String::from("category"), rpl_error.category().into());
+            for (key, value) in rpl_error.context() {
+                object.insert(String::from(key), value);
+            }
+        }
+        None => {
+            object.insert(String::from("category"), "other".into());
+        }
+    }
+    object.insert(String::from("message"), format!("{:#}", e).into());
+    serde_json::Value::Object(object)
+}
+
+async fn run(matches: ArgMatches<'static>, args: &[String]) -> Result<()> {
     let verbosity: u64 = matches.occurrences_of("verbose");
-    let skip = if let Some(_val) = matches.value_of("skip") {
+    let mut skip = if let Some(_val) = matches.value_of("skip") {
         value_t!(matches, "skip", u32).expect("Could not parse the value of skip")
     } else {
         0
     };
+    let mut leech_settings = LeechSettings::new().skip(skip);
+    if let Some(budget) = matches.value_of("upload_budget") {
+        leech_settings = leech_settings.upload_budget(util::parse_size_checked(budget)?);
+    }
+    let output_dir = matches
+        .value_of("output_dir")
+        .map(|dir| PathBuf::from(shellexpand::full(dir).unwrap().into_owned()));
+    if let Some(path) = output_dir.clone() {
+        if !path.exists() {
+            debug!("{} does not exist. I will create it now", path.display());
+            fs::create_dir_all(&path)?;
+        }
+        warn!(
+            "Chunks will be kept in {}, make sure it has enough space for the whole pack",
+            path.display()
+        );
+        leech_settings = leech_settings.output_dir(path);
+    }
 
     let lock = matches.is_present("log");
     let log_path = if let Some(log) = matches.value_of("log") {
@@ -860,32 +3054,312 @@ async fn main() -> Result<()> {
         let file = OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(false)
             .open(log_path.unwrap())
             .unwrap();
         file.lock_exclusive()?;
     }
 
     debug!("-----Logger is initialized. Starting main program!-----");
-    let file_config = get_rpl_config()?;
+    let file_config = get_rpl_config(matches.value_of("config"))?;
+    // the progress bars do not show in a log file, the progress is logged there instead
+    let progress_log_secs = match matches.is_present("log") || verbosity > 0 {
+        true => file_config.rpl.progress_log_secs,
+        false => 0,
+    };
 
-    let config = get_running_config(&file_config, &matches)?;
+    if let Some(seed_matches) = matches.subcommand_matches("seed") {
+        return seed_only(&file_config, &matches, seed_matches).await;
+    }
+    if let Some(verify_matches) = matches.subcommand_matches("verify") {
+        return verify_only(&file_config, &matches, verify_matches).await;
+    }
+    if let Some(config_matches) = matches.subcommand_matches("config") {
+        if config_matches.subcommand_matches("validate").is_some() {
+            return validate_config(&file_config, &matches).await;
+        }
+    }
+    if let Some(bench_matches) = matches.subcommand_matches("bench") {
+        return bench_only(&file_config, &matches, bench_matches).await;
+    }
+
+    let parsed_input = parse_input(&file_config, &matches, &matches).await?;
+    if matches.is_present("to_magnet") {
+        println!("{}", magnet_link(&parsed_input.torrent));
+        return Ok(());
+    }
+
+    // qBittorrent is only asked here when the chunk layout depends on it, the disk checks ask it
+    // once the dry runs are out of the way
+    let remote_free_space = match sized_by_free_space(&file_config, &matches) {
+        true => qbit_free_space(&file_config, &matches).await?,
+        false => None,
+    };
+    let mut config = get_running_config(&file_config, &matches, remote_free_space)?;
     let qbconfig = get_qb_config(&file_config, &matches)?;
     let rclone_config = get_rclone_config(&file_config, &matches)?;
-    let seed_config = get_seed_config(&file_config, &matches)?;
-    let parsed_input = parse_input(&matches).await?;
+    let mut seed_config = get_seed_config(&file_config, &matches, false)?;
+    let summary_path = match matches.value_of("summary_path") {
+        Some(path) => Some(path),
+        None if file_config.rpl.write_summary => Some(file_config.rpl.summary_path.as_str()),
+        None => None,
+    };
+    let summary_path = match summary_path {
+        Some("") => Some(
+            summary_dir()?
+                .join(parsed_input.torrent.info_hash())
+                .with_extension("json"),
+        ),
+        Some(path) => Some(PathBuf::from(shellexpand::full(path).unwrap().into_owned())),
+        None => None,
+    };
+    let nested = nested_dir(&file_config, &matches, &parsed_input.torrent.name);
+    if let Some(dir) = &nested {
+        config.nest(dir);
+        seed_config = seed_config.nest(dir);
+        let save_path = PathBuf::from(shellexpand::full(&config.save_path).unwrap().into_owned());
+        debug!("Leeching the pack in {}", save_path.display());
+        fs::create_dir_all(&save_path)?;
+    }
 
-    check_max_size_requirements(&config, &parsed_input.raw_data)?;
+    let mut pack_config = pack_layout(&file_config, &matches, &mut config, &parsed_input)?;
+    if let Some(dir) = &config.cross_seed_dir {
+        leech_settings = leech_settings.cross_seed(CrossSeed::load(dir)?);
+    }
+    if matches.is_present("verify_pieces") || file_config.rpl.verify_pieces {
+        leech_settings = leech_settings.verify_pieces(true);
+    }
+    if matches.is_present("final_verify_count") || file_config.rpl.final_verify_count {
+        leech_settings = leech_settings.final_verify_count(true);
+    }
+    let mut pipeline = matches.is_present("pipeline") || file_config.rpl.pipeline;
+    if pipeline && config.torrent_client != "qbittorrent" {
+        warn!("pipeline is only supported with qBittorrent, chunks are leeched one after another");
+        pipeline = false;
+    }
+    leech_settings = leech_settings.pipeline(pipeline);
+    let mut keep_files =
+        matches.is_present("keep_after_upload") || file_config.rpl.keep_after_upload;
+    if keep_files && config.torrent_client != "qbittorrent" {
+        warn!("keep_after_upload is only supported with qBittorrent, chunks are deleted after their upload");
+        keep_files = false;
+    }
+    if keep_files {
+        warn!(
+            "Chunks will be left in {} after their upload, max_size no longer bounds the disk usage so make sure it has enough space for the whole pack",
+            config.save_path
+        );
+    }
+    leech_settings = leech_settings.keep_files(keep_files);
 
-    let mut pack_config = TorrentPack::new(
-        Torrent::read_from_bytes(&parsed_input.raw_data).unwrap(),
-        config.ignore_warning,
-    )
-    .max_size(config.max_size as i64);
+    if rclone_config.rclone_command == "move" {
+        if output_dir.is_some() {
+            return Err(error::Error::RcloneMoveConflict("--output-dir").into());
+        }
+        if pipeline {
+            return Err(error::Error::RcloneMoveConflict("pipeline").into());
+        }
+        if keep_files {
+            return Err(error::Error::RcloneMoveConflict("keep_after_upload").into());
+        }
+        if config.upload_mode == UploadMode::Mirror && config.remote_paths.len() > 1 {
+            return Err(error::Error::RcloneMoveConflict("upload_mode = \"mirror\"").into());
+        }
+    }
 
-    let qbit = QbitConfig::new(&qbconfig.username, &qbconfig.password, &qbconfig.address).await?;
+    if let Some(speed) = matches.value_of("estimate") {
+        let speed = util::parse_speed(speed).ok_or(error::Error::InvalidSpeed)?;
+        let queue = build_queue(pack_config.chunks()?, parsed_input.torrent.clone())?;
+        let total_size = queue.total_size();
+        let transfer_secs = total_size as u64 / max(speed, 1);
+        println!(
+            "{} to transfer in {} chunks at {}/s",
+            total_size.file_size(file_size_opts::BINARY).unwrap(),
+            queue.no_jobs(),
+            speed.file_size(file_size_opts::BINARY).unwrap(),
+        );
+        println!(
+            "Estimated download time: {}",
+            util::format_duration(transfer_secs)
+        );
+        println!(
+            "Estimated upload time: {}",
+            util::format_duration(transfer_secs)
+        );
+        println!(
+            "Estimated total time: {}",
+            util::format_duration(transfer_secs * 2)
+        );
+        return Ok(());
+    }
+
+    if matches.is_present("dry_run") {
+        let queue = build_queue(pack_config.chunks()?, parsed_input.torrent.clone())?;
+        print!("{}", queue.describe());
+        return Ok(());
+    }
+
+    if matches.is_present("list_chunks") {
+        let queue = build_queue(pack_config.chunks()?, parsed_input.torrent.clone())?;
+        println!("{}", serde_json::to_string_pretty(queue.jobs())?);
+        return Ok(());
+    }
 
-    let torrent_config = QbitTorrent::default()
-        .torrents(Torrent::read_from_bytes(&parsed_input.raw_data).unwrap())
+    // chunks 1..skip must hold the files that were uploaded when the pack was last leeched
+    let plan_path = Plan::path(&plan_dir()?, &parsed_input.torrent.info_hash());
+    let input = matches.value_of("input").unwrap();
+    let input_reference = match parsed_input.input_type {
+        // `rpl resume` may run from another directory
+        RplInputType::NormalPath => fs::canonicalize(input)?.to_string_lossy().into_owned(),
+        _ => String::from(input),
+    };
+    let queue = build_queue(pack_config.chunks()?, parsed_input.torrent.clone())?;
+    let max_chunks =
+        number_arg::<u32>(&matches, "max_chunks")?.unwrap_or(file_config.rpl.max_chunks);
+    if max_chunks > 0 && queue.no_jobs() > max_chunks as usize {
+        return Err(error::Error::TooManyChunks(queue.no_jobs(), max_chunks).into());
+    }
+    let stored = Plan::load(&plan_path)?;
+    let mut only = match matches.value_of("only") {
+        Some(list) => Some(
+            util::parse_chunk_list(list)
+                .filter(|chunks| {
+                    chunks
+                        .iter()
+                        .all(|chunk| *chunk as usize <= queue.no_jobs())
+                })
+                .ok_or_else(|| error::Error::InvalidChunkSelection(list.to_owned()))?,
+        ),
+        None => None,
+    };
+    // an interrupted run of the pack continues after its last uploaded chunk
+    if !matches.is_present("skip") && only.is_none() && !matches.is_present("no_resume") {
+        // chunks that failed in that run are retried first
+        if let Some(stored) = stored
+            .as_ref()
+            .filter(|stored| !stored.is_complete() && !stored.failed().is_empty())
+        {
+            info!(
+                "Resuming an interrupted run of `{}`, retrying chunks {} that failed, rerun with --no-resume to start over",
+                stored.name(),
+                util::chunk_list(stored.failed())
+            );
+            only = Some(stored.remaining());
+        } else if let Some(stored) = stored
+            .as_ref()
+            .filter(|stored| !stored.is_complete() && stored.uploaded() > 0)
+        {
+            info!(
+                "Resuming an interrupted run of `{}` after chunk {}/{}, rerun with --no-resume to start over",
+                stored.name(),
+                stored.uploaded(),
+                stored.no_chunks()
+            );
+            skip = stored.uploaded();
+            leech_settings = leech_settings.skip(skip);
+        }
+    }
+    let mut plan = Plan::from_queue(&queue, &parsed_input.torrent.name, &input_reference, skip)
+        .replay(args, input, &env::current_dir()?);
+    // with --only the chunks uploaded back then are the ones left out
+    let mut uploaded_before = skip;
+    if let Some(chunks) = &only {
+        if let Some(stored) = &stored {
+            plan = plan.keep_progress(stored);
+            uploaded_before = stored.uploaded();
+        }
+        leech_settings = leech_settings.only(chunks.to_vec());
+    }
+    if uploaded_before > 0 && !matches.is_present("replan") {
+        match &stored {
+            Some(stored) => {
+                if let Some(chunk) = stored.diverges(&plan, uploaded_before) {
+                    error!(
+                        "The chunk layout changed since the last run (max_size, free space or manifest), skipping {} chunks would not skip the chunks uploaded back then",
+                        uploaded_before
+                    );
+                    info!("Check what is on the remote with `rpl verify` and rerun rpl with `--replan` to continue with the current layout, or with `--no-resume` to start over");
+                    return Err(error::Error::PlanMismatch(chunk).into());
+                }
+            }
+            None => warn!(
+                "No chunk layout is stored for this pack, rpl cannot check that --skip {} skips the chunks uploaded before",
+                skip
+            ),
+        }
+    }
+    plan.save(&plan_path)?;
+    leech_settings = leech_settings.plan(plan, plan_path.clone());
+
+    // the chunk sizes only bound the disk usage when every chunk is deleted after its upload
+    let save_path = PathBuf::from(shellexpand::full(&config.save_path).unwrap().into_owned());
+    let keep_chunks = match &output_dir {
+        Some(dir) => util::same_filesystem(&save_path, dir)?,
+        None => keep_files,
+    };
+    let peak = queue.peak_disk_usage(skip, only.as_deref(), keep_chunks, pipeline) as u64;
+    let remote_free_space = match remote_free_space {
+        Some(size) => Some(size),
+        None => qbit_free_space(&file_config, &matches).await?,
+    };
+    let free_space = match remote_free_space.map_or_else(|| fs2::available_space(&save_path), Ok) {
+        Ok(size) => size,
+        Err(_e) => return Err(error::Error::DiskSpaceReadError.into()),
+    };
+    // not even one chunk fits, max_size is larger than the disk
+    let largest_chunk = queue.largest_chunk(skip, only.as_deref()) as u64;
+    if largest_chunk > free_space {
+        let message = format!(
+            "The largest chunk is {} but only {} is free in save_path, lower max_size",
+            largest_chunk.file_size(file_size_opts::BINARY).unwrap(),
+            free_space.file_size(file_size_opts::BINARY).unwrap(),
+        );
+        if !config.ignore_warning {
+            error!(
+                "{}. If you really want to do this, rerun the program with -f/--force",
+                message
+            );
+            return Err(error::Error::InsufficientDiskSpace.into());
+        }
+        warn!("{}", message);
+    } else if peak > free_space {
+        let message = format!(
+            "The run may need up to {} of disk space{} but only {} is free in save_path",
+            peak.file_size(file_size_opts::BINARY).unwrap(),
+            match (keep_chunks, keep_files) {
+                (true, true) => " (chunks are left in save_path after their upload)",
+                (true, false) => " (chunks are kept in output_dir on the same disk)",
+                (false, _) => "",
+            },
+            free_space.file_size(file_size_opts::BINARY).unwrap(),
+        );
+        if matches.is_present("strict") {
+            error!("{}", message);
+            return Err(error::Error::InsufficientDiskSpace.into());
+        }
+        warn!("{}, rerun with --strict to stop here instead", message);
+    } else {
+        debug!(
+            "Peak disk usage of the run is {}, {} is free in save_path",
+            peak.file_size(file_size_opts::BINARY).unwrap(),
+            free_space.file_size(file_size_opts::BINARY).unwrap()
+        );
+    }
+
+    let mut torrent = parsed_input.torrent.clone();
+    if let Some(url) = matches.value_of("announce") {
+        if torrent.is_private() && !config.ignore_warning {
+            error!("Changing the tracker of a private torrent may get you banned from it. If you really want to do this, rerun the program with -f/--force");
+            return Err(error::Error::PrivateTorrentAnnounce.into());
+        }
+        info!("Announcing the torrent to {}", url);
+        override_announce(&mut torrent, url);
+    }
+
+    // the torrent for transmission and aria2, which do not take qBittorrent's add options
+    let plain_torrent = torrent.clone();
+    let mut torrent_config = QbitTorrent::default()
+        .torrents(torrent)
         .paused(true)
         .save_path(PathBuf::from(
             shellexpand::full(&config.save_path)
@@ -893,27 +3367,502 @@ async fn main() -> Result<()> {
                 .into_owned(),
         ))
         .upload_limit(qbconfig.upload_limit)
-        .download_limit(qbconfig.download_limit);
+        .download_limit(qbconfig.download_limit)
+        .tags(&qbconfig.tags);
+    if !qbconfig.category.is_empty() {
+        torrent_config = torrent_config.category(&qbconfig.category);
+    }
+    if let Some(path) = &qbconfig.download_path {
+        torrent_config = torrent_config.download_path(path.clone());
+    }
+    if file_config.qbittorrent.skip_checking_on_leech {
+        torrent_config = torrent_config.skip_hash_checking(true);
+    }
+    let no_op = matches.is_present("no_op");
+    if no_op {
+        warn!("Running in no-op mode: chunks will not be fully downloaded and nothing will be uploaded");
+        // keep whatever qBittorrent fetches before the chunk is dropped tiny
+        torrent_config = torrent_config.download_limit(NO_OP_DOWNLOAD_LIMIT);
+    }
 
-    let upload_client = RcloneClient::new(
+    let on_existing = rclone_config.on_existing();
+    let user_agent = rclone_config.user_agent();
+    let bwlimit = rclone_config.bandwidth_limit();
+    let service_account_file = rclone_config.service_account_file();
+    let drive = rclone_config.is_drive();
+    let mut upload_client = RcloneClient::new(
         config.upload_client,
         PathBuf::from(shellexpand::full(&config.save_path).unwrap().into_owned()),
         config.remote_path,
         rclone_config.transfers,
         rclone_config.drive_chunk_size,
         rclone_config.extra_custom_flags,
-    );
+    )
+    .filters(build_filters(
+        &rclone_config.incomplete_suffixes,
+        &rclone_config.exclude,
+        &rclone_config.include,
+    ))
+    .adaptive_transfers(rclone_config.adaptive_transfers)
+    .checkers(rclone_config.checkers)
+    .retries(
+        rclone_config.rclone_retries,
+        rclone_config.rclone_low_level_retries,
+    )
+    .upload_retries(rclone_config.upload_retries)
+    .progress_log(progress_log_secs)
+    .move_files(rclone_config.rclone_command == "move")
+    .atomic_upload(rclone_config.atomic_upload)
+    .preserve_mtime(rclone_config.preserve_mtime)
+    .on_existing_remote(on_existing)
+    .user_agent(user_agent)
+    .bwlimit(bwlimit)
+    .drive_flags(drive)
+    .drive_service_account_file(service_account_file)
+    .log_level(rclone_config.rclone_log_level)
+    .dry_run(no_op);
+
+    if matches.is_present("emit_script") {
+        match config.torrent_client.as_str() {
+            "transmission" => {
+                return Err(error::Error::TransmissionUnsupported("--emit-script").into())
+            }
+            "aria2" => return Err(error::Error::Aria2Unsupported("--emit-script").into()),
+            "rtorrent" => return Err(error::Error::RtorrentUnsupported("--emit-script").into()),
+            _ => (),
+        }
+        match upload_client.variant.as_str() {
+            "b2" => return Err(error::Error::B2Unsupported("--emit-script").into()),
+            "sftp" => return Err(error::Error::SftpUnsupported("--emit-script").into()),
+            _ => (),
+        }
+        if matches.is_present("announce") {
+            warn!("The emitted script adds the original torrent, --announce is not applied to it");
+        }
+        if config.remote_paths.len() > 1 {
+            warn!("The emitted script only uploads to the first remote path");
+        }
+        let input = matches.value_of("input").unwrap();
+        let torrent_source = match parsed_input.input_type {
+            RplInputType::NormalPath => format!(
+                "torrents=@{}",
+                shellexpand::full(input)
+                    .expect("Could not look up a variable in input")
+                    .into_owned()
+            ),
+            RplInputType::UrlLink | RplInputType::MagnetString => format!("urls={}", input),
+            RplInputType::Stdin => {
+                return Err(error::Error::StdinUnsupported("--emit-script").into());
+            }
+        };
+        let script = emit_script(
+            &mut pack_config,
+            parsed_input.torrent.clone(),
+            &torrent_config,
+            &torrent_source,
+            &qbconfig.address,
+            &qbconfig.username,
+            &upload_client,
+            &seed_config,
+            &leech_settings,
+        )?;
+        print!("{}", script);
+        return Ok(());
+    }
 
-    pack_config
-        .leech_torrent(
-            Torrent::read_from_bytes(&parsed_input.raw_data).unwrap(),
-            torrent_config,
-            qbit,
-            upload_client,
-            seed_config,
-            skip,
-        )
-        .await?;
+    let stop = match matches.is_present("graceful") || file_config.rpl.graceful_shutdown {
+        true => {
+            let stop = Arc::new(AtomicBool::new(false));
+            leech_settings = leech_settings.stop_flag(stop.clone());
+            upload_client = upload_client.own_process_group(true);
+            Some(stop)
+        }
+        false => None,
+    };
+
+    let name = parsed_input.torrent.name.to_owned();
+    let hash = parsed_input.torrent.info_hash();
+    let summary_torrent = summary_path.as_ref().map(|_| parsed_input.torrent.clone());
+    let mut timings = ChunkTimings::new();
+    let started = Instant::now();
+    let leech_result = match config.torrent_client.as_str() {
+        "transmission" => {
+            let transmission_config = &file_config.transmission;
+            let transmission = TransmissionConfig::new(
+                &transmission_config.address,
+                &transmission_config.username,
+                &transmission_config.password,
+            )
+            .await?
+            .no_op(no_op);
+            let download_limit = match no_op {
+                true => NO_OP_DOWNLOAD_LIMIT,
+                false => qbconfig.download_limit as i64,
+            };
+            let torrent_config = TransmissionTorrent::default()
+                .torrents(plain_torrent)
+                .paused(true)
+                .save_path(upload_client.get_source().to_path_buf())
+                .download_limit(download_limit / 1024);
+
+            let upload_client = boxed_upload_client(
+                &file_config,
+                upload_client,
+                &config.remote_paths,
+                config.upload_mode,
+            )
+            .await?;
+            let running_upload = upload_client.clone();
+            let (events, run) = pack_config.leech_torrent_with_events(
+                parsed_input.torrent,
+                torrent_config,
+                transmission,
+                upload_client,
+                seed_config,
+                leech_settings,
+            );
+            let leech_result = drive_leech(
+                events,
+                run,
+                config.webhook_url.as_deref(),
+                stop,
+                running_upload,
+                &mut timings,
+            )
+            .await;
+
+            if matches!(leech_result, Err(error::Error::Interrupted)) {
+                let removed = async {
+                    TransmissionConfig::new(
+                        &transmission_config.address,
+                        &transmission_config.username,
+                        &transmission_config.password,
+                    )
+                    .await?
+                    .delete_torrent(&hash, true)
+                    .await
+                };
+                match removed.await {
+                    Ok(_) => {
+                        info!("Removed the interrupted torrent and its data from Transmission")
+                    }
+                    Err(e) => error!(
+                        "Could not remove the interrupted torrent from Transmission: {}",
+                        e
+                    ),
+                }
+            }
+            leech_result
+        }
+        "aria2" => {
+            let aria2 = Aria2Config::new(&file_config.aria2.address, &file_config.aria2.secret)
+                .await?
+                .no_op(no_op);
+            let download_limit = match no_op {
+                true => NO_OP_DOWNLOAD_LIMIT,
+                false => qbconfig.download_limit as i64,
+            };
+            let torrent_config = Aria2Torrent::default()
+                .torrents(plain_torrent)
+                .paused(true)
+                .save_path(upload_client.get_source().to_path_buf())
+                .download_limit(download_limit);
+
+            let source = upload_client.get_source().to_path_buf();
+            let upload_client = boxed_upload_client(
+                &file_config,
+                upload_client,
+                &config.remote_paths,
+                config.upload_mode,
+            )
+            .await?;
+            let running_upload = upload_client.clone();
+            let (events, run) = pack_config.leech_torrent_with_events(
+                parsed_input.torrent,
+                torrent_config,
+                aria2,
+                upload_client,
+                seed_config,
+                leech_settings,
+            );
+            let leech_result = drive_leech(
+                events,
+                run,
+                config.webhook_url.as_deref(),
+                stop,
+                running_upload,
+                &mut timings,
+            )
+            .await;
+
+            if matches!(leech_result, Err(error::Error::Interrupted)) {
+                let removed = async {
+                    let aria2 =
+                        Aria2Config::new(&file_config.aria2.address, &file_config.aria2.secret)
+                            .await?;
+                    if let Some(gid) = aria2.find_torrent(&hash).await? {
+                        aria2.remove_torrent(&gid).await?;
+                    }
+                    remove_partial_files(&queue, &source)
+                };
+                match removed.await {
+                    Ok(_) => info!("Removed the interrupted torrent and its data from aria2"),
+                    Err(e) => error!("Could not remove the interrupted torrent from aria2: {}", e),
+                }
+            }
+            leech_result
+        }
+        "rtorrent" => {
+            let rtorrent_config = &file_config.rtorrent;
+            let rtorrent = RtorrentConfig::new(
+                &rtorrent_config.address,
+                &rtorrent_config.username,
+                &rtorrent_config.password,
+            )
+            .await?
+            .no_op(no_op);
+            let torrent_config = RtorrentTorrent::default()
+                .torrents(plain_torrent)
+                .save_path(upload_client.get_source().to_path_buf());
+
+            let source = upload_client.get_source().to_path_buf();
+            let upload_client = boxed_upload_client(
+                &file_config,
+                upload_client,
+                &config.remote_paths,
+                config.upload_mode,
+            )
+            .await?;
+            let running_upload = upload_client.clone();
+            let (events, run) = pack_config.leech_torrent_with_events(
+                parsed_input.torrent,
+                torrent_config,
+                rtorrent,
+                upload_client,
+                seed_config,
+                leech_settings,
+            );
+            let leech_result = drive_leech(
+                events,
+                run,
+                config.webhook_url.as_deref(),
+                stop,
+                running_upload,
+                &mut timings,
+            )
+            .await;
+
+            if matches!(leech_result, Err(error::Error::Interrupted)) {
+                let removed = async {
+                    RtorrentConfig::new(
+                        &rtorrent_config.address,
+                        &rtorrent_config.username,
+                        &rtorrent_config.password,
+                    )
+                    .await?
+                    .remove_torrent(&hash.to_uppercase())
+                    .await?;
+                    remove_partial_files(&queue, &source)
+                };
+                match removed.await {
+                    Ok(_) => info!("Removed the interrupted torrent and its data from rTorrent"),
+                    Err(e) => error!(
+                        "Could not remove the interrupted torrent from rTorrent: {}",
+                        e
+                    ),
+                }
+            }
+            leech_result
+        }
+        _ => {
+            if qbconfig.wait_for_client_secs > 0 {
+                QbitConfig::wait_for_client(
+                    &qbconfig.address,
+                    qbconfig.wait_for_client_secs,
+                    qbconfig.accept_invalid_certs,
+                )
+                .await?;
+            }
+
+            let qbit = QbitConfig::new(
+                &qbconfig.username,
+                &qbconfig.password,
+                &qbconfig.address,
+                &qbconfig.extra_headers,
+                qbconfig.pool_max_idle_per_host,
+                qbconfig.accept_invalid_certs,
+            )
+            .await?
+            .min_seeders(
+                qbconfig.min_seeders,
+                qbconfig.min_seeders_timeout,
+                qbconfig.skip_unseeded,
+            )
+            .checking_resume_data_timeout(qbconfig.checking_resume_data_timeout)
+            .heartbeat(match progress_log_secs {
+                0 => qbconfig.heartbeat_secs,
+                secs => secs,
+            })
+            .disk_full_wait(qbconfig.disk_full_wait_secs)
+            .stalled_poll_backoff(qbconfig.stalled_poll_max_secs)
+            .max_concurrent_requests(qbconfig.max_concurrent_requests)
+            .no_op(no_op);
+
+            if qbconfig.use_category_path && !qbconfig.category.is_empty() {
+                if let Some(category_path) = qbit.get_category_save_path(&qbconfig.category).await?
+                {
+                    info!(
+                        "Using save path `{}` from category `{}`",
+                        category_path, qbconfig.category
+                    );
+                    let path = PathBuf::from(category_path);
+                    torrent_config = torrent_config.save_path(path.clone());
+                    upload_client = upload_client.source(path);
+                }
+            }
+
+            let paused_torrents = match qbconfig.exclusive_mode {
+                true => qbit.pause_active_torrents().await?,
+                false => Vec::new(),
+            };
+            let alt_limits_enabled = match qbconfig.override_alt_limits {
+                true => qbit.disable_alt_speed_limits().await?,
+                false => false,
+            };
+            // qbit is moved into leech_torrent, keep a session of our own to resume the paused torrents
+            // and turn the alternative speed limits back on
+            let restore_qbit = match paused_torrents.is_empty() && !alt_limits_enabled {
+                true => None,
+                false => Some(
+                    QbitConfig::new(
+                        &qbconfig.username,
+                        &qbconfig.password,
+                        &qbconfig.address,
+                        &qbconfig.extra_headers,
+                        qbconfig.pool_max_idle_per_host,
+                        qbconfig.accept_invalid_certs,
+                    )
+                    .await?,
+                ),
+            };
+
+            let upload_client = boxed_upload_client(
+                &file_config,
+                upload_client,
+                &config.remote_paths,
+                config.upload_mode,
+            )
+            .await?;
+            let running_upload = upload_client.clone();
+            let torrent_id = qbit.torrent_id(&parsed_input.torrent);
+
+            let (events, run) = pack_config.leech_torrent_with_events(
+                parsed_input.torrent,
+                torrent_config,
+                qbit,
+                upload_client,
+                seed_config,
+                leech_settings,
+            );
+            let leech_result = drive_leech(
+                events,
+                run,
+                config.webhook_url.as_deref(),
+                stop,
+                running_upload,
+                &mut timings,
+            )
+            .await;
+
+            if matches!(leech_result, Err(error::Error::Interrupted)) {
+                if let Err(e) = remove_interrupted_torrent(&qbconfig, &torrent_id, keep_files).await
+                {
+                    error!(
+                        "Could not remove the interrupted torrent from qBittorrent: {}",
+                        e
+                    );
+                }
+            }
+
+            if let Some(restore_qbit) = restore_qbit {
+                if let Err(e) = restore_qbit.resume_torrents(&paused_torrents).await {
+                    error!(
+                        "Could not resume the torrents paused by exclusive_mode: {}. Their hashes are: {}",
+                        e,
+                        paused_torrents.join(", ")
+                    );
+                }
+                if alt_limits_enabled {
+                    match restore_qbit.set_alt_speed_limits(true).await {
+                        Ok(_) => info!("Turned qBittorrent's alternative speed limits back on"),
+                        Err(e) => error!(
+                            "Could not turn qBittorrent's alternative speed limits back on: {}",
+                            e
+                        ),
+                    }
+                }
+            }
+            leech_result
+        }
+    };
+    let error = leech_result.as_ref().err().map(|e| e.to_string());
+    let elapsed_secs = started.elapsed().as_secs();
+    let notify_client = reqwest::Client::new();
+    if let Some(url) = &config.notify_url {
+        let summary = webhook::discord_summary(
+            &name,
+            queue.total_size(),
+            queue.no_jobs(),
+            elapsed_secs,
+            error.as_deref(),
+        );
+        Webhook::new(url)
+            .client(notify_client.clone())
+            .post(&summary)
+            .await;
+    }
+    if let Some((bot_token, chat_id)) = &config.telegram {
+        let summary = webhook::telegram_summary(
+            chat_id,
+            &name,
+            queue.total_size(),
+            queue.no_jobs(),
+            elapsed_secs,
+            error.as_deref(),
+        );
+        Webhook::telegram(bot_token)
+            .client(notify_client)
+            .post(&summary)
+            .await;
+    }
+    if matches.is_present("desktop_notify") || file_config.notifications.desktop {
+        let (title, body) = webhook::desktop_summary(&name, elapsed_secs, error.as_deref());
+        webhook::desktop_notify(&title, &body);
+    }
+    if let (Some(path), Some(torrent)) = (&summary_path, &summary_torrent) {
+        let summary = RunSummary::new(torrent, &queue, &timings, elapsed_secs, error.as_deref());
+        if let Err(e) = summary.save(path) {
+            warn!(
+                "Could not write the run summary to {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+    leech_result?;
+
+    // nothing is left to resume once every chunk is on the remote
+    if Plan::load(&plan_path)?.is_some_and(|plan| plan.is_complete()) {
+        debug!("Removing the finished plan {}", plan_path.display());
+        fs::remove_file(&plan_path)?;
+    }
+    // the folder of the pack is empty once every chunk is uploaded and deleted
+    if nested.is_some() {
+        let save_path = PathBuf::from(shellexpand::full(&config.save_path).unwrap().into_owned());
+        if fs::remove_dir(&save_path).is_ok() {
+            debug!("Removed the empty folder {}", save_path.display());
+        }
+    }
 
     match parsed_input.input_type {
         RplInputType::NormalPath => info!(
@@ -928,6 +3877,7 @@ async fn main() -> Result<()> {
             "Magnet `{}` finished leeching!",
             matches.value_of("input").unwrap()
         ),
+        RplInputType::Stdin => info!("Torrent `{}` from stdin finished leeching!", name),
     }
 
     debug!("-----Everything is finished!-----");
@@ -940,3 +3890,50 @@ async fn main() -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subcommand_flags() {
+        let matches = build_app()
+            .get_matches_from_safe(vec!["rpl", "--config", "x.toml", "config", "validate"])
+            .unwrap();
+        assert_eq!(matches.value_of("config"), Some("x.toml"));
+        assert!(matches.subcommand_matches("config").is_some());
+
+        let matches = build_app()
+            .get_matches_from_safe(vec!["rpl", "-v", "resume", "--list"])
+            .unwrap();
+        assert_eq!(matches.occurrences_of("verbose"), 1);
+        assert!(matches.subcommand_matches("resume").is_some());
+
+        let matches = build_app()
+            .get_matches_from_safe(vec!["rpl", "verify", "t.torrent", "--config", "x.toml"])
+            .unwrap();
+        assert_eq!(matches.value_of("config"), Some("x.toml"));
+
+        let matches = build_app()
+            .get_matches_from_safe(vec!["rpl", "--config", "x.toml", "t.torrent"])
+            .unwrap();
+        assert_eq!(matches.value_of("input"), Some("t.torrent"));
+    }
+
+    #[test]
+    fn number_args() {
+        let matches = build_app()
+            .get_matches_from_safe(vec!["rpl", "--num-chunks", "4", "t.torrent"])
+            .unwrap();
+        assert_eq!(number_arg::<u32>(&matches, "num_chunks").unwrap(), Some(4));
+
+        let matches = build_app()
+            .get_matches_from_safe(vec!["rpl", "--num-chunks", "four", "t.torrent"])
+            .unwrap();
+        assert!(matches!(
+            number_arg::<u32>(&matches, "num_chunks"),
+            Err(error::Error::InvalidNumber(flag, _)) if flag == "--num-chunks"
+        ));
+        assert_eq!(number_arg::<u32>(&matches, "max_chunks").unwrap(), None);
+    }
+}